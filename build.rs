@@ -0,0 +1,17 @@
+fn main() {
+    // Regenerate the C header for the `ffi` feature
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=src/ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+            .expect("cbindgen.toml is valid");
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/ffi.rs", crate_dir))
+            .generate()
+            .expect("Unable to generate C bindings")
+            .write_to_file(format!("{}/include/dominant_colours.h", crate_dir));
+    }
+}
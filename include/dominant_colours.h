@@ -0,0 +1,67 @@
+#ifndef DOMINANT_COLOURS_H
+#define DOMINANT_COLOURS_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Analysis settings. A `colours` value of zero selects the default of 6.
+typedef struct DominantColoursOptions {
+  size_t colours;
+  bool silhouette;
+  // Cluster in CIE L*a*b* rather than RGB
+  bool lab;
+  // Resize and cluster in linear light
+  bool linear;
+} DominantColoursOptions;
+
+// One extracted colour.
+typedef struct DominantColour {
+  uint8_t rgb[3];
+  // Share of the image covered by the colour's cluster, in percent
+  double percentage;
+} DominantColour;
+
+// Palette filled in by `dominant_colours_extract`; release it with
+// `dominant_colours_result_free`.
+typedef struct DominantColoursResult {
+  // Colours sorted by prevalence, most prevalent first
+  struct DominantColour *colours;
+  size_t len;
+  double inertia;
+  // Silhouette coefficient, or NaN when not requested or undefined
+  double silhouette;
+} DominantColoursResult;
+
+// Extract the dominant colours of an encoded image (PNG, JPEG, ...) of `len` bytes.
+//
+// Returns 0 on success and fills in `result`. On failure returns -1, leaves `result`
+// untouched and records a message for `dominant_colours_last_error`. `options` may be
+// NULL for the defaults.
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes, `options` must be NULL or valid, and
+// `result` must point to writable memory for a `DominantColoursResult`.
+int dominant_colours_extract(const uint8_t *data,
+                             size_t len,
+                             const struct DominantColoursOptions *options,
+                             struct DominantColoursResult *result);
+
+// Free the colours of a result filled in by `dominant_colours_extract`. Safe to call
+// twice; the result is reset to empty.
+//
+// # Safety
+//
+// `result` must be NULL or point to a result produced by `dominant_colours_extract`.
+void dominant_colours_result_free(struct DominantColoursResult *result);
+
+// Message describing the last failure on this thread, or NULL if there was none. The
+// string stays valid until the next failing call on the same thread.
+const char *dominant_colours_last_error(void);
+
+#endif  /* DOMINANT_COLOURS_H */
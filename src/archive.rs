@@ -0,0 +1,405 @@
+//! Images inside ZIP and tar archives, analysed without unpacking them to disk.
+//!
+//! An image in an archive is named by the archive's path and the entry's name joined
+//! by `!`, e.g. `photos.zip!shoot/cat.jpg`, and can be given wherever an image file
+//! can. Entries are read into memory one at a time, each up to `MAX_IMAGE_BYTES`, and
+//! ZIP entries are checked against their CRC. ZIP archives are indexed by their central
+//! directory, so reading an entry is direct; a tar has no index, so reading an entry
+//! scans the archive up to it (decompressing again, for `.tar.gz`). `for_each_input`
+//! reads a whole run of entries in one pass instead.
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use flate2::read::GzDecoder;
+use tar::EntryType;
+use zip::ZipArchive;
+
+use crate::files::is_image_path;
+use crate::{DominantColoursError, MAX_IMAGE_BYTES};
+
+/// Separates an archive's path from the name of an entry in it.
+pub const MEMBER_SEPARATOR: char = '!';
+
+/// Largest GNU long name or PAX header read for a tar entry.
+const MAX_HEADER_BYTES: u64 = 64 * 1024;
+
+/// Entries read ahead by `for_each_input`, by their full `archive!entry` name, while
+/// they are being analysed.
+static LOADED: Mutex<Vec<(String, Arc<Vec<u8>>)>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn kind(path: &str) -> Option<Kind> {
+    let lower = path.to_ascii_lowercase();
+    if lower.ends_with(".zip") {
+        Some(Kind::Zip)
+    } else if lower.ends_with(".tar") {
+        Some(Kind::Tar)
+    } else if lower.ends_with(".tar.gz") || lower.ends_with(".tgz") {
+        Some(Kind::TarGz)
+    } else {
+        None
+    }
+}
+
+/// Whether `path` names a ZIP or tar archive (`.zip`, `.tar`, `.tar.gz` or `.tgz`).
+pub fn is_archive_path(path: &str) -> bool {
+    kind(path).is_some()
+}
+
+/// Split `archive.zip!entry` into the archive's path and the entry's name, if `filename`
+/// names an entry of an archive.
+pub fn split_member(filename: &str) -> Option<(&str, &str)> {
+    filename
+        .match_indices(MEMBER_SEPARATOR)
+        .map(|(i, _)| (&filename[..i], &filename[i + 1..]))
+        .find(|(archive, entry)| is_archive_path(archive) && !entry.is_empty())
+}
+
+/// Names of the images in `archive`, as `archive!entry`, in the order they are stored.
+/// Entries are told to be images by their extension.
+pub fn images(archive: &str) -> Result<Vec<String>, DominantColoursError> {
+    let names = match kind(archive) {
+        Some(Kind::Zip) => open_zip(archive)?
+            .map(|zip| (0..zip.len()).filter_map(|i| zip.name_for_index(i)).map(str::to_string).collect()),
+        Some(kind) => tar_names(open_tar(archive, kind)?),
+        None => Err(invalid("not a ZIP or tar archive")),
+    };
+    Ok(names
+        .map_err(|e| io_error(archive, e))?
+        .into_iter()
+        .filter(|name| is_image_path(Path::new(name)))
+        .map(|name| format!("{}{}{}", archive, MEMBER_SEPARATOR, name))
+        .collect())
+}
+
+/// The contents of `entry` in `archive`.
+pub fn read_member(archive: &str, entry: &str) -> Result<Vec<u8>, DominantColoursError> {
+    let name = format!("{}{}{}", archive, MEMBER_SEPARATOR, entry);
+    let loaded = LOADED.lock().unwrap().iter().find(|(loaded, _)| *loaded == name).map(|(_, bytes)| bytes.clone());
+    if let Some(bytes) = loaded {
+        return Ok(bytes.to_vec());
+    }
+    let contents = match kind(archive) {
+        Some(Kind::Zip) => open_zip(archive)?.and_then(|mut zip| zip_read(&mut zip, entry)),
+        Some(kind) => tar_read(open_tar(archive, kind)?, entry),
+        None => Err(invalid("not a ZIP or tar archive")),
+    };
+    contents.map_err(|e| io_error(&name, e))
+}
+
+/// Call `f` with each of `filenames` in turn. A run of entries of one archive is read in
+/// a single pass over it, as far as they are in the order they are stored, and each
+/// entry's contents are at hand to `read_member` while `f` runs for it. Entries that
+/// can't be read ahead are left for `f` to read, and report, itself.
+pub fn for_each_input<'a, S: AsRef<str>, E>(
+    filenames: &'a [S],
+    mut f: impl FnMut(&'a str) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut rest = filenames;
+    while let Some(first) = rest.first() {
+        let Some((archive, _)) = split_member(first.as_ref()) else {
+            f(first.as_ref())?;
+            rest = &rest[1..];
+            continue;
+        };
+        let run = rest.iter().take_while(|name| split_member(name.as_ref()).is_some_and(|(a, _)| a == archive)).count();
+        let members: Vec<&'a str> = rest[..run].iter().map(|name| name.as_ref()).collect();
+        let read = read_ahead(archive, &members, &mut f)?;
+        for member in &members[read..] {
+            f(member)?;
+        }
+        rest = &rest[run..];
+    }
+    Ok(())
+}
+
+/// Read `members` of `archive` in one pass, calling `f` with each while its contents are
+/// loaded. The number of members done; the rest were not found in order.
+fn read_ahead<'a, E>(
+    archive: &str,
+    members: &[&'a str],
+    f: &mut impl FnMut(&'a str) -> Result<(), E>,
+) -> Result<usize, E> {
+    fn entry(member: &str) -> &str {
+        split_member(member).map_or("", |(_, entry)| entry)
+    }
+    let mut done = 0;
+    match kind(archive) {
+        Some(Kind::Zip) => {
+            let Ok(Ok(mut zip)) = open_zip(archive) else {
+                return Ok(0);
+            };
+            for member in members {
+                match zip_read(&mut zip, entry(member)) {
+                    Ok(bytes) => with_loaded(member, bytes, || f(member))?,
+                    Err(_) => f(member)?,
+                }
+                done += 1;
+            }
+        }
+        Some(kind) => {
+            let Ok(reader) = open_tar(archive, kind) else {
+                return Ok(0);
+            };
+            let mut failed = None;
+            let _ = tar_walk(reader, |name, contents| {
+                if entry(members[done]) != name {
+                    return Ok(None);
+                }
+                let member = members[done];
+                let result = match read_capped(contents, MAX_IMAGE_BYTES) {
+                    Ok(bytes) => with_loaded(member, bytes, || f(member)),
+                    Err(_) => f(member),
+                };
+                done += 1;
+                if let Err(e) = result {
+                    failed = Some(e);
+                }
+                Ok((failed.is_some() || done == members.len()).then_some(()))
+            });
+            if let Some(e) = failed {
+                return Err(e);
+            }
+        }
+        None => {}
+    }
+    Ok(done)
+}
+
+/// Run `f` with `bytes` loaded as the contents of `member`.
+fn with_loaded<T>(member: &str, bytes: Vec<u8>, f: impl FnOnce() -> T) -> T {
+    let bytes = Arc::new(bytes);
+    LOADED.lock().unwrap().push((member.to_string(), bytes.clone()));
+    let result = f();
+    LOADED.lock().unwrap().retain(|(_, loaded)| !Arc::ptr_eq(loaded, &bytes));
+    result
+}
+
+fn open(archive: &str) -> Result<BufReader<File>, DominantColoursError> {
+    File::open(archive).map(BufReader::new).map_err(|e| io_error(archive, e))
+}
+
+/// The archive's central directory, or the error reading it.
+fn open_zip(archive: &str) -> Result<std::io::Result<ZipArchive<BufReader<File>>>, DominantColoursError> {
+    Ok(ZipArchive::new(open(archive)?).map_err(std::io::Error::from))
+}
+
+fn open_tar(archive: &str, kind: Kind) -> Result<Box<dyn Read>, DominantColoursError> {
+    let file = open(archive)?;
+    Ok(if kind == Kind::TarGz { Box::new(GzDecoder::new(file)) } else { Box::new(file) })
+}
+
+fn io_error(path: &str, source: std::io::Error) -> DominantColoursError {
+    DominantColoursError::Io { path: Some(path.to_string()), source }
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// All of `reader`, refusing anything over `limit` bytes, such as a deflate bomb.
+fn read_capped(reader: impl Read, limit: u64) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        return Err(invalid(&format!("entry larger than {} bytes", limit)));
+    }
+    Ok(bytes)
+}
+
+fn zip_read<R: Read + std::io::Seek>(zip: &mut ZipArchive<R>, name: &str) -> std::io::Result<Vec<u8>> {
+    let file = zip.by_name(name)?;
+    if file.size() > MAX_IMAGE_BYTES {
+        return Err(invalid(&format!("entry larger than {} bytes", MAX_IMAGE_BYTES)));
+    }
+    // The CRC is checked as the last of the entry is read
+    read_capped(file, MAX_IMAGE_BYTES)
+}
+
+/// Walk the regular files of a tar archive, calling `f` with each one's name and a
+/// reader over its contents until it returns `Some`. GNU long names and PAX headers are
+/// read here, up to `MAX_HEADER_BYTES`, rather than left to the `tar` crate, which reads
+/// them whatever their size.
+fn tar_walk<R, T, F>(reader: R, mut f: F) -> std::io::Result<Option<T>>
+where
+    R: Read,
+    F: FnMut(&str, &mut dyn Read) -> std::io::Result<Option<T>>,
+{
+    let mut archive = tar::Archive::new(reader);
+    let mut long_name = None;
+    for entry in archive.entries()?.raw(true) {
+        let mut entry = entry?;
+        match entry.header().entry_type() {
+            // A GNU long name for the entry that follows
+            EntryType::GNULongName => {
+                let name = read_capped(&mut entry, MAX_HEADER_BYTES)?;
+                long_name = Some(String::from_utf8_lossy(&name).trim_end_matches('\0').to_string());
+            }
+            // PAX attributes for the entry that follows
+            EntryType::XHeader => {
+                let records = read_capped(&mut entry, MAX_HEADER_BYTES)?;
+                long_name = tar::PaxExtensions::new(&records)
+                    .filter_map(Result::ok)
+                    .find(|extension| extension.key() == Ok("path"))
+                    .and_then(|extension| extension.value().ok().map(str::to_string));
+            }
+            EntryType::Regular => {
+                let name = match long_name.take() {
+                    Some(name) => name,
+                    None => entry.path()?.to_string_lossy().into_owned(),
+                };
+                if let Some(found) = f(&name, &mut entry)? {
+                    return Ok(Some(found));
+                }
+            }
+            _ => long_name = None,
+        }
+    }
+    Ok(None)
+}
+
+fn tar_names<R: Read>(reader: R) -> std::io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    tar_walk::<_, (), _>(reader, |name, _| {
+        names.push(name.to_string());
+        Ok(None)
+    })?;
+    Ok(names)
+}
+
+fn tar_read<R: Read>(reader: R, entry: &str) -> std::io::Result<Vec<u8>> {
+    tar_walk(reader, |name, contents| {
+        if name != entry {
+            return Ok(None);
+        }
+        read_capped(contents, MAX_IMAGE_BYTES).map(Some)
+    })?
+    .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "no such entry in the archive"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open_image;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn png(rgb: [u8; 3]) -> Vec<u8> {
+        let mut png = Vec::new();
+        image::RgbImage::from_pixel(4, 4, image::Rgb(rgb))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+            .unwrap();
+        png
+    }
+
+    /// A ZIP archive with the entries deflated.
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// A tar archive, with GNU long names for names too long for the tar header.
+    fn tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, *contents).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_archives() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let (red, blue) = (png([255, 0, 0]), png([0, 0, 255]));
+        let long = format!("{}blue.png", "shoot/".repeat(20));
+        let entries: [(&str, &[u8]); 3] = [("red.png", &red), ("notes.txt", b"not an image"), (&long, &blue)];
+
+        let tar_gz = {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&tar(&entries))?;
+            encoder.finish()?
+        };
+        for (name, data) in [("assets.zip", zip(&entries)), ("assets.tar", tar(&entries)), ("assets.tgz", tar_gz)] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, data)?;
+            let archive = path.to_str().unwrap();
+            let images = images(archive)?;
+            assert_eq!(images, [format!("{}!red.png", archive), format!("{}!{}", archive, long)]);
+            assert_eq!(split_member(&images[1]), Some((archive, long.as_str())));
+            assert_eq!(open_image(&images[1])?.to_rgb8().get_pixel(0, 0).0, [0, 0, 255], "{}", name);
+            assert!(matches!(open_image(&format!("{}!missing.png", archive)), Err(DominantColoursError::Io { .. })));
+        }
+        assert_eq!(split_member("not!an/archive.png"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_limits() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        assert!(read_capped(&[0u8; 10][..], 10).is_ok());
+        assert!(read_capped(&[0u8; 11][..], 10).is_err());
+
+        // A corrupted entry fails its CRC check
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let stored = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("red.png", stored)?;
+        writer.write_all(&png([255, 0, 0]))?;
+        let mut data = writer.finish()?.into_inner();
+        let at = data.windows(4).position(|w| w == b"IHDR").unwrap();
+        data[at + 4] ^= 0xFF;
+        let path = dir.path().join("corrupt.zip");
+        std::fs::write(&path, data)?;
+        assert!(read_member(path.to_str().unwrap(), "red.png").is_err());
+
+        // A long name header too big to read into memory
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::GNULongName);
+        header.set_size(MAX_HEADER_BYTES + 1);
+        builder.append(&header, &vec![b'a'; MAX_HEADER_BYTES as usize + 1][..])?;
+        let path = dir.path().join("long.tar");
+        std::fs::write(&path, builder.into_inner()?)?;
+        assert!(images(path.to_str().unwrap()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_for_each_input() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let (red, blue) = (png([255, 0, 0]), png([0, 0, 255]));
+        let path = dir.path().join("assets.tgz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&tar(&[("red.png", &red), ("blue.png", &blue)]))?;
+        std::fs::write(&path, encoder.finish()?)?;
+        let archive = path.to_str().unwrap();
+
+        // The archive is gone after the first image, so the rest must come from the one pass
+        // over it; the missing entry is left to be read, and fail, as usual
+        let inputs = ["red.png", "blue.png", "missing.png"].map(|entry| format!("{}!{}", archive, entry));
+        let mut seen = Vec::new();
+        for_each_input(&inputs, |input| {
+            seen.push((input, open_image(input).map(|img| img.to_rgb8().get_pixel(0, 0).0).ok()));
+            let _ = std::fs::remove_file(&path);
+            Ok::<_, DominantColoursError>(())
+        })?;
+        assert_eq!(seen, [(&*inputs[0], Some([255, 0, 0])), (&*inputs[1], Some([0, 0, 255])), (&*inputs[2], None)]);
+        assert!(LOADED.lock().unwrap().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! Summaries across a batch of analysed images.
+
+use crate::colour::Metric;
+use crate::names::{self, NamedColour};
+use crate::{rgb_to_hex, ColourInfo};
+
+/// A named colour and how widely it occurs across a set of images.
+#[derive(Debug)]
+pub struct CommonColour {
+    pub name: String,
+    /// Number of images with at least one cluster of this colour
+    pub images: usize,
+    /// Mean coverage across all images (percent), counting absent images as zero
+    pub percentage: f64,
+}
+
+/// Group every extracted colour by its nearest name (by `metric`) and rank the groups by
+/// how many images they appear in, then by average coverage. Colours fall back to their
+/// hex code when the name table is empty.
+pub fn common_colours(
+    palettes: &[&[ColourInfo]],
+    table: &[NamedColour],
+    limit: usize,
+    metric: Metric,
+) -> Vec<CommonColour> {
+    let mut common: Vec<CommonColour> = Vec::new();
+    for palette in palettes {
+        let mut seen: Vec<usize> = Vec::new();
+        for colour in palette.iter() {
+            let name = names::nearest(colour.rgb, table, metric)
+                .map(|(named, _)| named.name.clone())
+                .unwrap_or_else(|| rgb_to_hex(colour.rgb));
+            let index = match common.iter().position(|c| c.name == name) {
+                Some(index) => index,
+                None => {
+                    common.push(CommonColour { name, images: 0, percentage: 0.0 });
+                    common.len() - 1
+                }
+            };
+            if !seen.contains(&index) {
+                seen.push(index);
+                common[index].images += 1;
+            }
+            common[index].percentage += colour.percentage;
+        }
+    }
+
+    let count = palettes.len().max(1) as f64;
+    for c in &mut common {
+        c.percentage /= count;
+    }
+    common.sort_by(|a, b| b.images.cmp(&a.images).then(b.percentage.total_cmp(&a.percentage)));
+    common.truncate(limit);
+    common
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_colours() {
+        let first = vec![ColourInfo::new([255, 0, 0], 60.0), ColourInfo::new([254, 1, 1], 10.0), ColourInfo::new([0, 0, 255], 30.0)];
+        let second = vec![ColourInfo::new([255, 0, 0], 20.0), ColourInfo::new([255, 255, 255], 80.0)];
+        let common = common_colours(&[&first, &second], names::css(), 10, Metric::Cie76);
+
+        assert_eq!(common[0].name, "red");
+        assert_eq!(common[0].images, 2);
+        assert_eq!(common[0].percentage, 45.0);
+        assert_eq!(common[1].name, "white");
+        assert_eq!(common[1].percentage, 40.0);
+        assert_eq!(common.len(), 3);
+
+        assert_eq!(common_colours(&[&first, &second], names::css(), 1, Metric::Cie76).len(), 1);
+    }
+
+    #[test]
+    fn test_common_colours_without_names() {
+        let palette = vec![ColourInfo::new([18, 52, 86], 100.0)];
+        let common = common_colours(&[&palette], &[], 10, Metric::Cie76);
+        assert_eq!(common[0].name, "#123456");
+    }
+}
@@ -0,0 +1,104 @@
+//! Timing clustering settings against each other on the same pixels.
+
+use std::time::{Duration, Instant};
+
+use crate::{cluster_pixels, AnalysisOptions, ColourSpace, DominantColoursError, InitMethod, Pixels, Timings};
+
+/// Timings and inertia of one colour space and initialisation over repeated runs.
+#[derive(Debug)]
+pub struct BenchResult {
+    pub space: ColourSpace,
+    pub init: InitMethod,
+    /// Wall-clock clustering time of each run, excluding decoding and resizing
+    pub times: Vec<Duration>,
+    /// Inertia of each run, in the clustering space, so only comparable within one space
+    pub inertia: Vec<f64>,
+}
+
+impl BenchResult {
+    pub fn mean_time(&self) -> Duration {
+        self.times.iter().sum::<Duration>() / self.times.len() as u32
+    }
+
+    pub fn min_time(&self) -> Duration {
+        self.times.iter().copied().min().unwrap_or_default()
+    }
+
+    pub fn max_time(&self) -> Duration {
+        self.times.iter().copied().max().unwrap_or_default()
+    }
+
+    pub fn mean_inertia(&self) -> f64 {
+        self.inertia.iter().sum::<f64>() / self.inertia.len() as f64
+    }
+}
+
+/// Cluster `pixels` `runs` times with every combination of `spaces` and `inits`, the
+/// other settings taken from `options`.
+pub fn run(
+    pixels: &Pixels,
+    options: &AnalysisOptions,
+    spaces: &[ColourSpace],
+    inits: &[InitMethod],
+    runs: usize,
+) -> Result<Vec<BenchResult>, DominantColoursError> {
+    let mut results = Vec::new();
+    for &space in spaces {
+        for &init in inits {
+            let options = AnalysisOptions { space, init, silhouette: false, ..options.clone() };
+            let mut result = BenchResult { space, init, times: Vec::new(), inertia: Vec::new() };
+            for _ in 0..runs {
+                let input = Pixels {
+                    values: pixels.values.clone(),
+                    positions: None,
+                    source_pixels: pixels.source_pixels,
+                    timings: Timings::default(),
+                    warnings: Vec::new(),
+                };
+                let start = Instant::now();
+                let analysis = cluster_pixels(input, &options)?;
+                result.times.push(start.elapsed());
+                result.inertia.push(analysis.inertia);
+            }
+            results.push(result);
+        }
+    }
+    Ok(results)
+}
+
+/// Test image of eight colour bands with a little deterministic noise, so benchmarks
+/// can run without a corpus and give the same input on every machine.
+pub fn synthetic_image(width: u32, height: u32) -> image::RgbImage {
+    const BANDS: [[u8; 3]; 8] = [
+        [200, 30, 40], [240, 160, 20], [250, 230, 80], [40, 150, 60],
+        [30, 90, 200], [110, 50, 160], [240, 240, 240], [20, 20, 30],
+    ];
+    image::RgbImage::from_fn(width, height, |x, y| {
+        let band = BANDS[(x as usize * BANDS.len() / width as usize).min(BANDS.len() - 1)];
+        let noise = (x.wrapping_mul(7919) ^ y.wrapping_mul(104729)) % 21;
+        image::Rgb(band.map(|c| (c as i32 + noise as i32 - 10).clamp(0, 255) as u8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_runs_every_setting() -> anyhow::Result<()> {
+        let img = image::DynamicImage::ImageRgb8(synthetic_image(16, 8));
+        let options = AnalysisOptions { colours: 4, max_pixels: 16 * 8, ..AnalysisOptions::default() };
+        let pixels = crate::image_pixels(&img, &options);
+        let spaces = [ColourSpace::Rgb, ColourSpace::Lab];
+        let results = run(&pixels, &options, &spaces, &[InitMethod::KmeansPlusPlus, InitMethod::Random], 2)?;
+
+        assert_eq!(results.len(), 4);
+        assert_eq!((results[1].space, results[1].init), (ColourSpace::Rgb, InitMethod::Random));
+        for result in &results {
+            assert_eq!((result.times.len(), result.inertia.len()), (2, 2));
+            assert!(result.min_time() <= result.mean_time() && result.mean_time() <= result.max_time());
+            assert!(result.mean_inertia() >= 0.0);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! On-disk cache of analyses, so re-running over a mostly unchanged set of images only
+//! analyses the new or modified ones.
+//!
+//! Entries are JSON files named by a BLAKE3 hash of the image file's contents, the
+//! analysis options and the crate version. Editing an image, changing an option or
+//! upgrading all miss the cache rather than returning a stale palette. Old entries are
+//! never removed; delete the directory to reclaim the space.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::histogram::Histogram;
+use crate::vividness::Vividness;
+use crate::{analyze_image, Analysis, AnalysisOptions, ColourInfo, DominantColoursError, Timings};
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    colours: Vec<Colour>,
+    inertia: f64,
+    silhouette: Option<f64>,
+    warnings: Vec<String>,
+    greyscale: bool,
+    histograms: Vec<Histogram>,
+    vividness: Option<Vividness>,
+    sampled_pixels: usize,
+    source_pixels: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Colour {
+    rgb: [u8; 3],
+    percentage: f64,
+    std_dev: [f64; 3],
+    hsl_spread: [f64; 3],
+    location: Option<(u32, u32)>,
+}
+
+impl Cache {
+    /// Use `dir` for cache entries, creating it if needed.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Cache> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+        Ok(Cache { dir })
+    }
+
+    /// Cache key of analysing `path` with `options`, including the contents of any mask.
+    pub fn key(&self, path: &Path, options: &AnalysisOptions) -> std::io::Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(File::open(path)?)?;
+        if let Some(mask) = &options.mask {
+            hasher.update_reader(File::open(mask)?)?;
+        }
+        hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.update(format!("{:?}", options).as_bytes());
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// The cached analysis for `key`, if there is a readable entry. Stage timings are not
+    /// kept, so they are all zero.
+    pub fn get(&self, key: &str) -> Option<Analysis> {
+        let text = std::fs::read_to_string(self.entry_path(key)).ok()?;
+        let entry: Entry = serde_json::from_str(&text).ok()?;
+        Some(Analysis {
+            colours: entry
+                .colours
+                .into_iter()
+                .map(|c| ColourInfo {
+                    rgb: c.rgb,
+                    percentage: c.percentage,
+                    std_dev: c.std_dev,
+                    hsl_spread: c.hsl_spread,
+                    location: c.location,
+                })
+                .collect(),
+            inertia: entry.inertia,
+            silhouette: entry.silhouette,
+            timings: Timings::default(),
+            warnings: entry.warnings,
+            greyscale: entry.greyscale,
+            histograms: entry.histograms,
+            vividness: entry.vividness,
+            sampled_pixels: entry.sampled_pixels,
+            source_pixels: entry.source_pixels,
+        })
+    }
+
+    pub fn put(&self, key: &str, analysis: &Analysis) -> Result<()> {
+        let entry = Entry {
+            colours: analysis
+                .colours
+                .iter()
+                .map(|c| Colour {
+                    rgb: c.rgb,
+                    percentage: c.percentage,
+                    std_dev: c.std_dev,
+                    hsl_spread: c.hsl_spread,
+                    location: c.location,
+                })
+                .collect(),
+            inertia: analysis.inertia,
+            silhouette: analysis.silhouette,
+            warnings: analysis.warnings.clone(),
+            greyscale: analysis.greyscale,
+            histograms: analysis.histograms.clone(),
+            vividness: analysis.vividness,
+            sampled_pixels: analysis.sampled_pixels,
+            source_pixels: analysis.source_pixels,
+        };
+        // Write then rename, so a concurrent or interrupted run never sees half an entry
+        let path = self.entry_path(key);
+        let partial = path.with_extension("json.tmp");
+        std::fs::write(&partial, serde_json::to_string(&entry)?)?;
+        std::fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    /// Analyse `filename`, reusing the cached result when the file and options are unchanged.
+    /// Failing to write an entry only logs a warning.
+    pub fn analyze(&self, filename: &str, options: &AnalysisOptions) -> Result<Analysis, DominantColoursError> {
+        // An unreadable file gets the same error as without a cache
+        let Ok(key) = self.key(Path::new(filename), options) else {
+            return analyze_image(filename, options);
+        };
+        if let Some(analysis) = self.get(&key) {
+            debug!(file = filename, "Using cached analysis");
+            return Ok(analysis);
+        }
+        let analysis = analyze_image(filename, options)?;
+        if let Err(e) = self.put(&key, &analysis) {
+            warn!(file = filename, "Failed to write cache entry: {:#}", e);
+        }
+        Ok(analysis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_cache_hits_and_misses() -> Result<()> {
+        let dir = tempdir()?;
+        let image_path = dir.path().join("image.png");
+        image::RgbImage::from_fn(8, 8, |x, _| if x < 4 { image::Rgb([200, 0, 0]) } else { image::Rgb([0, 0, 200]) })
+            .save(&image_path)?;
+        let cache = Cache::open(dir.path().join("cache"))?;
+        let options = AnalysisOptions { colours: 2, ..AnalysisOptions::default() };
+        let filename = image_path.to_str().unwrap();
+
+        let analysis = cache.analyze(filename, &options)?;
+        let key = cache.key(&image_path, &options)?;
+        let cached = cache.get(&key).expect("entry written");
+        assert_eq!(
+            cached.colours.iter().map(|c| c.rgb).collect::<Vec<_>>(),
+            analysis.colours.iter().map(|c| c.rgb).collect::<Vec<_>>()
+        );
+        assert_eq!(cached.inertia, analysis.inertia);
+
+        // Other options or changed contents are different entries
+        let other = AnalysisOptions { colours: 3, ..options.clone() };
+        assert_ne!(cache.key(&image_path, &other)?, key);
+        image::RgbImage::from_pixel(8, 8, image::Rgb([0, 200, 0])).save(&image_path)?;
+        assert_ne!(cache.key(&image_path, &options)?, key);
+
+        assert!(matches!(
+            cache.analyze("does/not/exist.png", &options),
+            Err(DominantColoursError::Io { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! Coverage of a fixed set of candidate colours.
+//!
+//! Free clustering answers "what colours is this image made of?". Assigning every pixel
+//! to the nearest of a given palette instead answers "how much of this image is made of
+//! these colours?", e.g. the share of each brand or ink colour in an artwork.
+
+use std::time::Instant;
+
+use tracing::{debug, info};
+
+use crate::quantize::NearestCache;
+use crate::{
+    histogram, load_pixels, sort_colours, vividness, Analysis, AnalysisOptions, ColourInfo, ColourOrder,
+    DominantColoursError, HslSpread, Pixels,
+};
+
+/// Assign each pixel to the candidate nearest by delta-E, and report every candidate
+/// with the share of pixels it got, including those that got none. `--top` and `--sort`
+/// apply as to clustered colours; standard deviations are in RGB.
+pub fn coverage(pixels: Pixels, candidates: &[[u8; 3]], options: &AnalysisOptions) -> Result<Analysis, DominantColoursError> {
+    let Pixels { values, positions, mut timings, source_pixels, warnings } = pixels;
+    if candidates.is_empty() {
+        return Err(DominantColoursError::InvalidOptions("no candidate colours given".to_string()));
+    }
+    if values.is_empty() {
+        return Err(DominantColoursError::NoPixels("no pixels to cluster".to_string()));
+    }
+    debug!(pixels = values.len(), candidates = candidates.len(), "Assigning pixels to candidate colours");
+    let start = Instant::now();
+    let histograms = options.histogram.iter().map(|&kind| histogram::compute(kind, &values)).collect();
+    let vividness = options.vividness.then(|| vividness::compute(&values));
+
+    let mut nearest = NearestCache::new(candidates, options.metric);
+    let mut sizes = vec![0usize; candidates.len()];
+    let mut squared_deviation = vec![[0.0; 3]; candidates.len()];
+    let mut spread = vec![HslSpread::default(); candidates.len()];
+    let mut closest: Vec<Option<(f64, usize)>> = vec![None; candidates.len()];
+    for (i, v) in values.iter().enumerate() {
+        let rgb = v.map(|c| c.round() as u8);
+        let index = nearest.index(rgb);
+        sizes[index] += 1;
+        spread[index].add(rgb, 1.0);
+        let mut distance = 0.0;
+        for ((sq, value), candidate) in squared_deviation[index].iter_mut().zip(v).zip(candidates[index]) {
+            let d = (value - candidate as f64).powi(2);
+            *sq += d;
+            distance += d;
+        }
+        if closest[index].is_none_or(|(best, _)| distance < best) {
+            closest[index] = Some((distance, i));
+        }
+    }
+    let inertia = squared_deviation.iter().flatten().sum();
+
+    let mut colours: Vec<ColourInfo> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, &rgb)| ColourInfo {
+            rgb,
+            percentage: sizes[i] as f64 / values.len() as f64 * 100.0,
+            std_dev: squared_deviation[i].map(|sq| if sizes[i] == 0 { 0.0 } else { (sq / sizes[i] as f64).sqrt() }),
+            hsl_spread: spread[i].finish(),
+            location: closest[i].zip(positions.as_ref()).map(|((_, index), positions)| positions[index]),
+        })
+        .collect();
+    if let Some(top) = options.top {
+        sort_colours(&mut colours, ColourOrder::Percentage, false, options.metric);
+        colours.truncate(top);
+    }
+    sort_colours(&mut colours, options.sort, options.reverse, options.metric);
+    timings.predict += start.elapsed();
+
+    Ok(Analysis {
+        colours,
+        inertia,
+        silhouette: None,
+        timings,
+        warnings,
+        greyscale: false,
+        histograms,
+        vividness,
+        sampled_pixels: values.len(),
+        source_pixels,
+    })
+}
+
+/// Coverage of `candidates` in an image file.
+pub fn coverage_of_file(
+    filename: &str,
+    candidates: &[[u8; 3]],
+    options: &AnalysisOptions,
+) -> Result<Analysis, DominantColoursError> {
+    info!(file = filename, "Measuring candidate colours");
+    coverage(load_pixels(filename, options)?, candidates, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Timings;
+
+    #[test]
+    fn test_coverage() -> anyhow::Result<()> {
+        // Near-reds and near-blues go to the nearest candidate; green gets nothing
+        let values = vec![[250.0, 10.0, 0.0], [240.0, 0.0, 10.0], [255.0, 0.0, 0.0], [0.0, 0.0, 230.0]];
+        let pixels = Pixels { values, positions: None, source_pixels: 4, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions::default();
+        let analysis = coverage(pixels, &[[0, 0, 255], [0, 255, 0], [255, 0, 0]], &options)?;
+        let found: Vec<_> = analysis.colours.iter().map(|c| (c.rgb, c.percentage)).collect();
+        assert_eq!(found, [([255, 0, 0], 75.0), ([0, 0, 255], 25.0), ([0, 255, 0], 0.0)]);
+        assert_eq!(analysis.colours[1].std_dev, [0.0, 0.0, 25.0]);
+
+        let none = Pixels { values: vec![[0.0; 3]], positions: None, source_pixels: 1, timings: Timings::default(), warnings: Vec::new() };
+        assert!(coverage(none, &[], &options).is_err());
+        Ok(())
+    }
+}
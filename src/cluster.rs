@@ -0,0 +1,171 @@
+//! Clustering quality measures and helpers that work on the raw pixel data.
+
+use ndarray::{Array2, ArrayView1};
+
+/// Index of the nearest centroid to each row of `data`, by squared distance, ties going
+/// to the lower index. Both arrays have three columns. Assignment is the hot loop at
+/// high pixel counts, so it uses AVX when the CPU has it, or a compute shader for large
+/// inputs when built with the `gpu` feature.
+pub fn nearest_centroids(data: &Array2<f64>, centroids: &Array2<f64>) -> Vec<usize> {
+    #[cfg(feature = "gpu")]
+    if data.nrows() > crate::gpu::GPU_ABOVE_PIXELS {
+        if let Some(labels) = crate::gpu::nearest_centroids(data, centroids) {
+            return labels;
+        }
+    }
+    cpu_nearest_centroids(data, centroids)
+}
+
+pub(crate) fn cpu_nearest_centroids(data: &Array2<f64>, centroids: &Array2<f64>) -> Vec<usize> {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("avx") {
+        // SAFETY: the CPU supports AVX, checked just above
+        return unsafe { avx::nearest_centroids(data, centroids) };
+    }
+    nearest_centroids_scalar(data, centroids)
+}
+
+fn nearest_centroids_scalar(data: &Array2<f64>, centroids: &Array2<f64>) -> Vec<usize> {
+    data.outer_iter()
+        .map(|pixel| {
+            let mut best = (f64::INFINITY, 0);
+            for (c, centroid) in centroids.outer_iter().enumerate() {
+                let d = (0..3).map(|i| (pixel[i] - centroid[i]).powi(2)).sum::<f64>();
+                if d < best.0 {
+                    best = (d, c);
+                }
+            }
+            best.1
+        })
+        .collect()
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx {
+    use ndarray::Array2;
+    use std::arch::x86_64::*;
+
+    /// Four pixels at a time: channels are split out so each register holds one channel
+    /// of four pixels, compared against every centroid in turn.
+    #[target_feature(enable = "avx")]
+    pub unsafe fn nearest_centroids(data: &Array2<f64>, centroids: &Array2<f64>) -> Vec<usize> {
+        let n = data.nrows();
+        let channels: [Vec<f64>; 3] = std::array::from_fn(|i| data.column(i).to_vec());
+        let mut labels = Vec::with_capacity(n);
+        let mut start = 0;
+        while start + 4 <= n {
+            let x = _mm256_loadu_pd(channels[0][start..].as_ptr());
+            let y = _mm256_loadu_pd(channels[1][start..].as_ptr());
+            let z = _mm256_loadu_pd(channels[2][start..].as_ptr());
+            let mut best = _mm256_set1_pd(f64::INFINITY);
+            let mut best_index = _mm256_setzero_pd();
+            for (c, centroid) in centroids.outer_iter().enumerate() {
+                let dx = _mm256_sub_pd(x, _mm256_set1_pd(centroid[0]));
+                let dy = _mm256_sub_pd(y, _mm256_set1_pd(centroid[1]));
+                let dz = _mm256_sub_pd(z, _mm256_set1_pd(centroid[2]));
+                let d = _mm256_add_pd(
+                    _mm256_add_pd(_mm256_mul_pd(dx, dx), _mm256_mul_pd(dy, dy)),
+                    _mm256_mul_pd(dz, dz),
+                );
+                let closer = _mm256_cmp_pd::<_CMP_LT_OQ>(d, best);
+                best = _mm256_blendv_pd(best, d, closer);
+                best_index = _mm256_blendv_pd(best_index, _mm256_set1_pd(c as f64), closer);
+            }
+            let mut indices = [0.0; 4];
+            _mm256_storeu_pd(indices.as_mut_ptr(), best_index);
+            labels.extend(indices.map(|i| i as usize));
+            start += 4;
+        }
+        let rest = data.slice(ndarray::s![start.., ..]).to_owned();
+        labels.extend(super::nearest_centroids_scalar(&rest, centroids));
+        labels
+    }
+}
+
+fn distance(a: ArrayView1<f64>, b: ArrayView1<f64>) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y).powi(2)).sum::<f64>().sqrt()
+}
+
+/// Mean silhouette coefficient of a clustering, from -1.0 (wrong clusters) to 1.0 (well separated).
+///
+/// The exact score is quadratic in the number of pixels, so it is computed over an evenly
+/// strided sample of at most `max_samples` rows. Returns `None` when fewer than two
+/// clusters are populated, where the silhouette is undefined.
+pub fn silhouette_score(data: &Array2<f64>, labels: &[usize], k: usize, max_samples: usize) -> Option<f64> {
+    let step = data.nrows().div_ceil(max_samples.max(1)).max(1);
+    let sample: Vec<usize> = (0..data.nrows()).step_by(step).collect();
+
+    let mut sizes = vec![0usize; k];
+    for &i in &sample {
+        sizes[labels[i]] += 1;
+    }
+    if sizes.iter().filter(|&&n| n > 0).count() < 2 {
+        return None;
+    }
+
+    let mut total = 0.0;
+    for &i in &sample {
+        let own = labels[i];
+        if sizes[own] <= 1 {
+            // Singleton clusters score zero by convention
+            continue;
+        }
+        let mut sums = vec![0.0; k];
+        for &j in &sample {
+            if i != j {
+                sums[labels[j]] += distance(data.row(i), data.row(j));
+            }
+        }
+        let a = sums[own] / (sizes[own] - 1) as f64;
+        let b = (0..k)
+            .filter(|&c| c != own && sizes[c] > 0)
+            .map(|c| sums[c] / sizes[c] as f64)
+            .fold(f64::INFINITY, f64::min);
+        let max = a.max(b);
+        if max > 0.0 {
+            total += (b - a) / max;
+        }
+    }
+    Some(total / sample.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_centroids() {
+        let centroids = Array2::from_shape_vec((3, 3), vec![0.0, 0.0, 0.0, 100.0, 100.0, 100.0, 250.0, 0.0, 0.0]).unwrap();
+        // Enough rows to exercise the vector loop and the leftover scalar rows
+        let data = Array2::from_shape_fn((103, 3), |(i, c)| ((i * 37 + c * 101) % 256) as f64);
+        let labels = nearest_centroids(&data, &centroids);
+        assert_eq!(labels, nearest_centroids_scalar(&data, &centroids));
+        assert_eq!(labels.len(), 103);
+
+        // Equidistant pixels go to the first centroid
+        let tie = Array2::from_shape_vec((1, 3), vec![50.0, 50.0, 50.0]).unwrap();
+        assert_eq!(nearest_centroids(&tie, &centroids), [0]);
+    }
+
+    #[test]
+    fn test_silhouette_score() {
+        // Two tight, far-apart groups score close to 1
+        let data = Array2::from_shape_vec(
+            (6, 3),
+            vec![
+                0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+                200.0, 200.0, 200.0, 201.0, 200.0, 200.0, 200.0, 201.0, 200.0,
+            ],
+        )
+        .unwrap();
+        let labels = [0, 0, 0, 1, 1, 1];
+        let score = silhouette_score(&data, &labels, 2, 100).unwrap();
+        assert!(score > 0.95);
+
+        // Swapping labels across the groups makes the clustering actively wrong
+        let bad = [0, 1, 0, 1, 0, 1];
+        assert!(silhouette_score(&data, &bad, 2, 100).unwrap() < 0.0);
+
+        assert!(silhouette_score(&data, &[0; 6], 2, 100).is_none());
+    }
+}
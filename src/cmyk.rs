@@ -0,0 +1,114 @@
+//! CMYK conversion for print workflows.
+
+use anyhow::Result;
+
+/// Device-independent CMYK as percentages, with full grey-component replacement.
+///
+/// This ignores ink behaviour entirely; use an ICC profile for anything headed to press.
+pub fn naive(rgb: [u8; 3]) -> [f64; 4] {
+    let [r, g, b] = rgb.map(|c| c as f64 / 255.0);
+    let k = 1.0 - r.max(g).max(b);
+    if k >= 1.0 {
+        return [0.0, 0.0, 0.0, 100.0];
+    }
+    let ink = |c: f64| (1.0 - c - k) / (1.0 - k) * 100.0;
+    [ink(r), ink(g), ink(b), k * 100.0]
+}
+
+/// sRGB to CMYK through an output ICC profile (e.g. FOGRA39, GRACoL, SWOP). Built
+/// without lcms's cache, so it can be shared between threads.
+#[cfg(feature = "icc")]
+pub struct IccTransform(lcms2::Transform<[u8; 3], [u8; 4], lcms2::GlobalContext, lcms2::DisallowCache>);
+
+#[cfg(feature = "icc")]
+impl IccTransform {
+    pub fn open(path: &str) -> Result<Self> {
+        use anyhow::Context;
+        use lcms2::{Flags, GlobalContext, Intent, PixelFormat, Profile, Transform};
+
+        let output = Profile::new_file(path)
+            .with_context(|| format!("Failed to load ICC profile {}", path))?;
+        let transform = Transform::new_flags_context(
+            GlobalContext::new(),
+            &Profile::new_srgb(),
+            PixelFormat::RGB_8,
+            &output,
+            PixelFormat::CMYK_8,
+            Intent::Perceptual,
+            Flags::NO_CACHE,
+        )
+        .with_context(|| format!("ICC profile {} is not a usable CMYK output profile", path))?;
+        Ok(IccTransform(transform))
+    }
+
+    /// CMYK percentages for `rgb` under this profile.
+    pub fn convert(&self, rgb: [u8; 3]) -> [f64; 4] {
+        let mut out = [[0u8; 4]];
+        self.0.transform_pixels(&[rgb], &mut out);
+        out[0].map(|c| c as f64 / 255.0 * 100.0)
+    }
+}
+
+/// Stand-in used when the crate is built without the `icc` feature.
+#[cfg(not(feature = "icc"))]
+pub struct IccTransform(());
+
+#[cfg(not(feature = "icc"))]
+impl IccTransform {
+    pub fn open(_path: &str) -> Result<Self> {
+        anyhow::bail!("ICC profile support is not enabled; rebuild with `--features icc`")
+    }
+
+    pub fn convert(&self, rgb: [u8; 3]) -> [f64; 4] {
+        naive(rgb)
+    }
+}
+
+/// CMYK pixels, four ink amounts each (255 is full ink), to sRGB through a CMYK ICC
+/// profile such as one embedded in a print-ready image; `None` if it is not usable.
+#[cfg(feature = "icc")]
+pub fn profile_to_srgb(profile: &[u8], inks: &[u8]) -> Option<Vec<u8>> {
+    use lcms2::{Intent, PixelFormat, Profile, Transform};
+
+    let input = Profile::new_icc(profile).ok()?;
+    let transform: Transform<[u8; 4], [u8; 3]> =
+        Transform::new(&input, PixelFormat::CMYK_8, &Profile::new_srgb(), PixelFormat::RGB_8, Intent::Perceptual).ok()?;
+    let inks: Vec<[u8; 4]> = inks.chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]).collect();
+    let mut rgb = vec![[0u8; 3]; inks.len()];
+    transform.transform_pixels(&inks, &mut rgb);
+    Some(rgb.concat())
+}
+
+/// Without the `icc` feature no profile is usable.
+#[cfg(not(feature = "icc"))]
+pub fn profile_to_srgb(_profile: &[u8], _inks: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// sRGB for CMYK ink amounts (255 is full ink) by the naive formula, the inverse of [`naive`].
+pub fn naive_to_rgb(inks: [u8; 4]) -> [u8; 3] {
+    let [c, m, y, k] = inks.map(|ink| 255 - ink as u16);
+    [c, m, y].map(|ink| (ink * k / 255) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_naive() {
+        assert_eq!(naive([0, 0, 0]), [0.0, 0.0, 0.0, 100.0]);
+        assert_eq!(naive([255, 255, 255]), [0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(naive([255, 0, 0]), [0.0, 100.0, 100.0, 0.0]);
+        assert_eq!(naive_to_rgb([0, 255, 255, 0]), [255, 0, 0]);
+        assert_eq!(naive_to_rgb([0, 0, 0, 255]), [0, 0, 0]);
+        assert_eq!(naive_to_rgb([0, 0, 0, 0]), [255, 255, 255]);
+        assert_eq!(naive([0, 255, 255]), [100.0, 0.0, 0.0, 0.0]);
+
+        let [c, m, y, k] = naive([128, 64, 0]);
+        assert_eq!(c, 0.0);
+        assert!((m - 50.0).abs() < 0.01);
+        assert_eq!(y, 100.0);
+        assert!((k - 49.8).abs() < 0.1);
+    }
+}
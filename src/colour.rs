@@ -0,0 +1,522 @@
+//! Colour-space helpers shared by the analysis and output code.
+
+use clap::ValueEnum;
+
+/// Parse a `#rrggbb` or `#rgb` hex colour; the leading `#` is optional.
+pub fn parse_hex(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.trim().trim_start_matches('#');
+    if !hex.is_ascii() {
+        return None;
+    }
+    let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+    match hex.len() {
+        6 => Some([channel(&hex[0..2])?, channel(&hex[2..4])?, channel(&hex[4..6])?]),
+        3 => {
+            let short = [channel(&hex[0..1])?, channel(&hex[1..2])?, channel(&hex[2..3])?];
+            Some(short.map(|c| c * 17))
+        }
+        _ => None,
+    }
+}
+
+/// HSV saturation of an sRGB colour, in the range 0.0..=1.0.
+pub fn saturation(rgb: [u8; 3]) -> f64 {
+    let max = *rgb.iter().max().unwrap() as f64;
+    let min = *rgb.iter().min().unwrap() as f64;
+    if max == 0.0 {
+        0.0
+    } else {
+        (max - min) / max
+    }
+}
+
+/// Hue in degrees (0.0..360.0) plus the max and min channel values scaled to 0.0..=1.0.
+fn hue_max_min(rgb: [u8; 3]) -> (f64, f64, f64) {
+    let [r, g, b] = rgb.map(|c| c as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (hue, max, min)
+}
+
+/// Convert to HSL: hue in degrees, saturation and lightness as percentages.
+pub fn rgb_to_hsl(rgb: [u8; 3]) -> [f64; 3] {
+    let (hue, max, min) = hue_max_min(rgb);
+    let lightness = (max + min) / 2.0;
+    let saturation = if max == min {
+        0.0
+    } else {
+        (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+    };
+    [hue, saturation * 100.0, lightness * 100.0]
+}
+
+/// Convert to HSV: hue in degrees, saturation and value as percentages.
+pub fn rgb_to_hsv(rgb: [u8; 3]) -> [f64; 3] {
+    let (hue, max, _) = hue_max_min(rgb);
+    [hue, saturation(rgb) * 100.0, max * 100.0]
+}
+
+/// Whether a colour is a likely human skin tone, across light and dark complexions.
+/// A pixel must fall in the usual YCbCr chroma box and have a red-to-yellow hue with
+/// moderate saturation; either test alone lets through too much wood and sand.
+pub fn is_skin(rgb: [u8; 3]) -> bool {
+    let [r, g, b] = rgb.map(|c| c as f64);
+    let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    let [hue, saturation, value] = rgb_to_hsv(rgb);
+    (77.0..=127.0).contains(&cb)
+        && (133.0..=173.0).contains(&cr)
+        && !(40.0..345.0).contains(&hue)
+        && (10.0..=80.0).contains(&saturation)
+        && value >= 20.0
+}
+
+/// Euclidean distance between two colours in RGB space, normalised to 0.0..=1.0.
+pub fn rgb_distance(a: [u8; 3], b: [u8; 3]) -> f64 {
+    let sum: f64 = a
+        .iter()
+        .zip(b.iter())
+        .map(|(&x, &y)| (x as f64 - y as f64).powi(2))
+        .sum();
+    sum.sqrt() / (3.0 * 255.0_f64.powi(2)).sqrt()
+}
+
+/// Convert an 8-bit sRGB channel value to linear light.
+pub fn srgb_to_linear(channel: u8) -> f64 {
+    let c = channel as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Convert a linear-light value back to an 8-bit sRGB channel, clamping out-of-gamut values.
+pub fn linear_to_srgb(value: f64) -> u8 {
+    let v = value.clamp(0.0, 1.0);
+    let c = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+/// Convert an sRGB colour to CIE XYZ (D65 white point, Y in 0.0..=1.0).
+pub fn rgb_to_xyz(rgb: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb.map(srgb_to_linear);
+    [
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.0721750 * b,
+        0.0193339 * r + 0.1191920 * g + 0.9503041 * b,
+    ]
+}
+
+/// Convert an sRGB colour to CIE L*a*b* (D65 white point).
+pub fn rgb_to_lab(rgb: [u8; 3]) -> [f64; 3] {
+    let [x, y, z] = rgb_to_xyz(rgb);
+    let (x, z) = (x / 0.95047, z / 1.08883);
+
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Convert CIE L*a*b* (D65 white point) back to sRGB, clamping out-of-gamut colours.
+pub fn lab_to_rgb(lab: [f64; 3]) -> [u8; 3] {
+    let [l, a, b] = lab;
+    let fy = (l + 16.0) / 116.0;
+    let (fx, fz) = (fy + a / 500.0, fy - b / 200.0);
+    let f_inv = |t: f64| {
+        if t > 6.0 / 29.0 {
+            t.powi(3)
+        } else {
+            (116.0 * t - 16.0) * 27.0 / 24389.0
+        }
+    };
+    let (x, y, z) = (f_inv(fx) * 0.95047, f_inv(fy), f_inv(fz) * 1.08883);
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+    .map(linear_to_srgb)
+}
+
+/// CIE76 colour difference: Euclidean distance in L*a*b*.
+pub fn delta_e(a: [u8; 3], b: [u8; 3]) -> f64 {
+    let la = rgb_to_lab(a);
+    let lb = rgb_to_lab(b);
+    la.iter()
+        .zip(lb.iter())
+        .map(|(x, y)| (x - y).powi(2))
+        .sum::<f64>()
+        .sqrt()
+}
+
+/// CIE94 colour difference between two L*a*b* colours, with the graphic-arts weights.
+/// Chroma and hue differences count for less in vivid colours. It is not symmetric: the
+/// weights come from the chroma of `reference`.
+pub fn cie94(reference: [f64; 3], sample: [f64; 3]) -> f64 {
+    let [l1, a1, b1] = reference;
+    let [l2, a2, b2] = sample;
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let (dl, dc) = (l1 - l2, c1 - c2);
+    let dh_squared = ((a1 - a2).powi(2) + (b1 - b2).powi(2) - dc * dc).max(0.0);
+    let (s_c, s_h) = (1.0 + 0.045 * c1, 1.0 + 0.015 * c1);
+    (dl * dl + (dc / s_c).powi(2) + dh_squared / (s_h * s_h)).sqrt()
+}
+
+/// CIEDE2000 colour difference between two L*a*b* colours, following Sharma, Wu and
+/// Dalal (2005). It corrects CIE94 for blues and near-neutral colours.
+pub fn ciede2000(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+    let pow25_7 = 25f64.powi(7);
+    let c_bar7 = ((a1.hypot(b1) + a2.hypot(b2)) / 2.0).powi(7);
+    let g = 0.5 * (1.0 - (c_bar7 / (c_bar7 + pow25_7)).sqrt());
+    let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |a: f64, b: f64| if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) };
+    let (h1, h2) = (hue(a1, b1), hue(a2, b2));
+    let chromatic = c1 * c2 != 0.0;
+
+    let dl = l2 - l1;
+    let dc = c2 - c1;
+    let dh = match h2 - h1 {
+        _ if !chromatic => 0.0,
+        d if d > 180.0 => d - 360.0,
+        d if d < -180.0 => d + 360.0,
+        d => d,
+    };
+    let dh = 2.0 * (c1 * c2).sqrt() * (dh / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = match (h1 - h2).abs() {
+        _ if !chromatic => h1 + h2,
+        d if d <= 180.0 => (h1 + h2) / 2.0,
+        _ if h1 + h2 < 360.0 => (h1 + h2 + 360.0) / 2.0,
+        _ => (h1 + h2 - 360.0) / 2.0,
+    };
+    let cos = |degrees: f64| degrees.to_radians().cos();
+    let t = 1.0 - 0.17 * cos(h_bar - 30.0) + 0.24 * cos(2.0 * h_bar) + 0.32 * cos(3.0 * h_bar + 6.0)
+        - 0.20 * cos(4.0 * h_bar - 63.0);
+    let d_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let c_bar7 = c_bar.powi(7);
+    let r_c = 2.0 * (c_bar7 / (c_bar7 + pow25_7)).sqrt();
+    let s_l = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+    let (l, c, h) = (dl / s_l, dc / s_c, dh / s_h);
+    (l * l + c * c + h * h + r_t * c * h).sqrt()
+}
+
+/// How the difference between two colours is measured wherever colours are compared:
+/// merging near-identical colours, snapping pixels to a palette, comparing palettes and
+/// looking up colour names. Thresholds are in the metric's own units.
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum Metric {
+    /// Straight-line distance between sRGB values (0 to 441); fast, but far from perceptual
+    Euclidean,
+    /// CIE76 delta-E, the straight-line distance in L*a*b*
+    Cie76,
+    /// CIE94 delta-E, which forgives chroma and hue differences in vivid colours
+    Cie94,
+    /// CIEDE2000 delta-E, the closest to perceived difference, e.g. for brand colours
+    Ciede2000,
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Metric::Euclidean => write!(f, "euclidean"),
+            Metric::Cie76 => write!(f, "cie76"),
+            Metric::Cie94 => write!(f, "cie94"),
+            Metric::Ciede2000 => write!(f, "ciede2000"),
+        }
+    }
+}
+
+impl Metric {
+    /// Difference between two colours; for CIE94, `a` is the reference.
+    pub fn distance(self, a: [u8; 3], b: [u8; 3]) -> f64 {
+        match self {
+            Metric::Euclidean => a.iter().zip(b).map(|(&x, y)| (x as f64 - y as f64).powi(2)).sum::<f64>().sqrt(),
+            Metric::Cie76 => delta_e(a, b),
+            Metric::Cie94 => cie94(rgb_to_lab(a), rgb_to_lab(b)),
+            Metric::Ciede2000 => ciede2000(rgb_to_lab(a), rgb_to_lab(b)),
+        }
+    }
+}
+
+/// Chromaticity (x, y) of a black-body radiator at `kelvin`, valid for 1667..=25000 K
+/// (Kim et al. cubic spline approximation).
+fn planckian_locus(kelvin: f64) -> (f64, f64) {
+    let t = kelvin;
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t.powi(3) + 2.1070379e6 / t.powi(2) + 0.2226347e3 / t + 0.240390
+    };
+    let y = if t <= 2222.0 {
+        -1.1063814 * x.powi(3) - 1.34811020 * x.powi(2) + 2.18555832 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x.powi(3) - 1.37418593 * x.powi(2) + 2.09137015 * x - 0.16748867
+    } else {
+        3.0817580 * x.powi(3) - 5.87338670 * x.powi(2) + 3.75112997 * x - 0.37001483
+    };
+    (x, y)
+}
+
+/// CIE 1960 UCS (u, v) coordinates from xy chromaticity.
+fn xy_to_uv(x: f64, y: f64) -> (f64, f64) {
+    let d = -2.0 * x + 12.0 * y + 3.0;
+    (4.0 * x / d, 6.0 * y / d)
+}
+
+/// Correlated colour temperature in kelvin: the temperature of the nearest point on the
+/// Planckian locus in CIE 1960 UCS, searched in even mired steps over 1667..=25000 K.
+///
+/// Only meaningful for colours near the locus; saturated colours are pinned towards the
+/// ends of the range, which still gives a useful warm/cool reading.
+/// Returns `None` for black, which has no chromaticity.
+pub fn correlated_colour_temperature(rgb: [u8; 3]) -> Option<f64> {
+    const STEPS: usize = 1000;
+    const MIN_MIRED: f64 = 1e6 / 25000.0;
+    const MAX_MIRED: f64 = 1e6 / 1667.0;
+
+    let [x, y, z] = rgb_to_xyz(rgb);
+    let sum = x + y + z;
+    if sum <= 0.0 {
+        return None;
+    }
+    let (u, v) = xy_to_uv(x / sum, y / sum);
+
+    (0..=STEPS)
+        .map(|i| 1e6 / (MIN_MIRED + (MAX_MIRED - MIN_MIRED) * i as f64 / STEPS as f64))
+        .map(|kelvin| {
+            let (lx, ly) = planckian_locus(kelvin);
+            let (lu, lv) = xy_to_uv(lx, ly);
+            (kelvin, (lu - u).powi(2) + (lv - v).powi(2))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(kelvin, _)| kelvin)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Temperature {
+    Warm,
+    Neutral,
+    Cool,
+}
+
+impl std::fmt::Display for Temperature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Temperature::Warm => write!(f, "warm"),
+            Temperature::Neutral => write!(f, "neutral"),
+            Temperature::Cool => write!(f, "cool"),
+        }
+    }
+}
+
+/// Classify a colour as warm, neutral or cool from its colour temperature.
+///
+/// Near-greys are always neutral, since their temperature is dominated by noise.
+pub fn temperature_class(rgb: [u8; 3]) -> Temperature {
+    if saturation(rgb) < 0.1 {
+        return Temperature::Neutral;
+    }
+    match correlated_colour_temperature(rgb) {
+        Some(cct) if cct < 5000.0 => Temperature::Warm,
+        Some(cct) if cct > 7000.0 => Temperature::Cool,
+        _ => Temperature::Neutral,
+    }
+}
+
+/// WCAG relative luminance of an sRGB colour.
+pub fn relative_luminance(rgb: [u8; 3]) -> f64 {
+    0.2126 * srgb_to_linear(rgb[0])
+        + 0.7152 * srgb_to_linear(rgb[1])
+        + 0.0722 * srgb_to_linear(rgb[2])
+}
+
+/// WCAG contrast ratio between two colours, from 1.0 (identical) to 21.0.
+pub fn contrast_ratio(a: [u8; 3], b: [u8; 3]) -> f64 {
+    let la = relative_luminance(a);
+    let lb = relative_luminance(b);
+    let (lighter, darker) = if la > lb { (la, lb) } else { (lb, la) };
+    (lighter + 0.05) / (darker + 0.05)
+}
+
+/// Black or white, whichever has the higher contrast ratio against `background`.
+pub fn text_colour_on(background: [u8; 3]) -> [u8; 3] {
+    const BLACK: [u8; 3] = [0, 0, 0];
+    const WHITE: [u8; 3] = [255, 255, 255];
+    if contrast_ratio(background, BLACK) >= contrast_ratio(background, WHITE) {
+        BLACK
+    } else {
+        WHITE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("#ff8000"), Some([255, 128, 0]));
+        assert_eq!(parse_hex("FF8000"), Some([255, 128, 0]));
+        assert_eq!(parse_hex("#f80"), Some([255, 136, 0]));
+        assert_eq!(parse_hex(" #0057b8 "), Some([0, 87, 184]));
+        assert_eq!(parse_hex("#ff80"), None);
+        assert_eq!(parse_hex("#gg0000"), None);
+    }
+
+    #[test]
+    fn test_is_skin() {
+        for skin in [[241, 194, 167], [198, 134, 66], [141, 85, 36], [89, 47, 42]] {
+            assert!(is_skin(skin), "{:?} should be skin", skin);
+        }
+        for other in [[255, 0, 0], [194, 178, 128], [40, 150, 60], [128, 128, 128], [30, 90, 200]] {
+            assert!(!is_skin(other), "{:?} should not be skin", other);
+        }
+    }
+
+    #[test]
+    fn test_saturation() {
+        assert_eq!(saturation([0, 0, 0]), 0.0);
+        assert_eq!(saturation([128, 128, 128]), 0.0);
+        assert_eq!(saturation([255, 0, 0]), 1.0);
+        assert!((saturation([255, 128, 128]) - 0.498).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_rgb_to_hsl_hsv() {
+        assert_eq!(rgb_to_hsl([255, 0, 0]), [0.0, 100.0, 50.0]);
+        assert_eq!(rgb_to_hsl([255, 255, 255]), [0.0, 0.0, 100.0]);
+        assert_eq!(rgb_to_hsv([0, 255, 0]), [120.0, 100.0, 100.0]);
+        assert_eq!(rgb_to_hsv([0, 0, 0]), [0.0, 0.0, 0.0]);
+
+        let [h, s, l] = rgb_to_hsl([102, 51, 153]);
+        assert!((h - 270.0).abs() < 0.01);
+        assert!((s - 50.0).abs() < 0.01);
+        assert!((l - 40.0).abs() < 0.01);
+
+        let [h, s, v] = rgb_to_hsv([255, 0, 128]);
+        assert!((h - 329.9).abs() < 0.1);
+        assert_eq!((s, v), (100.0, 100.0));
+    }
+
+    #[test]
+    fn test_rgb_distance() {
+        assert_eq!(rgb_distance([10, 20, 30], [10, 20, 30]), 0.0);
+        assert!((rgb_distance([0, 0, 0], [255, 255, 255]) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_linear_round_trip() {
+        for c in [0u8, 1, 10, 50, 128, 200, 254, 255] {
+            assert_eq!(linear_to_srgb(srgb_to_linear(c)), c);
+        }
+    }
+
+    #[test]
+    fn test_rgb_to_lab() {
+        let white = rgb_to_lab([255, 255, 255]);
+        assert!((white[0] - 100.0).abs() < 0.01);
+        assert!(white[1].abs() < 0.01 && white[2].abs() < 0.01);
+        assert_eq!(rgb_to_lab([0, 0, 0]), [0.0, 0.0, 0.0]);
+
+        let red = rgb_to_lab([255, 0, 0]);
+        assert!((red[0] - 53.24).abs() < 0.05);
+        assert!((red[1] - 80.09).abs() < 0.05);
+        assert!((red[2] - 67.20).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_lab_to_rgb_round_trip() {
+        for rgb in [[0, 0, 0], [255, 255, 255], [255, 0, 0], [18, 200, 90], [128, 64, 250]] {
+            assert_eq!(lab_to_rgb(rgb_to_lab(rgb)), rgb);
+        }
+        // Out of gamut clamps rather than wrapping
+        assert_eq!(lab_to_rgb([100.0, 0.0, -200.0])[2], 255);
+    }
+
+    #[test]
+    fn test_delta_e() {
+        assert_eq!(delta_e([10, 20, 30], [10, 20, 30]), 0.0);
+        assert!((delta_e([0, 0, 0], [255, 255, 255]) - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_metrics() {
+        // Pairs from the CIEDE2000 test data of Sharma, Wu and Dalal
+        for (lab1, lab2, expected) in [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+        ] {
+            assert!((ciede2000(lab1, lab2) - expected).abs() < 1e-4, "{:?} {:?}", lab1, lab2);
+            assert!((ciede2000(lab2, lab1) - expected).abs() < 1e-4);
+        }
+        assert!((cie94([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485]) - 1.3950).abs() < 1e-4);
+
+        let (red, darker) = ([200, 30, 30], [180, 30, 30]);
+        assert_eq!(Metric::Euclidean.distance(red, darker), 20.0);
+        assert_eq!(Metric::Cie76.distance(red, darker), delta_e(red, darker));
+        for metric in Metric::value_variants() {
+            assert_eq!(metric.distance(red, red), 0.0, "{}", metric);
+        }
+        assert!(Metric::Ciede2000.distance(red, darker) < Metric::Cie76.distance(red, darker));
+    }
+
+    #[test]
+    fn test_colour_temperature() {
+        // sRGB white is D65, roughly 6500 K
+        let white = correlated_colour_temperature([255, 255, 255]).unwrap();
+        assert!((white - 6504.0).abs() < 50.0);
+        assert!(correlated_colour_temperature([255, 140, 40]).unwrap() < 3000.0);
+        assert!(correlated_colour_temperature([0, 0, 255]).unwrap() > 20000.0);
+        assert!(correlated_colour_temperature([0, 0, 0]).is_none());
+
+        assert_eq!(temperature_class([255, 140, 40]), Temperature::Warm);
+        assert_eq!(temperature_class([40, 90, 255]), Temperature::Cool);
+        assert_eq!(temperature_class([128, 128, 128]), Temperature::Neutral);
+    }
+
+    #[test]
+    fn test_contrast_ratio() {
+        assert!((contrast_ratio([0, 0, 0], [255, 255, 255]) - 21.0).abs() < 1e-9);
+        assert!((contrast_ratio([255, 255, 255], [0, 0, 0]) - 21.0).abs() < 1e-9);
+        assert_eq!(contrast_ratio([100, 50, 200], [100, 50, 200]), 1.0);
+    }
+
+    #[test]
+    fn test_text_colour_on() {
+        assert_eq!(text_colour_on([255, 255, 255]), [0, 0, 0]);
+        assert_eq!(text_colour_on([255, 255, 0]), [0, 0, 0]);
+        assert_eq!(text_colour_on([0, 0, 0]), [255, 255, 255]);
+        assert_eq!(text_colour_on([0, 0, 128]), [255, 255, 255]);
+    }
+}
@@ -0,0 +1,365 @@
+//! Palette similarity: Earth Mover's Distance and per-colour matching in Lab, and
+//! the colours added, removed and changed between two palettes.
+
+use crate::colour::Metric;
+use crate::ColourInfo;
+
+/// Earth Mover's Distance between two weighted palettes, with `metric` as the ground distance.
+///
+/// Weights are normalised so each palette sums to 1, making the result the average
+/// delta-E that has to be "moved" to turn one palette into the other: 0.0 for identical
+/// palettes, up to 100.0 for pure black against pure white (in CIE76).
+pub fn earth_movers_distance(a: &[ColourInfo], b: &[ColourInfo], metric: Metric) -> f64 {
+    let supply = normalised_weights(a);
+    let demand = normalised_weights(b);
+    if supply.is_empty() || demand.is_empty() {
+        return 0.0;
+    }
+    let cost: Vec<Vec<f64>> = a
+        .iter()
+        .map(|x| b.iter().map(|y| metric.distance(x.rgb, y.rgb)).collect())
+        .collect();
+    min_cost_transport(&supply, &demand, &cost)
+}
+
+/// Map an EMD onto a 0.0..=1.0 similarity score, where 1.0 means identical palettes.
+pub fn similarity(emd: f64) -> f64 {
+    (1.0 - emd / 100.0).clamp(0.0, 1.0)
+}
+
+/// The nearest colour in `other` for one colour of the reference palette.
+#[derive(Debug)]
+pub struct ColourMatch {
+    pub rgb: [u8; 3],
+    pub percentage: f64,
+    pub matched_rgb: [u8; 3],
+    pub matched_percentage: f64,
+    pub delta_e: f64,
+}
+
+/// Match every colour of `reference` to its nearest colour in `other` by `metric`.
+pub fn match_colours(reference: &[ColourInfo], other: &[ColourInfo], metric: Metric) -> Vec<ColourMatch> {
+    reference
+        .iter()
+        .filter_map(|r| {
+            other
+                .iter()
+                .map(|o| (o, metric.distance(r.rgb, o.rgb)))
+                .min_by(|x, y| x.1.total_cmp(&y.1))
+                .map(|(o, delta_e)| ColourMatch {
+                    rgb: r.rgb,
+                    percentage: r.percentage,
+                    matched_rgb: o.rgb,
+                    matched_percentage: o.percentage,
+                    delta_e,
+                })
+        })
+        .collect()
+}
+
+/// Mean delta-E of a set of matches, weighted by each reference colour's share.
+pub fn mean_delta_e(matches: &[ColourMatch]) -> f64 {
+    let total: f64 = matches.iter().map(|m| m.percentage).sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+    matches.iter().map(|m| m.delta_e * m.percentage).sum::<f64>() / total
+}
+
+/// Delta-E below which two colours look the same (about one just-noticeable difference).
+pub const UNNOTICEABLE_DELTA_E: f64 = 2.3;
+
+/// Coverage change, in percentage points, below which a colour's share counts as unchanged.
+pub const UNCHANGED_COVERAGE: f64 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Added,
+    Removed,
+    Shifted,
+    Unchanged,
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added => write!(f, "added"),
+            Change::Removed => write!(f, "removed"),
+            Change::Shifted => write!(f, "shifted"),
+            Change::Unchanged => write!(f, "unchanged"),
+        }
+    }
+}
+
+/// One colour of a palette diff: `old` and `new` are both set unless it was added or removed.
+#[derive(Debug)]
+pub struct ColourChange {
+    pub change: Change,
+    pub old: Option<ColourInfo>,
+    pub new: Option<ColourInfo>,
+    /// Delta-E from the old colour to the new one
+    pub delta_e: Option<f64>,
+}
+
+impl ColourChange {
+    /// Change in share of the image, in percentage points.
+    pub fn coverage_change(&self) -> f64 {
+        let percentage = |c: &Option<ColourInfo>| c.as_ref().map_or(0.0, |c| c.percentage);
+        percentage(&self.new) - percentage(&self.old)
+    }
+}
+
+/// Pair colours of `old` with colours of `new` one to one, as long as they are within
+/// `threshold` by `metric`. Old colours choose in order of coverage, largest first, each
+/// taking the closest new colour still free, so a sliver of a shade can't take the
+/// partner of the main colour it sits next to. For each old colour, the index of its
+/// partner in `new` and the difference between them.
+pub fn pair(old: &[ColourInfo], new: &[ColourInfo], threshold: f64, metric: Metric) -> Vec<Option<(usize, f64)>> {
+    let mut order: Vec<usize> = (0..old.len()).collect();
+    order.sort_by(|&i, &j| old[j].percentage.total_cmp(&old[i].percentage));
+
+    let mut partner: Vec<Option<(usize, f64)>> = vec![None; old.len()];
+    let mut paired = vec![false; new.len()];
+    for i in order {
+        let closest = new
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| !paired[j])
+            .map(|(j, y)| (j, metric.distance(old[i].rgb, y.rgb)))
+            .filter(|&(_, delta_e)| delta_e <= threshold)
+            .min_by(|p, q| p.1.total_cmp(&q.1));
+        if let Some((j, delta_e)) = closest {
+            partner[i] = Some((j, delta_e));
+            paired[j] = true;
+        }
+    }
+    partner
+}
+
+/// What changed from the `old` palette to the `new` one. Colours are paired as by
+/// `pair`; a pair is shifted if its colour or coverage visibly changed. Unpaired colours
+/// were removed or added. Old colours come first in their order, then the added ones.
+pub fn diff(old: &[ColourInfo], new: &[ColourInfo], threshold: f64, metric: Metric) -> Vec<ColourChange> {
+    let partner = pair(old, new, threshold, metric);
+    let mut paired = vec![false; new.len()];
+    for &(j, _) in partner.iter().flatten() {
+        paired[j] = true;
+    }
+
+    let mut changes: Vec<ColourChange> = old
+        .iter()
+        .zip(partner)
+        .map(|(o, partner)| match partner {
+            Some((j, delta_e)) => {
+                let n = &new[j];
+                let unchanged =
+                    delta_e < UNNOTICEABLE_DELTA_E && (n.percentage - o.percentage).abs() < UNCHANGED_COVERAGE;
+                ColourChange {
+                    change: if unchanged { Change::Unchanged } else { Change::Shifted },
+                    old: Some(o.clone()),
+                    new: Some(n.clone()),
+                    delta_e: Some(delta_e),
+                }
+            }
+            None => ColourChange { change: Change::Removed, old: Some(o.clone()), new: None, delta_e: None },
+        })
+        .collect();
+    changes.extend(new.iter().zip(paired).filter(|(_, paired)| !paired).map(|(n, _)| ColourChange {
+        change: Change::Added,
+        old: None,
+        new: Some(n.clone()),
+        delta_e: None,
+    }));
+    changes
+}
+
+fn normalised_weights(colours: &[ColourInfo]) -> Vec<f64> {
+    let total: f64 = colours.iter().map(|c| c.percentage).sum();
+    if total <= 0.0 {
+        return vec![1.0 / colours.len() as f64; colours.len()];
+    }
+    colours.iter().map(|c| c.percentage / total).collect()
+}
+
+/// Solve the transportation problem by successive shortest paths (Bellman-Ford on the
+/// residual graph). Palettes are small, so simplicity beats asymptotic cleverness here.
+fn min_cost_transport(supply: &[f64], demand: &[f64], cost: &[Vec<f64>]) -> f64 {
+    const EPSILON: f64 = 1e-12;
+    let (n, m) = (supply.len(), demand.len());
+    let mut supply = supply.to_vec();
+    let mut demand = demand.to_vec();
+    // flow[i][j] is the amount moved from supplier i to consumer j
+    let mut flow = vec![vec![0.0; m]; n];
+    let mut total = 0.0;
+
+    loop {
+        // Nodes 0..n are suppliers, n..n+m consumers. Sources are suppliers with stock left.
+        let mut dist = vec![f64::INFINITY; n + m];
+        let mut prev: Vec<Option<usize>> = vec![None; n + m];
+        for i in 0..n {
+            if supply[i] > EPSILON {
+                dist[i] = 0.0;
+            }
+        }
+        for _ in 0..n + m {
+            let mut changed = false;
+            for i in 0..n {
+                for j in 0..m {
+                    // Forward edge i -> j has unlimited capacity
+                    if dist[i] + cost[i][j] < dist[n + j] - EPSILON {
+                        dist[n + j] = dist[i] + cost[i][j];
+                        prev[n + j] = Some(i);
+                        changed = true;
+                    }
+                    // Backward edge j -> i exists wherever flow has already been sent
+                    if flow[i][j] > EPSILON && dist[n + j] - cost[i][j] < dist[i] - EPSILON {
+                        dist[i] = dist[n + j] - cost[i][j];
+                        prev[i] = Some(n + j);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        // Cheapest reachable consumer that still needs colour
+        let Some(sink) = (0..m)
+            .filter(|&j| demand[j] > EPSILON && dist[n + j].is_finite())
+            .min_by(|&x, &y| dist[n + x].total_cmp(&dist[n + y]))
+        else {
+            break;
+        };
+
+        // Walk back to the source to find the bottleneck along the path
+        let mut node = n + sink;
+        let mut path = vec![node];
+        while let Some(p) = prev[node] {
+            path.push(p);
+            node = p;
+            if path.len() > n + m {
+                break; // only reachable through floating-point noise
+            }
+        }
+        let source = *path.last().unwrap();
+        if source >= n || supply[source] <= EPSILON {
+            break;
+        }
+        let mut amount = supply[source].min(demand[sink]);
+        for pair in path.windows(2) {
+            let (to, from) = (pair[0], pair[1]);
+            if from >= n {
+                // Backward edge: limited by the flow it cancels
+                amount = amount.min(flow[to][from - n]);
+            }
+        }
+
+        for pair in path.windows(2) {
+            let (to, from) = (pair[0], pair[1]);
+            if from < n {
+                flow[from][to - n] += amount;
+                total += amount * cost[from][to - n];
+            } else {
+                flow[to][from - n] -= amount;
+                total -= amount * cost[to][from - n];
+            }
+        }
+        supply[source] -= amount;
+        demand[sink] -= amount;
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_palettes() {
+        let a = vec![ColourInfo::new([255, 0, 0], 60.0), ColourInfo::new([0, 0, 255], 40.0)];
+        let b = vec![ColourInfo::new([0, 0, 255], 40.0), ColourInfo::new([255, 0, 0], 60.0)];
+        assert!(earth_movers_distance(&a, &b, Metric::Cie76).abs() < 1e-9);
+        assert_eq!(similarity(0.0), 1.0);
+    }
+
+    #[test]
+    fn test_black_and_white() {
+        let black = vec![ColourInfo::new([0, 0, 0], 100.0)];
+        let white = vec![ColourInfo::new([255, 255, 255], 100.0)];
+        assert!((earth_movers_distance(&black, &white, Metric::Cie76) - 100.0).abs() < 0.01);
+
+        // Half the weight stays put, the other half moves the full distance
+        let half = vec![ColourInfo::new([0, 0, 0], 50.0), ColourInfo::new([255, 255, 255], 50.0)];
+        assert!((earth_movers_distance(&black, &half, Metric::Cie76) - 50.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_emd_prefers_optimal_transport() {
+        // A greedy nearest-first assignment moves too much weight the long way;
+        // the optimum needs the solver to re-route through a backward edge.
+        let a = vec![ColourInfo::new([0, 0, 0], 50.0), ColourInfo::new([128, 128, 128], 50.0)];
+        let b = vec![ColourInfo::new([100, 100, 100], 50.0), ColourInfo::new([255, 255, 255], 50.0)];
+        let emd = earth_movers_distance(&a, &b, Metric::Cie76);
+        let cost = |x: [u8; 3], y: [u8; 3]| Metric::Cie76.distance(x, y);
+        let straight = 0.5 * cost([0, 0, 0], [100, 100, 100]) + 0.5 * cost([128, 128, 128], [255, 255, 255]);
+        let crossed = 0.5 * cost([0, 0, 0], [255, 255, 255]) + 0.5 * cost([128, 128, 128], [100, 100, 100]);
+        assert!((emd - straight.min(crossed)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_match_colours() {
+        let a = vec![ColourInfo::new([250, 10, 10], 70.0)];
+        let b = vec![ColourInfo::new([0, 0, 255], 50.0), ColourInfo::new([255, 0, 0], 50.0)];
+        let matches = match_colours(&a, &b, Metric::Cie76);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].matched_rgb, [255, 0, 0]);
+        assert!(matches[0].delta_e < 5.0);
+        assert_eq!(mean_delta_e(&matches), matches[0].delta_e);
+        assert_eq!(mean_delta_e(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![
+            ColourInfo::new([255, 255, 255], 50.0),
+            ColourInfo::new([200, 0, 0], 30.0),
+            ColourInfo::new([0, 0, 200], 20.0),
+        ];
+        let new = vec![
+            ColourInfo::new([255, 255, 255], 50.5),
+            ColourInfo::new([215, 10, 0], 25.0),
+            ColourInfo::new([0, 160, 0], 24.5),
+        ];
+        let changes = diff(&old, &new, 10.0, Metric::Cie76);
+        let kinds: Vec<_> = changes.iter().map(|c| c.change).collect();
+        assert_eq!(kinds, [Change::Unchanged, Change::Shifted, Change::Removed, Change::Added]);
+        assert_eq!(changes[1].new.as_ref().unwrap().rgb, [215, 10, 0]);
+        assert!(changes[1].delta_e.unwrap() > UNNOTICEABLE_DELTA_E);
+        assert_eq!(changes[1].coverage_change(), -5.0);
+        assert_eq!((changes[2].coverage_change(), changes[3].coverage_change()), (-20.0, 24.5));
+
+        // With no tolerance for shifts, only identical colours pair up
+        let kinds: Vec<_> = diff(&old, &new, 0.0, Metric::Cie76).iter().map(|c| c.change).collect();
+        assert_eq!(kinds, [Change::Unchanged, Change::Removed, Change::Removed, Change::Added, Change::Added]);
+    }
+
+    #[test]
+    fn test_diff_pairs_by_coverage() {
+        // a.png is half red and half blue, b.png three quarters red and a quarter green.
+        // The main red must follow the red, not lose it to the sliver of a darker red.
+        let old = vec![
+            ColourInfo::new([254, 0, 0], 48.7),
+            ColourInfo::new([0, 0, 254], 50.6),
+            ColourInfo::new([247, 0, 8], 0.7),
+        ];
+        let new = vec![ColourInfo::new([250, 9, 10], 74.0), ColourInfo::new([0, 254, 0], 26.0)];
+        assert!(Metric::Cie76.distance([247, 0, 8], [250, 9, 10]) < Metric::Cie76.distance([254, 0, 0], [250, 9, 10]));
+
+        let changes = diff(&old, &new, 10.0, Metric::Cie76);
+        let kinds: Vec<_> = changes.iter().map(|c| c.change).collect();
+        assert_eq!(kinds, [Change::Shifted, Change::Removed, Change::Removed, Change::Added]);
+        assert_eq!(changes[0].new.as_ref().unwrap().rgb, [250, 9, 10]);
+        assert_eq!(changes[2].old.as_ref().unwrap().rgb, [247, 0, 8]);
+    }
+}
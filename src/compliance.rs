@@ -0,0 +1,104 @@
+//! Brand compliance: does an image contain the required colours at sufficient coverage?
+
+use crate::colour::Metric;
+use crate::ColourInfo;
+
+/// How well one required colour is represented in the palette.
+#[derive(Debug)]
+pub struct RequiredColour {
+    pub rgb: [u8; 3],
+    /// Share of the image (percent) in clusters within the allowed delta-E
+    pub coverage: f64,
+    /// Delta-E to the closest extracted colour
+    pub nearest_delta_e: f64,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub required: Vec<RequiredColour>,
+    /// Share of the image matching any required colour, counting each cluster once
+    pub total_coverage: f64,
+    pub passed: bool,
+}
+
+/// Check `colours` against the required brand colours.
+///
+/// The check passes when every required colour has at least one extracted colour within
+/// `max_delta_e` by `metric`, and the clusters matching any required colour together
+/// cover at least `min_coverage` percent of the image.
+pub fn check(
+    colours: &[ColourInfo],
+    required: &[[u8; 3]],
+    max_delta_e: f64,
+    min_coverage: f64,
+    metric: Metric,
+) -> Report {
+    // The required colour is the reference for asymmetric metrics
+    let matches = |c: &ColourInfo, rgb: [u8; 3]| metric.distance(rgb, c.rgb) <= max_delta_e;
+
+    let required: Vec<RequiredColour> = required
+        .iter()
+        .map(|&rgb| RequiredColour {
+            rgb,
+            coverage: colours
+                .iter()
+                .filter(|c| matches(c, rgb))
+                .fold(0.0, |sum, c| sum + c.percentage),
+            nearest_delta_e: colours
+                .iter()
+                .map(|c| metric.distance(rgb, c.rgb))
+                .fold(f64::INFINITY, f64::min),
+        })
+        .collect();
+
+    let total_coverage = colours
+        .iter()
+        .filter(|c| required.iter().any(|r| matches(c, r.rgb)))
+        .fold(0.0, |sum, c| sum + c.percentage);
+
+    let passed = required.iter().all(|r| r.coverage > 0.0) && total_coverage >= min_coverage;
+    Report { required, total_coverage, passed }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> Vec<ColourInfo> {
+        vec![
+            ColourInfo::new([250, 250, 250], 55.0),
+            ColourInfo::new([2, 90, 180], 30.0),
+            ColourInfo::new([250, 210, 10], 15.0),
+        ]
+    }
+
+    #[test]
+    fn test_check_passes() {
+        let report = check(&palette(), &[[0, 87, 184], [255, 215, 0]], 8.0, 30.0, Metric::Cie76);
+        assert!(report.passed);
+        assert_eq!(report.required[0].coverage, 30.0);
+        assert_eq!(report.total_coverage, 45.0);
+    }
+
+    #[test]
+    fn test_check_fails() {
+        // Coverage too low
+        assert!(!check(&palette(), &[[0, 87, 184], [255, 215, 0]], 8.0, 50.0, Metric::Cie76).passed);
+
+        // A required colour that is missing entirely
+        let report = check(&palette(), &[[0, 87, 184], [200, 0, 0]], 8.0, 0.0, Metric::Cie76);
+        assert!(!report.passed);
+        assert_eq!(report.required[1].coverage, 0.0);
+        assert!(report.required[1].nearest_delta_e > 8.0);
+    }
+
+    #[test]
+    fn test_check_metric() {
+        // A lighter print of pure blue looks close, which CIE76 overstates for blues
+        let palette = [ColourInfo::new([40, 60, 255], 100.0)];
+        assert!(!check(&palette, &[[0, 0, 255]], 10.0, 0.0, Metric::Cie76).passed);
+        let report = check(&palette, &[[0, 0, 255]], 10.0, 0.0, Metric::Ciede2000);
+        assert!(report.passed);
+        assert!((report.required[0].nearest_delta_e - 7.36).abs() < 0.01);
+    }
+}
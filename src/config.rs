@@ -0,0 +1,258 @@
+//! Defaults read from configuration files.
+//!
+//! Settings come from `$XDG_CONFIG_HOME/dominant-colours/config.toml` (usually
+//! `~/.config/...`) and then from the nearest `.dominant-colours.toml` in the current
+//! directory or its parents, so a project can standardise settings for everyone working
+//! in it. Project settings win over user settings, and options given on the command line
+//! win over both.
+//!
+//! ```toml
+//! colours = 8
+//! format = "json"
+//! space = "lab"
+//! sort = "hue"
+//! names = "xkcd"
+//! metric = "ciede2000"
+//!
+//! [swatch]
+//! output = "palette.svg"
+//! size = 80
+//! font = "Helvetica"
+//! ```
+
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::{Args, Command};
+use dominant_colours::colour::Metric;
+use dominant_colours::names::NameSet;
+use dominant_colours::{ColourOrder, ColourSpace, MAX_COLOURS};
+
+/// Name of the project-local configuration file.
+const PROJECT_FILE: &str = ".dominant-colours.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub colours: Option<usize>,
+    pub format: Option<String>,
+    pub space: Option<String>,
+    pub sort: Option<String>,
+    pub names: Option<String>,
+    pub metric: Option<String>,
+    pub swatch: SwatchConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SwatchConfig {
+    pub output: Option<String>,
+    pub size: Option<usize>,
+    pub font: Option<String>,
+}
+
+impl Config {
+    /// Read and merge the user and project configuration files, if present.
+    pub fn load() -> Result<Config> {
+        let mut config = Config::default();
+        let project = std::env::current_dir().ok().and_then(|dir| project_config_path(&dir));
+        for path in user_config_path().into_iter().chain(project) {
+            if path.is_file() {
+                config.merge(Config::from_file(&path)?);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Overlay `other` on top of these settings.
+    fn merge(&mut self, other: Config) {
+        self.colours = other.colours.or(self.colours);
+        self.format = other.format.or(self.format.take());
+        self.space = other.space.or(self.space.take());
+        self.sort = other.sort.or(self.sort.take());
+        self.names = other.names.or(self.names.take());
+        self.metric = other.metric.or(self.metric.take());
+        self.swatch.output = other.swatch.output.or(self.swatch.output.take());
+        self.swatch.size = other.swatch.size.or(self.swatch.size);
+        self.swatch.font = other.swatch.font.or(self.swatch.font.take());
+    }
+
+    /// Fill in every option the user did not give explicitly. Analysis settings and the
+    /// output format are global, so their sources are tracked on the top-level matches.
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) -> Result<()> {
+        if let Some(colours) = self.colours {
+            if !(1..=MAX_COLOURS).contains(&colours) {
+                anyhow::bail!("Invalid `colours` in config: {} is not from 1 to {}", colours, MAX_COLOURS);
+            }
+            if unset(matches, "colours") {
+                args.analysis.colours = colours;
+            }
+        }
+        if let Some(value) = &self.space {
+            if unset(matches, "space") {
+                args.analysis.space = parse_enum::<ColourSpace>("space", value)?;
+            }
+        }
+        if let Some(value) = &self.sort {
+            if unset(matches, "sort") {
+                args.analysis.sort = parse_enum::<ColourOrder>("sort", value)?;
+            }
+        }
+        if let Some(value) = &self.metric {
+            if unset(matches, "metric") {
+                args.analysis.metric = parse_enum::<Metric>("metric", value)?;
+            }
+        }
+        if let Some(value) = &self.format {
+            if unset(matches, "format") {
+                args.format = parse_enum("format", value)?;
+            }
+        }
+
+        let sub_matches = matches.subcommand().map_or(matches, |(_, m)| m);
+        let (extract, style) = match &mut args.command {
+            None => (Some(&mut args.extract), None),
+            Some(Command::Extract(extract)) => (Some(&mut **extract), None),
+            Some(Command::Swatch { swatch_style, .. }) => (None, Some(swatch_style)),
+            _ => (None, None),
+        };
+        let style = match extract {
+            Some(extract) => {
+                if let Some(value) = &self.names {
+                    if unset(sub_matches, "names") && unset(sub_matches, "names_file") {
+                        extract.names = parse_enum::<NameSet>("names", value)?;
+                    }
+                }
+                if let Some(output) = &self.swatch.output {
+                    if unset(sub_matches, "output") {
+                        extract.output = output.clone();
+                    }
+                }
+                Some(&mut extract.swatch_style)
+            }
+            None => style,
+        };
+        if let Some(style) = style {
+            if let Some(size) = self.swatch.size {
+                if !(1..=crate::MAX_SWATCH_SIZE).contains(&size) {
+                    anyhow::bail!("Invalid `swatch.size` in config: must be 1 to {}", crate::MAX_SWATCH_SIZE);
+                }
+                if unset(sub_matches, "swatch_size") {
+                    style.swatch_size = size;
+                }
+            }
+            if let Some(font) = &self.swatch.font {
+                if unset(sub_matches, "swatch_font") {
+                    style.swatch_font = font.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// True when an option was left at its default rather than given by the user.
+fn unset(matches: &ArgMatches, id: &str) -> bool {
+    matches!(matches.value_source(id), None | Some(ValueSource::DefaultValue))
+}
+
+fn parse_enum<T: ValueEnum>(key: &str, value: &str) -> Result<T> {
+    T::from_str(value, true).map_err(|e| anyhow::anyhow!("Invalid `{}` in config: {}", key, e))
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("dominant-colours").join("config.toml"))
+}
+
+/// The nearest project configuration file at or above `dir`.
+fn project_config_path(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().map(|d| d.join(PROJECT_FILE)).find(|p| p.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OutputFormat;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn parse(config: &str, argv: &[&str]) -> Result<Args> {
+        let config: Config = toml::from_str(config)?;
+        let matches = Args::command().try_get_matches_from(argv)?;
+        let mut args = Args::from_arg_matches(&matches)?;
+        config.apply(&mut args, &matches)?;
+        Ok(args)
+    }
+
+    #[test]
+    fn test_config_defaults_and_overrides() -> Result<()> {
+        let config = "colours = 8\nformat = \"json\"\nspace = \"lab\"\nsort = \"hue\"\nmetric = \"ciede2000\"\n[swatch]\nsize = 60\nfont = \"Helvetica\"\n";
+
+        let args = parse(config, &["program", "image.jpg"])?;
+        assert_eq!(args.analysis.colours, 8);
+        assert_eq!(args.format, OutputFormat::Json);
+        assert_eq!(args.analysis.space, ColourSpace::Lab);
+        assert_eq!(args.analysis.sort, ColourOrder::Hue);
+        assert_eq!(args.analysis.metric, Metric::Ciede2000);
+        assert_eq!(args.extract.swatch_style.swatch_size, 60);
+        assert_eq!(args.extract.swatch_style.swatch_font, "Helvetica");
+
+        // Command line wins, even when it repeats the built-in default
+        let args = parse(config, &["program", "-c", "6", "-f", "text", "image.jpg"])?;
+        assert_eq!(args.analysis.colours, 6);
+        assert_eq!(args.format, OutputFormat::Text);
+
+        // Global settings reach subcommands too, and the command line still wins there
+        let args = parse(config, &["program", "compare", "a.jpg", "b.jpg"])?;
+        assert_eq!(args.analysis.colours, 8);
+        assert_eq!(args.format, OutputFormat::Json);
+        let args = parse(config, &["program", "compare", "-c", "6", "a.jpg", "b.jpg"])?;
+        assert_eq!(args.analysis.colours, 6);
+
+        let args = parse(config, &["program", "swatch", "--swatch-font", "Futura", "a.jpg"])?;
+        match args.command {
+            Some(Command::Swatch { swatch_style, .. }) => {
+                assert_eq!(swatch_style.swatch_size, 60);
+                assert_eq!(swatch_style.swatch_font, "Futura");
+            }
+            _ => panic!("expected swatch subcommand"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_errors() {
+        assert!(parse("colour = 8", &["program", "image.jpg"]).is_err());
+        assert!(parse("format = \"xml\"", &["program", "image.jpg"]).is_err());
+        assert!(parse("colours = 300", &["program", "image.jpg"]).is_err());
+        assert!(parse("[swatch]\nsize = 0", &["program", "swatch", "image.jpg"]).is_err());
+        assert!(parse("[swatch]\nsize = 1000000", &["program", "swatch", "image.jpg"]).is_err());
+    }
+
+    #[test]
+    fn test_merge_and_discovery() -> Result<()> {
+        let mut config: Config = toml::from_str("colours = 8\nnames = \"xkcd\"")?;
+        config.merge(toml::from_str("colours = 4")?);
+        assert_eq!(config.colours, Some(4));
+        assert_eq!(config.names.as_deref(), Some("xkcd"));
+
+        let dir = tempfile::tempdir()?;
+        let nested = dir.path().join("a").join("b");
+        std::fs::create_dir_all(&nested)?;
+        std::fs::write(dir.path().join(PROJECT_FILE), "colours = 3")?;
+        assert_eq!(project_config_path(&nested), Some(dir.path().join(PROJECT_FILE)));
+        Ok(())
+    }
+}
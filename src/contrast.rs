@@ -0,0 +1,157 @@
+//! WCAG contrast analysis across the extracted palette.
+
+use anyhow::Result;
+use std::fs::File;
+use std::io::Write;
+
+use crate::colour;
+use crate::ColourInfo;
+
+/// Contrast ratio between every pair of colours; `matrix[i][j]` compares colour `i` with colour `j`.
+pub fn contrast_matrix(colours: &[ColourInfo]) -> Vec<Vec<f64>> {
+    colours
+        .iter()
+        .map(|a| {
+            colours
+                .iter()
+                .map(|b| colour::contrast_ratio(a.rgb, b.rgb))
+                .collect()
+        })
+        .collect()
+}
+
+/// WCAG 2 conformance levels, with the minimum contrast ratio each requires.
+pub const WCAG_LEVELS: [(&str, f64); 4] = [
+    ("AAA", 7.0),
+    ("AAA-large", 4.5),
+    ("AA", 4.5),
+    ("AA-large", 3.0),
+];
+
+/// A pair of palette colours that meets at least one WCAG contrast level.
+#[derive(Debug)]
+pub struct WcagPair {
+    pub first: [u8; 3],
+    pub second: [u8; 3],
+    pub ratio: f64,
+    pub levels: Vec<&'static str>,
+}
+
+/// Enumerate every unordered pair of colours meeting at least WCAG AA for large text,
+/// highest contrast first. Contrast is symmetric, so either colour can be the foreground.
+pub fn wcag_pairs(colours: &[ColourInfo]) -> Vec<WcagPair> {
+    let mut pairs = Vec::new();
+    for (i, a) in colours.iter().enumerate() {
+        for b in &colours[i + 1..] {
+            let ratio = colour::contrast_ratio(a.rgb, b.rgb);
+            let levels: Vec<&'static str> = WCAG_LEVELS
+                .iter()
+                .filter(|(_, min)| ratio >= *min)
+                .map(|(name, _)| *name)
+                .collect();
+            if !levels.is_empty() {
+                pairs.push(WcagPair { first: a.rgb, second: b.rgb, ratio, levels });
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.ratio.total_cmp(&a.ratio));
+    pairs
+}
+
+/// Map a contrast ratio onto a red (1:1) to green (7:1 and above) heat-map colour.
+fn heat_colour(ratio: f64) -> [u8; 3] {
+    let t = ((ratio - 1.0) / 6.0).clamp(0.0, 1.0);
+    [((1.0 - t) * 220.0) as u8, (t * 180.0) as u8, 60]
+}
+
+/// Save the contrast matrix as an SVG heat map, with the palette colours along both axes.
+pub fn save_contrast_heatmap(colours: &[ColourInfo], output_file: &str) -> Result<()> {
+    const CELL: usize = 60;
+    let matrix = contrast_matrix(colours);
+    let size = (colours.len() + 1) * CELL;
+
+    let mut svg = format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}">"#,
+        size, size
+    );
+
+    for (i, c) in colours.iter().enumerate() {
+        let offset = (i + 1) * CELL;
+        svg.push_str(&format!(
+            r#"
+    <rect x="{}" y="0" width="{}" height="{}" fill="rgb({}, {}, {})"/>
+    <rect x="0" y="{}" width="{}" height="{}" fill="rgb({}, {}, {})"/>"#,
+            offset, CELL, CELL, c.rgb[0], c.rgb[1], c.rgb[2],
+            offset, CELL, CELL, c.rgb[0], c.rgb[1], c.rgb[2]
+        ));
+    }
+
+    for (i, row) in matrix.iter().enumerate() {
+        for (j, ratio) in row.iter().enumerate() {
+            let x = (j + 1) * CELL;
+            let y = (i + 1) * CELL;
+            let heat = heat_colour(*ratio);
+            svg.push_str(&format!(
+                r#"
+    <rect x="{}" y="{}" width="{}" height="{}" fill="rgb({}, {}, {})"/>
+    <text x="{}" y="{}" font-family="Arial" font-size="12" fill="white" text-anchor="middle">{:.1}</text>"#,
+                x, y, CELL, CELL, heat[0], heat[1], heat[2],
+                x + CELL / 2, y + CELL / 2 + 4, ratio
+            ));
+        }
+    }
+
+    svg.push_str("\n</svg>");
+
+    let mut file = File::create(output_file)?;
+    file.write_all(svg.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn palette() -> Vec<ColourInfo> {
+        vec![
+            ColourInfo::new([0, 0, 0], 60.0),
+            ColourInfo::new([255, 255, 255], 40.0),
+        ]
+    }
+
+    #[test]
+    fn test_contrast_matrix() {
+        let matrix = contrast_matrix(&palette());
+        assert_eq!(matrix.len(), 2);
+        assert_eq!(matrix[0][0], 1.0);
+        assert!((matrix[0][1] - 21.0).abs() < 1e-9);
+        assert_eq!(matrix[0][1], matrix[1][0]);
+    }
+
+    #[test]
+    fn test_wcag_pairs() {
+        let mut colours = palette();
+        colours.push(ColourInfo::new([119, 119, 119], 0.0));
+        let pairs = wcag_pairs(&colours);
+
+        // black/white passes everything; white/grey passes only AA-large; black/grey AA and AAA-large
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(pairs[0].levels, vec!["AAA", "AAA-large", "AA", "AA-large"]);
+        assert_eq!(pairs[1].levels, vec!["AAA-large", "AA", "AA-large"]);
+        assert_eq!(pairs[2].levels, vec!["AA-large"]);
+    }
+
+    #[test]
+    fn test_contrast_heatmap() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let output_path = temp_dir.path().join("contrast.svg");
+        save_contrast_heatmap(&palette(), output_path.to_str().unwrap())?;
+
+        let content = std::fs::read_to_string(output_path)?;
+        assert!(content.contains("21.0"));
+        assert!(content.contains("rgb(255, 255, 255)"));
+        Ok(())
+    }
+}
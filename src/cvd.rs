@@ -0,0 +1,125 @@
+//! Colour vision deficiency (CVD) simulation.
+//!
+//! Uses the Machado, Oliveira & Fernandes (2009) matrices at full severity,
+//! applied to linear-light RGB.
+
+use clap::ValueEnum;
+
+use crate::colour::{self, Metric};
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum Deficiency {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl std::fmt::Display for Deficiency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Deficiency::Protanopia => write!(f, "protanopia"),
+            Deficiency::Deuteranopia => write!(f, "deuteranopia"),
+            Deficiency::Tritanopia => write!(f, "tritanopia"),
+        }
+    }
+}
+
+impl Deficiency {
+    fn matrix(self) -> [[f64; 3]; 3] {
+        match self {
+            Deficiency::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Deficiency::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Deficiency::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+}
+
+/// How `rgb` appears to someone with the given deficiency.
+pub fn simulate(rgb: [u8; 3], deficiency: Deficiency) -> [u8; 3] {
+    let linear = rgb.map(colour::srgb_to_linear);
+    deficiency.matrix().map(|row| {
+        let value: f64 = row.iter().zip(linear.iter()).map(|(m, c)| m * c).sum();
+        colour::linear_to_srgb(value)
+    })
+}
+
+/// Two palette colours that are distinct under normal vision but not under a deficiency.
+#[derive(Debug)]
+pub struct Conflict {
+    pub deficiency: Deficiency,
+    pub first: [u8; 3],
+    pub second: [u8; 3],
+    pub delta_e: f64,
+    pub simulated_delta_e: f64,
+}
+
+/// Find pairs of colours whose simulated difference by `metric` falls below `threshold`
+/// for any of `deficiencies`. Pairs that are already closer than `threshold` under normal
+/// vision are not reported, since they are not a colour-vision problem.
+pub fn conflicts(rgbs: &[[u8; 3]], deficiencies: &[Deficiency], threshold: f64, metric: Metric) -> Vec<Conflict> {
+    let mut found = Vec::new();
+    for &deficiency in deficiencies {
+        for (i, &a) in rgbs.iter().enumerate() {
+            for &b in &rgbs[i + 1..] {
+                let delta_e = metric.distance(a, b);
+                if delta_e < threshold {
+                    continue;
+                }
+                let simulated_delta_e = metric.distance(simulate(a, deficiency), simulate(b, deficiency));
+                if simulated_delta_e < threshold {
+                    found.push(Conflict { deficiency, first: a, second: b, delta_e, simulated_delta_e });
+                }
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate_preserves_greys() {
+        for deficiency in [Deficiency::Protanopia, Deficiency::Deuteranopia, Deficiency::Tritanopia] {
+            assert_eq!(simulate([0, 0, 0], deficiency), [0, 0, 0]);
+            assert_eq!(simulate([255, 255, 255], deficiency), [255, 255, 255]);
+            let grey = simulate([128, 128, 128], deficiency);
+            assert!(grey.iter().all(|&c| (c as i32 - 128).abs() <= 1));
+        }
+    }
+
+    #[test]
+    fn test_simulate_red_green_confusion() {
+        // Under protanopia and deuteranopia pure red loses most of its red/green distinction
+        let red = simulate([255, 0, 0], Deficiency::Protanopia);
+        assert!((red[0] as i32 - red[1] as i32).abs() < 40);
+        let red = simulate([255, 0, 0], Deficiency::Deuteranopia);
+        assert!((red[0] as i32 - red[1] as i32).abs() < 60);
+    }
+
+    #[test]
+    fn test_conflicts() {
+        // Red and green collapse for protanopes; blue against yellow survives
+        let rgbs = [[200, 60, 40], [150, 90, 40], [0, 0, 255], [255, 255, 0]];
+        let found = conflicts(&rgbs, &[Deficiency::Protanopia], 10.0, Metric::Cie76);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].first, [200, 60, 40]);
+        assert_eq!(found[0].second, [150, 90, 40]);
+        assert!(found[0].simulated_delta_e < 10.0);
+
+        assert!(conflicts(&rgbs[2..], &[Deficiency::Protanopia, Deficiency::Deuteranopia, Deficiency::Tritanopia], 10.0, Metric::Cie76).is_empty());
+    }
+}
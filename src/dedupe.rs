@@ -0,0 +1,64 @@
+//! Near-duplicate detection by palette similarity.
+
+use crate::colour::Metric;
+use crate::compare;
+use crate::ColourInfo;
+
+/// Group palettes whose Earth Mover's Distance (by `metric`) is at most `threshold`.
+///
+/// Grouping is single-linkage: if A matches B and B matches C, all three share a group
+/// even when A and C are further apart, which suits chains of crops and re-exports.
+/// Only groups with two or more members are returned, each sorted by index.
+pub fn groups(palettes: &[Vec<ColourInfo>], threshold: f64, metric: Metric) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..palettes.len()).collect();
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        // Path compression keeps later lookups flat
+        let mut node = i;
+        while parent[node] != root {
+            let next = parent[node];
+            parent[node] = root;
+            node = next;
+        }
+        root
+    }
+
+    for i in 0..palettes.len() {
+        for j in i + 1..palettes.len() {
+            if compare::earth_movers_distance(&palettes[i], &palettes[j], metric) <= threshold {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                if a != b {
+                    parent[b.max(a)] = a.min(b);
+                }
+            }
+        }
+    }
+
+    let mut by_root: Vec<Vec<usize>> = vec![Vec::new(); palettes.len()];
+    for i in 0..palettes.len() {
+        let root = find(&mut parent, i);
+        by_root[root].push(i);
+    }
+    by_root.into_iter().filter(|g| g.len() > 1).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_groups() {
+        let palettes = vec![
+            vec![ColourInfo::new([255, 0, 0], 60.0), ColourInfo::new([255, 255, 255], 40.0)],
+            vec![ColourInfo::new([0, 0, 255], 100.0)],
+            vec![ColourInfo::new([250, 5, 0], 58.0), ColourInfo::new([250, 250, 250], 42.0)],
+            vec![ColourInfo::new([0, 5, 250], 100.0)],
+            vec![ColourInfo::new([0, 200, 0], 100.0)],
+        ];
+        assert_eq!(groups(&palettes, 5.0, Metric::Cie76), vec![vec![0, 2], vec![1, 3]]);
+        assert!(groups(&palettes, 0.0, Metric::Cie76).is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+//! How diverse a palette is, so a media library can be sorted from monochromatic to
+//! highly colourful straight from the extracted palettes.
+
+use serde::{Deserialize, Serialize};
+
+use crate::colour::Metric;
+use crate::ColourInfo;
+
+/// Two views of a palette's diversity and a score combining them.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Diversity {
+    /// Shannon entropy of the colours' shares, in bits: 0 for one colour, up to
+    /// log2 of the number of colours when they share the image evenly
+    pub entropy: f64,
+    /// Mean difference between every pair of colours, in the metric's units
+    pub mean_delta_e: f64,
+    /// Entropy times mean delta-E: 0 for a single colour or near-identical shades, and
+    /// high for many distinct colours that all take a fair share
+    pub score: f64,
+}
+
+/// Measure the diversity of `colours`, with differences under `metric`.
+pub fn measure(colours: &[ColourInfo], metric: Metric) -> Diversity {
+    let total: f64 = colours.iter().map(|c| c.percentage).sum();
+    let entropy = if total > 0.0 {
+        -colours
+            .iter()
+            .map(|c| c.percentage / total)
+            .filter(|&p| p > 0.0)
+            .map(|p| p * p.log2())
+            .sum::<f64>()
+    } else {
+        0.0
+    };
+    let mut differences = Vec::new();
+    for (i, a) in colours.iter().enumerate() {
+        for b in &colours[i + 1..] {
+            differences.push(metric.distance(a.rgb, b.rgb));
+        }
+    }
+    let mean_delta_e = if differences.is_empty() { 0.0 } else { differences.iter().sum::<f64>() / differences.len() as f64 };
+    // A lone colour has -0.0 entropy
+    let entropy = entropy.max(0.0);
+    Diversity { entropy, mean_delta_e, score: entropy * mean_delta_e }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diversity() {
+        let single = measure(&[ColourInfo::new([200, 30, 30], 100.0)], Metric::Cie76);
+        assert_eq!(single, Diversity { entropy: 0.0, mean_delta_e: 0.0, score: 0.0 });
+
+        let even = [ColourInfo::new([200, 30, 30], 50.0), ColourInfo::new([30, 30, 200], 50.0)];
+        let diversity = measure(&even, Metric::Cie76);
+        assert!((diversity.entropy - 1.0).abs() < 1e-9);
+        assert!((diversity.score - diversity.mean_delta_e).abs() < 1e-9);
+
+        // Shades of one colour, and one colour dominating, both score lower
+        let shades = [ColourInfo::new([200, 30, 30], 50.0), ColourInfo::new([180, 40, 40], 50.0)];
+        let dominated = [ColourInfo::new([200, 30, 30], 95.0), ColourInfo::new([30, 30, 200], 5.0)];
+        assert!(measure(&shades, Metric::Cie76).score < diversity.score);
+        assert!(measure(&dominated, Metric::Cie76).score < diversity.score);
+    }
+}
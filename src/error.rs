@@ -0,0 +1,89 @@
+//! Errors returned by the analysis functions.
+
+use image::ImageError;
+use linfa_clustering::KMeansError;
+use std::io::ErrorKind;
+
+/// Why an analysis failed, for callers that need more than the message.
+#[derive(Debug)]
+pub enum DominantColoursError {
+    /// The image file could not be read
+    Io { path: Option<String>, source: std::io::Error },
+    /// The data is truncated, corrupt or not in a supported image format
+    Decode { path: Option<String>, source: ImageError },
+    /// k-means clustering failed
+    Clustering(KMeansError),
+    /// The analysis options cannot be used, e.g. zero colours
+    InvalidOptions(String),
+    /// Nothing is left to cluster, e.g. a fully transparent image or a mask that leaves
+    /// out every pixel
+    NoPixels(String),
+}
+
+impl DominantColoursError {
+    /// Sort an `image` error into reading or decoding trouble. A file that ends early
+    /// opens fine, so counts as corrupt rather than unreadable.
+    pub(crate) fn image(path: Option<&str>, err: ImageError) -> DominantColoursError {
+        let path = path.map(str::to_string);
+        match err {
+            ImageError::IoError(source)
+                if !matches!(source.kind(), ErrorKind::UnexpectedEof | ErrorKind::InvalidData) =>
+            {
+                DominantColoursError::Io { path, source }
+            }
+            source => DominantColoursError::Decode { path, source },
+        }
+    }
+
+    /// File the error concerns, if it came from reading one.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            DominantColoursError::Io { path, .. } | DominantColoursError::Decode { path, .. } => path.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for DominantColoursError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DominantColoursError::Io { .. } => write!(f, "Failed to open image file"),
+            DominantColoursError::Decode { .. } => write!(f, "Failed to decode image"),
+            DominantColoursError::Clustering(_) => write!(f, "Failed to cluster colours"),
+            DominantColoursError::InvalidOptions(message) | DominantColoursError::NoPixels(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for DominantColoursError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DominantColoursError::Io { source, .. } => Some(source),
+            DominantColoursError::Decode { source, .. } => Some(source),
+            DominantColoursError::Clustering(source) => Some(source),
+            DominantColoursError::InvalidOptions(_) | DominantColoursError::NoPixels(_) => None,
+        }
+    }
+}
+
+impl From<KMeansError> for DominantColoursError {
+    fn from(err: KMeansError) -> DominantColoursError {
+        DominantColoursError::Clustering(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_errors() {
+        let missing = image::open("does/not/exist.png").unwrap_err();
+        let err = DominantColoursError::image(Some("does/not/exist.png"), missing);
+        assert!(matches!(err, DominantColoursError::Io { .. }));
+        assert_eq!(err.path(), Some("does/not/exist.png"));
+
+        let truncated = image::load_from_memory(b"\x89PNG\r\n\x1a\n").unwrap_err();
+        assert!(matches!(DominantColoursError::image(None, truncated), DominantColoursError::Decode { path: None, .. }));
+    }
+}
@@ -0,0 +1,128 @@
+//! Process exit codes, so shell pipelines can tell failures apart.
+//!
+//! | Code | Meaning |
+//! |------|---------|
+//! | 0 | Success |
+//! | 1 | Any other error |
+//! | 2 | Invalid command line |
+//! | 3 | Input file missing or unreadable |
+//! | 4 | Unsupported or corrupt image format |
+//! | 5 | Clustering failed, or the image leaves nothing to cluster |
+//! | 6 | A check failed (`--require`, `--cvd-fail`) |
+//! | 7 | A warning was raised under `--strict` |
+
+use std::io::ErrorKind;
+use std::process::ExitCode;
+
+use dominant_colours::DominantColoursError;
+use image::ImageError;
+use linfa_clustering::KMeansError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exit {
+    Failure = 1,
+    Usage = 2,
+    Input = 3,
+    UnsupportedFormat = 4,
+    Clustering = 5,
+    CheckFailed = 6,
+    Strict = 7,
+}
+
+impl Exit {
+    /// Exit code for an error, from the first cause that identifies it.
+    pub fn of(err: &anyhow::Error) -> Exit {
+        for cause in err.chain() {
+            if let Some(failed) = cause.downcast_ref::<Failed>() {
+                return failed.exit;
+            }
+            if let Some(e) = cause.downcast_ref::<DominantColoursError>() {
+                return match e {
+                    DominantColoursError::Io { .. } => Exit::Input,
+                    DominantColoursError::Decode { .. } => Exit::UnsupportedFormat,
+                    DominantColoursError::Clustering(_) | DominantColoursError::NoPixels(_) => Exit::Clustering,
+                    DominantColoursError::InvalidOptions(_) => Exit::Usage,
+                };
+            }
+            if let Some(e) = cause.downcast_ref::<ImageError>() {
+                return match e {
+                    ImageError::IoError(io) => io_exit(io),
+                    ImageError::Unsupported(_) | ImageError::Decoding(_) => Exit::UnsupportedFormat,
+                    _ => Exit::Failure,
+                };
+            }
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                return io_exit(io);
+            }
+            if cause.is::<KMeansError>() {
+                return Exit::Clustering;
+            }
+            if let Some(e) = cause.downcast_ref::<clap::Error>() {
+                return if e.use_stderr() { Exit::Usage } else { Exit::Failure };
+            }
+        }
+        Exit::Failure
+    }
+}
+
+/// Truncated or malformed data is a bad image; anything else means the file could not be read.
+fn io_exit(err: &std::io::Error) -> Exit {
+    match err.kind() {
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData => Exit::UnsupportedFormat,
+        _ => Exit::Input,
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> ExitCode {
+        ExitCode::from(exit as u8)
+    }
+}
+
+/// An error that already knows its exit code, e.g. failed checks or a batch with failures.
+#[derive(Debug)]
+pub struct Failed {
+    pub exit: Exit,
+    pub message: String,
+}
+
+impl Failed {
+    pub fn new(exit: Exit, message: impl Into<String>) -> Failed {
+        Failed { exit, message: message.into() }
+    }
+}
+
+impl std::fmt::Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_exit_codes() {
+        let missing = image::open("does/not/exist.png").context("Failed to open image file").unwrap_err();
+        assert_eq!(Exit::of(&missing), Exit::Input);
+
+        let corrupt = image::load_from_memory(b"\x89PNG\r\n\x1a\n garbage").context("Failed to decode").unwrap_err();
+        assert_eq!(Exit::of(&corrupt), Exit::UnsupportedFormat);
+        let unknown = image::load_from_memory(b"plain text").unwrap_err();
+        assert_eq!(Exit::of(&unknown.into()), Exit::UnsupportedFormat);
+
+        let invalid = DominantColoursError::InvalidOptions("colours must be at least 1".to_string());
+        assert_eq!(Exit::of(&invalid.into()), Exit::Usage);
+        // A valid command line on an image with nothing to cluster is not a usage error
+        let empty = DominantColoursError::NoPixels("the mask leaves no pixels to cluster".to_string());
+        assert_eq!(Exit::of(&anyhow::Error::new(empty).context("a.png")), Exit::Clustering);
+
+        let failed = anyhow::Error::new(Failed::new(Exit::CheckFailed, "Brand compliance check failed"));
+        assert_eq!(Exit::of(&failed.context("a.png")), Exit::CheckFailed);
+        assert_eq!(Exit::of(&anyhow::anyhow!("something else")), Exit::Failure);
+    }
+}
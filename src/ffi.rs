@@ -0,0 +1,181 @@
+//! C interface (`ffi` feature). The header is generated into `include/dominant_colours.h`
+//! by cbindgen when the crate is built with the feature.
+//!
+//! ```c
+//! DominantColoursOptions options = { .colours = 6, .silhouette = false, .lab = false };
+//! DominantColoursResult result;
+//! if (dominant_colours_extract(data, len, &options, &result) != 0) {
+//!     fprintf(stderr, "%s\n", dominant_colours_last_error());
+//! } else {
+//!     for (size_t i = 0; i < result.len; i++) { ... result.colours[i].rgb ... }
+//!     dominant_colours_result_free(&result);
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::{analyze_bytes, AnalysisOptions, ColourSpace};
+
+/// Analysis settings. A `colours` value of zero selects the default of 6.
+#[repr(C)]
+pub struct DominantColoursOptions {
+    pub colours: usize,
+    pub silhouette: bool,
+    /// Cluster in CIE L*a*b* rather than RGB
+    pub lab: bool,
+    /// Resize and cluster in linear light
+    pub linear: bool,
+}
+
+/// One extracted colour.
+#[repr(C)]
+pub struct DominantColour {
+    pub rgb: [u8; 3],
+    /// Share of the image covered by the colour's cluster, in percent
+    pub percentage: f64,
+}
+
+/// Palette filled in by `dominant_colours_extract`; release it with
+/// `dominant_colours_result_free`.
+#[repr(C)]
+pub struct DominantColoursResult {
+    /// Colours sorted by prevalence, most prevalent first
+    pub colours: *mut DominantColour,
+    pub len: usize,
+    pub inertia: f64,
+    /// Silhouette coefficient, or NaN when not requested or undefined
+    pub silhouette: f64,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).expect("interior NULs removed");
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Extract the dominant colours of an encoded image (PNG, JPEG, ...) of `len` bytes.
+///
+/// Returns 0 on success and fills in `result`. On failure returns -1, leaves `result`
+/// untouched and records a message for `dominant_colours_last_error`. `options` may be
+/// NULL for the defaults.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `options` must be NULL or valid, and
+/// `result` must point to writable memory for a `DominantColoursResult`.
+#[no_mangle]
+pub unsafe extern "C" fn dominant_colours_extract(
+    data: *const u8,
+    len: usize,
+    options: *const DominantColoursOptions,
+    result: *mut DominantColoursResult,
+) -> c_int {
+    if data.is_null() || result.is_null() {
+        set_last_error("data and result must not be NULL".to_string());
+        return -1;
+    }
+    let bytes = std::slice::from_raw_parts(data, len);
+    let options = match options.as_ref() {
+        Some(o) => AnalysisOptions {
+            colours: if o.colours == 0 { 6 } else { o.colours },
+            silhouette: o.silhouette,
+            space: if o.lab { ColourSpace::Lab } else { ColourSpace::Rgb },
+            linear: o.linear,
+            ..AnalysisOptions::default()
+        },
+        None => AnalysisOptions::default(),
+    };
+
+    let analysis = match panic::catch_unwind(AssertUnwindSafe(|| analyze_bytes(bytes, &options))) {
+        Ok(Ok(analysis)) => analysis,
+        Ok(Err(e)) => {
+            set_last_error(format!("{:#}", anyhow::Error::new(e)));
+            return -1;
+        }
+        Err(_) => {
+            set_last_error("internal error during analysis".to_string());
+            return -1;
+        }
+    };
+
+    let colours: Box<[DominantColour]> = analysis
+        .colours
+        .iter()
+        .map(|c| DominantColour { rgb: c.rgb, percentage: c.percentage })
+        .collect();
+    let len = colours.len();
+    result.write(DominantColoursResult {
+        colours: Box::into_raw(colours) as *mut DominantColour,
+        len,
+        inertia: analysis.inertia,
+        silhouette: analysis.silhouette.unwrap_or(f64::NAN),
+    });
+    0
+}
+
+/// Free the colours of a result filled in by `dominant_colours_extract`. Safe to call
+/// twice; the result is reset to empty.
+///
+/// # Safety
+///
+/// `result` must be NULL or point to a result produced by `dominant_colours_extract`.
+#[no_mangle]
+pub unsafe extern "C" fn dominant_colours_result_free(result: *mut DominantColoursResult) {
+    let Some(result) = result.as_mut() else { return };
+    if !result.colours.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(result.colours, result.len)));
+    }
+    result.colours = ptr::null_mut();
+    result.len = 0;
+}
+
+/// Message describing the last failure on this thread, or NULL if there was none. The
+/// string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn dominant_colours_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::mem::MaybeUninit;
+
+    #[test]
+    fn test_extract() {
+        let img = image::RgbImage::from_fn(20, 20, |_, _| image::Rgb([0, 128, 255]));
+        let mut png = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png).unwrap();
+
+        let options = DominantColoursOptions { colours: 1, silhouette: false, lab: true, linear: false };
+        let mut result = MaybeUninit::uninit();
+        let status = unsafe { dominant_colours_extract(png.as_ptr(), png.len(), &options, result.as_mut_ptr()) };
+        assert_eq!(status, 0);
+
+        let mut result = unsafe { result.assume_init() };
+        assert_eq!(result.len, 1);
+        let colour = unsafe { &*result.colours };
+        assert_eq!(colour.rgb, [0, 128, 255]);
+        assert!(result.silhouette.is_nan());
+
+        unsafe { dominant_colours_result_free(&mut result) };
+        assert!(result.colours.is_null());
+    }
+
+    #[test]
+    fn test_extract_error() {
+        let data = b"not an image";
+        let mut result = MaybeUninit::uninit();
+        let status = unsafe { dominant_colours_extract(data.as_ptr(), data.len(), ptr::null(), result.as_mut_ptr()) };
+        assert_eq!(status, -1);
+        let message = unsafe { CStr::from_ptr(dominant_colours_last_error()) };
+        assert!(message.to_str().unwrap().contains("decode"));
+    }
+}
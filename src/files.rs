@@ -0,0 +1,77 @@
+//! Finding image files for the commands that work over many images.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// File extensions the `image` crate can decode, plus SVG, read as vector art, and PDF,
+/// rendered a page at a time.
+const IMAGE_EXTENSIONS: [&str; 14] = [
+    "jpg", "jpeg", "png", "gif", "bmp", "tif", "tiff", "webp", "ico", "tga", "pnm", "ppm", "svg", "pdf",
+];
+
+pub fn is_image_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// All image files under `dir`, recursively, in sorted order.
+pub fn collect_images(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if is_image_path(&path) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Expand a list of files and directories into image files: directories are searched
+/// recursively, files are kept as given whatever their extension.
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for input in inputs {
+        let path = PathBuf::from(input);
+        if path.is_dir() {
+            found.extend(collect_images(&path)?);
+        } else {
+            found.push(path);
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_collect_images() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let nested = temp_dir.path().join("nested");
+        std::fs::create_dir(&nested)?;
+        std::fs::write(temp_dir.path().join("b.PNG"), b"")?;
+        std::fs::write(temp_dir.path().join("notes.txt"), b"")?;
+        std::fs::write(nested.join("a.jpg"), b"")?;
+
+        let found = collect_images(temp_dir.path())?;
+        let names: Vec<_> = found.iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, ["b.PNG", "a.jpg"]);
+
+        let inputs = [temp_dir.path().join("notes.txt"), nested.clone()]
+            .map(|p| p.to_str().unwrap().to_string());
+        let expanded = expand_inputs(&inputs)?;
+        assert_eq!(expanded, [temp_dir.path().join("notes.txt"), nested.join("a.jpg")]);
+        Ok(())
+    }
+}
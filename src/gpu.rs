@@ -0,0 +1,422 @@
+//! k-means in wgpu compute shaders.
+//!
+//! Each Lloyd iteration is two passes: one assigns every pixel to its nearest centroid,
+//! the next moves each centroid to the mean of its pixels. The pixels are uploaded once
+//! and stay on the device for the whole fit, and only the centroid shifts come back,
+//! every `ITERATIONS_PER_CHECK` iterations, to test for convergence.
+//!
+//! The device is opened on first use and kept for the life of the process, so batches
+//! and video frames only pay for it once. Shaders work in `f32`, which is ample for
+//! colour values in 0-255. Without a usable adapter, or for inputs too large for one
+//! storage buffer, callers get `None` and cluster on the CPU instead.
+
+use ndarray::Array2;
+use std::sync::OnceLock;
+use tracing::{debug, warn};
+use wgpu::util::DeviceExt;
+
+/// Below this many pixels uploading to the GPU costs more than clustering on the CPU.
+/// The default 150x150 sample is above it.
+pub(crate) const GPU_ABOVE_PIXELS: usize = 1 << 14;
+
+/// Lloyd iterations submitted together before the centroid shift is read back.
+const ITERATIONS_PER_CHECK: usize = 10;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+const SHADER: &str = r#"
+struct Params {
+    pixels: u32,
+    centroids: u32,
+    row: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> pixels: array<f32>;
+@group(0) @binding(2) var<storage, read_write> centroids: array<f32>;
+@group(0) @binding(3) var<storage, read_write> labels: array<u32>;
+@group(0) @binding(4) var<storage, read_write> shifts: array<f32>;
+
+fn pixel(i: u32) -> vec3<f32> {
+    return vec3<f32>(pixels[3u * i], pixels[3u * i + 1u], pixels[3u * i + 2u]);
+}
+
+fn centroid(c: u32) -> vec3<f32> {
+    return vec3<f32>(centroids[3u * c], centroids[3u * c + 1u], centroids[3u * c + 2u]);
+}
+
+@compute @workgroup_size(64)
+fn assign(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.y * params.row + id.x;
+    if (i >= params.pixels) {
+        return;
+    }
+    let p = pixel(i);
+    var best = 3.4e38;
+    var best_index = 0u;
+    for (var c = 0u; c < params.centroids; c++) {
+        let d = p - centroid(c);
+        let distance = dot(d, d);
+        if (distance < best) {
+            best = distance;
+            best_index = c;
+        }
+    }
+    labels[i] = best_index;
+}
+
+var<workgroup> sums: array<vec3<f32>, 256>;
+var<workgroup> counts: array<u32, 256>;
+
+// One workgroup per centroid. As in linfa, the old centroid counts as one more pixel of
+// its cluster, so empty clusters stay where they are.
+@compute @workgroup_size(256)
+fn update(@builtin(workgroup_id) group: vec3<u32>, @builtin(local_invocation_index) local: u32) {
+    let c = group.x;
+    var sum = vec3<f32>(0.0);
+    var count = 0u;
+    for (var i = local; i < params.pixels; i += 256u) {
+        if (labels[i] == c) {
+            sum += pixel(i);
+            count += 1u;
+        }
+    }
+    sums[local] = sum;
+    counts[local] = count;
+    workgroupBarrier();
+    for (var stride = 128u; stride > 0u; stride /= 2u) {
+        if (local < stride) {
+            sums[local] += sums[local + stride];
+            counts[local] += counts[local + stride];
+        }
+        workgroupBarrier();
+    }
+    if (local == 0u) {
+        let old = centroid(c);
+        let mean = (sums[0] + old) / f32(counts[0] + 1u);
+        centroids[3u * c] = mean.x;
+        centroids[3u * c + 1u] = mean.y;
+        centroids[3u * c + 2u] = mean.z;
+        let d = mean - old;
+        shifts[c] = dot(d, d);
+    }
+}
+"#;
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    layout: wgpu::BindGroupLayout,
+    assign: wgpu::ComputePipeline,
+    update: wgpu::ComputePipeline,
+}
+
+static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+
+fn gpu() -> Option<&'static Gpu> {
+    GPU.get_or_init(|| {
+        let gpu = pollster::block_on(open());
+        if gpu.is_none() {
+            warn!("No GPU adapter available, clustering on the CPU");
+        }
+        gpu
+    })
+    .as_ref()
+}
+
+async fn open() -> Option<Gpu> {
+    let instance = wgpu::Instance::default();
+    let adapter = instance.request_adapter(&wgpu::RequestAdapterOptions::default()).await?;
+    debug!(adapter = %adapter.get_info().name, "Opened GPU adapter");
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("dominant-colours"),
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+                memory_hints: wgpu::MemoryHints::Performance,
+            },
+            None,
+        )
+        .await
+        .ok()?;
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("k-means"),
+        source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+    });
+    // Spelled out rather than derived, as each entry point uses only some of the bindings
+    let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("k-means"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            storage(1, true),
+            storage(2, false),
+            storage(3, false),
+            storage(4, false),
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("k-means"),
+        bind_group_layouts: &[&layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = |entry_point| {
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point,
+            compilation_options: Default::default(),
+            cache: None,
+        })
+    };
+    let assign = pipeline("assign");
+    let update = pipeline("update");
+    Some(Gpu { device, queue, layout, assign, update })
+}
+
+/// Pixels and centroids uploaded to the device, with the buffers the passes write.
+struct Job {
+    gpu: &'static Gpu,
+    centroids: wgpu::Buffer,
+    labels: wgpu::Buffer,
+    shifts: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Workgroups dispatched by the assignment pass, as columns and rows
+    groups: (u32, u32),
+    k: u32,
+}
+
+impl Job {
+    fn new(data: &Array2<f64>, centroids: &Array2<f64>) -> Option<Job> {
+        let gpu = gpu()?;
+        let n = data.nrows();
+        let limits = gpu.device.limits();
+        let bytes = (n * 3 * std::mem::size_of::<f32>()) as u64;
+        if bytes > limits.max_storage_buffer_binding_size as u64 || bytes > limits.max_buffer_size {
+            debug!(pixels = n, "Too many pixels for one GPU buffer, clustering on the CPU");
+            return None;
+        }
+
+        let pixels: Vec<f32> = data.iter().map(|&v| v as f32).collect();
+        let means: Vec<f32> = centroids.iter().map(|&v| v as f32).collect();
+        let k = centroids.nrows() as u32;
+        let groups = (n as u32).div_ceil(WORKGROUP_SIZE);
+        let columns = groups.min(limits.max_compute_workgroups_per_dimension);
+        let rows = groups.div_ceil(columns);
+        let params = [n as u32, k, columns * WORKGROUP_SIZE, 0];
+
+        let device = &gpu.device;
+        let storage = |label, contents: &[u8], usage| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor { label: Some(label), contents, usage })
+        };
+        let params = storage("params", bytemuck::cast_slice(&params), wgpu::BufferUsages::UNIFORM);
+        let pixels = storage("pixels", bytemuck::cast_slice(&pixels), wgpu::BufferUsages::STORAGE);
+        let centroids = storage(
+            "centroids",
+            bytemuck::cast_slice(&means),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+        let output = |label, size| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        };
+        let labels = output("labels", (n * std::mem::size_of::<u32>()) as u64);
+        let shifts = output("shifts", k as u64 * std::mem::size_of::<f32>() as u64);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &gpu.layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: pixels.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: centroids.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: labels.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: shifts.as_entire_binding() },
+            ],
+        });
+        Some(Job { gpu, centroids, labels, shifts, bind_group, groups: (columns, rows), k })
+    }
+
+    fn encoder(&self) -> wgpu::CommandEncoder {
+        self.gpu.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None })
+    }
+
+    /// Label every pixel with its nearest centroid.
+    fn assign(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
+        pass.set_pipeline(&self.gpu.assign);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups(self.groups.0, self.groups.1, 1);
+    }
+
+    /// Move every centroid to the mean of its pixels, recording how far each moved.
+    fn update(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
+        pass.set_pipeline(&self.gpu.update);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups(self.k, 1, 1);
+    }
+
+    /// Submit `encoder` and read `buffer` back once its passes have run.
+    fn read<T: bytemuck::Pod>(&self, mut encoder: wgpu::CommandEncoder, buffer: &wgpu::Buffer) -> Option<Vec<T>> {
+        let device = &self.gpu.device;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, buffer.size());
+        self.gpu.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        if let Err(e) = receiver.recv().ok()? {
+            warn!(error = %e, "Reading GPU results failed, clustering on the CPU");
+            return None;
+        }
+        let values = bytemuck::pod_collect_to_vec(&slice.get_mapped_range());
+        readback.unmap();
+        Some(values)
+    }
+
+    fn labels(&self, encoder: wgpu::CommandEncoder) -> Option<Vec<usize>> {
+        let labels: Vec<u32> = self.read(encoder, &self.labels)?;
+        Some(labels.into_iter().map(|l| l as usize).collect())
+    }
+}
+
+/// Index of the nearest centroid to each row of `data`, or `None` if the GPU can't be used.
+pub(crate) fn nearest_centroids(data: &Array2<f64>, centroids: &Array2<f64>) -> Option<Vec<usize>> {
+    let job = Job::new(data, centroids)?;
+    let mut encoder = job.encoder();
+    job.assign(&mut encoder);
+    job.labels(encoder)
+}
+
+/// Lloyd's algorithm from `initial`, stopping after `max_iterations` or once the centroids
+/// move less than `tolerance` (Euclidean, over all of them) in an iteration, as linfa
+/// does. Returns the centroids and each row's nearest one, or `None` if the GPU can't be
+/// used.
+pub(crate) fn kmeans(
+    data: &Array2<f64>,
+    initial: &Array2<f64>,
+    max_iterations: usize,
+    tolerance: f64,
+) -> Option<(Array2<f64>, Vec<usize>)> {
+    let job = Job::new(data, initial)?;
+    let mut iterations = 0;
+    while iterations < max_iterations {
+        let batch = ITERATIONS_PER_CHECK.min(max_iterations - iterations);
+        let mut encoder = job.encoder();
+        for _ in 0..batch {
+            job.assign(&mut encoder);
+            job.update(&mut encoder);
+        }
+        iterations += batch;
+        let shifts: Vec<f32> = job.read(encoder, &job.shifts)?;
+        if shifts.iter().map(|&s| s as f64).sum::<f64>().sqrt() < tolerance {
+            break;
+        }
+    }
+    debug!(iterations, "Finished k-means on the GPU");
+    let centroids: Vec<f32> = job.read(job.encoder(), &job.centroids)?;
+    let centroids = Array2::from_shape_vec(initial.raw_dim(), centroids.into_iter().map(f64::from).collect()).ok()?;
+    let mut encoder = job.encoder();
+    job.assign(&mut encoder);
+    Some((centroids, job.labels(encoder)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linfa::traits::Fit;
+    use linfa::Dataset;
+    use linfa_clustering::{KMeans, KMeansInit};
+    use std::time::Instant;
+
+    fn bands(n: usize) -> Array2<f64> {
+        Array2::from_shape_fn((n, 3), |(i, c)| ((i % 5) * 50 + (i * 37 + c * 101) % 20) as f64 + 0.25)
+    }
+
+    fn initial() -> Array2<f64> {
+        Array2::from_shape_vec((3, 3), vec![0.0, 0.0, 0.0, 100.0, 100.0, 100.0, 250.0, 0.0, 0.0]).unwrap()
+    }
+
+    /// linfa's Lloyd iterations from `initial`, for comparison
+    fn cpu_kmeans(data: &Array2<f64>, initial: &Array2<f64>) -> Array2<f64> {
+        let dataset = Dataset::new(data.clone(), ndarray::Array1::<f64>::zeros(data.nrows()));
+        let kmeans = KMeans::params(initial.nrows())
+            .init_method(KMeansInit::Precomputed(initial.clone()))
+            .n_runs(1)
+            .max_n_iterations(100)
+            .fit(&dataset)
+            .unwrap();
+        kmeans.centroids().clone()
+    }
+
+    #[test]
+    fn test_gpu_matches_cpu() {
+        let data = Array2::from_shape_fn((1000, 3), |(i, c)| ((i * 37 + c * 101) % 256) as f64 + 0.25);
+        // Machines without an adapter fall back, which is also correct
+        if let Some(labels) = nearest_centroids(&data, &initial()) {
+            assert_eq!(labels, crate::cluster::cpu_nearest_centroids(&data, &initial()));
+        }
+    }
+
+    #[test]
+    fn test_gpu_kmeans_matches_linfa() {
+        let data = bands(5000);
+        if let Some((centroids, labels)) = kmeans(&data, &initial(), 100, 1e-4) {
+            let expected = cpu_kmeans(&data, &initial());
+            for (a, b) in centroids.iter().zip(expected.iter()) {
+                assert!((a - b).abs() < 0.01, "{centroids} != {expected}");
+            }
+            assert_eq!(labels, crate::cluster::cpu_nearest_centroids(&data, &centroids));
+        }
+    }
+
+    /// `cargo test --release --features gpu -- --ignored --nocapture gpu_speedup`
+    #[test]
+    #[ignore = "timing; needs a GPU adapter"]
+    fn gpu_speedup() {
+        for n in [GPU_ABOVE_PIXELS, crate::DEFAULT_MAX_PIXELS, 250_000, 4_000_000] {
+            let data = bands(n);
+            let start = Instant::now();
+            cpu_kmeans(&data, &initial());
+            let cpu = start.elapsed();
+            let start = Instant::now();
+            let gpu = kmeans(&data, &initial(), 100, 1e-4);
+            let gpu_time = start.elapsed();
+            assert!(gpu.is_some(), "no GPU adapter");
+            println!("{n:>8} pixels: CPU {:>8.1} ms, GPU {:>8.1} ms", cpu.as_secs_f64() * 1000.0, gpu_time.as_secs_f64() * 1000.0);
+        }
+    }
+}
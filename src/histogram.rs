@@ -0,0 +1,247 @@
+//! Histograms of the clustered pixels: a continuous view of an image's colour content
+//! to go alongside the discrete palette.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::Write;
+
+use crate::{colour, numbers};
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum HistogramKind {
+    /// Hue around the colour wheel, weighted by saturation
+    Hue,
+    /// Red, green and blue channel values
+    Rgb,
+    /// Rec. 709 luma of the gamma-encoded values, for exposure
+    Luma,
+}
+
+impl std::fmt::Display for HistogramKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistogramKind::Hue => write!(f, "hue"),
+            HistogramKind::Rgb => write!(f, "rgb"),
+            HistogramKind::Luma => write!(f, "luma"),
+        }
+    }
+}
+
+/// Bins around the hue circle, 10 degrees each.
+pub const HUE_BINS: usize = 36;
+
+/// Bins of channel and luma histograms, one per 8-bit level.
+pub const LEVELS: usize = 256;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Histogram {
+    /// Share (percent) of the saturation-weighted pixels with hue in each bin; bin `i`
+    /// covers `i * 10` up to `(i + 1) * 10` degrees. All zero for a greyscale image.
+    Hue(Vec<f64>),
+    /// Percentage of pixels at each level of the red, green and blue channels
+    Rgb([Vec<f64>; 3]),
+    /// Percentage of pixels at each luma level
+    Luma(Vec<f64>),
+}
+
+impl Histogram {
+    pub fn kind(&self) -> HistogramKind {
+        match self {
+            Histogram::Hue(_) => HistogramKind::Hue,
+            Histogram::Rgb(_) => HistogramKind::Rgb,
+            Histogram::Luma(_) => HistogramKind::Luma,
+        }
+    }
+}
+
+/// Histogram of RGB pixel values.
+pub fn compute(kind: HistogramKind, values: &[[f64; 3]]) -> Histogram {
+    match kind {
+        HistogramKind::Hue => Histogram::Hue(hue(values)),
+        HistogramKind::Rgb => Histogram::Rgb(std::array::from_fn(|c| levels(values.iter().map(|v| v[c])))),
+        HistogramKind::Luma => {
+            Histogram::Luma(levels(values.iter().map(|v| 0.2126 * v[0] + 0.7152 * v[1] + 0.0722 * v[2])))
+        }
+    }
+}
+
+/// Percentage of `values` (0-255) at each 8-bit level.
+fn levels(values: impl Iterator<Item = f64>) -> Vec<f64> {
+    let mut bins = vec![0.0; LEVELS];
+    let mut count = 0;
+    for value in values {
+        bins[(value.round() as usize).min(LEVELS - 1)] += 1.0;
+        count += 1;
+    }
+    if count > 0 {
+        bins.iter_mut().for_each(|b| *b *= 100.0 / count as f64);
+    }
+    bins
+}
+
+/// Grey pixels have no meaningful hue, so each pixel counts in proportion to its HSV
+/// saturation and pure greys not at all.
+fn hue(values: &[[f64; 3]]) -> Vec<f64> {
+    let mut bins = vec![0.0; HUE_BINS];
+    for value in values {
+        let [hue, saturation, _] = colour::rgb_to_hsv(value.map(|c| c.round() as u8));
+        let bin = ((hue / 360.0 * HUE_BINS as f64) as usize).min(HUE_BINS - 1);
+        bins[bin] += saturation / 100.0;
+    }
+    let total: f64 = bins.iter().sum();
+    if total > 0.0 {
+        bins.iter_mut().for_each(|b| *b *= 100.0 / total);
+    }
+    bins
+}
+
+/// Save the histograms as an SVG, one panel per histogram, stacked vertically.
+pub fn save_svg(histograms: &[Histogram], output_file: &str) -> Result<()> {
+    const WIDTH: f64 = 400.0;
+    let mut body = String::new();
+    let mut height = 0.0;
+    for histogram in histograms {
+        let (panel, panel_height) = match histogram {
+            Histogram::Hue(bins) => (hue_panel(bins, WIDTH), WIDTH),
+            Histogram::Rgb(channels) => {
+                let series: Vec<_> = channels.iter().zip(["#e03030", "#30a030", "#3050e0"]).collect();
+                (levels_panel(&series, WIDTH, LEVELS_HEIGHT), LEVELS_HEIGHT)
+            }
+            Histogram::Luma(bins) => (levels_panel(&[(bins, "#606060")], WIDTH, LEVELS_HEIGHT), LEVELS_HEIGHT),
+        };
+        body.push_str(&format!("\n    <g transform=\"translate(0, {})\">{}\n    </g>", height, panel));
+        height += panel_height;
+    }
+
+    let svg = format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}">{}
+</svg>"#,
+        WIDTH, height, body
+    );
+    let mut file = File::create(output_file)?;
+    file.write_all(svg.as_bytes())?;
+    Ok(())
+}
+
+/// Height of an RGB or luma panel.
+const LEVELS_HEIGHT: f64 = 200.0;
+
+/// Level histograms as translucent filled curves over a shared axis, dark levels on
+/// the left, scaled so the tallest bin of any series reaches the top.
+fn levels_panel(series: &[(&Vec<f64>, &str)], width: f64, height: f64) -> String {
+    const MARGIN: f64 = 10.0;
+    let max = series.iter().flat_map(|(bins, _)| bins.iter().copied()).fold(0.0, f64::max);
+    let (plot_width, plot_height) = (width - 2.0 * MARGIN, height - 2.0 * MARGIN);
+    let baseline = MARGIN + plot_height;
+    let mut panel = format!(
+        r##"
+        <rect x="{m}" y="{m}" width="{w}" height="{h}" fill="none" stroke="#cccccc"/>"##,
+        m = MARGIN, w = plot_width, h = plot_height
+    );
+    for (bins, colour) in series {
+        let step = plot_width / (bins.len() - 1).max(1) as f64;
+        let mut points = format!("{:.1},{:.1}", MARGIN, baseline);
+        for (i, &share) in bins.iter().enumerate() {
+            let y = if max > 0.0 { baseline - plot_height * share / max } else { baseline };
+            points.push_str(&format!(" {:.1},{:.1}", MARGIN + i as f64 * step, y));
+        }
+        points.push_str(&format!(" {:.1},{:.1}", MARGIN + plot_width, baseline));
+        panel.push_str(&format!(
+            r#"
+        <polygon points="{}" fill="{c}" fill-opacity="0.35" stroke="{c}"/>"#,
+            points, c = colour
+        ));
+    }
+    panel
+}
+
+/// Polar plot of a hue histogram: one wedge per bin, red at the top and hue increasing
+/// clockwise, the fullest bin reaching the edge.
+fn hue_panel(bins: &[f64], size: f64) -> String {
+    let centre = size / 2.0;
+    let radius = centre - 20.0;
+    let max = bins.iter().copied().fold(0.0, f64::max);
+    let point = |r: f64, degrees: f64| {
+        let angle = degrees.to_radians() - PI / 2.0;
+        (centre + r * angle.cos(), centre + r * angle.sin())
+    };
+
+    let mut panel = format!(
+        r##"
+        <circle cx="{c}" cy="{c}" r="{r}" fill="none" stroke="#cccccc"/>
+        <circle cx="{c}" cy="{c}" r="{h}" fill="none" stroke="#eeeeee"/>"##,
+        c = centre, r = radius, h = radius / 2.0
+    );
+    let step = 360.0 / bins.len() as f64;
+    for (i, &share) in bins.iter().enumerate() {
+        if share <= 0.0 {
+            continue;
+        }
+        let r = radius * share / max;
+        let start = i as f64 * step;
+        let (x0, y0) = point(r, start);
+        let (x1, y1) = point(r, start + step);
+        panel.push_str(&format!(
+            r#"
+        <path d="M {:.1} {:.1} L {:.1} {:.1} A {:.1} {:.1} 0 0 1 {:.1} {:.1} Z" fill="hsl({}, 100%, 50%)"><title>{:.0}-{:.0}°: {}%</title></path>"#,
+            centre, centre, x0, y0, r, r, x1, y1, start + step / 2.0, start, start + step, numbers::share(share, 1)
+        ));
+    }
+    panel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_hue_histogram() -> Result<()> {
+        // Saturated red counts fully, half-saturated blue half as much, grey not at all
+        let values = [[255.0, 0.0, 0.0], [255.0, 0.0, 0.0], [128.0, 128.0, 255.0], [90.0, 90.0, 90.0]];
+        let Histogram::Hue(bins) = compute(HistogramKind::Hue, &values) else { panic!("expected hue") };
+        assert_eq!(bins.len(), HUE_BINS);
+        assert!((bins.iter().sum::<f64>() - 100.0).abs() < 1e-9);
+        assert!((bins[0] - 100.0 * 2.0 / 2.5).abs() < 0.5);
+        assert!(bins[24] > 0.0 && bins[24] < bins[0]);
+
+        let Histogram::Hue(grey) = compute(HistogramKind::Hue, &[[90.0, 90.0, 90.0]]) else { panic!("expected hue") };
+        assert!(grey.iter().all(|&b| b == 0.0));
+
+        let dir = tempdir()?;
+        let path = dir.path().join("hue.svg");
+        save_svg(&[Histogram::Hue(bins)], path.to_str().unwrap())?;
+        let svg = std::fs::read_to_string(path)?;
+        assert!(svg.contains("<path") && svg.contains("hsl(5, 100%, 50%)"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_level_histograms() -> Result<()> {
+        let values = [[255.0, 0.0, 0.0], [255.0, 128.0, 0.0], [0.0, 0.0, 0.0], [255.0, 255.0, 255.0]];
+        let rgb = compute(HistogramKind::Rgb, &values);
+        let Histogram::Rgb([r, g, b]) = &rgb else { panic!("expected rgb") };
+        assert_eq!(r.len(), LEVELS);
+        assert_eq!((r[255], r[0]), (75.0, 25.0));
+        assert_eq!((g[0], g[128], g[255]), (50.0, 25.0, 25.0));
+        assert_eq!(b[0], 75.0);
+
+        let luma = compute(HistogramKind::Luma, &values);
+        let Histogram::Luma(bins) = &luma else { panic!("expected luma") };
+        assert_eq!((bins[0], bins[54], bins[255]), (25.0, 25.0, 25.0));
+        assert!((bins.iter().sum::<f64>() - 100.0).abs() < 1e-9);
+
+        // Panels stack in the order given
+        let dir = tempdir()?;
+        let path = dir.path().join("levels.svg");
+        save_svg(&[rgb, luma], path.to_str().unwrap())?;
+        let svg = std::fs::read_to_string(path)?;
+        assert!(svg.contains(r#"viewBox="0 0 400 400""#));
+        assert_eq!(svg.matches("<polygon").count(), 4);
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+//! Palette index: palettes stored in SQLite so images can be searched by colour.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+
+use crate::colour::{self, Metric};
+use crate::ColourInfo;
+
+/// Open (creating if needed) a palette index database.
+pub fn open(path: &str) -> Result<Connection> {
+    let conn = Connection::open(path)
+        .with_context(|| format!("Failed to open palette index {}", path))?;
+    conn.execute_batch(
+        "PRAGMA foreign_keys = ON;
+         CREATE TABLE IF NOT EXISTS images (
+             id INTEGER PRIMARY KEY,
+             path TEXT NOT NULL UNIQUE
+         );
+         CREATE TABLE IF NOT EXISTS colours (
+             image_id INTEGER NOT NULL REFERENCES images(id) ON DELETE CASCADE,
+             r INTEGER NOT NULL,
+             g INTEGER NOT NULL,
+             b INTEGER NOT NULL,
+             lab_l REAL NOT NULL,
+             lab_a REAL NOT NULL,
+             lab_b REAL NOT NULL,
+             percentage REAL NOT NULL
+         );
+         CREATE INDEX IF NOT EXISTS colours_lab ON colours (lab_l, lab_a, lab_b);",
+    )?;
+    Ok(conn)
+}
+
+/// Store the palette for `path`, replacing any earlier entry for the same file.
+pub fn insert(conn: &mut Connection, path: &str, colours: &[ColourInfo]) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM images WHERE path = ?1", params![path])?;
+    tx.execute("INSERT INTO images (path) VALUES (?1)", params![path])?;
+    let image_id = tx.last_insert_rowid();
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO colours (image_id, r, g, b, lab_l, lab_a, lab_b, percentage)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for c in colours {
+            let [l, a, b] = colour::rgb_to_lab(c.rgb);
+            stmt.execute(params![image_id, c.rgb[0], c.rgb[1], c.rgb[2], l, a, b, c.percentage])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// One image that contains a colour close to the query.
+#[derive(Debug)]
+pub struct Hit {
+    pub path: String,
+    /// Share of the image (percent) in colours within the tolerance
+    pub coverage: f64,
+    /// Difference from the closest matching colour, by the query's metric
+    pub delta_e: f64,
+}
+
+/// Images containing colours within `tolerance` of `rgb` by `metric`, ranked by coverage.
+pub fn query(conn: &Connection, rgb: [u8; 3], tolerance: f64, limit: usize, metric: Metric) -> Result<Vec<Hit>> {
+    let [l, a, b] = colour::rgb_to_lab(rgb);
+    // A bounding box in Lab lets SQLite use the index; the exact test by `metric` follows.
+    let radius = search_radius(rgb, tolerance, metric);
+    let mut stmt = conn.prepare(
+        "SELECT images.path, colours.r, colours.g, colours.b, colours.percentage
+         FROM colours JOIN images ON images.id = colours.image_id
+         WHERE lab_l BETWEEN ?1 AND ?2 AND lab_a BETWEEN ?3 AND ?4 AND lab_b BETWEEN ?5 AND ?6",
+    )?;
+    let rows = stmt.query_map(
+        params![l - radius, l + radius, a - radius, a + radius, b - radius, b + radius],
+        |row| Ok((row.get::<_, String>(0)?, [row.get(1)?, row.get(2)?, row.get(3)?], row.get::<_, f64>(4)?)),
+    )?;
+
+    let mut hits: Vec<Hit> = Vec::new();
+    for row in rows {
+        let (path, found, percentage) = row?;
+        let delta_e = metric.distance(rgb, found);
+        if delta_e > tolerance {
+            continue;
+        }
+        match hits.iter_mut().find(|h| h.path == path) {
+            Some(hit) => {
+                hit.coverage += percentage;
+                hit.delta_e = hit.delta_e.min(delta_e);
+            }
+            None => hits.push(Hit { path, coverage: percentage, delta_e }),
+        }
+    }
+    hits.sort_by(|x, y| y.coverage.total_cmp(&x.coverage));
+    hits.truncate(limit);
+    Ok(hits)
+}
+
+/// Half the side of a Lab box holding every colour within `tolerance` of `rgb` by
+/// `metric`. CIE94 divides differences by at most 1 + 0.045 times the reference's chroma;
+/// CIEDE2000 and RGB distance give no useful bound, so their box holds everything.
+fn search_radius(rgb: [u8; 3], tolerance: f64, metric: Metric) -> f64 {
+    match metric {
+        Metric::Cie76 => tolerance,
+        Metric::Cie94 => {
+            let [_, a, b] = colour::rgb_to_lab(rgb);
+            tolerance * (1.0 + 0.045 * a.hypot(b))
+        }
+        Metric::Ciede2000 | Metric::Euclidean => f64::INFINITY,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_query() -> Result<()> {
+        let mut conn = open(":memory:")?;
+        insert(&mut conn, "sunset.jpg", &[ColourInfo::new([255, 100, 0], 40.0), ColourInfo::new([250, 110, 10], 20.0)])?;
+        insert(&mut conn, "logo.png", &[ColourInfo::new([255, 102, 0], 10.0), ColourInfo::new([0, 0, 0], 90.0)])?;
+        insert(&mut conn, "sea.jpg", &[ColourInfo::new([0, 80, 200], 100.0)])?;
+
+        let hits = query(&conn, [255, 102, 0], 15.0, 10, Metric::Cie76)?;
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].path, "sunset.jpg");
+        assert_eq!(hits[0].coverage, 60.0);
+        assert_eq!(hits[1].path, "logo.png");
+        assert_eq!(hits[1].delta_e, 0.0);
+
+        // Re-indexing an image replaces its palette
+        insert(&mut conn, "sunset.jpg", &[ColourInfo::new([0, 0, 255], 100.0)])?;
+        let hits = query(&conn, [255, 102, 0], 15.0, 10, Metric::Cie76)?;
+        assert_eq!(hits.len(), 1);
+
+        // A vivid red 13.7 apart in b*, but close by the more forgiving metrics
+        insert(&mut conn, "poster.jpg", &[ColourInfo::new([255, 0, 40], 100.0)])?;
+        assert!(query(&conn, [255, 0, 0], 10.0, 10, Metric::Cie76)?.is_empty());
+        for metric in [Metric::Cie94, Metric::Ciede2000] {
+            let hits = query(&conn, [255, 0, 0], 10.0, 10, metric)?;
+            assert_eq!(hits.len(), 1, "{}", metric);
+            assert!((hits[0].delta_e - metric.distance([255, 0, 0], [255, 0, 40])).abs() < 1e-9);
+        }
+        Ok(())
+    }
+}
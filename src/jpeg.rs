@@ -0,0 +1,105 @@
+//! CMYK JPEGs, as saved for print, which need more care than the general decoder gives.
+//!
+//! Adobe applications store CMYK inverted and mark the file with an APP14 segment; the
+//! decoder undoes the inversion whether or not the mark is there, so files from other
+//! writers come out as negatives. The inks are then converted through the image's
+//! embedded ICC profile with the `icc` feature, and otherwise by the naive formula, with
+//! a warning that the colours are approximate.
+
+use std::io::Read;
+
+use image::{DynamicImage, ImageError, RgbImage};
+use tracing::debug;
+
+use crate::{cmyk, DominantColoursError};
+
+/// Whether `reader` holds a JPEG with four colour components (CMYK or YCCK).
+pub fn is_cmyk(reader: impl Read) -> bool {
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+    decoder.read_info().is_ok() && decoder.info().is_some_and(|info| info.pixel_format == jpeg_decoder::PixelFormat::CMYK32)
+}
+
+/// Decode a CMYK JPEG to RGB, with a warning when the conversion had to guess.
+pub fn decode_cmyk(filename: &str, data: &[u8]) -> Result<(DynamicImage, Option<String>), DominantColoursError> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let mut inks = decoder.decode().map_err(|e| {
+        DominantColoursError::image(Some(filename), ImageError::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, e)))
+    })?;
+    let info = decoder.info().expect("decode succeeded");
+    let adobe = has_adobe_marker(data);
+    if !adobe {
+        inks.iter_mut().for_each(|ink| *ink = 255 - *ink);
+    }
+    let profile = decoder.icc_profile();
+    debug!(file = filename, adobe, profile = profile.is_some(), "Converting CMYK JPEG");
+    let (rgb, warning) = to_rgb(&inks, adobe, profile.as_deref());
+    let image = RgbImage::from_raw(info.width as u32, info.height as u32, rgb).expect("an RGB pixel per CMYK pixel");
+    Ok((DynamicImage::ImageRgb8(image), warning))
+}
+
+/// RGB for `inks`, through `profile` where it can be used, and a warning naming anything
+/// guessed: the naive conversion, or inks taken as not inverted for want of an Adobe mark.
+fn to_rgb(inks: &[u8], adobe: bool, profile: Option<&[u8]>) -> (Vec<u8>, Option<String>) {
+    let mut guesses = Vec::new();
+    if !adobe {
+        guesses.push("has no Adobe marker, so its inks were taken as not inverted");
+    }
+    let rgb = match profile.and_then(|profile| cmyk::profile_to_srgb(profile, inks)) {
+        Some(rgb) => rgb,
+        None => {
+            guesses.push(match profile {
+                Some(_) if !cfg!(feature = "icc") => "was converted naively, as its ICC profile needs the `icc` feature",
+                Some(_) => "was converted naively, as its ICC profile is not a usable CMYK profile",
+                None => "was converted naively, having no ICC profile",
+            });
+            inks.chunks_exact(4).flat_map(|p| cmyk::naive_to_rgb([p[0], p[1], p[2], p[3]])).collect()
+        }
+    };
+    let warning = (!guesses.is_empty()).then(|| format!("CMYK JPEG {}; colours are approximate", guesses.join(" and ")));
+    (rgb, warning)
+}
+
+/// Whether the JPEG has an Adobe APP14 segment before its image data.
+fn has_adobe_marker(data: &[u8]) -> bool {
+    let mut i = 2;
+    while i + 4 <= data.len() && data[i] == 0xFF {
+        let marker = data[i + 1];
+        if marker == 0xFF {
+            // Fill byte
+            i += 1;
+            continue;
+        }
+        if marker == 0xDA {
+            break;
+        }
+        if marker == 0xEE && data[i + 4..].starts_with(b"Adobe") {
+            return true;
+        }
+        i += 2 + u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmyk_conversion() {
+        let comment = [0xFF, 0xFE, 0x00, 0x04, b'h', b'i'];
+        let adobe = [0xFF, 0xEE, 0x00, 0x0E, b'A', b'd', b'o', b'b', b'e', 0, 100, 0, 0, 0, 0, 0];
+        let scan = [0xFF, 0xDA, 0x00, 0x02];
+        assert!(has_adobe_marker(&[&[0xFF, 0xD8][..], &comment, &adobe, &scan].concat()));
+        assert!(!has_adobe_marker(&[&[0xFF, 0xD8][..], &comment, &scan, &adobe].concat()));
+
+        // Pure cyan ink and no ink
+        let inks = [255, 0, 0, 0, 0, 0, 0, 0];
+        let (rgb, warning) = to_rgb(&inks, true, None);
+        assert_eq!(rgb, [0, 255, 255, 255, 255, 255]);
+        assert!(warning.unwrap().contains("no ICC profile"));
+        let (_, warning) = to_rgb(&inks, false, Some(b"not a profile"));
+        let warning = warning.unwrap();
+        assert!(warning.contains("no Adobe marker") && warning.contains("ICC profile"));
+        assert!(!is_cmyk(&b"\x89PNG\r\n\x1a\n"[..]));
+    }
+}
@@ -0,0 +1,1737 @@
+//! Dominant colour extraction using k-means clustering.
+//!
+//! The `dominant-colours` binary is a thin command line front end over this crate; the
+//! same analysis is available to other Rust code, to C through the `ffi` feature, to
+//! Python through the `python` feature and, with the `wasm` feature, to JavaScript
+//! through `wasm-bindgen`.
+
+use clap::ValueEnum;
+use linfa::Dataset;
+use linfa::traits::Fit;
+use linfa_clustering::{KMeans, KMeansInit};
+use ndarray::{Array2, Array1, Axis};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use colour::Metric;
+use histogram::{Histogram, HistogramKind};
+use mask::MaskMode;
+use vividness::Vividness;
+
+pub mod archive;
+pub mod batch;
+pub mod bench;
+pub mod cache;
+pub mod candidates;
+pub mod cluster;
+pub mod cmyk;
+pub mod colour;
+pub mod compare;
+pub mod compliance;
+pub mod contrast;
+pub mod cvd;
+pub mod dedupe;
+pub mod diversity;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod files;
+#[cfg(feature = "gpu")]
+mod gpu;
+pub mod histogram;
+#[cfg(feature = "index")]
+pub mod index;
+pub mod jpeg;
+pub mod lqip;
+pub mod mask;
+pub mod mix;
+pub mod multiscale;
+pub mod names;
+pub mod numbers;
+pub mod palette;
+pub mod pdf;
+mod preprocess;
+#[cfg(feature = "python")]
+mod python;
+pub mod quantize;
+pub mod regions;
+#[cfg(feature = "remote")]
+pub mod remote;
+mod stream;
+pub mod svg;
+pub mod theme;
+pub mod timeline;
+pub mod tracking;
+pub mod transform;
+pub mod vividness;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use error::DominantColoursError;
+
+/// Options that control how an image is analysed, shared by every palette-extracting command.
+#[derive(clap::Args, Clone, Debug)]
+pub struct AnalysisOptions {
+    /// Number of colours to extract, from 1 to 256
+    #[arg(short, long, global = true, default_value_t = 6, value_parser = count_parser(1..=MAX_COLOURS as u64))]
+    pub colours: usize,
+
+    /// Report only the N most prevalent colours; clustering with more colours than are
+    /// reported often gives a better palette
+    #[arg(long, global = true, value_name = "N", value_parser = count_parser(1..))]
+    pub top: Option<usize>,
+
+    /// Most pixels to cluster; larger images are downsampled to fit
+    #[arg(long, global = true, default_value_t = DEFAULT_MAX_PIXELS, value_parser = count_parser(1..))]
+    pub max_pixels: usize,
+
+    /// Compute the silhouette coefficient of the clustering (sampled; slower)
+    #[arg(long, global = true, conflicts_with = "exact")]
+    pub silhouette: bool,
+
+    /// Colour space to cluster in; Lab groups colours the way people perceive them
+    #[arg(long, global = true, default_value_t = ColourSpace::Rgb)]
+    pub space: ColourSpace,
+
+    /// Resize (and, in RGB, cluster) in linear light instead of gamma-encoded sRGB,
+    /// so averaged colours are not darkened
+    #[arg(long, global = true)]
+    pub linear: bool,
+
+    /// How k-means picks its starting centroids; k-means++ is more stable at higher counts
+    #[arg(long, global = true, default_value_t = InitMethod::KmeansPlusPlus, conflicts_with = "exact")]
+    pub init: InitMethod,
+
+    /// Order of the reported colours, in text, JSON and swatches
+    #[arg(long, global = true, default_value_t = ColourOrder::Percentage)]
+    pub sort: ColourOrder,
+
+    /// Reverse the --sort order
+    #[arg(long, global = true)]
+    pub reverse: bool,
+
+    /// Also compute histograms of the clustered pixels (comma-separated)
+    #[arg(long, global = true, value_delimiter = ',', value_name = "KIND")]
+    pub histogram: Vec<HistogramKind>,
+
+    /// Also measure the whole image's colourfulness (Hasler-Süsstrunk), mean saturation
+    /// and contrast, for sorting or filtering images by vividness
+    #[arg(long, global = true)]
+    pub vividness: bool,
+
+    /// Stretch the luminance range to full black and white before clustering, so a dark
+    /// or hazy photo isn't reduced to near-black or washed-out clusters
+    #[arg(long, alias = "normalize", global = true)]
+    pub auto_levels: bool,
+
+    /// Gamma adjustment before clustering, after any --auto-levels: values above 1 lift
+    /// the shadows of an underexposed photo, below 1 deepen them
+    #[arg(long, global = true, value_name = "GAMMA", value_parser = parse_positive)]
+    pub gamma: Option<f64>,
+
+    /// Filter out sensor noise and JPEG artefacts before downsampling, so they don't pull
+    /// the colours of small areas towards muddy mixtures
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub denoise: Option<Denoise>,
+
+    /// Leave out likely skin-tone pixels before clustering, so portraits give the palette
+    /// of the clothes and background
+    #[arg(long, global = true)]
+    pub exclude_skin: bool,
+
+    /// Mask of the part of the image to take colours from, e.g. a segmentation or
+    /// background-removal output: greyscale, white over the subject, or a cut-out whose
+    /// transparency marks the background. Stretched to the image's size
+    #[arg(long, global = true, value_name = "FILE")]
+    pub mask: Option<String>,
+
+    /// How --mask values count: weighting soft edges partly, or gating each pixel in or out
+    #[arg(long, global = true, default_value_t = MaskMode::Weight, requires = "mask")]
+    pub mask_mode: MaskMode,
+
+    /// Page of a PDF input to analyse, from 1 (rendered with Poppler's pdftoppm)
+    #[arg(long, global = true, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub page: u32,
+
+    /// What to extract: clustered colours or brightness bands, or skip clustering for a
+    /// single average or median colour
+    #[arg(long, global = true, default_value_t = AnalysisMode::Colour)]
+    pub mode: AnalysisMode,
+
+    /// Count unique colours instead of clustering, for pixel art, logos and screenshots.
+    /// Colours within TOLERANCE delta-E of a more common one are counted as that colour
+    #[arg(
+        long,
+        global = true,
+        value_name = "TOLERANCE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0",
+        value_parser = parse_non_negative,
+        conflicts_with = "mode"
+    )]
+    pub exact: Option<f64>,
+
+    /// Colour difference used wherever colours are matched: merging with --exact,
+    /// snapping to candidates and palettes, comparing palettes and naming colours.
+    /// Delta-E thresholds are in this metric's units
+    #[arg(long, global = true, default_value_t = Metric::Cie76)]
+    pub metric: Metric,
+}
+
+/// Most colours an analysis extracts; beyond this clustering is slow and the palette
+/// is no longer a summary.
+pub const MAX_COLOURS: usize = 256;
+
+/// A `usize` command line value in `range`.
+fn count_parser(range: impl std::ops::RangeBounds<u64>) -> clap::builder::RangedU64ValueParser<usize> {
+    clap::builder::RangedU64ValueParser::new().range(range)
+}
+
+/// Parse a command line number greater than zero, such as a gamma.
+pub fn parse_positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        _ => Err(format!("{:?} is not a number greater than zero", s)),
+    }
+}
+
+/// Parse a command line number of zero or more, such as a delta-E threshold.
+pub fn parse_non_negative(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value >= 0.0 && value.is_finite() => Ok(value),
+        _ => Err(format!("{:?} is not a number of 0 or more", s)),
+    }
+}
+
+impl AnalysisOptions {
+    /// Number of colours an analysis reports when the image has enough of them.
+    pub fn reported_colours(&self) -> usize {
+        match self.mode {
+            AnalysisMode::Average | AnalysisMode::Median => 1,
+            _ => self.top.map_or(self.colours, |top| top.min(self.colours)),
+        }
+    }
+}
+
+impl Default for AnalysisOptions {
+    /// The command line defaults.
+    fn default() -> Self {
+        AnalysisOptions {
+            colours: 6,
+            top: None,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            silhouette: false,
+            space: ColourSpace::Rgb,
+            linear: false,
+            init: InitMethod::KmeansPlusPlus,
+            sort: ColourOrder::Percentage,
+            reverse: false,
+            histogram: Vec::new(),
+            vividness: false,
+            auto_levels: false,
+            gamma: None,
+            denoise: None,
+            exclude_skin: false,
+            mask: None,
+            mask_mode: MaskMode::Weight,
+            page: 1,
+            mode: AnalysisMode::Colour,
+            exact: None,
+            metric: Metric::Cie76,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum ColourSpace {
+    Rgb,
+    Lab,
+}
+
+impl ColourSpace {
+    /// Short channel names, e.g. for labelling per-channel statistics.
+    pub fn channels(self) -> [&'static str; 3] {
+        match self {
+            ColourSpace::Rgb => ["R", "G", "B"],
+            ColourSpace::Lab => ["L", "a", "b"],
+        }
+    }
+}
+
+impl std::fmt::Display for ColourSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColourSpace::Rgb => write!(f, "rgb"),
+            ColourSpace::Lab => write!(f, "lab"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum AnalysisMode {
+    /// Dominant colours
+    Colour,
+    /// Dominant brightness bands: clusters CIE lightness only and reports each band as
+    /// the grey of its lightness, for exposure checks and black-and-white work
+    Luma,
+    /// One colour, the mean of every pixel (in linear light with `--linear`)
+    Average,
+    /// One colour, the per-channel median of every pixel
+    Median,
+}
+
+impl std::fmt::Display for AnalysisMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalysisMode::Colour => write!(f, "colour"),
+            AnalysisMode::Luma => write!(f, "luma"),
+            AnalysisMode::Average => write!(f, "average"),
+            AnalysisMode::Median => write!(f, "median"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum Denoise {
+    /// 3x3 median: removes specks and keeps edges
+    Median,
+    /// Edge-preserving smoothing: averages nearby pixels of similar colour
+    Bilateral,
+}
+
+impl std::fmt::Display for Denoise {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Denoise::Median => write!(f, "median"),
+            Denoise::Bilateral => write!(f, "bilateral"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum InitMethod {
+    /// Spread the starting centroids out, favouring pixels far from those already chosen
+    #[value(name = "kmeans++")]
+    KmeansPlusPlus,
+    /// Start from randomly chosen pixels
+    Random,
+}
+
+impl std::fmt::Display for InitMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InitMethod::KmeansPlusPlus => write!(f, "kmeans++"),
+            InitMethod::Random => write!(f, "random"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum ColourOrder {
+    /// Most prevalent first
+    Percentage,
+    /// Around the colour wheel from red, with greys last from light to dark
+    Hue,
+    /// Lightest first
+    Luminance,
+    /// Most saturated first
+    Saturation,
+    /// A short path, by `--metric`, from the lightest colour, so each colour sits next
+    /// to similar ones and swatches change gradually
+    Path,
+}
+
+impl std::fmt::Display for ColourOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColourOrder::Percentage => write!(f, "percentage"),
+            ColourOrder::Hue => write!(f, "hue"),
+            ColourOrder::Luminance => write!(f, "luminance"),
+            ColourOrder::Saturation => write!(f, "saturation"),
+            ColourOrder::Path => write!(f, "path"),
+        }
+    }
+}
+
+/// HSV saturation below which a colour has no meaningful hue.
+const GREY_SATURATION: f64 = 0.05;
+
+/// Put colours in the given order, or its reverse. `metric` measures the steps of a path.
+pub fn sort_colours(colours: &mut [ColourInfo], order: ColourOrder, reverse: bool, metric: Metric) {
+    // Greys go after every coloured entry when sorting by hue
+    let key = |c: &ColourInfo| match order {
+        ColourOrder::Percentage => (false, -c.percentage),
+        ColourOrder::Hue if colour::saturation(c.rgb) < GREY_SATURATION => (true, -colour::relative_luminance(c.rgb)),
+        ColourOrder::Hue => (false, colour::rgb_to_hsl(c.rgb)[0]),
+        // The path starts from the lightest colour
+        ColourOrder::Luminance | ColourOrder::Path => (false, -colour::relative_luminance(c.rgb)),
+        ColourOrder::Saturation => (false, -colour::saturation(c.rgb)),
+    };
+    colours.sort_by(|a, b| {
+        let (a, b) = (key(a), key(b));
+        a.0.cmp(&b.0).then(a.1.total_cmp(&b.1))
+    });
+    if order == ColourOrder::Path {
+        shorten_path(colours, metric);
+    }
+    if reverse {
+        colours.reverse();
+    }
+}
+
+/// Reorder `colours[1..]` so that walking the palette from `colours[0]` covers a short
+/// total difference by `metric`: the nearest unvisited colour each step, then 2-opt,
+/// reversing any stretch of the path that makes it shorter, until no reversal helps.
+fn shorten_path(colours: &mut [ColourInfo], metric: Metric) {
+    for i in 1..colours.len() {
+        let last = colours[i - 1].rgb;
+        let next = (i..colours.len())
+            .min_by(|&a, &b| metric.distance(last, colours[a].rgb).total_cmp(&metric.distance(last, colours[b].rgb)))
+            .unwrap();
+        colours.swap(i, next);
+    }
+
+    let distance = |a: &ColourInfo, b: &ColourInfo| metric.distance(a.rgb, b.rgb);
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for i in 1..colours.len() {
+            for j in i + 1..colours.len() {
+                // Reversing i..=j swaps edges (i-1, i) and (j, j+1) for (i-1, j) and (i, j+1)
+                let before = distance(&colours[i - 1], &colours[i])
+                    + colours.get(j + 1).map_or(0.0, |next| distance(&colours[j], next));
+                let after = distance(&colours[i - 1], &colours[j])
+                    + colours.get(j + 1).map_or(0.0, |next| distance(&colours[i], next));
+                if after < before - 1e-9 {
+                    colours[i..=j].reverse();
+                    improved = true;
+                }
+            }
+        }
+    }
+}
+
+/// The colour covering the largest share of the image, whatever order the palette is in.
+pub fn most_prevalent(colours: &[ColourInfo]) -> Option<&ColourInfo> {
+    colours.iter().min_by(|a, b| b.percentage.total_cmp(&a.percentage))
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum AggregateMode {
+    /// Cluster the pooled pixels of every image
+    Pixels,
+    /// Cluster the per-image palettes, weighted by image size
+    Palettes,
+}
+
+/// An extracted colour and the share of the image its cluster covers.
+#[derive(Clone, Debug)]
+pub struct ColourInfo {
+    pub rgb: [u8; 3],
+    pub percentage: f64,
+    /// Per-channel standard deviation of the cluster's pixels around its centroid,
+    /// in the channels of the clustering space (see [`ColourSpace::channels`])
+    pub std_dev: [f64; 3],
+    /// Spread of the cluster's pixels in hue, saturation and lightness (see [`HslSpread`]),
+    /// whatever the clustering space
+    pub hsl_spread: [f64; 3],
+    /// Original-image coordinates of the pixel closest to the centroid
+    pub location: Option<(u32, u32)>,
+}
+
+impl ColourInfo {
+    pub fn new(rgb: [u8; 3], percentage: f64) -> Self {
+        ColourInfo { rgb, percentage, std_dev: [0.0; 3], hsl_spread: [0.0; 3], location: None }
+    }
+}
+
+/// Running totals for how far a cluster's pixels spread in hue, saturation and lightness,
+/// so a tight cluster of one brand blue can be told from a loose one of assorted darks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HslSpread {
+    weight: f64,
+    /// Sums of each pixel's hue as a unit vector, weighted by its saturation so that
+    /// greys, whose hue means nothing, don't count
+    hue: [f64; 2],
+    chroma_weight: f64,
+    /// Sums and sums of squares of saturation and lightness
+    sums: [f64; 2],
+    squares: [f64; 2],
+}
+
+impl HslSpread {
+    /// Count `weight` pixels of colour `rgb`.
+    pub fn add(&mut self, rgb: [u8; 3], weight: f64) {
+        let [hue, saturation, lightness] = colour::rgb_to_hsl(rgb);
+        let chroma = saturation / 100.0 * weight;
+        self.hue[0] += chroma * hue.to_radians().cos();
+        self.hue[1] += chroma * hue.to_radians().sin();
+        self.chroma_weight += chroma;
+        self.weight += weight;
+        for (i, value) in [saturation, lightness].into_iter().enumerate() {
+            self.sums[i] += value * weight;
+            self.squares[i] += value * value * weight;
+        }
+    }
+
+    /// Fold in the pixels of another cluster.
+    pub fn merge(&mut self, other: &HslSpread) {
+        self.weight += other.weight;
+        self.chroma_weight += other.chroma_weight;
+        for i in 0..2 {
+            self.hue[i] += other.hue[i];
+            self.sums[i] += other.sums[i];
+            self.squares[i] += other.squares[i];
+        }
+    }
+
+    /// Angular deviation of hue in degrees (0 for one hue, at most about 81 for hues all
+    /// round the wheel), and standard deviations of saturation and lightness in percent.
+    pub fn finish(&self) -> [f64; 3] {
+        if self.weight == 0.0 {
+            return [0.0; 3];
+        }
+        let hue = if self.chroma_weight > 0.0 {
+            let resultant = (self.hue[0].hypot(self.hue[1]) / self.chroma_weight).min(1.0);
+            (2.0 * (1.0 - resultant)).sqrt().to_degrees()
+        } else {
+            0.0
+        };
+        let std_dev = |i: usize| {
+            let mean = self.sums[i] / self.weight;
+            (self.squares[i] / self.weight - mean * mean).max(0.0).sqrt()
+        };
+        [hue, std_dev(0), std_dev(1)]
+    }
+}
+
+/// Result of analysing one image.
+#[derive(Debug)]
+pub struct Analysis {
+    /// Colours in the order asked for by `AnalysisOptions::sort`, by default most prevalent first
+    pub colours: Vec<ColourInfo>,
+    /// Sum of squared distances from each pixel to its cluster centroid
+    pub inertia: f64,
+    /// Silhouette coefficient, when requested and defined
+    pub silhouette: Option<f64>,
+    pub timings: Timings,
+    /// Problems that did not stop the analysis, e.g. fewer distinct colours than requested
+    pub warnings: Vec<String>,
+    /// The image is effectively monochrome, so the colours are grey levels (as they
+    /// always are with `AnalysisMode::Luma`)
+    pub greyscale: bool,
+    /// Histograms asked for by `AnalysisOptions::histogram`, in the same order
+    pub histograms: Vec<Histogram>,
+    /// Vividness of the clustered pixels, if `AnalysisOptions::vividness` asked for it
+    pub vividness: Option<Vividness>,
+    /// Pixels clustered, after downsampling and masking; each colour's `percentage` is
+    /// its share of these
+    pub sampled_pixels: usize,
+    /// Pixel count of the original image(s), before resizing
+    pub source_pixels: u64,
+}
+
+impl Analysis {
+    /// Clustered pixels nearest `colour`.
+    pub fn pixel_count(&self, colour: &ColourInfo) -> u64 {
+        (colour.percentage / 100.0 * self.sampled_pixels as f64).round() as u64
+    }
+
+    /// Estimated area of `colour` in the original image(s), in pixels, assuming the
+    /// sample is representative of the whole.
+    pub fn area(&self, colour: &ColourInfo) -> u64 {
+        (colour.percentage / 100.0 * self.source_pixels as f64).round() as u64
+    }
+}
+
+/// Wall-clock time spent in each stage of an analysis.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timings {
+    pub decode: Duration,
+    pub resize: Duration,
+    /// Building the clustering input, including conversion to the clustering colour space
+    pub convert: Duration,
+    pub fit: Duration,
+    /// Assigning pixels to clusters and gathering the per-cluster statistics
+    pub predict: Duration,
+    pub silhouette: Duration,
+}
+
+impl Timings {
+    /// Stage names and durations, in pipeline order.
+    pub fn stages(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("decode", self.decode),
+            ("resize", self.resize),
+            ("convert", self.convert),
+            ("fit", self.fit),
+            ("predict", self.predict),
+            ("silhouette", self.silhouette),
+        ]
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages().iter().map(|(_, d)| *d).sum()
+    }
+}
+
+impl std::ops::AddAssign for Timings {
+    fn add_assign(&mut self, other: Timings) {
+        self.decode += other.decode;
+        self.resize += other.resize;
+        self.convert += other.convert;
+        self.fit += other.fit;
+        self.predict += other.predict;
+        self.silhouette += other.silhouette;
+    }
+}
+
+pub fn rgb_to_hex(rgb: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+/// Pick the colour that works best as a highlight against the rest of the palette.
+///
+/// The most prevalent colour is treated as the background. Every other colour is
+/// scored on its saturation, its prevalence and its distance from the background;
+/// the square root damps prevalence so a small vivid cluster can beat a large dull one.
+pub fn select_accent(colours: &[ColourInfo]) -> Option<&ColourInfo> {
+    let background = most_prevalent(colours)?;
+    colours
+        .iter()
+        .filter(|c| !std::ptr::eq(*c, background))
+        .map(|c| {
+            let score = colour::saturation(c.rgb)
+                * (c.percentage / 100.0).sqrt()
+                * colour::rgb_distance(c.rgb, background.rgb);
+            (c, score)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(c, _)| c)
+        .or(Some(background))
+}
+
+/// Prevalence-weighted mean of the palette, averaged in linear light.
+pub fn palette_mean(colours: &[ColourInfo]) -> Option<[u8; 3]> {
+    let total: f64 = colours.iter().map(|c| c.percentage).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let mut sum = [0.0; 3];
+    for c in colours {
+        for (s, &channel) in sum.iter_mut().zip(c.rgb.iter()) {
+            *s += colour::srgb_to_linear(channel) * c.percentage;
+        }
+    }
+    Some(sum.map(|s| colour::linear_to_srgb(s / total)))
+}
+
+/// Pixels sampled for the silhouette score, which is quadratic in the sample size.
+pub const SILHOUETTE_SAMPLES: usize = 2000;
+
+/// Pixels ready for clustering, as f64 RGB triples.
+pub struct Pixels {
+    pub values: Vec<[f64; 3]>,
+    /// Original-image coordinates of each value, when they come from a single image
+    pub positions: Option<Vec<(u32, u32)>>,
+    /// Pixel count of the original image(s), before resizing
+    pub source_pixels: u64,
+    /// Time spent producing these pixels (decode, resize, convert)
+    pub timings: Timings,
+    /// Guesses made reading the colours, e.g. CMYK converted without a profile
+    pub warnings: Vec<String>,
+}
+
+pub fn analyze_image(filename: &str, options: &AnalysisOptions) -> Result<Analysis, DominantColoursError> {
+    if svg::is_svg(filename) && options.mode == AnalysisMode::Colour && options.exact.is_none() {
+        // Vector art has exact colours to count rather than blend
+        let options = AnalysisOptions { exact: Some(0.0), ..options.clone() };
+        return cluster_pixels(load_pixels(filename, &options)?, &options);
+    }
+    cluster_pixels(load_pixels(filename, options)?, options)
+}
+
+pub fn load_pixels(filename: &str, options: &AnalysisOptions) -> Result<Pixels, DominantColoursError> {
+    info!(file = filename, "Loading image");
+    if svg::is_svg(filename) {
+        return svg::pixels(filename, &read_input(filename)?);
+    }
+    let start = Instant::now();
+    // Sampling while decoding averages pixels, which would make up colours to count, and
+    // would leave nothing for --denoise to filter. Images in archives and at URLs are
+    // decoded from memory.
+    if options.exact.is_none() && options.denoise.is_none() && !in_memory(filename) && !pdf::is_pdf(filename) {
+        if let Some(mut sampled) = stream::sample_file(filename, sample_side(options.max_pixels), options.linear)? {
+            let timings = Timings { decode: start.elapsed(), ..Timings::default() };
+            preprocess::apply(&mut sampled.image, options);
+            let mut pixels = sampled_pixels(&sampled.image, sampled.width, sampled.height, timings);
+            apply_mask(&mut pixels, sampled.width, sampled.height, options)?;
+            return Ok(pixels);
+        }
+    }
+    let (img, warnings) = if pdf::is_pdf(filename) {
+        // Rendered a little larger than the sample, so downsampling still smooths it
+        (pdf::render_page(filename, options.page, 2 * sample_side(options.max_pixels))?, Vec::new())
+    } else {
+        decode_image(filename)?
+    };
+    let decode = start.elapsed();
+    let mut pixels = image_pixels(&img, options);
+    pixels.timings.decode = decode;
+    pixels.warnings = warnings;
+    apply_mask(&mut pixels, img.width(), img.height(), options)?;
+    Ok(pixels)
+}
+
+/// Apply `options.mask`, if any, to pixels sampled from a `width` x `height` image.
+pub(crate) fn apply_mask(pixels: &mut Pixels, width: u32, height: u32, options: &AnalysisOptions) -> Result<(), DominantColoursError> {
+    match &options.mask {
+        Some(path) => mask::apply(pixels, &mask::load(path)?, width, height, options.mask_mode),
+        None => Ok(()),
+    }
+}
+
+/// Whether `filename` is read into memory before decoding rather than opened as a file.
+fn in_memory(filename: &str) -> bool {
+    #[cfg(feature = "remote")]
+    if remote::is_remote(filename) {
+        return true;
+    }
+    archive::split_member(filename).is_some()
+}
+
+/// Decode an image file, an image in an archive (`photos.zip!cat.jpg`, see `archive`) or,
+/// with the `remote` feature, an `s3://` object or `http(s)://` URL.
+/// CMYK JPEGs are converted as `jpeg` describes.
+pub fn open_image(filename: &str) -> Result<image::DynamicImage, DominantColoursError> {
+    decode_image(filename).map(|(img, _)| img)
+}
+
+/// Decode an image as `open_image` does, with a warning for each guess made about its colours.
+fn decode_image(filename: &str) -> Result<(image::DynamicImage, Vec<String>), DominantColoursError> {
+    let mut warnings = Vec::new();
+    let img = if !in_memory(filename) {
+        let cmyk = std::fs::File::open(filename).is_ok_and(|file| jpeg::is_cmyk(std::io::BufReader::new(file)));
+        if cmyk {
+            let (img, warning) = jpeg::decode_cmyk(filename, &read_input(filename)?)?;
+            warnings.extend(warning);
+            img
+        } else {
+            image::open(filename).map_err(|e| DominantColoursError::image(Some(filename), e))?
+        }
+    } else {
+        let data = read_input(filename)?;
+        if jpeg::is_cmyk(&data[..]) {
+            let (img, warning) = jpeg::decode_cmyk(filename, &data)?;
+            warnings.extend(warning);
+            img
+        } else {
+            image::load_from_memory(&data).map_err(|e| DominantColoursError::image(Some(filename), e))?
+        }
+    };
+    warnings.extend(clipping_warning(&img));
+    for message in &warnings {
+        warn!(file = filename, "{}", message);
+    }
+    Ok((img, warnings))
+}
+
+/// A warning for floating-point (HDR) pixels brighter than white, which the 8-bit
+/// analysis clips, losing the colour of bright highlights.
+fn clipping_warning(img: &image::DynamicImage) -> Option<String> {
+    let values = match img {
+        image::DynamicImage::ImageRgb32F(img) => img.as_raw(),
+        image::DynamicImage::ImageRgba32F(img) => img.as_raw(),
+        _ => return None,
+    };
+    let clipped = values.iter().filter(|&&v| v > 1.0).count();
+    (clipped > 0).then(|| {
+        format!("{:.1}% of the high dynamic range values are brighter than white and were clipped", clipped as f64 * 100.0 / values.len() as f64)
+    })
+}
+
+/// The bytes of an image file, archive member or URL.
+fn read_input(filename: &str) -> Result<Vec<u8>, DominantColoursError> {
+    match archive::split_member(filename) {
+        Some((archive, entry)) => archive::read_member(archive, entry),
+        #[cfg(feature = "remote")]
+        None if remote::is_remote(filename) => remote::fetch(filename),
+        None => std::fs::read(filename).map_err(|source| DominantColoursError::Io { path: Some(filename.to_string()), source }),
+    }
+}
+
+/// Pixels clustered per image by default: a 150x150 box.
+pub const DEFAULT_MAX_PIXELS: usize = 150 * 150;
+
+/// Largest image read into memory whole: an archive entry, an `s3://` or `http(s)://`
+/// input, or an image sent to or fetched by the HTTP API.
+pub const MAX_IMAGE_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Rough bytes used per clustered pixel: its value and position, the data array and
+/// linfa's copy of it, and the cluster assignments.
+const BYTES_PER_SAMPLE: u64 = 96;
+
+/// Most memory clustering may take, whatever `--max-pixels` asks for.
+pub const MAX_CLUSTER_MEMORY: u64 = 2 << 30;
+
+/// Estimated memory needed to cluster `pixels` pixels.
+pub fn cluster_memory(pixels: u64) -> u64 {
+    pixels * BYTES_PER_SAMPLE
+}
+
+/// Side of the square box images are downsampled into, so at most `max_pixels` are
+/// clustered and the clustering stays within `MAX_CLUSTER_MEMORY`.
+fn sample_side(max_pixels: usize) -> u32 {
+    let affordable = MAX_CLUSTER_MEMORY / BYTES_PER_SAMPLE;
+    let budget = if max_pixels as u64 > affordable {
+        warn!(
+            "--max-pixels {} would need about {} MiB to cluster; using {} pixels",
+            max_pixels,
+            cluster_memory(max_pixels as u64) >> 20,
+            affordable
+        );
+        affordable
+    } else {
+        max_pixels as u64
+    };
+    ((budget as f64).sqrt() as u32).max(1)
+}
+
+/// Downsample a decoded image into clustering input.
+pub fn image_pixels(img: &image::DynamicImage, options: &AnalysisOptions) -> Pixels {
+    let side = sample_side(options.max_pixels);
+    debug!(width = img.width(), height = img.height(), side, linear = options.linear, "Resizing image");
+    let start = Instant::now();
+    let mut resized = sample_image(img, side, options);
+    preprocess::apply(&mut resized, options);
+    let timings = Timings { resize: start.elapsed(), ..Timings::default() };
+    sampled_pixels(&resized, img.width(), img.height(), timings)
+}
+
+/// Clustering input from a downsampled image, with positions in the `width` x `height` original.
+fn sampled_pixels(resized: &image::RgbImage, width: u32, height: u32, mut timings: Timings) -> Pixels {
+    debug!(
+        pixels = resized.len() / 3,
+        memory_mib = cluster_memory(resized.len() as u64 / 3) >> 20,
+        "Estimated clustering memory"
+    );
+
+    // Map resized coordinates back to the centre of the matching region in the original
+    let scale_x = width as f64 / resized.width() as f64;
+    let scale_y = height as f64 / resized.height() as f64;
+    let to_original = |x: u32, y: u32| {
+        (
+            (((x as f64 + 0.5) * scale_x) as u32).min(width - 1),
+            (((y as f64 + 0.5) * scale_y) as u32).min(height - 1),
+        )
+    };
+
+    debug!("Converting to pixels");
+    let start = Instant::now();
+    let (values, positions) = resized.enumerate_pixels()
+        .map(|(x, y, rgb)| ([
+            rgb[0] as f64,
+            rgb[1] as f64,
+            rgb[2] as f64,
+        ], to_original(x, y)))
+        .unzip();
+    timings.convert = start.elapsed();
+
+    Pixels {
+        values,
+        positions: Some(positions),
+        source_pixels: width as u64 * height as u64,
+        timings,
+        warnings: Vec::new(),
+    }
+}
+
+/// Resize to fit a `side` square, in linear light if asked.
+pub(crate) fn downsample(img: &image::DynamicImage, side: u32, linear: bool) -> image::RgbImage {
+    if linear {
+        resize_linear(img, side)
+    } else {
+        img.resize(side, side, image::imageops::FilterType::Lanczos3).to_rgb8()
+    }
+}
+
+/// Fit a `side` square the way `options` ask: denoised first if requested, then picking
+/// pixels for `--exact` or resampling otherwise.
+pub(crate) fn sample_image(img: &image::DynamicImage, side: u32, options: &AnalysisOptions) -> image::RgbImage {
+    let denoised;
+    let img = match options.denoise {
+        Some(method) => {
+            denoised = image::DynamicImage::ImageRgb8(preprocess::denoise(&img.to_rgb8(), method));
+            &denoised
+        }
+        None => img,
+    };
+    if options.exact.is_some() {
+        pick_pixels(img, side)
+    } else {
+        downsample(img, side, options.linear)
+    }
+}
+
+/// Fit a `side` square by picking pixels instead of blending them, so that no new colours
+/// appear. Images with no more pixels than the square are kept whole.
+fn pick_pixels(img: &image::DynamicImage, side: u32) -> image::RgbImage {
+    if img.width() as u64 * img.height() as u64 <= side as u64 * side as u64 {
+        img.to_rgb8()
+    } else {
+        img.resize(side, side, image::imageops::FilterType::Nearest).to_rgb8()
+    }
+}
+
+/// Resize with the filter working on linear-light values, then encode back to sRGB.
+fn resize_linear(img: &image::DynamicImage, side: u32) -> image::RgbImage {
+    let srgb = img.to_rgb8();
+    let linear = image::Rgb32FImage::from_fn(srgb.width(), srgb.height(), |x, y| {
+        image::Rgb(srgb.get_pixel(x, y).0.map(|c| colour::srgb_to_linear(c) as f32))
+    });
+    let resized = image::DynamicImage::ImageRgb32F(linear)
+        .resize(side, side, image::imageops::FilterType::Lanczos3)
+        .into_rgb32f();
+    image::RgbImage::from_fn(resized.width(), resized.height(), |x, y| {
+        image::Rgb(resized.get_pixel(x, y).0.map(|c| colour::linear_to_srgb(c as f64)))
+    })
+}
+
+/// Pseudo-pixels generated when clustering per-image palettes together.
+pub const AGGREGATE_SAMPLES: usize = DEFAULT_MAX_PIXELS;
+
+/// Build one palette representing a whole set of images.
+pub fn aggregate_images(
+    filenames: &[String],
+    mode: AggregateMode,
+    options: &AnalysisOptions,
+) -> Result<Analysis, DominantColoursError> {
+    let inputs: Vec<(String, f64)> = filenames.iter().map(|filename| (filename.clone(), 1.0)).collect();
+    aggregate_weighted(&inputs, mode, options)
+}
+
+/// Build one palette representing a whole set of images, each counting `weight` times
+/// as much as an image of weight 1 would. With `AggregateMode::Pixels`, lighter images
+/// contribute an evenly spaced subset of their pixels; with `AggregateMode::Palettes`,
+/// the weight scales the share each image's colours stand in for.
+pub fn aggregate_weighted(
+    inputs: &[(String, f64)],
+    mode: AggregateMode,
+    options: &AnalysisOptions,
+) -> Result<Analysis, DominantColoursError> {
+    if let Some((filename, weight)) = inputs.iter().find(|(_, weight)| !(*weight > 0.0 && weight.is_finite())) {
+        return Err(DominantColoursError::InvalidOptions(format!("weight {} of {} must be greater than zero", weight, filename)));
+    }
+    let mut loaded = Vec::new();
+    let filenames: Vec<&str> = inputs.iter().map(|(filename, _)| filename.as_str()).collect();
+    archive::for_each_input(&filenames, |filename| {
+        loaded.push(load_pixels(filename, options)?);
+        Ok::<_, DominantColoursError>(())
+    })?;
+    let source_pixels = loaded.iter().map(|p| p.source_pixels).sum::<u64>();
+    let warnings = loaded
+        .iter()
+        .zip(inputs)
+        .flat_map(|(p, (filename, _))| p.warnings.iter().map(move |w| format!("{}: {}", filename, w)))
+        .collect();
+    let mut timings = Timings::default();
+    for pixels in &loaded {
+        timings += pixels.timings;
+    }
+    let heaviest = inputs.iter().map(|(_, weight)| *weight).fold(0.0, f64::max);
+    let weighted_source: f64 = loaded.iter().zip(inputs).map(|(p, (_, weight))| p.source_pixels as f64 * weight).sum();
+
+    let sampled_pixels = loaded.iter().map(|p| p.values.len()).sum();
+    let values = match mode {
+        AggregateMode::Pixels => loaded
+            .into_iter()
+            .zip(inputs)
+            .flat_map(|(p, (_, weight))| {
+                let count = (p.values.len() as f64 * weight / heaviest).round() as usize;
+                (0..count).map(move |i| p.values[i * p.values.len() / count])
+            })
+            .collect(),
+        AggregateMode::Palettes => {
+            // Stand each palette colour in for a share of pixels proportional to its
+            // coverage and to the size and weight of the image it came from.
+            let mut values = Vec::new();
+            for (pixels, (_, weight)) in loaded.into_iter().zip(inputs) {
+                let share = pixels.source_pixels as f64 * weight / weighted_source;
+                // Every cluster of every image counts towards the combined palette
+                let per_image = AnalysisOptions { top: None, ..options.clone() };
+                let analysis = cluster_pixels(Pixels { timings: Timings::default(), ..pixels }, &per_image)?;
+                timings += analysis.timings;
+                for colour in analysis.colours {
+                    let count = (AGGREGATE_SAMPLES as f64 * share * colour.percentage / 100.0).round() as usize;
+                    values.extend(std::iter::repeat_n(colour.rgb.map(|c| c as f64), count));
+                }
+            }
+            values
+        }
+    };
+
+    let mut analysis = cluster_pixels(Pixels { values, positions: None, source_pixels, timings, warnings }, options)?;
+    // Palette colours stood in for pseudo-pixels; count the pixels actually sampled
+    if mode == AggregateMode::Palettes {
+        analysis.sampled_pixels = sampled_pixels;
+    }
+    Ok(analysis)
+}
+
+/// A populated cluster, keyed by the colour it is reported as.
+struct Cluster {
+    rgb: [u8; 3],
+    size: usize,
+    squared_deviation: [f64; 3],
+    spread: HslSpread,
+    /// Squared distance and index of the pixel nearest the centroid
+    closest: Option<(f64, usize)>,
+}
+
+/// Lab chroma below which a pixel counts as grey, allowing for noise and compression.
+pub const GREY_CHROMA: f64 = 8.0;
+
+/// Whether nearly all the pixels are grey, so only their lightness is worth clustering.
+pub fn is_greyscale(values: &[[f64; 3]]) -> bool {
+    let grey = values
+        .iter()
+        .filter(|v| {
+            let [_, a, b] = colour::rgb_to_lab(v.map(|c| c.round() as u8));
+            a.hypot(b) < GREY_CHROMA
+        })
+        .count();
+    !values.is_empty() && grey as f64 >= values.len() as f64 * 0.98
+}
+
+pub fn cluster_pixels(pixels: Pixels, options: &AnalysisOptions) -> Result<Analysis, DominantColoursError> {
+    let Pixels { mut values, mut positions, mut timings, source_pixels, mut warnings } = pixels;
+    if !(1..=MAX_COLOURS).contains(&options.colours) {
+        return Err(DominantColoursError::InvalidOptions(format!("colours must be from 1 to {}", MAX_COLOURS)));
+    }
+    if options.top == Some(0) {
+        return Err(DominantColoursError::InvalidOptions("top must be at least 1".to_string()));
+    }
+    if values.is_empty() {
+        return Err(DominantColoursError::NoPixels("no pixels to cluster".to_string()));
+    }
+    if options.gamma.is_some_and(|gamma| !(gamma > 0.0 && gamma.is_finite())) {
+        return Err(DominantColoursError::InvalidOptions("gamma must be greater than zero".to_string()));
+    }
+    if options.exact.is_some_and(|tolerance| !(tolerance >= 0.0 && tolerance.is_finite())) {
+        return Err(DominantColoursError::InvalidOptions("exact tolerance must be a delta-E of 0 or more".to_string()));
+    }
+    if options.exclude_skin {
+        let start = Instant::now();
+        let keep: Vec<bool> = values.iter().map(|v| !colour::is_skin(v.map(|c| c.round() as u8))).collect();
+        let kept = keep.iter().filter(|&&k| k).count();
+        debug!(skin = values.len() - kept, pixels = values.len(), "Masking skin tones");
+        if kept == 0 {
+            // Better a palette of skin than none at all
+            let message = "Every pixel looks like skin; clustering them all".to_string();
+            warn!("{}", message);
+            warnings.push(message);
+        } else {
+            let mut mask = keep.iter();
+            values.retain(|_| *mask.next().unwrap());
+            if let Some(positions) = positions.as_mut() {
+                let mut mask = keep.iter();
+                positions.retain(|_| *mask.next().unwrap());
+            }
+        }
+        timings.convert += start.elapsed();
+    }
+
+    let sampled_pixels = values.len();
+    debug!(pixels = sampled_pixels, space = %options.space, "Preparing data for clustering");
+    let start = Instant::now();
+    let greyscale = is_greyscale(&values);
+    if greyscale {
+        debug!("Image is greyscale; clustering lightness only");
+    }
+    let lightness_only = greyscale || options.mode == AnalysisMode::Luma;
+    let histograms = options.histogram.iter().map(|&kind| histogram::compute(kind, &values)).collect();
+    let vividness = options.vividness.then(|| vividness::compute(&values));
+    if matches!(options.mode, AnalysisMode::Average | AnalysisMode::Median) {
+        timings.convert += start.elapsed();
+        let start = Instant::now();
+        let (colour, inertia) = summary_colour(&values, positions.as_deref(), options);
+        timings.predict += start.elapsed();
+        return Ok(Analysis {
+            colours: vec![colour],
+            inertia,
+            silhouette: None,
+            timings,
+            warnings,
+            greyscale,
+            histograms,
+            vividness,
+            sampled_pixels,
+            source_pixels,
+        });
+    }
+    if let Some(tolerance) = options.exact {
+        timings.convert += start.elapsed();
+        debug!(tolerance, "Counting unique colours");
+        let start = Instant::now();
+        let (mut colours, inertia) = exact_colours(&values, positions.as_deref(), tolerance, options.metric);
+        colours.truncate(options.colours);
+        timings.predict += start.elapsed();
+        finish_palette(&mut colours, options, &mut warnings);
+        return Ok(Analysis {
+            colours,
+            inertia,
+            silhouette: None,
+            timings,
+            warnings,
+            greyscale,
+            histograms,
+            vividness,
+            sampled_pixels,
+            source_pixels,
+        });
+    }
+    let rgb: Vec<[u8; 3]> = values.iter().map(|v| v.map(|c| c.round() as u8)).collect();
+    let values: Vec<[f64; 3]> = match options.space {
+        _ if lightness_only => values.into_iter().map(|v| [colour::rgb_to_lab(v.map(|c| c.round() as u8))[0], 0.0, 0.0]).collect(),
+        ColourSpace::Rgb if options.linear => {
+            values.into_iter().map(|v| v.map(|c| colour::srgb_to_linear(c.round() as u8) * 255.0)).collect()
+        }
+        ColourSpace::Rgb => values,
+        ColourSpace::Lab => values.into_iter().map(|v| colour::rgb_to_lab(v.map(|c| c.round() as u8))).collect(),
+    };
+    let data = Array2::from(values);
+
+    timings.convert += start.elapsed();
+
+    debug!(k = options.colours, init = %options.init, "Running k-means clustering");
+    let start = Instant::now();
+    let (centroids, labels) = fit_kmeans(&data, options)?;
+    timings.fit += start.elapsed();
+
+    debug!("Analyzing clusters");
+    let start = Instant::now();
+    // Get cluster assignments for each pixel
+    let predictions = labels.unwrap_or_else(|| cluster::nearest_centroids(&data, &centroids));
+    let total_pixels = predictions.len() as f64;
+
+    // Count pixels in each cluster, their squared deviation from the centroid,
+    // and which pixel sits closest to the centroid. Deviation is in the clustering space.
+    let mut cluster_sizes = vec![0; options.colours];
+    let mut squared_deviation = vec![[0.0; 3]; options.colours];
+    let mut spread = vec![HslSpread::default(); options.colours];
+    let mut closest: Vec<Option<(f64, usize)>> = vec![None; options.colours];
+    for (i, (pixel, &cluster)) in data.outer_iter().zip(predictions.iter()).enumerate() {
+        cluster_sizes[cluster] += 1;
+        spread[cluster].add(rgb[i], 1.0);
+        let mut distance = 0.0;
+        for c in 0..3 {
+            let sq = (pixel[c] - centroids[[cluster, c]]).powi(2);
+            squared_deviation[cluster][c] += sq;
+            distance += sq;
+        }
+        if closest[cluster].is_none_or(|(best, _)| distance < best) {
+            closest[cluster] = Some((distance, i));
+        }
+    }
+    let inertia = squared_deviation.iter().flatten().sum();
+    timings.predict += start.elapsed();
+
+    let silhouette = if options.silhouette {
+        debug!(samples = SILHOUETTE_SAMPLES, "Computing silhouette score");
+        let start = Instant::now();
+        let score = cluster::silhouette_score(&data, &predictions, options.colours, SILHOUETTE_SAMPLES);
+        timings.silhouette += start.elapsed();
+        score
+    } else {
+        None
+    };
+
+    // Empty clusters have nothing to report, and clusters that settle on the same colour
+    // are one colour split in two, so drop the first and merge the second to keep the
+    // percentages meaningful.
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for (i, cent) in centroids.outer_iter().enumerate() {
+        if cluster_sizes[i] == 0 {
+            continue;
+        }
+        let rgb = match options.space {
+            _ if lightness_only => colour::lab_to_rgb([cent[0], 0.0, 0.0]),
+            ColourSpace::Rgb if options.linear => [cent[0], cent[1], cent[2]].map(|c| colour::linear_to_srgb(c / 255.0)),
+            ColourSpace::Rgb => [cent[0] as u8, cent[1] as u8, cent[2] as u8],
+            ColourSpace::Lab => colour::lab_to_rgb([cent[0], cent[1], cent[2]]),
+        };
+        match clusters.iter_mut().find(|cluster| cluster.rgb == rgb) {
+            Some(cluster) => {
+                cluster.size += cluster_sizes[i];
+                for (total, sq) in cluster.squared_deviation.iter_mut().zip(squared_deviation[i]) {
+                    *total += sq;
+                }
+                cluster.spread.merge(&spread[i]);
+                if closest[i].zip(cluster.closest).is_some_and(|((a, _), (b, _))| a < b) {
+                    cluster.closest = closest[i];
+                }
+            }
+            None => clusters.push(Cluster {
+                rgb,
+                size: cluster_sizes[i],
+                squared_deviation: squared_deviation[i],
+                spread: spread[i],
+                closest: closest[i],
+            }),
+        }
+    }
+
+    // Create vector of ColourInfo with percentages
+    let mut colours: Vec<ColourInfo> = clusters
+        .into_iter()
+        .map(|cluster| ColourInfo {
+            rgb: cluster.rgb,
+            percentage: (cluster.size as f64 / total_pixels) * 100.0,
+            std_dev: cluster.squared_deviation.map(|sq| (sq / cluster.size as f64).sqrt()),
+            hsl_spread: cluster.spread.finish(),
+            location: cluster.closest
+                .zip(positions.as_ref())
+                .map(|((_, index), positions)| positions[index]),
+        })
+        .collect();
+    finish_palette(&mut colours, options, &mut warnings);
+
+    Ok(Analysis {
+        colours,
+        inertia,
+        silhouette,
+        timings,
+        warnings,
+        greyscale,
+        histograms,
+        vividness,
+        sampled_pixels,
+        source_pixels,
+    })
+}
+
+/// Lloyd iterations k-means may run before it stops without converging.
+const KMEANS_ITERATIONS: usize = 100;
+
+/// Centroid movement in one iteration below which k-means has converged (linfa's default).
+const KMEANS_TOLERANCE: f64 = 1e-4;
+
+/// Cluster `data` into `options.colours` centroids, with each row's nearest centroid if
+/// the fit worked it out along the way. With the `gpu` feature, inputs over
+/// `gpu::GPU_ABOVE_PIXELS` are seeded by linfa's best of its ten starts after one
+/// iteration, and the rest of the iterations run on the GPU.
+fn fit_kmeans(data: &Array2<f64>, options: &AnalysisOptions) -> Result<(Array2<f64>, Option<Vec<usize>>), DominantColoursError> {
+    let targets: Array1<f64> = Array1::zeros(data.len_of(Axis(0)));
+    let dataset = Dataset::new(data.clone(), targets);
+    let init = match options.init {
+        InitMethod::KmeansPlusPlus => KMeansInit::KMeansPlusPlus,
+        InitMethod::Random => KMeansInit::Random,
+    };
+    let params = KMeans::params(options.colours).init_method(init).tolerance(KMEANS_TOLERANCE);
+    #[cfg(feature = "gpu")]
+    if data.nrows() > gpu::GPU_ABOVE_PIXELS {
+        let seed = params.clone().max_n_iterations(1).fit(&dataset)?;
+        if let Some((centroids, labels)) = gpu::kmeans(data, seed.centroids(), KMEANS_ITERATIONS - 1, KMEANS_TOLERANCE) {
+            return Ok((centroids, Some(labels)));
+        }
+    }
+    let kmeans = params.max_n_iterations(KMEANS_ITERATIONS as u64).fit(&dataset)?;
+    Ok((kmeans.centroids().clone(), None))
+}
+
+/// Warn if fewer colours were found than requested, then apply `--top` and `--sort`.
+fn finish_palette(colours: &mut Vec<ColourInfo>, options: &AnalysisOptions, warnings: &mut Vec<String>) {
+    if colours.len() < options.reported_colours() {
+        let message = format!(
+            "Only {} of {} colours found; the image has fewer distinct colours than requested",
+            colours.len(), options.reported_colours()
+        );
+        warn!("{}", message);
+        warnings.push(message);
+    }
+    if let Some(top) = options.top {
+        sort_colours(colours, ColourOrder::Percentage, false, options.metric);
+        colours.truncate(top);
+    }
+    sort_colours(colours, options.sort, options.reverse, options.metric);
+}
+
+/// Every distinct colour of `values`, most common first, for `--exact`, and the inertia
+/// of counting merged colours as the one they were merged into. Going down the counts,
+/// a colour within `tolerance` (by `metric`) of one already kept is merged into the most
+/// common such colour.
+fn exact_colours(
+    values: &[[f64; 3]],
+    positions: Option<&[(u32, u32)]>,
+    tolerance: f64,
+    metric: Metric,
+) -> (Vec<ColourInfo>, f64) {
+    // Pixel count and first index of each colour
+    let mut counts: HashMap<[u8; 3], (usize, usize)> = HashMap::new();
+    for (i, v) in values.iter().enumerate() {
+        counts.entry(v.map(|c| c.round() as u8)).or_insert((0, i)).0 += 1;
+    }
+    let mut unique: Vec<([u8; 3], usize, usize)> = counts.into_iter().map(|(rgb, (count, first))| (rgb, count, first)).collect();
+    unique.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let mut groups: Vec<Cluster> = Vec::new();
+    let mut labs: Vec<[f64; 3]> = Vec::new();
+    for (rgb, count, first) in unique {
+        let lab = colour::rgb_to_lab(rgb);
+        let merge_into = (tolerance > 0.0)
+            .then(|| {
+                // Lab-based metrics reuse the kept colours' Lab values rather than converting again
+                labs.iter().zip(&groups).position(|(kept, group)| {
+                    let distance = match metric {
+                        Metric::Euclidean => metric.distance(group.rgb, rgb),
+                        Metric::Cie76 => (0..3).map(|c| (kept[c] - lab[c]).powi(2)).sum::<f64>().sqrt(),
+                        Metric::Cie94 => colour::cie94(*kept, lab),
+                        Metric::Ciede2000 => colour::ciede2000(*kept, lab),
+                    };
+                    distance <= tolerance
+                })
+            })
+            .flatten();
+        match merge_into {
+            Some(g) => {
+                let group = &mut groups[g];
+                group.size += count;
+                for ((sq, c), kept) in group.squared_deviation.iter_mut().zip(rgb).zip(group.rgb) {
+                    *sq += (c as f64 - kept as f64).powi(2) * count as f64;
+                }
+                group.spread.add(rgb, count as f64);
+            }
+            None => {
+                labs.push(lab);
+                let mut spread = HslSpread::default();
+                spread.add(rgb, count as f64);
+                groups.push(Cluster { rgb, size: count, squared_deviation: [0.0; 3], spread, closest: Some((0.0, first)) });
+            }
+        }
+    }
+
+    let inertia = groups.iter().flat_map(|g| g.squared_deviation).sum();
+    let colours = groups
+        .into_iter()
+        .map(|group| ColourInfo {
+            rgb: group.rgb,
+            percentage: group.size as f64 / values.len() as f64 * 100.0,
+            std_dev: group.squared_deviation.map(|sq| (sq / group.size as f64).sqrt()),
+            hsl_spread: group.spread.finish(),
+            location: group.closest.zip(positions).map(|((_, index), positions)| positions[index]),
+        })
+        .collect();
+    (colours, inertia)
+}
+
+/// The single colour of `AnalysisMode::Average` or `Median`, and the inertia of treating
+/// every pixel as that colour.
+fn summary_colour(values: &[[f64; 3]], positions: Option<&[(u32, u32)]>, options: &AnalysisOptions) -> (ColourInfo, f64) {
+    let rgb = if options.mode == AnalysisMode::Median {
+        std::array::from_fn(|c| {
+            let mut channel: Vec<f64> = values.iter().map(|v| v[c]).collect();
+            let middle = channel.len() / 2;
+            *channel.select_nth_unstable_by(middle, f64::total_cmp).1 as u8
+        })
+    } else if options.linear {
+        let sum = values.iter().fold([0.0; 3], |sum, v| std::array::from_fn(|c| sum[c] + colour::srgb_to_linear(v[c].round() as u8)));
+        sum.map(|c| colour::linear_to_srgb(c / values.len() as f64))
+    } else {
+        let sum = values.iter().fold([0.0; 3], |sum, v| std::array::from_fn(|c| sum[c] + v[c]));
+        sum.map(|c| (c / values.len() as f64).round() as u8)
+    };
+
+    let centre = rgb.map(|c| c as f64);
+    let squared_deviation = values
+        .iter()
+        .fold([0.0; 3], |sum, v| std::array::from_fn(|c| sum[c] + (v[c] - centre[c]).powi(2)));
+    let distance = |v: &[f64; 3]| (0..3).map(|c| (v[c] - centre[c]).powi(2)).sum::<f64>();
+    let closest = (0..values.len()).min_by(|&a, &b| distance(&values[a]).total_cmp(&distance(&values[b])));
+    let spread = values.iter().fold(HslSpread::default(), |mut spread, v| {
+        spread.add(v.map(|c| c.round() as u8), 1.0);
+        spread
+    });
+    let colour = ColourInfo {
+        rgb,
+        percentage: 100.0,
+        std_dev: squared_deviation.map(|sq| (sq / values.len() as f64).sqrt()),
+        hsl_spread: spread.finish(),
+        location: closest.zip(positions).map(|(index, positions)| positions[index]),
+    };
+    (colour, squared_deviation.iter().sum())
+}
+
+/// Analyse an encoded image (PNG, JPEG, ...) held in memory.
+pub fn analyze_bytes(bytes: &[u8], options: &AnalysisOptions) -> Result<Analysis, DominantColoursError> {
+    let start = Instant::now();
+    let img = image::load_from_memory(bytes).map_err(|e| DominantColoursError::image(None, e))?;
+    let decode = start.elapsed();
+    let mut pixels = image_pixels(&img, options);
+    pixels.timings.decode = decode;
+    cluster_pixels(pixels, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_rgb_to_hex() {
+        assert_eq!(rgb_to_hex([255, 255, 255]), "#ffffff");
+        assert_eq!(rgb_to_hex([0, 0, 0]), "#000000");
+        assert_eq!(rgb_to_hex([255, 0, 0]), "#ff0000");
+        assert_eq!(rgb_to_hex([0, 255, 0]), "#00ff00");
+        assert_eq!(rgb_to_hex([0, 0, 255]), "#0000ff");
+        assert_eq!(rgb_to_hex([85, 85, 85]), "#555555");
+    }
+
+    #[test]
+    fn test_analyze_bytes() -> Result<()> {
+        let img = image::RgbImage::from_fn(40, 40, |x, _| {
+            if x < 30 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) }
+        });
+        let mut png = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+
+        let options = AnalysisOptions { colours: 2, ..AnalysisOptions::default() };
+        let analysis = analyze_bytes(&png, &options)?;
+        assert!(colour::delta_e(analysis.colours[0].rgb, [255, 0, 0]) < 5.0);
+        assert!((analysis.colours[0].percentage - 75.0).abs() < 5.0);
+
+        assert!(matches!(analyze_bytes(b"not an image", &options), Err(DominantColoursError::Decode { .. })));
+        let none = AnalysisOptions { colours: 0, ..options };
+        assert!(matches!(analyze_bytes(&png, &none), Err(DominantColoursError::InvalidOptions(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pixel_counts() -> Result<()> {
+        // 100 sampled pixels standing for a 1000x1000 original
+        let values = [[250.0, 20.0, 20.0]; 60].into_iter().chain([[20.0, 20.0, 250.0]; 40]).collect();
+        let pixels = Pixels { values, positions: None, source_pixels: 1_000_000, timings: Timings::default(), warnings: Vec::new() };
+        let analysis = cluster_pixels(pixels, &AnalysisOptions { colours: 2, ..AnalysisOptions::default() })?;
+        let counts: Vec<_> = analysis.colours.iter().map(|c| (analysis.pixel_count(c), analysis.area(c))).collect();
+        assert_eq!(counts, [(60, 600_000), (40, 400_000)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_weighted() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut inputs = Vec::new();
+        for (name, rgb, weight) in [("red.png", [255, 0, 0], 3.0), ("blue.png", [0, 0, 255], 1.0)] {
+            let path = dir.path().join(name);
+            image::RgbImage::from_pixel(20, 20, image::Rgb(rgb)).save(&path)?;
+            inputs.push((path.to_str().unwrap().to_string(), weight));
+        }
+        let options = AnalysisOptions { colours: 2, ..AnalysisOptions::default() };
+        for mode in [AggregateMode::Pixels, AggregateMode::Palettes] {
+            let analysis = aggregate_weighted(&inputs, mode, &options)?;
+            assert_eq!(analysis.colours[0].rgb, [255, 0, 0], "{:?}", mode);
+            assert!((analysis.colours[0].percentage - 75.0).abs() < 0.1, "{:?}: {}", mode, analysis.colours[0].percentage);
+        }
+        inputs[1].1 = 0.0;
+        assert!(matches!(aggregate_weighted(&inputs, AggregateMode::Pixels, &options), Err(DominantColoursError::InvalidOptions(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hsl_spread() -> Result<()> {
+        // A tight brand blue and a loose mix of darks of every hue
+        let darks = [[60.0, 10.0, 10.0], [10.0, 60.0, 10.0], [10.0, 10.0, 60.0], [50.0, 50.0, 10.0], [30.0, 30.0, 30.0]];
+        let values = [[20.0, 60.0, 230.0]; 50].into_iter().chain(darks.into_iter().cycle().take(50)).collect();
+        let pixels = Pixels { values, positions: None, source_pixels: 100, timings: Timings::default(), warnings: Vec::new() };
+        let analysis = cluster_pixels(pixels, &AnalysisOptions { colours: 2, ..AnalysisOptions::default() })?;
+        let blue = analysis.colours.iter().find(|c| c.rgb == [20, 60, 230]).unwrap();
+        let dark = analysis.colours.iter().find(|c| c.rgb != [20, 60, 230]).unwrap();
+        assert!(blue.hsl_spread.iter().all(|&s| s < 0.01), "{:?}", blue.hsl_spread);
+        assert!(dark.hsl_spread[0] > 45.0 && dark.hsl_spread[1] > 10.0, "{:?}", dark.hsl_spread);
+
+        // Opposite hues leave no mean direction; greys add no hue spread
+        let mut spread = HslSpread::default();
+        spread.add([255, 0, 0], 1.0);
+        spread.add([0, 255, 255], 1.0);
+        assert!((spread.finish()[0] - 2f64.sqrt().to_degrees()).abs() < 1e-9);
+        let mut spread = HslSpread::default();
+        spread.add([255, 0, 0], 3.0);
+        spread.add([128, 128, 128], 1.0);
+        assert_eq!(spread.finish()[0], 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cluster_lab() -> Result<()> {
+        let values = [[250.0, 20.0, 20.0]; 60].into_iter().chain([[20.0, 20.0, 250.0]; 40]).collect();
+        let pixels = Pixels { values, positions: None, source_pixels: 100, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions { colours: 2, space: ColourSpace::Lab, ..AnalysisOptions::default() };
+        let analysis = cluster_pixels(pixels, &options)?;
+
+        assert_eq!(analysis.colours[0].rgb, [250, 20, 20]);
+        assert_eq!(analysis.colours[0].percentage, 60.0);
+        assert_eq!(analysis.colours[1].rgb, [20, 20, 250]);
+        assert_eq!(analysis.timings.decode, Duration::ZERO);
+        assert!(analysis.timings.fit > Duration::ZERO);
+        assert!(analysis.timings.total() >= analysis.timings.fit);
+        assert!(analysis.warnings.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_linear_resize() -> Result<()> {
+        // Fine black and white stripes average to mid-grey in linear light, not sRGB 128
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(300, 300, |x, _| {
+            if x % 2 == 0 { image::Rgb([0, 0, 0]) } else { image::Rgb([255, 255, 255]) }
+        }));
+        let options = AnalysisOptions { colours: 1, ..AnalysisOptions::default() };
+        let gamma = cluster_pixels(image_pixels(&img, &options), &options)?;
+        let options = AnalysisOptions { linear: true, ..options };
+        let linear = cluster_pixels(image_pixels(&img, &options), &options)?;
+        assert!((gamma.colours[0].rgb[0] as i32 - 128).abs() < 5);
+        assert!((linear.colours[0].rgb[0] as i32 - 188).abs() < 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_greyscale() -> Result<()> {
+        // Slightly tinted greys, as from a scanned black and white photo
+        let values: Vec<[f64; 3]> = (0..100).map(|i| [i as f64 * 2.5, i as f64 * 2.5 + 2.0, i as f64 * 2.5]).collect();
+        assert!(is_greyscale(&values));
+        assert!(!is_greyscale(&[[250.0, 20.0, 20.0]; 10]));
+
+        let pixels = Pixels { values, positions: None, source_pixels: 100, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions { colours: 3, ..AnalysisOptions::default() };
+        let analysis = cluster_pixels(pixels, &options)?;
+        assert!(analysis.greyscale);
+        for colour in &analysis.colours {
+            assert!(colour.rgb[0].abs_diff(colour.rgb[1]) <= 1 && colour.rgb[1].abs_diff(colour.rgb[2]) <= 1);
+        }
+        let mut levels: Vec<u8> = analysis.colours.iter().map(|c| c.rgb[0]).collect();
+        levels.sort();
+        assert!(levels[1] - levels[0] > 40 && levels[2] - levels[1] > 40);
+        Ok(())
+    }
+
+    #[test]
+    fn test_too_few_distinct_colours() -> Result<()> {
+        let pixels = Pixels { values: vec![[10.0, 200.0, 30.0]; 50], positions: None, source_pixels: 50, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions { colours: 3, ..AnalysisOptions::default() };
+        let analysis = cluster_pixels(pixels, &options)?;
+        assert_eq!(analysis.colours.len(), 1);
+        assert_eq!(analysis.colours[0].percentage, 100.0);
+        assert_eq!(analysis.warnings.len(), 1);
+        assert!(analysis.warnings[0].starts_with("Only 1 of 3 colours"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_clusters() -> Result<()> {
+        let values = [[250.0, 20.0, 20.0]; 70].into_iter().chain([[20.0, 20.0, 250.0]; 30]).collect();
+        let pixels = Pixels { values, positions: None, source_pixels: 100, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions { colours: 5, ..AnalysisOptions::default() };
+        let analysis = cluster_pixels(pixels, &options)?;
+        let found: Vec<([u8; 3], f64)> = analysis.colours.iter().map(|c| (c.rgb, c.percentage)).collect();
+        assert_eq!(found, [([250, 20, 20], 70.0), ([20, 20, 250], 30.0)]);
+        assert!(analysis.warnings[0].starts_with("Only 2 of 5 colours"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_skin() -> Result<()> {
+        // A face filling most of the frame in front of a blue wall
+        let values: Vec<[f64; 3]> = [[224.0, 172.0, 140.0]; 80].into_iter().chain([[30.0, 90.0, 200.0]; 20]).collect();
+        let positions = Some((0..100).map(|i| (i, 0)).collect());
+        let pixels = Pixels { values: values.clone(), positions, source_pixels: 100, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions { colours: 1, exclude_skin: true, ..AnalysisOptions::default() };
+        let analysis = cluster_pixels(pixels, &options)?;
+        assert_eq!(analysis.colours[0].rgb, [30, 90, 200]);
+        assert_eq!(analysis.colours[0].percentage, 100.0);
+        assert!(analysis.colours[0].location.unwrap().0 >= 80);
+
+        // All skin is clustered anyway, with a warning
+        let pixels = Pixels { values: values[..80].to_vec(), positions: None, source_pixels: 80, timings: Timings::default(), warnings: Vec::new() };
+        let analysis = cluster_pixels(pixels, &options)?;
+        assert_eq!(analysis.colours[0].rgb, [224, 172, 140]);
+        assert!(analysis.warnings[0].starts_with("Every pixel looks like skin"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_luma_mode() -> Result<()> {
+        // Saturated red and blue of similar lightness fall in one band, apart from white
+        let values = [[230.0, 0.0, 0.0]; 40].into_iter().chain([[0.0, 0.0, 255.0]; 40]).chain([[255.0, 255.0, 255.0]; 20]).collect();
+        let pixels = Pixels { values, positions: None, source_pixels: 100, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions { colours: 2, mode: AnalysisMode::Luma, ..AnalysisOptions::default() };
+        let analysis = cluster_pixels(pixels, &options)?;
+        assert!(!analysis.greyscale);
+        assert_eq!(analysis.colours.len(), 2);
+        assert_eq!(analysis.colours[0].percentage, 80.0);
+        for colour in &analysis.colours {
+            assert!(colour.rgb[0].abs_diff(colour.rgb[1]) <= 1 && colour.rgb[1].abs_diff(colour.rgb[2]) <= 1);
+        }
+        assert_eq!(analysis.colours[1].rgb, [255, 255, 255]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exact_colours() -> Result<()> {
+        // 10x10 sprite: 60 red, 30 blue, 9 near-blue and one stray pixel
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(10, 10, |x, y| match (x, y) {
+            (9, 9) => image::Rgb([0, 255, 0]),
+            (_, 0..=5) => image::Rgb([255, 0, 0]),
+            (0..=8, _) => image::Rgb([0, 0, 255]),
+            _ => image::Rgb([0, 0, 250]),
+        }));
+        let options = AnalysisOptions { colours: 3, exact: Some(0.0), ..AnalysisOptions::default() };
+        let analysis = cluster_pixels(image_pixels(&img, &options), &options)?;
+        let found: Vec<_> = analysis.colours.iter().map(|c| (c.rgb, c.percentage)).collect();
+        assert_eq!(found, [([255, 0, 0], 60.0), ([0, 0, 255], 36.0), ([0, 0, 250], 3.0)]);
+        assert_eq!(analysis.colours[1].location, Some((0, 6)));
+        assert_eq!(analysis.inertia, 0.0);
+
+        // A tolerance folds the near-blue into blue
+        let options = AnalysisOptions { exact: Some(3.0), ..options };
+        let analysis = cluster_pixels(image_pixels(&img, &options), &options)?;
+        let found: Vec<_> = analysis.colours.iter().map(|c| (c.rgb, c.percentage)).collect();
+        assert_eq!(found, [([255, 0, 0], 60.0), ([0, 0, 255], 39.0), ([0, 255, 0], 1.0)]);
+        assert_eq!(analysis.inertia, 75.0);
+
+        let invalid = AnalysisOptions { exact: Some(-1.0), ..options };
+        assert!(cluster_pixels(image_pixels(&img, &invalid), &invalid).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_modes() -> Result<()> {
+        let values: Vec<[f64; 3]> = vec![[0.0, 0.0, 0.0], [100.0, 10.0, 200.0], [110.0, 20.0, 210.0], [255.0, 255.0, 255.0]];
+        let positions = Some((0..4).map(|i| (i, i)).collect());
+        let pixels = Pixels { values: values.clone(), positions, source_pixels: 4, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions { mode: AnalysisMode::Average, ..AnalysisOptions::default() };
+        let analysis = cluster_pixels(pixels, &options)?;
+        assert_eq!(analysis.colours.len(), 1);
+        assert_eq!((analysis.colours[0].rgb, analysis.colours[0].percentage), ([116, 71, 166], 100.0));
+        assert_eq!(analysis.colours[0].location, Some((2, 2)));
+        assert!(analysis.warnings.is_empty());
+        assert_eq!(options.reported_colours(), 1);
+
+        let pixels = Pixels { values, positions: None, source_pixels: 4, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions { mode: AnalysisMode::Median, ..AnalysisOptions::default() };
+        assert_eq!(cluster_pixels(pixels, &options)?.colours[0].rgb, [110, 20, 210]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_denoise_large_jpeg() -> Result<()> {
+        // Grey with one pixel in ten white, big enough to be sampled while decoding
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("noisy.jpg");
+        image::RgbImage::from_fn(1600, 1600, |x, y| image::Rgb(if (x * 7 + y * 13) % 10 == 0 { [255; 3] } else { [100; 3] }))
+            .save(&path)?;
+        let filename = path.to_str().unwrap();
+        let options = AnalysisOptions::default();
+        assert!(stream::sample_file(filename, sample_side(options.max_pixels), false)?.is_some());
+
+        let brightest = |options: &AnalysisOptions| -> Result<f64> {
+            Ok(load_pixels(filename, options)?.values.iter().map(|v| v[0]).fold(0.0, f64::max))
+        };
+        // Median filtering removes the white specks rather than averaging them into the grey
+        let denoised = brightest(&AnalysisOptions { denoise: Some(Denoise::Median), ..options.clone() })?;
+        assert!(denoised < brightest(&options)?);
+        assert!(denoised < 110.0, "brightest denoised pixel {denoised}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_pixels() {
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::new(400, 200));
+        let options = AnalysisOptions { max_pixels: 10_000, ..AnalysisOptions::default() };
+        let pixels = image_pixels(&img, &options);
+        assert_eq!(pixels.values.len(), 100 * 50);
+        assert_eq!(pixels.source_pixels, 80_000);
+
+        assert_eq!(sample_side(DEFAULT_MAX_PIXELS), 150);
+        assert_eq!(sample_side(0), 1);
+        let side = sample_side(usize::MAX) as u64;
+        assert!(cluster_memory(side * side) <= MAX_CLUSTER_MEMORY);
+    }
+
+    #[test]
+    fn test_top() -> Result<()> {
+        let values = [[250.0, 20.0, 20.0]; 50]
+            .into_iter()
+            .chain([[20.0, 20.0, 250.0]; 30])
+            .chain([[20.0, 250.0, 20.0]; 20])
+            .collect();
+        let pixels = Pixels { values, positions: None, source_pixels: 100, timings: Timings::default(), warnings: Vec::new() };
+        let options = AnalysisOptions { colours: 3, top: Some(2), sort: ColourOrder::Hue, ..AnalysisOptions::default() };
+        let analysis = cluster_pixels(pixels, &options)?;
+        let found: Vec<([u8; 3], f64)> = analysis.colours.iter().map(|c| (c.rgb, c.percentage)).collect();
+        assert_eq!(found, [([250, 20, 20], 50.0), ([20, 20, 250], 30.0)]);
+        assert!(analysis.warnings.is_empty());
+        assert_eq!(options.reported_colours(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort_colours() {
+        let mut colours = vec![
+            ColourInfo::new([0, 0, 255], 40.0),
+            ColourInfo::new([128, 128, 128], 10.0),
+            ColourInfo::new([255, 0, 0], 30.0),
+            ColourInfo::new([0, 255, 0], 20.0),
+        ];
+        let order = |colours: &[ColourInfo]| colours.iter().map(|c| rgb_to_hex(c.rgb)).collect::<Vec<_>>();
+
+        sort_colours(&mut colours, ColourOrder::Hue, false, Metric::Cie76);
+        assert_eq!(order(&colours), ["#ff0000", "#00ff00", "#0000ff", "#808080"]);
+        sort_colours(&mut colours, ColourOrder::Luminance, false, Metric::Cie76);
+        assert_eq!(order(&colours), ["#00ff00", "#808080", "#ff0000", "#0000ff"]);
+        sort_colours(&mut colours, ColourOrder::Percentage, true, Metric::Cie76);
+        assert_eq!(order(&colours), ["#808080", "#00ff00", "#ff0000", "#0000ff"]);
+        assert_eq!(most_prevalent(&colours).unwrap().rgb, [0, 0, 255]);
+
+        // Light to dark through each hue family, rather than alternating
+        let mut colours: Vec<ColourInfo> = [[30, 30, 120], [250, 250, 250], [200, 30, 30], [60, 60, 200], [240, 120, 120], [90, 10, 10]]
+            .into_iter()
+            .map(|rgb| ColourInfo::new(rgb, 10.0))
+            .collect();
+        sort_colours(&mut colours, ColourOrder::Path, false, Metric::Cie76);
+        let path = order(&colours);
+        assert_eq!(path[0], "#fafafa");
+        let reds: Vec<usize> = ["#f07878", "#c81e1e", "#5a0a0a"]
+            .iter()
+            .map(|hex| path.iter().position(|p| p == hex).unwrap())
+            .collect();
+        assert!(reds.iter().max().unwrap() - reds.iter().min().unwrap() == 2);
+        let length = |colours: &[ColourInfo]| colours.windows(2).map(|w| colour::delta_e(w[0].rgb, w[1].rgb)).sum::<f64>();
+        let path_length = length(&colours);
+        sort_colours(&mut colours, ColourOrder::Luminance, false, Metric::Cie76);
+        assert!(path_length < length(&colours));
+    }
+
+    #[test]
+    fn test_palette_mean() {
+        let colours = vec![
+            ColourInfo::new([255, 0, 0], 50.0),
+            ColourInfo::new([0, 0, 255], 50.0),
+        ];
+        assert_eq!(palette_mean(&colours), Some([188, 0, 188]));
+        assert_eq!(palette_mean(&[]), None);
+    }
+
+    #[test]
+    fn test_select_accent() {
+        let colours = vec![
+            ColourInfo::new([240, 240, 240], 70.0),
+            ColourInfo::new([120, 120, 120], 20.0),
+            ColourInfo::new([230, 20, 40], 10.0),
+        ];
+        assert_eq!(select_accent(&colours).unwrap().rgb, [230, 20, 40]);
+
+        let single = vec![ColourInfo::new([0, 0, 0], 100.0)];
+        assert_eq!(select_accent(&single).unwrap().rgb, [0, 0, 0]);
+        assert!(select_accent(&[]).is_none());
+    }
+}
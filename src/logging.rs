@@ -0,0 +1,67 @@
+//! Diagnostic logging to stderr.
+//!
+//! Progress and per-file problems are logged with `tracing` rather than printed, so stdout
+//! only carries the requested output. Warnings and errors are shown by default, `-v` adds
+//! progress, `-vv` the individual analysis stages and `-vvv` everything. `RUST_LOG`, when
+//! set, takes precedence, e.g. `RUST_LOG=dominant_colours=debug`.
+
+use clap::ValueEnum;
+use std::io::IsTerminal;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+use crate::progress;
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log aggregators
+    Json,
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Most detailed level shown for a number of `-v` flags.
+pub fn level(verbose: u8) -> LevelFilter {
+    match verbose {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+pub fn init(verbose: u8, format: LogFormat) {
+    let filter = EnvFilter::builder()
+        .with_default_directive(level(verbose).into())
+        .from_env_lossy();
+    let output = tracing_subscriber::fmt::layer()
+        .with_writer(|| progress::LogWriter)
+        .with_ansi(std::io::stderr().is_terminal());
+    // The spinner sees every stage, whatever the log level
+    let registry = tracing_subscriber::registry().with(progress::StageLayer);
+    match format {
+        LogFormat::Text => registry.with(output.with_target(false).with_filter(filter)).init(),
+        LogFormat::Json => registry.with(output.json().with_filter(filter)).init(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(0), LevelFilter::WARN);
+        assert_eq!(level(2), LevelFilter::DEBUG);
+        assert_eq!(level(7), LevelFilter::TRACE);
+    }
+}
@@ -0,0 +1,270 @@
+//! Low-quality image placeholders, shown while the real image lazy-loads.
+//!
+//! A PNG a few pixels across turns into a soft blur when the browser scales it up to
+//! the image's size, and is small enough to inline as a data URI. Where not even that
+//! is wanted, a CSS gradient of the image's colours from top to bottom will do.
+//!
+//! BlurHash and ThumbHash pack a blur of the image into a short string instead, for
+//! pages that decode it with the matching JavaScript library. Both keep the first few
+//! cosine (DCT) components of a small copy of the image.
+
+use base64::Engine;
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+use crate::colour::{linear_to_srgb, srgb_to_linear};
+use crate::{open_image, rgb_to_hex, DominantColoursError};
+
+/// Longest side of a PNG placeholder, in pixels.
+pub const SIDE: u32 = 16;
+
+/// Bands of colour, top to bottom, in a CSS placeholder.
+pub const BANDS: u32 = 4;
+
+/// Horizontal and vertical BlurHash components: the usual choice for landscape photos.
+pub const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Longest side the image is scaled to before hashing. The hashes keep only the
+/// lowest frequencies, which a small copy has as well as the original.
+pub const BLURHASH_SIDE: u32 = 32;
+/// ThumbHash is specified for images up to 100 pixels across.
+pub const THUMBHASH_SIDE: u32 = 100;
+
+/// Digits of BlurHash's base 83.
+const BASE83: &[u8; 83] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum LqipStyle {
+    /// A tiny PNG as a `data:` URI
+    Png,
+    /// A CSS `linear-gradient` from the top of the image to the bottom
+    Css,
+}
+
+impl std::fmt::Display for LqipStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LqipStyle::Png => write!(f, "png"),
+            LqipStyle::Css => write!(f, "css"),
+        }
+    }
+}
+
+/// A placeholder for `img` in `style`.
+pub fn placeholder(img: &DynamicImage, style: LqipStyle) -> Result<String, DominantColoursError> {
+    match style {
+        LqipStyle::Png => {
+            let tiny = img.resize(SIDE, SIDE, FilterType::Triangle).to_rgb8();
+            let mut png = Vec::new();
+            DynamicImage::ImageRgb8(tiny)
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+                .map_err(|e| DominantColoursError::image(None, e))?;
+            Ok(format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(png)))
+        }
+        LqipStyle::Css => {
+            let bands = img.resize_exact(1, BANDS, FilterType::Triangle).to_rgb8();
+            let stops: Vec<String> = bands.pixels().map(|p| rgb_to_hex(p.0)).collect();
+            Ok(format!("linear-gradient(180deg, {})", stops.join(", ")))
+        }
+    }
+}
+
+/// A placeholder for an image file.
+pub fn placeholder_of_file(filename: &str, style: LqipStyle) -> Result<String, DominantColoursError> {
+    let img = open_image(filename)?;
+    placeholder(&img, style)
+}
+
+/// The BlurHash of `img`, with `BLURHASH_COMPONENTS` components.
+pub fn blurhash(img: &DynamicImage) -> String {
+    let img = img.resize(BLURHASH_SIDE, BLURHASH_SIDE, FilterType::Triangle).to_rgb8();
+    let (width, height) = img.dimensions();
+    let (nx, ny) = BLURHASH_COMPONENTS;
+    let linear: Vec<[f64; 3]> = img.pixels().map(|p| p.0.map(srgb_to_linear)).collect();
+
+    let mut factors = Vec::new();
+    for j in 0..ny {
+        for i in 0..nx {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0; 3];
+            for (index, rgb) in linear.iter().enumerate() {
+                let (x, y) = (index as u32 % width, index as u32 / width);
+                let basis = normalisation
+                    * (std::f64::consts::PI * i as f64 * x as f64 / width as f64).cos()
+                    * (std::f64::consts::PI * j as f64 * y as f64 / height as f64).cos();
+                for (f, c) in factor.iter_mut().zip(rgb) {
+                    *f += basis * c;
+                }
+            }
+            factors.push(factor.map(|f| f / (width * height) as f64));
+        }
+    }
+    let (dc, ac) = factors.split_first().unwrap();
+
+    let mut hash = String::new();
+    encode83(&mut hash, (nx - 1) + (ny - 1) * 9, 1);
+    let maximum = ac.iter().flatten().fold(0.0f64, |max, f| max.max(f.abs()));
+    let quantised_maximum = if ac.is_empty() { 0 } else { ((maximum * 166.0 - 0.5).floor() as i64).clamp(0, 82) as u32 };
+    encode83(&mut hash, quantised_maximum, 1);
+    let maximum = (quantised_maximum + 1) as f64 / 166.0;
+    let [r, g, b] = dc.map(|c| linear_to_srgb(c) as u32);
+    encode83(&mut hash, (r << 16) + (g << 8) + b, 4);
+    for factor in ac {
+        let [r, g, b] = factor.map(|f| {
+            let scaled = f / maximum;
+            ((scaled.signum() * scaled.abs().sqrt() * 9.0 + 9.5).floor() as i64).clamp(0, 18) as u32
+        });
+        encode83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    hash
+}
+
+/// Append `value` to `hash` as `length` base-83 digits.
+fn encode83(hash: &mut String, value: u32, length: u32) {
+    for i in (0..length).rev() {
+        hash.push(BASE83[(value / 83u32.pow(i) % 83) as usize] as char);
+    }
+}
+
+/// The ThumbHash of `img`, base64-encoded as it is usually sent to browsers.
+pub fn thumbhash(img: &DynamicImage) -> String {
+    let img = img.resize(THUMBHASH_SIDE, THUMBHASH_SIDE, FilterType::Triangle).to_rgba8();
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    let rgba: Vec<[f64; 4]> = img.pixels().map(|p| p.0.map(|c| c as f64 / 255.0)).collect();
+
+    // Average colour, weighted by alpha, for transparent pixels to be composited on
+    let mut average = [0.0; 4];
+    for [r, g, b, a] in &rgba {
+        average[0] += a * r;
+        average[1] += a * g;
+        average[2] += a * b;
+        average[3] += a;
+    }
+    let opacity = average[3];
+    if opacity > 0.0 {
+        for c in &mut average[..3] {
+            *c /= opacity;
+        }
+    }
+    let has_alpha = opacity < (w * h) as f64;
+
+    // Luminance, yellow-blue, red-green and alpha channels
+    let mut channels = [vec![], vec![], vec![], vec![]];
+    for &[r, g, b, a] in &rgba {
+        let composite = |c: f64, behind: f64| behind * (1.0 - a) + a * c;
+        let (r, g, b) = (composite(r, average[0]), composite(g, average[1]), composite(b, average[2]));
+        channels[0].push((r + g + b) / 3.0);
+        channels[1].push((r + g) / 2.0 - b);
+        channels[2].push(r - g);
+        channels[3].push(a);
+    }
+
+    // Fewer luminance components if there's alpha to encode as well
+    let l_limit = if has_alpha { 5.0 } else { 7.0 };
+    let longest = w.max(h) as f64;
+    let lx = ((l_limit * w as f64 / longest).round() as usize).max(1);
+    let ly = ((l_limit * h as f64 / longest).round() as usize).max(1);
+    let encode = |channel: &[f64], nx: usize, ny: usize| {
+        let mut dc = 0.0;
+        let mut ac = Vec::new();
+        let mut scale = 0.0f64;
+        for cy in 0..ny {
+            let mut cx = 0;
+            while cx * ny < nx * (ny - cy) {
+                let mut f = 0.0;
+                for y in 0..h {
+                    let fy = (std::f64::consts::PI / h as f64 * cy as f64 * (y as f64 + 0.5)).cos();
+                    for x in 0..w {
+                        let fx = (std::f64::consts::PI / w as f64 * cx as f64 * (x as f64 + 0.5)).cos();
+                        f += channel[x + y * w] * fx * fy;
+                    }
+                }
+                f /= (w * h) as f64;
+                if cx > 0 || cy > 0 {
+                    ac.push(f);
+                    scale = scale.max(f.abs());
+                } else {
+                    dc = f;
+                }
+                cx += 1;
+            }
+        }
+        if scale > 0.0 {
+            for f in &mut ac {
+                *f = 0.5 + 0.5 / scale * *f;
+            }
+        }
+        (dc, ac, scale)
+    };
+    let (l_dc, l_ac, l_scale) = encode(&channels[0], lx.max(3), ly.max(3));
+    let (p_dc, p_ac, p_scale) = encode(&channels[1], 3, 3);
+    let (q_dc, q_ac, q_scale) = encode(&channels[2], 3, 3);
+
+    let round = |v: f64| v.round() as u32;
+    let is_landscape = w > h;
+    let header24 = round(63.0 * l_dc)
+        | round(31.5 + 31.5 * p_dc) << 6
+        | round(31.5 + 31.5 * q_dc) << 12
+        | round(31.0 * l_scale) << 18
+        | (has_alpha as u32) << 23;
+    let header16 = (if is_landscape { ly } else { lx }) as u32
+        | round(63.0 * p_scale) << 3
+        | round(63.0 * q_scale) << 9
+        | (is_landscape as u32) << 15;
+    let mut hash = vec![header24 as u8, (header24 >> 8) as u8, (header24 >> 16) as u8, header16 as u8, (header16 >> 8) as u8];
+    let mut acs = vec![l_ac, p_ac, q_ac];
+    if has_alpha {
+        let (a_dc, a_ac, a_scale) = encode(&channels[3], 5, 5);
+        hash.push((round(15.0 * a_dc) | round(15.0 * a_scale) << 4) as u8);
+        acs.push(a_ac);
+    }
+    // Two 4-bit factors to a byte, low nibble first
+    for (i, f) in acs.iter().flatten().enumerate() {
+        if i % 2 == 0 {
+            hash.push(0);
+        }
+        *hash.last_mut().unwrap() |= (round(15.0 * f) << ((i % 2) * 4)) as u8;
+    }
+    base64::engine::general_purpose::STANDARD.encode(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholder() -> anyhow::Result<()> {
+        // Red above blue, twice as wide as it is tall
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 32, |_, y| {
+            if y < 16 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) }
+        }));
+        let css = placeholder(&img, LqipStyle::Css)?;
+        assert!(css.starts_with("linear-gradient(180deg, #ff0000, "), "{}", css);
+        assert!(css.ends_with(", #0000ff)"), "{}", css);
+
+        let uri = placeholder(&img, LqipStyle::Png)?;
+        let png = base64::engine::general_purpose::STANDARD.decode(uri.strip_prefix("data:image/png;base64,").unwrap())?;
+        let tiny = image::load_from_memory(&png)?;
+        assert_eq!((tiny.width(), tiny.height()), (16, 8));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hashes() -> anyhow::Result<()> {
+        // 4x3 components, the maximum AC value, red as the DC component, then 11 AC components
+        let red = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(64, 32, image::Rgb([255, 0, 0])));
+        let hash = blurhash(&red);
+        assert_eq!(hash.len(), 28, "{}", hash);
+        assert_eq!((&hash[..1], &hash[2..6]), ("L", "TI:j"), "{}", hash);
+
+        // Opaque and landscape, with the average grey in the header
+        let grey = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(64, 32, image::Rgb([128, 128, 128])));
+        let hash = base64::engine::general_purpose::STANDARD.decode(thumbhash(&grey))?;
+        let header24 = hash[0] as u32 | (hash[1] as u32) << 8 | (hash[2] as u32) << 16;
+        assert_eq!(header24 & 63, 32, "luminance");
+        assert_eq!(header24 >> 23, 0, "alpha");
+        assert_eq!(hash[4] >> 7, 1, "landscape");
+        Ok(())
+    }
+}
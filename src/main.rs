@@ -1,208 +1,3083 @@
 use anyhow::{Result, Context};
-use clap::Parser;
-use clap::ValueEnum;
-use image::GenericImageView;
-use linfa::Dataset;
-use linfa::prelude::Predict;
-use linfa::traits::Fit;
-use linfa_clustering::KMeans;
-use ndarray::{Array2, Array1, Axis};
-use serde_json;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use rayon::prelude::*;
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
+use std::process::ExitCode;
+use std::sync::OnceLock;
+
+mod config;
+mod exit;
+mod logging;
+mod progress;
+#[cfg(feature = "server")]
+mod server;
+
+use exit::{Exit, Failed};
+use logging::LogFormat;
+use dominant_colours::numbers::{self, share, share_json, DecimalSeparator};
+use dominant_colours::colour::Metric;
+use dominant_colours::cvd::Deficiency;
+use tracing::{error, info, warn};
+use dominant_colours::names::{NameSet, NamedColour};
+use dominant_colours::cache::Cache;
+use dominant_colours::compare::Change;
+use dominant_colours::histogram::{self, Histogram};
+use dominant_colours::lqip::{self, LqipStyle};
+use dominant_colours::multiscale::{self, Stability};
+use dominant_colours::regions::{self, Region, RegionPalette};
+use dominant_colours::timeline::{self, Bucket};
+use dominant_colours::tracking::{self, Identity};
+use dominant_colours::transform::{self, Transform};
+use dominant_colours::theme::{self, Scheme, Theme, WebColours};
+#[cfg(feature = "index")]
+use dominant_colours::index;
+use dominant_colours::{
+    aggregate_weighted, analyze_image, archive, batch, bench, candidates, cluster_pixels, cmyk, colour, compare, compliance,
+    contrast, cvd, dedupe, diversity, files, image_pixels, load_pixels, mix, names, palette, palette_mean, parse_non_negative,
+    quantize, rgb_to_hex, select_accent, AggregateMode, Analysis, AnalysisMode, AnalysisOptions,
+    ColourInfo, ColourOrder, ColourSpace, InitMethod, Timings,
+};
 
 #[derive(Parser)]
 #[command(name = "dominant-colours")]
 #[command(about = "Extract dominant colours from images using k-means clustering")]
+#[command(subcommand_negates_reqs = true)]
+#[command(override_usage = "dominant-colours [OPTIONS] <FILENAMES>...\n       dominant-colours [OPTIONS] <COMMAND>")]
+#[command(after_help = "Without a subcommand, `dominant-colours IMAGE...` is the same as `dominant-colours extract IMAGE...`.
+
+Exit status: 0 success, 1 other error, 2 invalid command line, 3 input missing or unreadable,
+4 unsupported image format, 5 clustering failed or nothing to cluster, 6 a check failed,
+7 a warning under --strict.")]
 struct Args {
-    /// Image file to analyze
-    filename: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Options for the bare `dominant-colours IMAGE...` form, an alias for `extract`
+    #[command(flatten)]
+    extract: ExtractArgs,
+
+    #[command(flatten)]
+    analysis: AnalysisOptions,
+
+    /// Output format for colour data
+    #[arg(short = 'f', long, global = true, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Log progress to stderr; repeat for more detail (-vv)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Format of log messages on stderr
+    #[arg(long, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Don't draw progress bars (they are only drawn when stderr is a terminal)
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Worker threads for decoding and clustering (defaults to one per core)
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    threads: Option<u16>,
+
+    /// Reuse analyses stored in this directory for images and options seen before
+    #[arg(long, global = true, value_name = "DIR")]
+    cache_dir: Option<String>,
+
+    /// Send this header, as "Name: value", when fetching http(s):// images, e.g. a bearer
+    /// token; repeat for several
+    #[cfg(feature = "remote")]
+    #[arg(long, global = true, value_name = "HEADER", value_parser = parse_header)]
+    http_header: Vec<(String, String)>,
+
+    /// Decimal places for percentages in text, JSON and swatch labels (JSON otherwise
+    /// keeps full precision)
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=10))]
+    precision: Option<u8>,
+
+    /// Decimal separator for percentages in text output and swatch labels
+    #[arg(long, global = true, value_name = "SEP", default_value_t = DecimalSeparator::Point)]
+    decimal_separator: DecimalSeparator,
+
+    /// Treat warnings, such as finding fewer distinct colours than requested or skipping
+    /// unreadable files, as errors (exit code 7)
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Print the JSON Schema of the `-f json` extraction output
+    #[arg(long, exclusive = true)]
+    schema: bool,
+
+    /// Print a roff man page generated from these definitions, for packagers
+    #[arg(long, hide = true, exclusive = true)]
+    generate_man: bool,
+}
+
+/// Options of the `extract` command.
+#[derive(clap::Args, Clone, Debug)]
+struct ExtractArgs {
+    /// Image file(s) to analyze; with --aggregate, `hero.jpg:3` counts an image three
+    /// times as much as the others
+    #[arg(required_unless_present = "files_from")]
+    filenames: Vec<String>,
+
+    /// Read the images to analyse from this file, one path per line ("-" for stdin),
+    /// several at a time on the worker threads; JSON output is one line per image
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["filenames", "aggregate"])]
+    files_from: Option<String>,
+
+    /// Output SVG swatch
+    #[arg(short, long)]
+    swatch: bool,
+
+    /// Swatch output file (defaults to "swatch.svg"; a .png extension writes a PNG)
+    #[arg(short, long, default_value = "swatch.svg")]
+    output: String,
+
+    #[command(flatten)]
+    swatch_style: SwatchStyle,
+
+    /// Also report a single accent colour suitable for UI highlights
+    #[arg(long)]
+    accent: bool,
+
+    /// Report the WCAG contrast ratio between every pair of colours
+    #[arg(long)]
+    contrast_matrix: bool,
+
+    /// Also save the contrast matrix as an SVG heat map to this file
+    #[arg(long, value_name = "FILE", requires = "contrast_matrix")]
+    contrast_svg: Option<String>,
+
+    /// Also plot the --histogram results to this SVG file (hue as a polar plot, levels as curves)
+    #[arg(long, value_name = "FILE", requires = "histogram")]
+    histogram_svg: Option<String>,
+
+    /// List colour pairs meeting WCAG AA/AAA contrast for normal and large text
+    #[arg(long)]
+    wcag_pairs: bool,
+
+    /// Simulate how the palette appears under colour vision deficiencies (comma-separated)
+    #[arg(long, value_delimiter = ',', value_name = "DEFICIENCY")]
+    simulate: Vec<Deficiency>,
+
+    /// Flag colours that become indistinguishable under colour vision deficiencies
+    #[arg(long)]
+    cvd_check: bool,
+
+    /// Delta-E below which two colours count as indistinguishable
+    #[arg(long, default_value_t = 10.0, value_name = "DELTA_E", value_parser = parse_non_negative)]
+    cvd_threshold: f64,
+
+    /// Exit with an error if the colour vision check finds any conflicts
+    #[arg(long, requires = "cvd_check")]
+    cvd_fail: bool,
+
+    /// Colour-name table used to label each colour
+    #[arg(long, default_value_t = NameSet::Css)]
+    names: NameSet,
+
+    /// Custom `name,hex` CSV to match colour names against instead of a built-in table
+    #[arg(long, value_name = "FILE", conflicts_with = "names")]
+    names_file: Option<String>,
+
+    /// Report colour temperature and a warm/neutral/cool tag per colour and for the image
+    #[arg(long)]
+    temperature: bool,
+
+    /// Include HSL, HSV and CIE Lab values for each colour in JSON output
+    #[arg(long)]
+    extended_values: bool,
+
+    /// Include CMYK values for each colour
+    #[arg(long)]
+    cmyk: bool,
+
+    /// Convert to CMYK through this ICC output profile (requires the `icc` feature)
+    #[arg(long, value_name = "FILE", requires = "cmyk")]
+    icc_profile: Option<String>,
+
+    /// Report per-cluster standard deviation, spread in hue, saturation and lightness, and
+    /// overall inertia
+    #[arg(long)]
+    stats: bool,
+
+    /// Report how diverse the palette is: the entropy of the colours' shares, their mean
+    /// pairwise delta-E, and a score combining the two for sorting images
+    #[arg(long)]
+    diversity: bool,
+
+    /// Report the original-image coordinates of a representative pixel for each colour
+    #[arg(long)]
+    locate: bool,
+
+    /// Report how many sampled pixels each colour has, and its estimated area in pixels
+    /// of the original image
+    #[arg(long, conflicts_with = "timeline")]
+    counts: bool,
+
+    /// Report how long each stage of the analysis took
+    #[arg(long)]
+    timings: bool,
+
+    /// Also report a palette for each named rectangle or polygon in this JSON file
+    #[arg(long, value_name = "FILE", conflicts_with = "aggregate")]
+    regions: Option<String>,
+
+    /// Instead of clustering, assign every pixel to the nearest colour of this palette
+    /// file (hex list, saved JSON output, GPL or ASE) and report each one's coverage
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["aggregate", "timeline", "multiscale", "exact", "mode"])]
+    candidates: Option<String>,
+
+    /// Match the colours to those of a previous JSON result for the same image, nearest in
+    /// Lab, and keep their IDs and names; colours without a match get new IDs
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["aggregate", "timeline"])]
+    previous: Option<String>,
+
+    /// Treat each input as an animation or video (decoded with ffmpeg) and also report the
+    /// dominant colours of each time bucket of this many seconds (at least 0.01)
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1",
+        value_parser = parse_bucket_seconds,
+        conflicts_with_all = ["aggregate", "regions"]
+    )]
+    timeline: Option<f64>,
+
+    /// Also cluster at coarser sampling scales and report how stable each colour is,
+    /// warning about colours that may only be resampling artefacts
+    #[arg(long, conflicts_with_all = ["aggregate", "timeline"])]
+    multiscale: bool,
+
+    /// Also save the --timeline as an SVG strip, one column per bucket
+    #[arg(long, value_name = "FILE", requires = "timeline")]
+    timeline_svg: Option<String>,
+
+    /// Also save a sprite sheet of swatches with one row per frame of the --timeline input,
+    /// each frame clustered on its own, as SVG, or as PNG for a .png file; cells are
+    /// --swatch-size pixels
+    #[arg(long, value_name = "FILE", requires = "timeline")]
+    timeline_sheet: Option<String>,
+
+    /// Save a mask PNG per colour into this directory, white where the original image's
+    /// pixels are nearest that colour, named after the image, rank and hex code
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["aggregate", "timeline"])]
+    masks: Option<String>,
+
+    /// Also write the analysis of each local image next to it as `<image>.colours.json`,
+    /// with the options and version that produced it and a hash of the image's contents
+    #[arg(long, conflicts_with = "aggregate")]
+    sidecar: bool,
+
+    /// Also report a tiny placeholder for lazy loading: a blurry PNG data URI, or a CSS
+    /// gradient of the image's colours from top to bottom
+    #[arg(
+        long,
+        value_name = "STYLE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "png",
+        conflicts_with_all = ["aggregate", "timeline"]
+    )]
+    lqip: Option<LqipStyle>,
+
+    /// Combine all input images into one palette, pooling pixels or size-weighted palettes
+    #[arg(long, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "pixels")]
+    aggregate: Option<AggregateMode>,
+
+    /// Brand colours the image must contain (comma-separated hex); fails if not met
+    #[arg(long, value_delimiter = ',', value_parser = parse_hex_arg, value_name = "HEX")]
+    require: Vec<[u8; 3]>,
+
+    /// Maximum delta-E for an extracted colour to count as a required colour
+    #[arg(long, default_value_t = 10.0, value_name = "DELTA_E", value_parser = parse_non_negative)]
+    max_delta_e: f64,
+
+    /// Minimum percentage of the image the required colours must cover together
+    #[arg(long, default_value_t = 0.0, value_name = "PERCENT", value_parser = parse_percentage)]
+    min_coverage: f64,
+}
+
+fn parse_hex_arg(s: &str) -> Result<[u8; 3], String> {
+    colour::parse_hex(s).ok_or_else(|| format!("{:?} is not a hex colour like #ff8800", s))
+}
+
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(format!("{:?} is not a ratio from 0 to 1", s)),
+    }
+}
+
+fn parse_percentage(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(percentage) if (0.0..=100.0).contains(&percentage) => Ok(percentage),
+        _ => Err(format!("{:?} is not a percentage from 0 to 100", s)),
+    }
+}
+
+fn parse_bucket_seconds(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(seconds) if seconds >= timeline::MIN_BUCKET_SECONDS && seconds.is_finite() => Ok(seconds),
+        _ => Err(format!("{:?} is not a number of seconds from {}", s, timeline::MIN_BUCKET_SECONDS)),
+    }
+}
+
+#[cfg(feature = "remote")]
+fn parse_header(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("{:?} is not a header like \"Authorization: Bearer TOKEN\"", s)),
+    }
+}
+
+/// Largest swatch cell, in pixels, so PNG swatches stay a size that can be allocated.
+const MAX_SWATCH_SIZE: usize = 4096;
+
+/// Largest PNG swatch rendered, in pixels: 384 MiB of RGB.
+const MAX_SWATCH_PIXELS: u64 = 1 << 27;
+
+/// Appearance of generated SVG swatches.
+#[derive(clap::Args, Clone, Debug)]
+struct SwatchStyle {
+    /// Side of each colour block in the swatch, in pixels
+    #[arg(long, default_value_t = 100, value_name = "PX", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=MAX_SWATCH_SIZE as u64))]
+    swatch_size: usize,
+
+    /// Font family for swatch labels
+    #[arg(long, default_value = "Arial", value_name = "FAMILY")]
+    swatch_font: String,
+}
+
+impl Default for SwatchStyle {
+    fn default() -> Self {
+        SwatchStyle { swatch_size: 100, swatch_font: "Arial".to_string() }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Extract the dominant colours of one or more images (the default command)
+    Extract(Box<ExtractArgs>),
+
+    /// Save a swatch of an image's palette as SVG, or as PNG for a .png output file
+    Swatch {
+        /// Image to analyze
+        #[arg(required_unless_present = "from")]
+        image: Option<String>,
+
+        /// Draw the colours of a palette file (saved JSON output, possibly hand-edited, hex
+        /// list, GPL or ASE) instead of analysing an image
+        #[arg(long, value_name = "FILE", conflicts_with = "image")]
+        from: Option<String>,
+
+        /// Swatch output file
+        #[arg(short, long, default_value = "swatch.svg")]
+        output: String,
+
+        /// Add a row simulating each colour vision deficiency (comma-separated)
+        #[arg(long, value_delimiter = ',', value_name = "DEFICIENCY")]
+        simulate: Vec<Deficiency>,
+
+        #[command(flatten)]
+        swatch_style: SwatchStyle,
+    },
+
+    /// Recolour an image using only its dominant colours
+    Quantize {
+        /// Image to recolour
+        image: String,
+
+        /// Output image; the format follows the extension
+        #[arg(short, long, default_value = "quantized.png")]
+        output: String,
+
+        /// Recolour with this palette file (hex list, saved JSON output, GPL or ASE)
+        /// instead of the image's own colours
+        #[arg(long, value_name = "FILE")]
+        palette: Option<String>,
+    },
+
+    /// Compare the palettes of two images, or an image against a reference palette
+    Compare {
+        /// Reference image (or the image to check, with --palette)
+        first: String,
+
+        /// Image to compare against the reference
+        #[arg(required_unless_present = "palette", conflicts_with = "palette")]
+        second: Option<String>,
+
+        /// Reference palette file (hex list, saved JSON output, GPL or ASE) to compare the image against
+        #[arg(long, value_name = "FILE")]
+        palette: Option<String>,
+    },
+
+    /// Report the colours added, removed and shifted between two versions of an image
+    Diff {
+        /// Earlier version: an image, palette file or hex colour
+        old: String,
+
+        /// Later version
+        new: String,
+
+        /// Most delta-E for a colour to count as shifted rather than removed and replaced
+        #[arg(long, default_value_t = 10.0, value_parser = parse_non_negative)]
+        threshold: f64,
+
+        /// Exit with an error if any colour was added, removed or shifted
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Derive a CSS colour theme (background, foreground, accent) from an image
+    Theme {
+        /// Image to take the theme from
+        image: String,
+
+        /// Derive matching light and dark themes instead (comma-separated); in CSS the
+        /// first is the default and the rest apply through `prefers-color-scheme`, and
+        /// `--format meta` gives a `theme-color` tag per scheme
+        #[arg(long, value_delimiter = ',', value_name = "SCHEMES")]
+        themes: Vec<Scheme>,
+    },
+
+    /// Find groups of near-duplicate images by palette similarity
+    Dedupe {
+        /// Images, or directories to search recursively
+        #[arg(required = true)]
+        inputs: Vec<String>,
+
+        /// Maximum Earth Mover's Distance (delta-E) for two images to count as duplicates
+        #[arg(long, default_value_t = 5.0, value_parser = parse_non_negative)]
+        threshold: f64,
+    },
+
+    /// Find the extracted colour closest to a given colour, with its delta-E and coverage
+    Nearest {
+        /// Colour to look for, e.g. "#3c79b8"
+        #[arg(value_parser = parse_hex_arg)]
+        colour: [u8; 3],
+
+        /// Image or palette file to search
+        input: String,
+
+        /// Exit with an error unless the nearest colour is within this delta-E
+        #[arg(long, value_name = "DELTA_E", value_parser = parse_non_negative)]
+        max_delta_e: Option<f64>,
+    },
+
+    /// Blend two palettes in Lab, e.g. for themed variations between two reference images
+    Mix {
+        /// Image, palette file or hex colour to start from
+        from: String,
+
+        /// Image, palette file or hex colour to blend towards
+        to: String,
+
+        /// How far to blend, from 0 (all `from`) to 1 (all `to`)
+        #[arg(long, default_value_t = 0.5, value_parser = parse_ratio, conflicts_with = "steps")]
+        ratio: f64,
+
+        /// Instead of one mix, this many evenly spaced palettes between the two
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+        steps: Option<u16>,
+    },
+
+    /// Adjust every colour of a palette, e.g. for seasonal variants of a brand palette:
+    /// hue rotation, then lightening or darkening, then desaturation, all in LCh
+    Transform {
+        /// Image, palette file or hex colour to adjust
+        input: String,
+
+        /// Degrees to turn each hue by; positive turns red towards yellow
+        #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_name = "DEGREES")]
+        rotate: f64,
+
+        /// Points of lightness (L*, 0 to 100) to add
+        #[arg(long, default_value_t = 0.0, value_parser = parse_percentage, value_name = "PERCENT")]
+        lighten: f64,
+
+        /// Points of lightness to take away
+        #[arg(long, default_value_t = 0.0, value_parser = parse_percentage, value_name = "PERCENT", conflicts_with = "lighten")]
+        darken: f64,
+
+        /// Percentage of each colour's chroma to take away; 100 leaves greys
+        #[arg(long, default_value_t = 0.0, value_parser = parse_percentage, value_name = "PERCENT")]
+        desaturate: f64,
+    },
+
+    /// Time colour spaces and k-means initialisations on an image, to pick settings for a corpus
+    Bench {
+        /// Image to benchmark on; without one, a synthetic test image is used
+        image: Option<String>,
+
+        /// Colour spaces to try (comma-separated)
+        #[arg(long, value_delimiter = ',', default_value = "rgb,lab")]
+        spaces: Vec<ColourSpace>,
+
+        /// Initialisation methods to try (comma-separated)
+        #[arg(long, value_delimiter = ',', default_value = "kmeans++,random")]
+        inits: Vec<InitMethod>,
+
+        /// Times to cluster with each combination
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..))]
+        runs: u16,
+    },
+
+    /// Analyse every image in a directory and store the palettes in a SQLite index
+    #[cfg(feature = "index")]
+    Index {
+        /// Directory to scan (recursively) for images
+        dir: String,
+
+        /// Palette index database
+        #[arg(long, default_value = "palettes.db")]
+        db: String,
+    },
+
+    /// Find indexed images containing a colour, ranked by coverage
+    #[cfg(feature = "index")]
+    Query {
+        /// Colour to search for, e.g. "#ff6600"
+        #[arg(value_parser = parse_hex_arg)]
+        colour: [u8; 3],
+
+        /// Palette index database
+        #[arg(long, default_value = "palettes.db")]
+        db: String,
+
+        /// Maximum delta-E for a palette colour to count as a match
+        #[arg(long, default_value_t = 15.0, value_parser = parse_non_negative)]
+        tolerance: f64,
+
+        /// Maximum number of images to return
+        #[arg(long, default_value_t = 20, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        limit: usize,
+    },
+
+    /// Serve palette extraction over HTTP
+    #[cfg(feature = "server")]
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: String,
+
+        /// Let `?url=` fetch images from public http(s) addresses
+        #[arg(long)]
+        allow_fetch: bool,
+    },
+
+    /// Print a shell completion script for bash, zsh, fish, PowerShell or elvish
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+enum OutputFormat {
+    /// Readable report
+    Text,
+    /// Machine-readable document
+    Json,
+    /// `theme-color` meta tag and web app manifest colours (extract, theme and transform only)
+    Meta,
+    /// CSS `linear-gradient` with a band per colour, sized by its share (extract and transform only)
+    Gradient,
+    /// BlurHash string of the image (extract only)
+    Blurhash,
+    /// Base64 ThumbHash of the image (extract only)
+    Thumbhash,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::Meta => write!(f, "meta"),
+            OutputFormat::Gradient => write!(f, "gradient"),
+            OutputFormat::Blurhash => write!(f, "blurhash"),
+            OutputFormat::Thumbhash => write!(f, "thumbhash"),
+        }
+    }
+}
+
+fn save_colour_swatch(
+    colours: &[ColourInfo],
+    simulate: &[Deficiency],
+    style: &SwatchStyle,
+    output_file: &str,
+) -> Result<()> {
+    let is_png = std::path::Path::new(output_file)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+    let data = if is_png {
+        swatch_png(colours, simulate, style)?
+    } else {
+        swatch_svg(colours, simulate, style).into_bytes()
+    };
+    let mut file = File::create(output_file)?;
+    file.write_all(&data)?;
+    Ok(())
+}
+
+fn swatch_svg(colours: &[ColourInfo], simulate: &[Deficiency], style: &SwatchStyle) -> String {
+    let size = style.swatch_size;
+    let font = xml_escape(&style.swatch_font);
+    let row = size + 20;
+    let mut svg = format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}">"#,
+        size * colours.len().max(6),
+        size + 40 + simulate.len() * row
+    );
+
+    for (i, colour) in colours.iter().enumerate() {
+        let x = i * size;
+        svg.push_str(&format!(
+            r#"
+    <rect x="{}" y="0" width="{}" height="{}" fill="rgb({}, {}, {})"/>
+    <text x="{}" y="{}" font-family="{}" font-size="10" fill="black">{}, {}, {}</text>
+    <text x="{}" y="{}" font-family="{}" font-size="10" fill="black">{}%</text>"#,
+            x, size, size, colour.rgb[0], colour.rgb[1], colour.rgb[2],
+            x + 5, size + 15, font, colour.rgb[0], colour.rgb[1], colour.rgb[2],
+            x + 5, size + 30, font, share(colour.percentage, 1)
+        ));
+    }
+
+    // One extra row per simulated colour vision deficiency
+    for (r, &deficiency) in simulate.iter().enumerate() {
+        let y = size + 40 + r * row;
+        for (i, colour) in colours.iter().enumerate() {
+            let sim = cvd::simulate(colour.rgb, deficiency);
+            svg.push_str(&format!(
+                r#"
+    <rect x="{}" y="{}" width="{}" height="{}" fill="rgb({}, {}, {})"/>"#,
+                i * size, y, size, size, sim[0], sim[1], sim[2]
+            ));
+        }
+        svg.push_str(&format!(
+            r#"
+    <text x="5" y="{}" font-family="{}" font-size="10" fill="black">{}</text>"#,
+            y + size + 15, font, deficiency
+        ));
+    }
+
+    svg.push_str("\n</svg>");
+    svg
+}
+
+/// Raster version of the SVG swatch: one block per colour, plus one row per simulated
+/// colour vision deficiency. PNG swatches carry no text labels.
+fn swatch_png(colours: &[ColourInfo], simulate: &[Deficiency], style: &SwatchStyle) -> Result<Vec<u8>> {
+    let too_large = || anyhow::anyhow!(
+        "A {}-pixel swatch of {} colours and {} simulations is too large to render",
+        style.swatch_size, colours.len(), simulate.len()
+    );
+    let block = u32::try_from(style.swatch_size.max(1)).map_err(|_| too_large())?;
+    let width = u32::try_from(colours.len().max(1)).ok().and_then(|n| block.checked_mul(n)).ok_or_else(too_large)?;
+    let height = u32::try_from(simulate.len() + 1).ok().and_then(|n| block.checked_mul(n)).ok_or_else(too_large)?;
+    if u64::from(width) * u64::from(height) > MAX_SWATCH_PIXELS {
+        return Err(too_large());
+    }
+    let img = image::RgbImage::from_fn(width, height, |x, y| {
+        let rgb = match colours.get((x / block) as usize) {
+            Some(colour) => match (y / block) as usize {
+                0 => colour.rgb,
+                row => cvd::simulate(colour.rgb, simulate[row - 1]),
+            },
+            None => [255, 255, 255],
+        };
+        image::Rgb(rgb)
+    });
+
+    let mut png = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('"', "&quot;")
+}
+
+fn temperature_json(rgb: [u8; 3]) -> serde_json::Value {
+    serde_json::json!({
+        "cct": colour::correlated_colour_temperature(rgb),
+        "class": colour::temperature_class(rgb).to_string()
+    })
+}
+
+/// Everything the output stage needs besides the colours themselves.
+struct OutputContext<'a> {
+    args: &'a ExtractArgs,
+    analysis: &'a AnalysisOptions,
+    format: OutputFormat,
+    names: Cow<'static, [NamedColour]>,
+    icc: Option<cmyk::IccTransform>,
+    regions: Vec<Region>,
+    candidates: Vec<[u8; 3]>,
+    previous: Option<tracking::Previous>,
+}
+
+impl<'a> OutputContext<'a> {
+    /// Load the lookup tables and profiles requested on the command line.
+    fn new(args: &'a ExtractArgs, analysis: &'a AnalysisOptions, format: OutputFormat) -> Result<Self> {
+        let names = match &args.names_file {
+            Some(path) => Cow::Owned(names::load_csv(path)?),
+            None => Cow::Borrowed(args.names.table()),
+        };
+        let icc = args.icc_profile.as_deref().map(cmyk::IccTransform::open).transpose()?;
+        let regions = args.regions.as_deref().map(regions::load).transpose()?.unwrap_or_default();
+        let candidates = match &args.candidates {
+            Some(path) => palette::load(path)?.iter().map(|c| c.rgb).collect(),
+            None => Vec::new(),
+        };
+        let previous = args.previous.as_deref().map(tracking::load_previous).transpose()?;
+        Ok(OutputContext { args, analysis, format, names, icc, regions, candidates, previous })
+    }
+}
+
+/// Histograms keyed by kind, e.g. `{"hue": [...]}`.
+fn histograms_json(histograms: &[Histogram]) -> serde_json::Value {
+    let mut json = serde_json::json!({});
+    for histogram in histograms {
+        json[histogram.kind().to_string()] = match histogram {
+            Histogram::Hue(bins) | Histogram::Luma(bins) => serde_json::json!(bins),
+            Histogram::Rgb([r, g, b]) => serde_json::json!({ "r": r, "g": g, "b": b }),
+        };
+    }
+    json
+}
+
+/// Width of the longest bar in text histograms.
+const HISTOGRAM_BAR: usize = 40;
+
+fn print_histogram(histogram: &Histogram) {
+    match histogram {
+        Histogram::Hue(bins) => {
+            println!("\nHue distribution (saturation-weighted):");
+            let max = bins.iter().copied().fold(0.0, f64::max);
+            let step = 360 / bins.len();
+            // Bins that would print as 0.0% are left out
+            for (i, &share) in bins.iter().enumerate().filter(|(_, &share)| share >= 0.05) {
+                let bar = "#".repeat((share / max * HISTOGRAM_BAR as f64).round() as usize);
+                let range = format!("{}-{}°", i * step, (i + 1) * step);
+                println!("{:>9}: {:>5}% {}", range, numbers::share(share, 1), bar);
+            }
+        }
+        Histogram::Rgb([r, g, b]) => {
+            println!("\nChannel distribution (% of pixels per {} levels):", TEXT_LEVELS);
+            println!("{:>9}  {:>5}  {:>5}  {:>5}", "levels", "R", "G", "B");
+            let (r, g, b) = (coarse(r), coarse(g), coarse(b));
+            for (i, ((r, g), b)) in r.iter().zip(&g).zip(&b).enumerate() {
+                let range = format!("{}-{}", i * TEXT_LEVELS, (i + 1) * TEXT_LEVELS - 1);
+                println!("{:>9}: {:>5}% {:>5}% {:>5}%", range, share(*r, 1), share(*g, 1), share(*b, 1));
+            }
+        }
+        Histogram::Luma(bins) => {
+            println!("\nLuma distribution:");
+            let bins = coarse(bins);
+            let max = bins.iter().copied().fold(0.0, f64::max);
+            for (i, &share) in bins.iter().enumerate() {
+                let bar = "#".repeat((share / max * HISTOGRAM_BAR as f64).round() as usize);
+                let range = format!("{}-{}", i * TEXT_LEVELS, (i + 1) * TEXT_LEVELS - 1);
+                println!("{:>9}: {:>5}% {}", range, numbers::share(share, 1), bar);
+            }
+        }
+    }
+}
+
+/// Levels per line of text channel and luma histograms.
+const TEXT_LEVELS: usize = 16;
+
+/// Sum runs of `TEXT_LEVELS` levels, so a 256-level histogram fits on a screen.
+fn coarse(bins: &[f64]) -> Vec<f64> {
+    bins.chunks(TEXT_LEVELS).map(|chunk| chunk.iter().sum()).collect()
+}
+
+fn colour_json(colour: &ColourInfo, ctx: &OutputContext) -> serde_json::Value {
+    let args = ctx.args;
+    let mut json = serde_json::json!({
+        "rgb": colour.rgb,
+        "percentage": share_json(colour.percentage),
+        "hex": rgb_to_hex(colour.rgb),
+        "text_on": rgb_to_hex(colour::text_colour_on(colour.rgb))
+    });
+    if let Some((named, distance)) = names::nearest(colour.rgb, &ctx.names, ctx.analysis.metric) {
+        json["name"] = named.name.clone().into();
+        json["name_delta_e"] = distance.into();
+    }
+    if args.stats {
+        json["std_dev"] = serde_json::json!(colour.std_dev);
+        json["hsl_spread"] = serde_json::json!(colour.hsl_spread);
+    }
+    if args.locate {
+        json["location"] = colour
+            .location
+            .map(|(x, y)| serde_json::json!({ "x": x, "y": y }))
+            .unwrap_or(serde_json::Value::Null);
+    }
+    if args.extended_values {
+        json["hsl"] = serde_json::json!(colour::rgb_to_hsl(colour.rgb));
+        json["hsv"] = serde_json::json!(colour::rgb_to_hsv(colour.rgb));
+        json["lab"] = serde_json::json!(colour::rgb_to_lab(colour.rgb));
+    }
+    if args.cmyk {
+        json["cmyk"] = serde_json::json!(cmyk::naive(colour.rgb));
+        if let Some(icc) = &ctx.icc {
+            json["cmyk_icc"] = serde_json::json!(icc.convert(colour.rgb));
+        }
+    }
+    if args.temperature {
+        json["temperature"] = temperature_json(colour.rgb);
+    }
+    if !args.simulate.is_empty() {
+        json["simulated"] = args
+            .simulate
+            .iter()
+            .map(|&d| (d.to_string(), rgb_to_hex(cvd::simulate(colour.rgb, d)).into()))
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+    }
+    json
+}
+
+/// Version of the JSON output format, bumped on incompatible changes. Every JSON document
+/// carries it as `schema_version`.
+const SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema for the extraction output, printed by `--schema`.
+const SCHEMA: &str = include_str!("data/schema.json");
+
+fn with_schema_version(mut json: serde_json::Value) -> serde_json::Value {
+    json["schema_version"] = SCHEMA_VERSION.into();
+    json
+}
+
+fn print_json(json: serde_json::Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&with_schema_version(json))?);
+    Ok(())
+}
+
+/// Per-image results beyond the main palette.
+#[derive(Default)]
+struct Extras {
+    regions: Vec<RegionPalette>,
+    timeline: Vec<Bucket>,
+    /// Palette of each frame, for `--timeline-sheet`
+    frames: Vec<Bucket>,
+    stability: Option<Stability>,
+    lqip: Option<String>,
+    hash: Option<String>,
+    /// `--previous` identities of the main palette's colours, in the same order
+    identities: Vec<Identity>,
+    /// The main palette checked against `--require`, if any colours are required
+    compliance: Option<compliance::Report>,
+}
+
+/// The `--require` check of `colours`, made once per image for every output and the exit
+/// status to share.
+fn compliance_report(colours: &[ColourInfo], ctx: &OutputContext) -> Option<compliance::Report> {
+    let args = ctx.args;
+    (!args.require.is_empty())
+        .then(|| compliance::check(colours, &args.require, args.max_delta_e, args.min_coverage, ctx.analysis.metric))
+}
+
+/// Add each colour's `--counts` to the `colours` of `json`.
+fn add_counts(json: &mut serde_json::Value, analysis: &Analysis) {
+    for (i, colour) in analysis.colours.iter().enumerate() {
+        json["colours"][i]["pixels"] = analysis.pixel_count(colour).into();
+        json["colours"][i]["area"] = analysis.area(colour).into();
+    }
+}
+
+fn build_json(analysis: &Analysis, extras: &Extras, ctx: &OutputContext) -> serde_json::Value {
+    let args = ctx.args;
+    let colours = &analysis.colours;
+    let mut json = serde_json::json!({
+        "colours": colours.iter().map(|c| colour_json(c, ctx)).collect::<Vec<_>>()
+    });
+    if args.counts {
+        add_counts(&mut json, analysis);
+    }
+    for (i, identity) in extras.identities.iter().enumerate() {
+        json["colours"][i]["id"] = identity.id.into();
+        if let Some(name) = &identity.name {
+            if json["colours"][i]["name"] != name.as_str() {
+                json["colours"][i]["name"] = name.as_str().into();
+                json["colours"][i].as_object_mut().unwrap().remove("name_delta_e");
+            }
+        }
+    }
+    if let Some(stability) = &extras.stability {
+        json["scales"] = serde_json::json!(stability.sides);
+        for (i, &found) in stability.scales_found.iter().enumerate() {
+            json["colours"][i]["stability"] = (found as f64 / stability.sides.len() as f64).into();
+        }
+    }
+    if args.regions.is_some() {
+        json["regions"] = extras
+            .regions
+            .iter()
+            .map(|region| {
+                let mut json = serde_json::json!({
+                    "name": region.name,
+                    "colours": region.analysis.colours.iter().map(|c| colour_json(c, ctx)).collect::<Vec<_>>()
+                });
+                if args.counts {
+                    add_counts(&mut json, &region.analysis);
+                }
+                json
+            })
+            .collect();
+    }
+    if args.timeline.is_some() {
+        json["timeline"] = extras
+            .timeline
+            .iter()
+            .map(|bucket| serde_json::json!({
+                "start": bucket.start,
+                "end": bucket.end,
+                "colours": bucket.analysis.colours.iter().map(|c| colour_json(c, ctx)).collect::<Vec<_>>()
+            }))
+            .collect();
+    }
+    if analysis.greyscale {
+        json["is_grayscale"] = true.into();
+    }
+    if ctx.candidates.is_empty() && colours.len() < ctx.analysis.reported_colours() {
+        json["effective_colours"] = colours.len().into();
+    }
+    if !analysis.warnings.is_empty() {
+        json["warnings"] = analysis.warnings.clone().into();
+    }
+    if args.accent {
+        json["accent"] = select_accent(colours)
+            .map(|c| colour_json(c, ctx))
+            .unwrap_or(serde_json::Value::Null);
+    }
+    if args.stats {
+        json["inertia"] = analysis.inertia.into();
+    }
+    if ctx.analysis.silhouette {
+        json["silhouette"] = analysis.silhouette.into();
+    }
+    if let Some(lqip) = &extras.lqip {
+        json["lqip"] = lqip.as_str().into();
+    }
+    if args.timings {
+        json["timings"] = timings_json(&analysis.timings);
+    }
+    if args.temperature {
+        json["temperature"] = palette_mean(colours)
+            .map(temperature_json)
+            .unwrap_or(serde_json::Value::Null);
+    }
+    if args.contrast_matrix {
+        json["contrast_matrix"] = serde_json::json!(contrast::contrast_matrix(colours));
+    }
+    if !analysis.histograms.is_empty() {
+        json["histograms"] = histograms_json(&analysis.histograms);
+    }
+    if let Some(vividness) = &analysis.vividness {
+        json["vividness"] = serde_json::json!(vividness);
+    }
+    if args.diversity {
+        json["diversity"] = serde_json::json!(diversity::measure(colours, ctx.analysis.metric));
+    }
+    if args.wcag_pairs {
+        json["wcag_pairs"] = contrast::wcag_pairs(colours)
+            .iter()
+            .map(|p| serde_json::json!({
+                "colours": [rgb_to_hex(p.first), rgb_to_hex(p.second)],
+                "ratio": p.ratio,
+                "levels": p.levels
+            }))
+            .collect();
+    }
+    if let Some(report) = &extras.compliance {
+        json["compliance"] = serde_json::json!({
+            "passed": report.passed,
+            "total_coverage": share_json(report.total_coverage),
+            "required": report.required.iter().map(|r| serde_json::json!({
+                "hex": rgb_to_hex(r.rgb),
+                "coverage": share_json(r.coverage),
+                "nearest_delta_e": r.nearest_delta_e
+            })).collect::<Vec<_>>()
+        });
+    }
+    if args.cvd_check {
+        json["cvd_conflicts"] = cvd_conflicts(colours, ctx)
+            .iter()
+            .map(|c| serde_json::json!({
+                "deficiency": c.deficiency.to_string(),
+                "colours": [rgb_to_hex(c.first), rgb_to_hex(c.second)],
+                "delta_e": c.delta_e,
+                "simulated_delta_e": c.simulated_delta_e
+            }))
+            .collect();
+    }
+    json
+}
+
+/// Stage durations in milliseconds, plus the total.
+fn timings_json(timings: &Timings) -> serde_json::Value {
+    let mut json: serde_json::Map<_, _> = timings
+        .stages()
+        .iter()
+        .map(|(stage, d)| (format!("{}_ms", stage), serde_json::json!(d.as_secs_f64() * 1000.0)))
+        .collect();
+    json.insert("total_ms".to_string(), serde_json::json!(timings.total().as_secs_f64() * 1000.0));
+    json.into()
+}
+
+fn cvd_conflicts(colours: &[ColourInfo], ctx: &OutputContext) -> Vec<cvd::Conflict> {
+    let rgbs: Vec<[u8; 3]> = colours.iter().map(|c| c.rgb).collect();
+    cvd::conflicts(&rgbs, Deficiency::value_variants(), ctx.args.cvd_threshold, ctx.analysis.metric)
+}
+
+fn print_temperature(label: &str, rgb: [u8; 3]) {
+    let cct = colour::correlated_colour_temperature(rgb)
+        .map(|k| format!("{:.0}K", k))
+        .unwrap_or_else(|| "n/a".to_string());
+    println!("{}: {} ({})", label, cct, colour::temperature_class(rgb));
+}
+
+/// Prefix for the environment variables that stand in for command line options.
+const ENV_PREFIX: &str = "DOMINANT_COLOURS";
+
+/// Let every option also be set with an environment variable: `DOMINANT_COLOURS_<OPTION>`
+/// for global options and those of the bare command (and so of `extract`), and
+/// `DOMINANT_COLOURS_<SUBCOMMAND>_<OPTION>` for a subcommand's own, e.g.
+/// `DOMINANT_COLOURS_DEDUPE_THRESHOLD=8`. Flags accept `1`/`0`, `true`/`false`, `yes`/`no`,
+/// and lists are comma-separated as on the command line.
+fn with_env_vars(cmd: clap::Command) -> clap::Command {
+    with_env_prefix(cmd, ENV_PREFIX.to_string())
+}
+
+fn with_env_prefix(mut cmd: clap::Command, prefix: String) -> clap::Command {
+    cmd = cmd.mut_args(|arg| {
+        // Global options are copied into subcommands keeping the name given at the top
+        if arg.is_positional() || arg.is_hide_set() || (arg.is_global_set() && prefix != ENV_PREFIX) {
+            return arg;
+        }
+        let name = format!("{}_{}", prefix, arg.get_id().as_str().to_uppercase());
+        let arg = arg.env(name);
+        if matches!(arg.get_action(), clap::ArgAction::SetTrue) {
+            arg.value_parser(clap::builder::FalseyValueParser::new())
+        } else {
+            arg
+        }
+    });
+    let subcommands: Vec<String> = cmd.get_subcommands().map(|s| s.get_name().to_string()).collect();
+    for name in subcommands {
+        let prefix = match name.as_str() {
+            "extract" => prefix.clone(),
+            _ => format!("{}_{}", prefix, name.to_uppercase().replace('-', "_")),
+        };
+        cmd = cmd.mut_subcommand(name, |sub| with_env_prefix(sub, prefix));
+    }
+    cmd
+}
+
+/// Options of the bare command belong to `extract`. In front of a subcommand they would
+/// be silently ignored, so reject them instead.
+fn check_option_placement(cmd: &mut clap::Command, matches: &ArgMatches) -> Result<(), clap::Error> {
+    let Some((name, _)) = matches.subcommand() else {
+        return Ok(());
+    };
+    let misplaced = cmd.get_arguments().find(|arg| {
+        !arg.is_global_set() && matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+    });
+    match misplaced {
+        Some(arg) => {
+            let flag = match arg.get_long() {
+                Some(long) => format!("--{}", long),
+                None => arg.get_id().to_string(),
+            };
+            let message = format!("the argument '{}' cannot be used before the '{}' subcommand", flag, name);
+            Err(cmd.error(ErrorKind::ArgumentConflict, message))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Only `extract`, `theme` and `transform` have a palette to suggest web colours from,
+/// only `extract` and `transform` one to draw as a gradient, and only `extract` an image
+/// to hash. A hash describes one image, so not a combined palette or a video timeline.
+fn check_format_support(cmd: &mut clap::Command, args: &Args) -> Result<(), clap::Error> {
+    let supported = match args.format {
+        OutputFormat::Text | OutputFormat::Json => return Ok(()),
+        OutputFormat::Meta => "the extract, theme and transform commands",
+        OutputFormat::Gradient => "the extract and transform commands",
+        OutputFormat::Blurhash | OutputFormat::Thumbhash => "the extract command",
+    };
+    let extract = match (&args.command, args.format) {
+        (None, _) => &args.extract,
+        (Some(Command::Extract(extract)), _) => extract,
+        (Some(Command::Theme { .. }), OutputFormat::Meta) => return Ok(()),
+        (Some(Command::Transform { .. }), OutputFormat::Meta | OutputFormat::Gradient) => return Ok(()),
+        _ => {
+            return Err(cmd.error(
+                ErrorKind::InvalidValue,
+                format!("'--format {}' is only supported by {}", args.format, supported),
+            ))
+        }
+    };
+    let hash = matches!(args.format, OutputFormat::Blurhash | OutputFormat::Thumbhash);
+    if hash && (extract.aggregate.is_some() || extract.timeline.is_some()) {
+        return Err(cmd.error(
+            ErrorKind::ArgumentConflict,
+            format!("'--format {}' cannot be used with '--aggregate' or '--timeline'", args.format),
+        ));
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            Exit::of(&e).into()
+        }
+    }
+}
+
+/// The worker pool for decoding and clustering, sized by `--threads`.
+fn thread_pool(threads: u16) -> rayon::ThreadPoolBuilder {
+    rayon::ThreadPoolBuilder::new().num_threads(threads.into())
+}
+
+fn run() -> Result<()> {
+    let mut cmd = with_env_vars(Args::command());
+    let matches = cmd.clone().get_matches();
+    check_option_placement(&mut cmd, &matches).unwrap_or_else(|e| e.exit());
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    progress::init(args.quiet);
+    logging::init(args.verbose, args.log_format);
+    numbers::init(args.precision, args.decimal_separator);
+    config::Config::load()?.apply(&mut args, &matches)?;
+    if let Some(threads) = args.threads {
+        thread_pool(threads).build_global().context("Failed to start worker threads")?;
+    }
+    if let Some(dir) = &args.cache_dir {
+        let _ = CACHE.set(Cache::open(dir)?);
+    }
+    #[cfg(feature = "remote")]
+    dominant_colours::remote::set_headers(args.http_header.clone());
+    if args.schema {
+        println!("{}", SCHEMA.trim_end());
+        return Ok(());
+    }
+    if args.generate_man {
+        clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout())?;
+        return Ok(());
+    }
+    check_format_support(&mut cmd, &args).unwrap_or_else(|e| e.exit());
+    let (analysis, format, strict) = (&args.analysis, args.format, args.strict);
+
+    match &args.command {
+        None => run_extract(&args.extract, analysis, format, strict),
+        Some(Command::Extract(extract)) => run_extract(extract, analysis, format, strict),
+        Some(Command::Swatch { image, from, output, simulate, swatch_style }) => {
+            let colours = match (from, image) {
+                (Some(path), _) => palette::load(path)?,
+                (None, Some(image)) => analyze(image, analysis, strict)?.colours,
+                (None, None) => unreachable!("clap requires an image or --from"),
+            };
+            info!(file = %output, "Saving colour swatch");
+            save_colour_swatch(&colours, simulate, swatch_style, output).context("Failed to save colour swatch")
+        }
+        Some(Command::Quantize { image, output, palette }) => {
+            run_quantize(image, output, palette.as_deref(), analysis, strict)
+        }
+        Some(Command::Compare { first, second, palette }) => {
+            let (reference_name, reference, image) = match (palette, second) {
+                (Some(path), _) => (path.as_str(), palette::load(path)?, first.as_str()),
+                (None, Some(second)) => (first.as_str(), analyze(first, analysis, strict)?.colours, second.as_str()),
+                (None, None) => unreachable!("clap requires a second image or --palette"),
+            };
+            let colours = analyze(image, analysis, strict)?.colours;
+            print_comparison(reference_name, &reference, image, &colours, analysis.metric, format)
+        }
+        Some(Command::Diff { old, new, threshold, check }) => {
+            run_diff(old, new, *threshold, *check, analysis, format, strict)
+        }
+        Some(Command::Theme { image, themes }) => run_theme(image, themes, analysis, format, strict),
+        Some(Command::Dedupe { inputs, threshold }) => run_dedupe(inputs, *threshold, analysis, format, strict),
+        Some(Command::Nearest { colour, input, max_delta_e }) => {
+            run_nearest(*colour, input, *max_delta_e, analysis, format, strict)
+        }
+        Some(Command::Mix { from, to, ratio, steps }) => run_mix(from, to, *ratio, *steps, analysis, format, strict),
+        Some(Command::Transform { input, rotate, lighten, darken, desaturate }) => {
+            let transform = Transform { rotate: *rotate, lighten: lighten - darken, desaturate: *desaturate };
+            run_transform(input, &transform, analysis, format, strict)
+        }
+        Some(Command::Bench { image, spaces, inits, runs }) => {
+            run_bench(image.as_deref(), spaces, inits, *runs, analysis, format)
+        }
+        #[cfg(feature = "index")]
+        Some(Command::Index { dir, db }) => run_index(dir, db, analysis, strict),
+        #[cfg(feature = "index")]
+        Some(Command::Query { colour, db, tolerance, limit }) => {
+            run_query(*colour, db, *tolerance, *limit, analysis.metric, format)
+        }
+        #[cfg(feature = "server")]
+        Some(Command::Serve { addr, allow_fetch }) => server::run(addr, *allow_fetch),
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut Args::command(), "dominant-colours", &mut std::io::stdout());
+            Ok(())
+        }
+    }
+}
+
+/// Analysis cache, when `--cache-dir` is given.
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+/// Analyse one image behind a progress spinner.
+fn analyze(image: &str, options: &AnalysisOptions, strict: bool) -> Result<Analysis> {
+    let _stage = progress::stage(image);
+    let analysis = match CACHE.get() {
+        Some(cache) => cache.analyze(image, options)?,
+        None => analyze_image(image, options)?,
+    };
+    check_warnings(&analysis, strict)?;
+    Ok(analysis)
+}
+
+/// Palettes of the `--regions` of an image, if any were given.
+fn region_palettes(image: &str, ctx: &OutputContext, strict: bool) -> Result<Vec<RegionPalette>> {
+    if ctx.regions.is_empty() {
+        return Ok(Vec::new());
+    }
+    let palettes = regions::analyze_file(image, &ctx.regions, ctx.analysis)?;
+    for region in &palettes {
+        check_warnings(&region.analysis, strict).with_context(|| format!("Region {:?}", region.name))?;
+    }
+    Ok(palettes)
+}
+
+/// Analyse one input with everything `ExtractArgs` asks for besides the main palette,
+/// writing its `--sidecar` if asked.
+fn analyze_input(image: &str, ctx: &OutputContext, strict: bool) -> Result<(Analysis, Extras)> {
+    let (analysis, mut extras) = analyze_extras(image, ctx, strict)?;
+    extras.compliance = compliance_report(&analysis.colours, ctx);
+    if ctx.args.sidecar {
+        write_sidecar(image, &analysis, &extras, ctx)?;
+    }
+    Ok((analysis, extras))
+}
+
+fn analyze_extras(image: &str, ctx: &OutputContext, strict: bool) -> Result<(Analysis, Extras)> {
+    if !ctx.candidates.is_empty() {
+        let analysis = {
+            let _stage = progress::stage(image);
+            candidates::coverage_of_file(image, &ctx.candidates, ctx.analysis)?
+        };
+        let regions = region_palettes(image, ctx, strict)?;
+        let (lqip, hash) = placeholders(image, ctx)?;
+        let identities = identities(&analysis, ctx);
+        return Ok((analysis, Extras { regions, lqip, hash, identities, ..Extras::default() }));
+    }
+    let Some(seconds) = ctx.args.timeline else {
+        let mut analysis = analyze(image, ctx.analysis, strict)?;
+        let regions = region_palettes(image, ctx, strict)?;
+        let stability = if ctx.args.multiscale {
+            let stability = {
+                let _stage = progress::stage(image);
+                multiscale::stability_of_file(image, &analysis, ctx.analysis)?
+            };
+            analysis.warnings.extend(stability.warnings.iter().cloned());
+            check_warnings(&analysis, strict)?;
+            Some(stability)
+        } else {
+            None
+        };
+        let (lqip, hash) = placeholders(image, ctx)?;
+        let identities = identities(&analysis, ctx);
+        return Ok((analysis, Extras { regions, stability, lqip, hash, identities, ..Extras::default() }));
+    };
+    let timeline = {
+        let _stage = progress::stage(image);
+        timeline::analyze(image, seconds, ctx.args.timeline_sheet.is_some(), ctx.analysis)?
+    };
+    check_warnings(&timeline.palette, strict)?;
+    for bucket in &timeline.buckets {
+        check_warnings(&bucket.analysis, strict)
+            .with_context(|| format!("Timeline at {:.1} s", bucket.start))?;
+    }
+    Ok((timeline.palette, Extras { timeline: timeline.buckets, frames: timeline.frames, ..Extras::default() }))
+}
+
+/// Write `photo.jpg.colours.json` beside `photo.jpg`: its JSON analysis plus what made it,
+/// so that tools can reuse the result and spot when the image or the options changed.
+/// Archive members and remote images have nowhere to put one and are skipped.
+fn write_sidecar(image: &str, analysis: &Analysis, extras: &Extras, ctx: &OutputContext) -> Result<()> {
+    if !std::path::Path::new(image).is_file() {
+        warn!(file = image, "Not writing a sidecar for an input that is not a local file");
+        return Ok(());
+    }
+    let contents = std::fs::read(image).with_context(|| format!("Failed to read {} to hash it", image))?;
+    let file = std::path::Path::new(image).file_name().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let json = with_schema_version(serde_json::json!({
+        "file": file,
+        "content_hash": format!("blake3:{}", blake3::hash(&contents).to_hex()),
+        "tool": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+        "options": options_json(ctx.analysis),
+        "analysis": build_json(analysis, extras, ctx),
+    }));
+    let path = format!("{}.colours.json", image);
+    info!(file = %path, "Saving sidecar");
+    std::fs::write(&path, serde_json::to_string_pretty(&json)? + "\n")
+        .with_context(|| format!("Failed to save sidecar {}", path))
+}
+
+/// The analysis options as JSON, with values spelt as on the command line.
+fn options_json(options: &AnalysisOptions) -> serde_json::Value {
+    serde_json::json!({
+        "colours": options.colours,
+        "top": options.top,
+        "max_pixels": options.max_pixels,
+        "silhouette": options.silhouette,
+        "space": options.space.to_string(),
+        "linear": options.linear,
+        "init": options.init.to_string(),
+        "sort": options.sort.to_string(),
+        "reverse": options.reverse,
+        "histogram": options.histogram.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        "vividness": options.vividness,
+        "auto_levels": options.auto_levels,
+        "gamma": options.gamma,
+        "denoise": options.denoise.map(|d| d.to_string()),
+        "exclude_skin": options.exclude_skin,
+        "mask": options.mask,
+        "mask_mode": options.mask_mode.to_string(),
+        "page": options.page,
+        "mode": options.mode.to_string(),
+        "exact": options.exact,
+        "metric": options.metric.to_string(),
+    })
+}
+
+/// Identities carried over from the `--previous` result, if there is one.
+fn identities(analysis: &Analysis, ctx: &OutputContext) -> Vec<Identity> {
+    ctx.previous.as_ref().map(|previous| tracking::assign(previous, &analysis.colours, ctx.analysis.metric)).unwrap_or_default()
+}
+
+/// The `--lqip` placeholder and the `--format blurhash` or `thumbhash` string of an
+/// image, whichever are asked for, from one decode.
+fn placeholders(image: &str, ctx: &OutputContext) -> Result<(Option<String>, Option<String>)> {
+    let hash: Option<fn(&image::DynamicImage) -> String> = match ctx.format {
+        OutputFormat::Blurhash => Some(lqip::blurhash),
+        OutputFormat::Thumbhash => Some(lqip::thumbhash),
+        _ => None,
+    };
+    if ctx.args.lqip.is_none() && hash.is_none() {
+        return Ok((None, None));
+    }
+    let _stage = progress::stage(image);
+    let img = dominant_colours::open_image(image).with_context(|| format!("Failed to open {} for a placeholder", image))?;
+    let placeholder = ctx.args.lqip.map(|style| lqip::placeholder(&img, style)).transpose()?;
+    Ok((placeholder, hash.map(|hash| hash(&img))))
+}
+
+/// Under `--strict`, fail on the warnings raised during an analysis.
+fn check_warnings(analysis: &Analysis, strict: bool) -> Result<()> {
+    if strict && !analysis.warnings.is_empty() {
+        return Err(Failed::new(Exit::Strict, analysis.warnings.join("; ")).into());
+    }
+    Ok(())
+}
+
+/// Log a file that is being skipped, or under `--strict` fail instead.
+fn skip(path: &str, err: anyhow::Error, strict: bool) -> Result<()> {
+    if strict {
+        return Err(err.context(format!("{} (not skipped under --strict)", path)));
+    }
+    warn!(file = %path, "Skipping: {:#}", err);
+    Ok(())
+}
+
+fn run_quantize(image: &str, output: &str, palette: Option<&str>, options: &AnalysisOptions, strict: bool) -> Result<()> {
+    let stage = progress::stage(image);
+    info!(file = image, "Loading image");
+    let img = dominant_colours::open_image(image).context("Failed to open image file")?;
+    let colours = match palette {
+        Some(path) => palette::load(path)?,
+        None => {
+            let analysis = cluster_pixels(image_pixels(&img, options), options)?;
+            check_warnings(&analysis, strict)?;
+            analysis.colours
+        }
+    };
+    drop(stage);
+    let palette: Vec<[u8; 3]> = colours.iter().map(|c| c.rgb).collect();
+    info!(file = output, "Saving quantized image");
+    quantize::quantize(&img.to_rgb8(), &palette, options.metric)
+        .save(output)
+        .context("Failed to save quantized image")?;
+    Ok(())
+}
+
+fn run_theme(image: &str, schemes: &[Scheme], options: &AnalysisOptions, format: OutputFormat, strict: bool) -> Result<()> {
+    let colours = analyze(image, options, strict)?.colours;
+    if !schemes.is_empty() {
+        let themes = schemes
+            .iter()
+            .map(|&scheme| Ok((scheme, Theme::for_scheme(&colours, scheme).context("No colours extracted")?)))
+            .collect::<Result<Vec<_>>>()?;
+        match format {
+            OutputFormat::Meta => {
+                let manifest = WebColours::from_theme(&themes[0].1).to_manifest();
+                println!("\n{}\n\n{}", theme::schemes_html(&themes), manifest);
+            }
+            OutputFormat::Json => {
+                let json: serde_json::Map<_, _> =
+                    themes.iter().map(|(scheme, theme)| (scheme.to_string(), theme_json(theme))).collect();
+                print_json(json.into())?;
+            }
+            _ => println!("\n{}", theme::schemes_css(&themes)),
+        }
+        return Ok(());
+    }
+    let theme = Theme::from_palette(&colours).context("No colours extracted")?;
+    match format {
+        OutputFormat::Text | OutputFormat::Gradient | OutputFormat::Blurhash | OutputFormat::Thumbhash => {
+            println!("\n{}", theme.to_css())
+        }
+        OutputFormat::Meta => print_web_colours(&colours)?,
+        OutputFormat::Json => print_json(theme_json(&theme))?,
+    }
+    Ok(())
+}
+
+fn theme_json(theme: &Theme) -> serde_json::Value {
+    serde_json::json!({
+        "background": rgb_to_hex(theme.background),
+        "foreground": rgb_to_hex(theme.foreground),
+        "accent": rgb_to_hex(theme.accent),
+        "on_accent": rgb_to_hex(theme.on_accent),
+        "palette": theme.palette.iter().map(|&rgb| rgb_to_hex(rgb)).collect::<Vec<_>>()
+    })
+}
+
+/// The `theme-color` meta tag and manifest members for a palette.
+fn print_web_colours(colours: &[ColourInfo]) -> Result<()> {
+    let web = WebColours::from_palette(colours).context("No colours extracted")?;
+    println!("\n{}\n\n{}", web.to_html(), web.to_manifest());
+    Ok(())
+}
+
+/// A palette given on the command line: a hex colour, an image to extract a palette
+/// from, or a palette file.
+fn palette_input(input: &str, options: &AnalysisOptions, strict: bool) -> Result<Vec<ColourInfo>> {
+    if let (Some(rgb), false) = (colour::parse_hex(input), std::path::Path::new(input).exists()) {
+        return Ok(vec![ColourInfo::new(rgb, 100.0)]);
+    }
+    match image::ImageFormat::from_path(input) {
+        Ok(_) => Ok(analyze(input, options, strict)?.colours),
+        Err(_) => palette::load(input),
+    }
+}
+
+fn run_nearest(
+    target: [u8; 3],
+    input: &str,
+    max_delta_e: Option<f64>,
+    options: &AnalysisOptions,
+    format: OutputFormat,
+    strict: bool,
+) -> Result<()> {
+    let colours = palette_input(input, options, strict)?;
+    let nearest = compare::match_colours(&[ColourInfo::new(target, 100.0)], &colours, options.metric)
+        .pop()
+        .context("No colours extracted")?;
+
+    match format {
+        OutputFormat::Text
+        | OutputFormat::Meta
+        | OutputFormat::Gradient
+        | OutputFormat::Blurhash
+        | OutputFormat::Thumbhash => println!(
+            "\nNearest to {}: {} (delta-E {:.1}), {}% of {}",
+            rgb_to_hex(target), rgb_to_hex(nearest.matched_rgb), nearest.delta_e, share(nearest.matched_percentage, 1), input
+        ),
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "colour": rgb_to_hex(target),
+                "nearest": { "hex": rgb_to_hex(nearest.matched_rgb), "percentage": share_json(nearest.matched_percentage) },
+                "delta_e": nearest.delta_e
+            });
+            print_json(json)?;
+        }
+    }
+    match max_delta_e {
+        Some(max) if nearest.delta_e > max => Err(Failed::new(
+            Exit::CheckFailed,
+            format!("Nothing within delta-E {} of {}; nearest is {:.1} away", max, rgb_to_hex(target), nearest.delta_e),
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+fn run_diff(
+    old: &str,
+    new: &str,
+    threshold: f64,
+    check: bool,
+    options: &AnalysisOptions,
+    format: OutputFormat,
+    strict: bool,
+) -> Result<()> {
+    let (old_colours, new_colours) = (palette_input(old, options, strict)?, palette_input(new, options, strict)?);
+    let changes = compare::diff(&old_colours, &new_colours, threshold, options.metric);
+    let count = |change: Change| changes.iter().filter(|c| c.change == change).count();
+    let (added, removed, shifted) = (count(Change::Added), count(Change::Removed), count(Change::Shifted));
+
+    match format {
+        OutputFormat::Text
+        | OutputFormat::Meta
+        | OutputFormat::Gradient
+        | OutputFormat::Blurhash
+        | OutputFormat::Thumbhash => {
+            println!("\nPalette changes ({} -> {}):", old, new);
+            for c in &changes {
+                match (&c.old, &c.new) {
+                    (Some(o), Some(n)) => println!(
+                        "{} {} {}% -> {} {}% (delta-E {:.1}, {:+.1} points)",
+                        if c.change == Change::Unchanged { '=' } else { '~' },
+                        rgb_to_hex(o.rgb), share(o.percentage, 1), rgb_to_hex(n.rgb), share(n.percentage, 1),
+                        c.delta_e.unwrap_or_default(), c.coverage_change()
+                    ),
+                    (Some(o), None) => println!("- {} {}%", rgb_to_hex(o.rgb), share(o.percentage, 1)),
+                    (None, Some(n)) => println!("+ {} {}%", rgb_to_hex(n.rgb), share(n.percentage, 1)),
+                    (None, None) => {}
+                }
+            }
+            println!(
+                "{} added, {} removed, {} shifted, {} unchanged",
+                added, removed, shifted, count(Change::Unchanged)
+            );
+        }
+        OutputFormat::Json => {
+            let colour = |c: &Option<ColourInfo>| {
+                c.as_ref().map(|c| serde_json::json!({ "hex": rgb_to_hex(c.rgb), "percentage": share_json(c.percentage) }))
+            };
+            let json = serde_json::json!({
+                "old": old,
+                "new": new,
+                "changes": changes.iter().map(|c| serde_json::json!({
+                    "change": c.change.to_string(),
+                    "old": colour(&c.old),
+                    "new": colour(&c.new),
+                    "delta_e": c.delta_e,
+                    "coverage_change": c.coverage_change()
+                })).collect::<Vec<_>>(),
+                "summary": {
+                    "added": added,
+                    "removed": removed,
+                    "shifted": shifted,
+                    "unchanged": count(Change::Unchanged)
+                }
+            });
+            print_json(json)?;
+        }
+    }
+    if check && added + removed + shifted > 0 {
+        return Err(Failed::new(
+            Exit::CheckFailed,
+            format!("Palette changed: {} added, {} removed, {} shifted", added, removed, shifted),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn run_mix(
+    from: &str,
+    to: &str,
+    ratio: f64,
+    steps: Option<u16>,
+    options: &AnalysisOptions,
+    format: OutputFormat,
+    strict: bool,
+) -> Result<()> {
+    let a = palette_input(from, options, strict)?;
+    let b = palette_input(to, options, strict)?;
+    let ratios: Vec<f64> = match steps {
+        Some(steps) => (1..=steps).map(|i| i as f64 / (steps + 1) as f64).collect(),
+        None => vec![ratio],
+    };
+    let palettes: Vec<(f64, Vec<ColourInfo>)> =
+        ratios.into_iter().map(|r| (r, mix::mix(&a, &b, r, options.metric))).collect();
+
+    match format {
+        OutputFormat::Text
+        | OutputFormat::Meta
+        | OutputFormat::Gradient
+        | OutputFormat::Blurhash
+        | OutputFormat::Thumbhash => {
+            for (ratio, colours) in &palettes {
+                println!("\n{:.0}% of the way from {} to {}:", ratio * 100.0, from, to);
+                for c in colours {
+                    println!("{} - {}%", rgb_to_hex(c.rgb), share(c.percentage, 1));
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "from": from,
+                "to": to,
+                "palettes": palettes.iter().map(|(ratio, colours)| serde_json::json!({
+                    "ratio": ratio,
+                    "colours": colours
+                        .iter()
+                        .map(|c| serde_json::json!({ "hex": rgb_to_hex(c.rgb), "percentage": share_json(c.percentage) }))
+                        .collect::<Vec<_>>()
+                })).collect::<Vec<_>>()
+            });
+            print_json(json)?;
+        }
+    }
+    Ok(())
+}
+
+fn run_transform(
+    input: &str,
+    transform: &Transform,
+    options: &AnalysisOptions,
+    format: OutputFormat,
+    strict: bool,
+) -> Result<()> {
+    let colours = palette_input(input, options, strict)?;
+    let transformed = transform::transform(&colours, transform);
+
+    match format {
+        OutputFormat::Text | OutputFormat::Blurhash | OutputFormat::Thumbhash => {
+            println!("\nTransformed colours of {}:", input);
+            for (before, after) in colours.iter().zip(&transformed) {
+                println!("{} -> {} - {}%", rgb_to_hex(before.rgb), rgb_to_hex(after.rgb), share(after.percentage, 1));
+            }
+        }
+        OutputFormat::Json => {
+            // Loads back as a palette file, so transforms can be chained
+            let json = serde_json::json!({
+                "input": input,
+                "transform": {
+                    "rotate": transform.rotate,
+                    "lighten": transform.lighten,
+                    "desaturate": transform.desaturate,
+                },
+                "colours": colours.iter().zip(&transformed).map(|(before, after)| serde_json::json!({
+                    "hex": rgb_to_hex(after.rgb),
+                    "percentage": share_json(after.percentage),
+                    "original": rgb_to_hex(before.rgb),
+                })).collect::<Vec<_>>()
+            });
+            print_json(json)?;
+        }
+        OutputFormat::Meta => print_web_colours(&transformed)?,
+        OutputFormat::Gradient => println!("\nbackground: {};", theme::css_gradient(&transformed)),
+    }
+    Ok(())
+}
+
+/// Side of the synthetic image `bench` uses when no image is given.
+const BENCH_IMAGE_SIZE: u32 = 512;
+
+fn run_bench(
+    image: Option<&str>,
+    spaces: &[ColourSpace],
+    inits: &[InitMethod],
+    runs: u16,
+    options: &AnalysisOptions,
+    format: OutputFormat,
+) -> Result<()> {
+    let pixels = match image {
+        Some(path) => load_pixels(path, options).with_context(|| format!("Failed to load {}", path))?,
+        None => {
+            let img = bench::synthetic_image(BENCH_IMAGE_SIZE, BENCH_IMAGE_SIZE);
+            image_pixels(&image::DynamicImage::ImageRgb8(img), options)
+        }
+    };
+    let source = image.unwrap_or("synthetic image");
+    let pixel_count = pixels.values.len();
+    info!(image = source, pixels = pixel_count, runs, "Benchmarking clustering settings");
+    let results = bench::run(&pixels, options, spaces, inits, runs.into())?;
+    let ms = |d: std::time::Duration| d.as_secs_f64() * 1000.0;
+
+    match format {
+        OutputFormat::Text
+        | OutputFormat::Meta
+        | OutputFormat::Gradient
+        | OutputFormat::Blurhash
+        | OutputFormat::Thumbhash => {
+            println!(
+                "\nBenchmark of {} ({} pixels, {} colours, {} runs each):\n",
+                source, pixel_count, options.colours, runs
+            );
+            println!("{:<6} {:<9} {:>9} {:>9} {:>9} {:>14}", "space", "init", "mean ms", "min ms", "max ms", "inertia");
+            for result in &results {
+                println!(
+                    "{:<6} {:<9} {:>9.1} {:>9.1} {:>9.1} {:>14.1}",
+                    result.space.to_string(),
+                    result.init.to_string(),
+                    ms(result.mean_time()),
+                    ms(result.min_time()),
+                    ms(result.max_time()),
+                    result.mean_inertia()
+                );
+            }
+            println!("\nInertia is measured in each colour space, so compare it within a space only.");
+        }
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "image": image,
+                "pixels": pixel_count,
+                "colours": options.colours,
+                "runs": runs,
+                "results": results.iter().map(|result| serde_json::json!({
+                    "space": result.space.to_string(),
+                    "init": result.init.to_string(),
+                    "mean_ms": ms(result.mean_time()),
+                    "min_ms": ms(result.min_time()),
+                    "max_ms": ms(result.max_time()),
+                    "inertia": result.mean_inertia()
+                })).collect::<Vec<_>>()
+            });
+            print_json(json)?;
+        }
+    }
+    Ok(())
+}
+
+fn run_dedupe(
+    inputs: &[String],
+    threshold: f64,
+    options: &AnalysisOptions,
+    format: OutputFormat,
+    strict: bool,
+) -> Result<()> {
+    let mut paths = Vec::new();
+    let mut palettes = Vec::new();
+    let inputs = files::expand_inputs(inputs)?;
+    let bar = progress::files(inputs.len());
+    let analyses: Vec<_> = inputs
+        .par_iter()
+        .map(|path| {
+            let path = path.to_string_lossy().into_owned();
+            let analysis = analyze(&path, options, strict);
+            bar.inc(1);
+            (path, analysis)
+        })
+        .collect();
+    bar.finish_and_clear();
+    for (path, analysis) in analyses {
+        match analysis {
+            Ok(analysis) => {
+                paths.push(path);
+                palettes.push(analysis.colours);
+            }
+            Err(e) => skip(&path, e, strict)?,
+        }
+    }
+    let groups = dedupe::groups(&palettes, threshold, options.metric);
+
+    match format {
+        OutputFormat::Text
+        | OutputFormat::Meta
+        | OutputFormat::Gradient
+        | OutputFormat::Blurhash
+        | OutputFormat::Thumbhash => {
+            println!("\n{} group(s) of likely duplicates among {} images:", groups.len(), paths.len());
+            for (n, group) in groups.iter().enumerate() {
+                println!("\nGroup {}:", n + 1);
+                for &i in group {
+                    println!("  {}", paths[i]);
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "threshold": threshold,
+                "images": paths.len(),
+                "groups": groups
+                    .iter()
+                    .map(|g| g.iter().map(|&i| paths[i].clone()).collect::<Vec<_>>())
+                    .collect::<Vec<_>>()
+            });
+            print_json(json)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "index")]
+fn run_index(dir: &str, db: &str, options: &AnalysisOptions, strict: bool) -> Result<()> {
+    let mut conn = index::open(db)?;
+    let images = files::collect_images(std::path::Path::new(dir))?;
+    let mut failures = 0;
+    let bar = progress::files(images.len());
+    let analyses: Vec<_> = images
+        .par_iter()
+        .map(|path| {
+            let path = path.to_string_lossy();
+            info!(file = %path, "Indexing");
+            let analysis = analyze(&path, options, strict);
+            bar.inc(1);
+            analysis
+        })
+        .collect();
+    bar.finish_and_clear();
+    // Insert in directory order, on this thread, which owns the connection
+    for (path, analysis) in images.iter().zip(analyses) {
+        let path = path.to_string_lossy();
+        match analysis {
+            Ok(analysis) => index::insert(&mut conn, &path, &analysis.colours)?,
+            Err(e) => {
+                skip(&path, e, strict)?;
+                failures += 1;
+            }
+        }
+    }
+    println!("\nIndexed {} of {} images into {}", images.len() - failures, images.len(), db);
+    Ok(())
+}
+
+#[cfg(feature = "index")]
+fn run_query(rgb: [u8; 3], db: &str, tolerance: f64, limit: usize, metric: Metric, format: OutputFormat) -> Result<()> {
+    let conn = index::open(db)?;
+    let hits = index::query(&conn, rgb, tolerance, limit, metric)?;
+    match format {
+        OutputFormat::Text
+        | OutputFormat::Meta
+        | OutputFormat::Gradient
+        | OutputFormat::Blurhash
+        | OutputFormat::Thumbhash => {
+            println!("Images containing {} (delta-E <= {}):", rgb_to_hex(rgb), tolerance);
+            for hit in &hits {
+                println!("{:>5}%  {} (nearest delta-E {:.1})", share(hit.coverage, 1), hit.path, hit.delta_e);
+            }
+        }
+        OutputFormat::Json => {
+            let json = serde_json::json!({
+                "colour": rgb_to_hex(rgb),
+                "tolerance": tolerance,
+                "results": hits.iter().map(|h| serde_json::json!({
+                    "path": h.path,
+                    "coverage": share_json(h.coverage),
+                    "delta_e": h.delta_e
+                })).collect::<Vec<_>>()
+            });
+            print_json(json)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_comparison(
+    first: &str,
+    a: &[ColourInfo],
+    second: &str,
+    b: &[ColourInfo],
+    metric: Metric,
+    format: OutputFormat,
+) -> Result<()> {
+    let emd = compare::earth_movers_distance(a, b, metric);
+    let matches = compare::match_colours(a, b, metric);
+    let deviation = compare::mean_delta_e(&matches);
+
+    match format {
+        OutputFormat::Text
+        | OutputFormat::Meta
+        | OutputFormat::Gradient
+        | OutputFormat::Blurhash
+        | OutputFormat::Thumbhash => {
+            println!("\nPalette similarity: {:.3} (EMD {:.2} delta-E)", compare::similarity(emd), emd);
+            println!("Mean deviation: {:.2} delta-E", deviation);
+            println!("\nMatches ({} -> {}):", first, second);
+            for m in &matches {
+                println!(
+                    "{} {}% -> {} {}% (delta-E {:.1})",
+                    rgb_to_hex(m.rgb), share(m.percentage, 1),
+                    rgb_to_hex(m.matched_rgb), share(m.matched_percentage, 1), m.delta_e
+                );
+            }
+        }
+        OutputFormat::Json => {
+            let palette = |colours: &[ColourInfo]| {
+                colours
+                    .iter()
+                    .map(|c| serde_json::json!({ "hex": rgb_to_hex(c.rgb), "percentage": share_json(c.percentage) }))
+                    .collect::<Vec<_>>()
+            };
+            let json = serde_json::json!({
+                "emd": emd,
+                "similarity": compare::similarity(emd),
+                "deviation": deviation,
+                "first": palette(a),
+                "second": palette(b),
+                "matches": matches.iter().map(|m| serde_json::json!({
+                    "colour": rgb_to_hex(m.rgb),
+                    "percentage": share_json(m.percentage),
+                    "match": rgb_to_hex(m.matched_rgb),
+                    "match_percentage": share_json(m.matched_percentage),
+                    "delta_e": m.delta_e
+                })).collect::<Vec<_>>()
+            });
+            print_json(json)?;
+        }
+    }
+    Ok(())
+}
+
+fn run_extract(args: &ExtractArgs, options: &AnalysisOptions, format: OutputFormat, strict: bool) -> Result<()> {
+    let expanded;
+    let args = if args.filenames.iter().any(|f| archive::is_archive_path(f)) {
+        expanded = ExtractArgs { filenames: expand_archives(&args.filenames)?, ..args.clone() };
+        &expanded
+    } else {
+        args
+    };
+    let ctx = OutputContext::new(args, options, format)?;
+    let mut failures = Vec::new();
+
+    if let Some(source) = &args.files_from {
+        failures.extend(run_files_from(&ctx, source, strict)?);
+    } else if let Some(mode) = args.aggregate {
+        if format == OutputFormat::Text {
+            println!("\nCombined palette of {} images:", args.filenames.len());
+        }
+        let analysis = {
+            let _stage = progress::stage(&format!("{} images", args.filenames.len()));
+            let inputs: Vec<_> = args.filenames.iter().map(|input| weighted_input(input)).collect();
+            aggregate_weighted(&inputs, mode, options).map_err(|e| match e.path() {
+                Some(path) => {
+                    let context = format!("Failed to load {}", path);
+                    anyhow::Error::new(e).context(context)
+                }
+                None => e.into(),
+            })?
+        };
+        check_warnings(&analysis, strict)?;
+        let extras = Extras { compliance: compliance_report(&analysis.colours, &ctx), ..Extras::default() };
+        report(&analysis, &extras, &ctx, None)?;
+        failures.extend(check_gates(&analysis.colours, &extras, &ctx));
+    } else if args.filenames.len() == 1 {
+        let (analysis, extras) = analyze_input(&args.filenames[0], &ctx, strict)?;
+        report(&analysis, &extras, &ctx, None)?;
+        failures.extend(check_gates(&analysis.colours, &extras, &ctx));
+    } else {
+        failures.extend(run_batch(&ctx, strict)?);
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    // Report why images could not be analysed in preference to failed checks
+    let exit = failures
+        .iter()
+        .map(|f| f.exit)
+        .find(|&exit| exit != Exit::CheckFailed)
+        .unwrap_or(Exit::CheckFailed);
+    let message = failures.iter().map(|f| f.message.as_str()).collect::<Vec<_>>().join("; ");
+    Err(Failed::new(exit, message).into())
+}
+
+/// Paths read from a `--files-from` list per round of analyses, for each worker thread:
+/// enough to keep them all busy without reading a huge list into memory.
+const FILES_PER_THREAD: usize = 4;
+
+/// Analyse the images listed in `source` a batch at a time on the worker pool, printing
+/// each result as soon as its batch is done. Results keep the order of the list, and
+/// unreadable images are reported and skipped. JSON output is newline-delimited: one
+/// analysis or failure object per image, with no summary.
+fn run_files_from(ctx: &OutputContext, source: &str, strict: bool) -> Result<Vec<Failed>> {
+    let reader: Box<dyn BufRead> = if source == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(source).with_context(|| format!("Failed to open file list {}", source))?))
+    };
+    let mut lines = reader.lines();
+    let batch_size = rayon::current_num_threads() * FILES_PER_THREAD;
+    let mut failures = Vec::new();
+    loop {
+        let mut filenames = Vec::new();
+        for line in lines.by_ref() {
+            let line = line.with_context(|| format!("Failed to read file list {}", source))?;
+            if !line.trim().is_empty() {
+                filenames.push(line.trim().to_string());
+            }
+            if filenames.len() == batch_size {
+                break;
+            }
+        }
+        if filenames.is_empty() {
+            return Ok(failures);
+        }
+
+        let results: Vec<_> = filenames.par_iter().map(|filename| analyze_input(filename, ctx, strict)).collect();
+        for (filename, result) in filenames.iter().zip(results) {
+            match result {
+                Ok((analysis, extras)) => {
+                    if ctx.format != OutputFormat::Json {
+                        progress::suspend(|| println!("\n== {} ==", filename));
+                    }
+                    progress::suspend(|| report(&analysis, &extras, ctx, Some(filename)))?;
+                    if ctx.format == OutputFormat::Json {
+                        let mut json = build_json(&analysis, &extras, ctx);
+                        json["file"] = filename.as_str().into();
+                        println!("{}", serde_json::to_string(&with_schema_version(json))?);
+                    }
+                    failures.extend(check_gates(&analysis.colours, &extras, ctx).into_iter().map(|f| Failed {
+                        message: format!("{}: {}", filename, f.message),
+                        ..f
+                    }));
+                }
+                Err(e) => {
+                    error!(file = %filename, "{:#}", e);
+                    if ctx.format == OutputFormat::Json {
+                        let json = serde_json::json!({ "file": filename, "error": format!("{:#}", e) });
+                        println!("{}", serde_json::to_string(&with_schema_version(json))?);
+                    }
+                    failures.push(Failed::new(Exit::of(&e), format!("{}: {:#}", filename, e)));
+                }
+            }
+        }
+    }
+}
+
+/// Number of colours listed in the batch summary.
+const BATCH_SUMMARY_COLOURS: usize = 10;
+
+/// Analyse several images independently. Unreadable images are reported and skipped so
+/// that one bad file does not abort the batch; JSON output is a single document with a
+/// per-image array, a summary across the set and the list of failures.
+fn run_batch<'a>(ctx: &OutputContext<'a>, strict: bool) -> Result<Vec<Failed>> {
+    let args = ctx.args;
+    let mut failures = Vec::new();
+    let mut errors = Vec::new();
+    let mut results = Vec::new();
+
+    let bar = progress::files(args.filenames.len());
+    let batch_size = rayon::current_num_threads() * FILES_PER_THREAD;
+    let mut pending = Vec::new();
+    // Analyse the pending images on the worker pool, then report them in order
+    let mut analyse_pending = |pending: &mut Vec<&'a str>| -> Result<()> {
+        let analyses: Vec<_> = pending.par_iter().map(|filename| analyze_input(filename, ctx, strict)).collect();
+        for (filename, result) in pending.drain(..).zip(analyses) {
+            bar.set_message(filename.to_string());
+            if ctx.format != OutputFormat::Json {
+                progress::suspend(|| println!("\n== {} ==", filename));
+            }
+            match result {
+                Ok((analysis, extras)) => {
+                    progress::suspend(|| report(&analysis, &extras, ctx, Some(filename)))?;
+                    failures.extend(check_gates(&analysis.colours, &extras, ctx).into_iter().map(|f| Failed {
+                        message: format!("{}: {}", filename, f.message),
+                        ..f
+                    }));
+                    results.push((filename, analysis, extras));
+                }
+                Err(e) => {
+                    error!(file = %filename, "{:#}", e);
+                    failures.push(Failed::new(Exit::of(&e), format!("{}: {:#}", filename, e)));
+                    errors.push((filename, e));
+                }
+            }
+            bar.inc(1);
+        }
+        Ok(())
+    };
+    // Images from an archive are read in one pass over it, and each is only held in
+    // memory while `for_each_input` is on it, so a batch ends at an archive member
+    archive::for_each_input(&args.filenames, |filename| -> Result<()> {
+        pending.push(filename);
+        if archive::split_member(filename).is_some() || pending.len() == batch_size {
+            analyse_pending(&mut pending)?;
+        }
+        Ok(())
+    })?;
+    analyse_pending(&mut pending)?;
+    bar.finish_and_clear();
+
+    let palettes: Vec<&[ColourInfo]> = results.iter().map(|(_, a, _)| a.colours.as_slice()).collect();
+    let common = batch::common_colours(&palettes, &ctx.names, BATCH_SUMMARY_COLOURS, ctx.analysis.metric);
+
+    match ctx.format {
+        OutputFormat::Text => {
+            println!("\nAnalyzed {} of {} images", results.len(), args.filenames.len());
+            println!("Most common colours:");
+            for c in &common {
+                println!("{}: {} image(s), {}% on average", c.name, c.images, share(c.percentage, 1));
+            }
+        }
+        OutputFormat::Json => {
+            let images: Vec<_> = results
+                .iter()
+                .map(|(filename, analysis, extras)| {
+                    let mut json = build_json(analysis, extras, ctx);
+                    json["file"] = serde_json::json!(filename);
+                    json
+                })
+                .collect();
+            let json = serde_json::json!({
+                "images": images,
+                "summary": {
+                    "images": args.filenames.len(),
+                    "analyzed": results.len(),
+                    "failed": errors.len(),
+                    "common_colours": common.iter().map(|c| serde_json::json!({
+                        "name": c.name,
+                        "images": c.images,
+                        "percentage": share_json(c.percentage),
+                    })).collect::<Vec<_>>(),
+                },
+                "failures": errors.iter().map(|(filename, e)| serde_json::json!({
+                    "file": filename,
+                    "error": format!("{:#}", e),
+                })).collect::<Vec<_>>(),
+            });
+            print_json(json)?;
+        }
+        OutputFormat::Meta | OutputFormat::Gradient | OutputFormat::Blurhash | OutputFormat::Thumbhash => {}
+    }
+
+    Ok(failures)
+}
+
+/// Replace each ZIP or tar archive among `filenames` with the images in it.
+fn expand_archives(filenames: &[String]) -> Result<Vec<String>> {
+    let mut expanded = Vec::new();
+    for filename in filenames {
+        if !archive::is_archive_path(filename) {
+            expanded.push(filename.clone());
+            continue;
+        }
+        let images = archive::images(filename).with_context(|| format!("Failed to read archive {}", filename))?;
+        if images.is_empty() {
+            warn!(file = %filename, "No images in archive");
+        }
+        expanded.extend(images);
+    }
+    if expanded.is_empty() {
+        anyhow::bail!("No images to analyse in {}", filenames.join(", "));
+    }
+    Ok(expanded)
+}
+
+/// Split an `--aggregate` input into its file and weight: `hero.jpg:3` has weight 3 and
+/// a bare file weight 1. A file that exists under the whole name, colon and all, is
+/// taken as it is.
+fn weighted_input(input: &str) -> (String, f64) {
+    if !std::path::Path::new(input).exists() {
+        if let Some((filename, weight)) = input.rsplit_once(':') {
+            if let Ok(weight) = weight.parse() {
+                return (filename.to_string(), weight);
+            }
+        }
+    }
+    (input.to_string(), 1.0)
+}
+
+/// Derive a per-image output path, e.g. `swatch.svg` + `photos/cat.jpg` -> `swatch-cat.svg`.
+fn per_image_path(output: &str, image: &str) -> String {
+    let output = std::path::Path::new(output);
+    let image_stem = std::path::Path::new(image)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = output.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match output.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, image_stem, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, image_stem),
+    };
+    output.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Print one line per colour, with its share of the `whole` and its nearest name; with
+/// `identities`, each line starts with the colour's ID and names carried over replace
+/// the nearest named colour.
+fn print_colours(colours: &[ColourInfo], identities: &[Identity], whole: &str, ctx: &OutputContext) {
+    for (i, colour) in colours.iter().enumerate() {
+        let identity = identities.get(i);
+        let name = match identity.and_then(|identity| identity.name.as_ref()) {
+            Some(name) => format!(" ({})", name),
+            None => names::nearest(colour.rgb, &ctx.names, ctx.analysis.metric)
+                .map(|(named, distance)| format!(" ({}, delta-E {:.1})", named.name, distance))
+                .unwrap_or_default(),
+        };
+        let id = identity.map(|identity| format!("[{}] ", identity.id)).unwrap_or_default();
+        println!(
+            "{}RGB: ({}, {}, {}) - {}% of {}{}",
+            id, colour.rgb[0], colour.rgb[1], colour.rgb[2], share(colour.percentage, 1), whole, name
+        );
+    }
+}
+
+fn print_counts(analysis: &Analysis) {
+    println!(
+        "\nPixel counts ({} sampled of {} in the original):",
+        analysis.sampled_pixels, analysis.source_pixels
+    );
+    for colour in &analysis.colours {
+        println!(
+            "{}: {} sampled, about {} in the original",
+            rgb_to_hex(colour.rgb), analysis.pixel_count(colour), analysis.area(colour)
+        );
+    }
+}
+
+/// Print one analysis and write any requested files. In a batch, `image` names the
+/// source so that each image gets its own output files and JSON is left to the caller.
+fn report(analysis: &Analysis, extras: &Extras, ctx: &OutputContext, image: Option<&str>) -> Result<()> {
+    let args = ctx.args;
+    let colours = &analysis.colours;
+
+    match ctx.format {
+        OutputFormat::Text => {
+            let order = match (ctx.analysis.sort, ctx.analysis.reverse) {
+                (ColourOrder::Percentage, false) => "prevalence".to_string(),
+                (ColourOrder::Percentage, true) => "prevalence, least first".to_string(),
+                (sort, false) => sort.to_string(),
+                (sort, true) => format!("{}, reversed", sort),
+            };
+            if ctx.analysis.mode == AnalysisMode::Luma {
+                println!("\nDominant brightness bands (sorted by {}):", order);
+            } else if ctx.analysis.mode == AnalysisMode::Average {
+                println!("\nAverage colour:");
+            } else if ctx.analysis.mode == AnalysisMode::Median {
+                println!("\nMedian colour:");
+            } else if !ctx.candidates.is_empty() {
+                println!("\nCoverage of candidate colours (sorted by {}):", order);
+            } else if ctx.analysis.exact.is_some() {
+                println!("\nMost common colours (exact counts, sorted by {}):", order);
+            } else if analysis.greyscale {
+                println!("\nDominant grey levels (greyscale image, sorted by {}):", order);
+            } else {
+                println!("\nDominant colours (sorted by {}):", order);
+            }
+            print_colours(colours, &extras.identities, "image", ctx);
+            if ctx.candidates.is_empty() && colours.len() < ctx.analysis.reported_colours() {
+                println!("({} of {} requested colours found)", colours.len(), ctx.analysis.reported_colours());
+            }
+            if let Some(lqip) = &extras.lqip {
+                println!("\nPlaceholder: {}", lqip);
+            }
+            if let Some(stability) = &extras.stability {
+                let sides: Vec<_> = stability.sides.iter().map(|s| s.to_string()).collect();
+                println!("\nStability across sampling scales ({} px):", sides.join(", "));
+                for (colour, &found) in colours.iter().zip(&stability.scales_found) {
+                    let note = if found * 2 < stability.sides.len() { " (may be a resampling artefact)" } else { "" };
+                    println!("{}: found at {} of {} scales{}", rgb_to_hex(colour.rgb), found, stability.sides.len(), note);
+                }
+            }
+            if args.counts {
+                print_counts(analysis);
+            }
+            for region in &extras.regions {
+                println!("\nRegion {}:", region.name);
+                print_colours(&region.analysis.colours, &[], "region", ctx);
+                if args.counts {
+                    print_counts(&region.analysis);
+                }
+            }
+            if let Some(seconds) = args.timeline {
+                println!("\nTimeline ({} s buckets):", seconds);
+                for bucket in &extras.timeline {
+                    let colours: Vec<_> = bucket
+                        .analysis
+                        .colours
+                        .iter()
+                        .map(|c| format!("{} {}%", rgb_to_hex(c.rgb), share(c.percentage, 0)))
+                        .collect();
+                    println!("{:>7.1}-{:<7.1} {}", bucket.start, bucket.end, colours.join(", "));
+                }
+            }
+            if args.stats {
+                println!("\nCluster statistics:");
+                for colour in colours {
+                    // Greyscale images and brightness bands are clustered on Lab lightness
+                    // alone; summary colours, exact counts and candidates are taken in RGB
+                    let space = match ctx.analysis.mode {
+                        _ if ctx.analysis.exact.is_some() || !ctx.candidates.is_empty() => ColourSpace::Rgb,
+                        AnalysisMode::Average | AnalysisMode::Median => ColourSpace::Rgb,
+                        AnalysisMode::Luma => ColourSpace::Lab,
+                        AnalysisMode::Colour if analysis.greyscale => ColourSpace::Lab,
+                        AnalysisMode::Colour => ctx.analysis.space,
+                    };
+                    let [c0, c1, c2] = space.channels();
+                    let [hue, saturation, lightness] = colour.hsl_spread;
+                    println!(
+                        "{}: std dev {} {:.1} {} {:.1} {} {:.1}; spread hue {:.1}° saturation {:.1}% lightness {:.1}%",
+                        rgb_to_hex(colour.rgb), c0, colour.std_dev[0], c1, colour.std_dev[1], c2, colour.std_dev[2],
+                        hue, saturation, lightness
+                    );
+                }
+                println!("Inertia: {:.1}", analysis.inertia);
+            }
+            if ctx.analysis.silhouette {
+                match analysis.silhouette {
+                    Some(score) => println!("\nSilhouette score: {:.3}", score),
+                    None => println!("\nSilhouette score: n/a (fewer than two clusters)"),
+                }
+            }
+            if args.timings {
+                println!("\nTimings:");
+                for (stage, d) in analysis.timings.stages() {
+                    println!("{:>10}: {:>8.1} ms", stage, d.as_secs_f64() * 1000.0);
+                }
+                println!("{:>10}: {:>8.1} ms", "total", analysis.timings.total().as_secs_f64() * 1000.0);
+            }
+            if args.locate {
+                println!("\nRepresentative pixels:");
+                for colour in colours {
+                    if let Some((x, y)) = colour.location {
+                        println!("{}: ({}, {})", rgb_to_hex(colour.rgb), x, y);
+                    }
+                }
+            }
+            if args.accent {
+                if let Some(accent) = select_accent(colours) {
+                    println!(
+                        "\nAccent colour: RGB: ({}, {}, {}) {}",
+                        accent.rgb[0], accent.rgb[1], accent.rgb[2], rgb_to_hex(accent.rgb)
+                    );
+                }
+            }
+            if args.cmyk {
+                println!("\nCMYK:");
+                for colour in colours {
+                    let [c, m, y, k] = match &ctx.icc {
+                        Some(icc) => icc.convert(colour.rgb),
+                        None => cmyk::naive(colour.rgb),
+                    };
+                    println!(
+                        "{}: C {:.0}% M {:.0}% Y {:.0}% K {:.0}%",
+                        rgb_to_hex(colour.rgb), c, m, y, k
+                    );
+                }
+            }
+            if args.temperature {
+                println!("\nColour temperature:");
+                for colour in colours {
+                    print_temperature(&rgb_to_hex(colour.rgb), colour.rgb);
+                }
+                if let Some(mean) = palette_mean(colours) {
+                    print_temperature("Overall", mean);
+                }
+            }
+            if args.contrast_matrix {
+                println!("\nContrast ratios:");
+                let matrix = contrast::contrast_matrix(colours);
+                print!("{:>8}", "");
+                for colour in colours {
+                    print!(" {:>8}", rgb_to_hex(colour.rgb));
+                }
+                println!();
+                for (colour, row) in colours.iter().zip(&matrix) {
+                    print!("{:>8}", rgb_to_hex(colour.rgb));
+                    for ratio in row {
+                        print!(" {:>8.2}", ratio);
+                    }
+                    println!();
+                }
+            }
+            for histogram in &analysis.histograms {
+                print_histogram(histogram);
+            }
+            if let Some(vividness) = &analysis.vividness {
+                println!(
+                    "\nColourfulness: {:.1}; mean saturation {:.1}%; contrast {:.3}",
+                    vividness.colourfulness, vividness.mean_saturation, vividness.contrast
+                );
+            }
+            if args.diversity {
+                let diversity = diversity::measure(colours, ctx.analysis.metric);
+                println!(
+                    "\nPalette diversity: {:.1} (entropy {:.2} bits; mean delta-E {:.1})",
+                    diversity.score, diversity.entropy, diversity.mean_delta_e
+                );
+            }
+            for &deficiency in &args.simulate {
+                println!("\nSimulated {}:", deficiency);
+                for colour in colours {
+                    let sim = cvd::simulate(colour.rgb, deficiency);
+                    println!(
+                        "RGB: ({}, {}, {}) -> ({}, {}, {}) {}",
+                        colour.rgb[0], colour.rgb[1], colour.rgb[2],
+                        sim[0], sim[1], sim[2], rgb_to_hex(sim)
+                    );
+                }
+            }
+            if args.cvd_check {
+                println!("\nColour vision check (delta-E < {}):", args.cvd_threshold);
+                let conflicts = cvd_conflicts(colours, ctx);
+                if conflicts.is_empty() {
+                    println!("No conflicts found");
+                }
+                for c in &conflicts {
+                    println!(
+                        "{}: {} / {} - delta-E {:.1} -> {:.1}",
+                        c.deficiency, rgb_to_hex(c.first), rgb_to_hex(c.second),
+                        c.delta_e, c.simulated_delta_e
+                    );
+                }
+            }
+            if let Some(report) = &extras.compliance {
+                println!("\nBrand compliance: {}", if report.passed { "PASS" } else { "FAIL" });
+                for r in &report.required {
+                    println!(
+                        "{}: {}% coverage (nearest delta-E {:.1})",
+                        rgb_to_hex(r.rgb), share(r.coverage, 1), r.nearest_delta_e
+                    );
+                }
+                println!("Total coverage: {}% (minimum {}%)", share(report.total_coverage, 1), share(args.min_coverage, 1));
+            }
+            if args.wcag_pairs {
+                println!("\nWCAG contrast pairs:");
+                for pair in contrast::wcag_pairs(colours) {
+                    println!(
+                        "{} / {} - {:.2}:1 ({})",
+                        rgb_to_hex(pair.first), rgb_to_hex(pair.second),
+                        pair.ratio, pair.levels.join(", ")
+                    );
+                }
+            }
+        }
+        OutputFormat::Json => {
+            if image.is_none() {
+                let json = build_json(analysis, extras, ctx);
+                print_json(json)?;
+            }
+        }
+        OutputFormat::Meta => print_web_colours(colours)?,
+        OutputFormat::Gradient => println!("\nbackground: {};", theme::css_gradient(colours)),
+        OutputFormat::Blurhash | OutputFormat::Thumbhash => {
+            if let Some(hash) = &extras.hash {
+                println!("{}", hash);
+            }
+        }
+    }
 
-    /// Number of colours to extract
-    #[arg(short, long, default_value_t = 6)]
-    colours: usize,
+    let output_path = |path: &str| match image {
+        Some(image) => per_image_path(path, image),
+        None => path.to_string(),
+    };
 
-    /// Output format for colour data
-    #[arg(short = 'f', long, default_value_t = OutputFormat::Text)]
-    format: OutputFormat,
+    // Save swatch if requested
+    if args.swatch {
+        let path = output_path(&args.output);
+        info!(file = %path, "Saving colour swatch");
+        save_colour_swatch(colours, &args.simulate, &args.swatch_style, &path)
+            .context("Failed to save colour swatch")?;
+    }
 
-    /// Output SVG swatch
-    #[arg(short, long)]
-    swatch: bool,
+    if let Some(path) = &args.contrast_svg {
+        let path = output_path(path);
+        info!(file = %path, "Saving contrast heat map");
+        contrast::save_contrast_heatmap(colours, &path)
+            .context("Failed to save contrast heat map")?;
+    }
 
-    /// SVG swatch output file (defaults to "swatch.svg" if not specified)
-    #[arg(short, long, default_value = "swatch.svg")]
-    output: String,
+    if let Some(path) = &args.histogram_svg {
+        let path = output_path(path);
+        info!(file = %path, "Saving histogram plot");
+        histogram::save_svg(&analysis.histograms, &path).context("Failed to save histogram plot")?;
+    }
+
+    if let Some(path) = &args.timeline_svg {
+        let path = output_path(path);
+        info!(file = %path, "Saving timeline strip");
+        timeline::save_svg(&extras.timeline, &path).context("Failed to save timeline strip")?;
+    }
+
+    if let Some(path) = &args.timeline_sheet {
+        let path = output_path(path);
+        info!(file = %path, "Saving timeline sprite sheet");
+        let cell = u32::try_from(args.swatch_style.swatch_size).context("--swatch-size is too large for a sprite sheet")?;
+        timeline::save_sheet(&extras.frames, &path, cell)
+            .context("Failed to save timeline sprite sheet")?;
+    }
+
+    if let Some(dir) = &args.masks {
+        save_masks(colours, image.unwrap_or(&args.filenames[0]), dir, ctx.analysis.metric)?;
+    }
+
+    Ok(())
 }
 
-#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
-enum OutputFormat {
-    Text,
-    Json,
+/// Write the `--masks` of `image` in `dir`, e.g. `dir/photo-1-3c79b8.png` for its first colour.
+fn save_masks(colours: &[ColourInfo], image: &str, dir: &str, metric: Metric) -> Result<()> {
+    let img = dominant_colours::open_image(image).with_context(|| format!("Failed to open {} for masks", image))?;
+    let palette: Vec<[u8; 3]> = colours.iter().map(|c| c.rgb).collect();
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create mask directory {}", dir))?;
+    let stem = std::path::Path::new(image).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    for (i, (mask, rgb)) in quantize::masks(&img.to_rgb8(), &palette, metric).into_iter().zip(palette).enumerate() {
+        let hex = rgb_to_hex(rgb);
+        let path = std::path::Path::new(dir).join(format!("{}-{}-{}.png", stem, i + 1, &hex[1..]));
+        info!(file = %path.display(), "Saving colour mask");
+        mask.save(&path).with_context(|| format!("Failed to save mask {}", path.display()))?;
+    }
+    Ok(())
 }
 
-impl std::fmt::Display for OutputFormat {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            OutputFormat::Text => write!(f, "text"),
-            OutputFormat::Json => write!(f, "json"),
+/// Pass/fail checks that should make the run exit with an error.
+fn check_gates(colours: &[ColourInfo], extras: &Extras, ctx: &OutputContext) -> Vec<Failed> {
+    let args = ctx.args;
+    let mut failures = Vec::new();
+    if extras.compliance.as_ref().is_some_and(|report| !report.passed) {
+        failures.push(Failed::new(Exit::CheckFailed, "Brand compliance check failed"));
+    }
+    if args.cvd_fail {
+        let count = cvd_conflicts(colours, ctx).len();
+        if count > 0 {
+            let message = format!("Colour vision check failed: {} indistinguishable pair(s)", count);
+            failures.push(Failed::new(Exit::CheckFailed, message));
         }
     }
+    failures
 }
 
-// Struct to hold colour and its cluster size
-#[derive(Debug)]
-struct ColourInfo {
-    rgb: [u8; 3],
-    percentage: f64,
-}
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use dominant_colours::vividness;
+    use image::{RgbImage, Rgb};
+    use tempfile::tempdir;
 
-fn rgb_to_hex(rgb: [u8; 3]) -> String {
-    format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
-}
+    #[test]
+    fn test_arg_parsing() {
+        let args = Args::parse_from(["program", "test.jpg"]);
+        assert_eq!(args.extract.filenames, ["test.jpg"]);
+        assert_eq!(args.analysis.colours, 6); // default value
+        assert!(!args.extract.swatch); // default false
 
-fn save_colour_swatch(colours: &Vec<ColourInfo>, output_file: &str) -> Result<()> {
-    let mut svg = String::from(
-        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
-<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 600 140">"#
-    );
+        let args = Args::parse_from(["program", "-c", "8", "test.jpg"]);
+        assert_eq!(args.analysis.colours, 8);
 
-    for (i, colour) in colours.iter().enumerate() {
-        let x = i * 100;
-        svg.push_str(&format!(
-            r#"
-    <rect x="{}" y="0" width="100" height="100" fill="rgb({}, {}, {})"/>
-    <text x="{}" y="115" font-family="Arial" font-size="10" fill="black">{}, {}, {}</text>
-    <text x="{}" y="130" font-family="Arial" font-size="10" fill="black">{:.1}%</text>"#,
-            x, colour.rgb[0], colour.rgb[1], colour.rgb[2],
-            x + 5, colour.rgb[0], colour.rgb[1], colour.rgb[2],
-            x + 5, colour.percentage
-        ));
+        let args = Args::parse_from(["program", "--swatch", "-o", "custom.svg", "test.jpg"]);
+        assert!(args.extract.swatch);
+        assert_eq!(args.extract.output, "custom.svg");
     }
 
-    svg.push_str("\n</svg>");
+    /// A 20x20 image, `left` on its left half and `right` on its right.
+    fn halves(left: [u8; 3], right: [u8; 3]) -> image::DynamicImage {
+        image::DynamicImage::ImageRgb8(RgbImage::from_fn(20, 20, |x, _| Rgb(if x < 10 { left } else { right })))
+    }
 
-    let mut file = File::create(output_file)?;
-    file.write_all(svg.as_bytes())?;
-    Ok(())
-}
+    #[test]
+    fn test_space_args() -> Result<()> {
+        // Black, dark blue and grey in two clusters: black is nearer the blue in RGB but
+        // looks nearer the grey, so in Lab the blue keeps a cluster of its own
+        let thirds = image::DynamicImage::ImageRgb8(RgbImage::from_fn(30, 30, |x, _| {
+            Rgb([[0, 0, 0], [0, 0, 120], [90, 90, 90]][x as usize / 10])
+        }));
+        let has_blue = |argv: &[&str]| -> Result<bool> {
+            let args = Args::parse_from(["program", "-c", "2"].iter().chain(argv).chain(&["test.jpg"]));
+            let analysis = cluster_pixels(image_pixels(&thirds, &args.analysis), &args.analysis)?;
+            Ok(analysis.colours.iter().any(|c| colour::delta_e(c.rgb, [0, 0, 120]) < 5.0))
+        };
+        assert_eq!(Args::parse_from(["program", "test.jpg"]).analysis.space, ColourSpace::Rgb);
+        assert!(!has_blue(&[])?);
+        assert_eq!(Args::parse_from(["program", "--space", "lab", "test.jpg"]).analysis.space, ColourSpace::Lab);
+        assert!(has_blue(&["--space", "lab"])?);
+        Ok(())
+    }
 
-fn analyze_image(args: &Args) -> Result<Vec<ColourInfo>> {
-    println!("Loading image...");
-    let img = image::open(&args.filename)
-        .context("Failed to open image file")?;
+    #[test]
+    fn test_init_args() -> Result<()> {
+        assert_eq!(Args::parse_from(["program", "test.jpg"]).analysis.init, InitMethod::KmeansPlusPlus);
+        let args = Args::parse_from(["program", "--init", "random", "-c", "1", "test.jpg"]);
+        assert_eq!(args.analysis.init, InitMethod::Random);
 
-    println!("Resizing image...");
-    let resized = img.resize(150, 150, image::imageops::FilterType::Lanczos3);
+        // Random starting centroids still converge: one cluster lands on the mean colour
+        let analysis = cluster_pixels(image_pixels(&halves([200, 40, 0], [0, 40, 200]), &args.analysis), &args.analysis)?;
+        assert_eq!(analysis.colours[0].rgb, [100, 40, 100]);
+        Ok(())
+    }
 
-    println!("Converting to pixels...");
-    let pixels: Vec<[f64; 3]> = resized.pixels()
-        .map(|(_, _, rgb)| [
-            rgb[0] as f64,
-            rgb[1] as f64,
-            rgb[2] as f64,
-        ])
-        .collect();
+    #[test]
+    fn test_mode_args() -> Result<()> {
+        assert_eq!(Args::parse_from(["program", "test.jpg"]).analysis.mode, AnalysisMode::Colour);
 
-    println!("Preparing data for clustering...");
-    let data = Array2::from_shape_vec(
-        (pixels.len(), 3),
-        pixels.into_iter().flatten().collect(),
-    ).context("Failed to create data array")?;
-
-    let targets: Array1<f64> = Array1::zeros(data.len_of(Axis(0)));
-    let dataset = Dataset::new(data.clone(), targets);
-
-    println!("Running k-means clustering...");
-    let kmeans = KMeans::params(args.colours)
-        .max_n_iterations(100)
-        .fit(&dataset)?;
-
-    println!("Analyzing clusters...");
-    // Get cluster assignments for each pixel
-    let predictions = kmeans.predict(&dataset);
-    let total_pixels = predictions.len() as f64;
-
-    // Count pixels in each cluster
-    let mut cluster_sizes = vec![0; args.colours];
-    for &cluster in predictions.iter() {
-        cluster_sizes[cluster] += 1;
-    }
-
-    // Create vector of ColourInfo with percentages
-    let mut colours: Vec<ColourInfo> = kmeans
-        .centroids()
-        .outer_iter()
-        .enumerate()
-        .map(|(i, cent)| ColourInfo {
-            rgb: [cent[0] as u8, cent[1] as u8, cent[2] as u8],
-            percentage: (cluster_sizes[i] as f64 / total_pixels) * 100.0,
-        })
-        .collect();
+        // Luma bands are greys
+        let args = Args::parse_from(["program", "theme", "--mode", "luma", "-c", "2", "test.jpg"]);
+        assert_eq!(args.analysis.mode, AnalysisMode::Luma);
+        let analysis = cluster_pixels(image_pixels(&halves([230, 0, 0], [255, 255, 255]), &args.analysis), &args.analysis)?;
+        assert!(analysis.colours.iter().all(|c| c.rgb[0].abs_diff(c.rgb[1]) <= 1 && c.rgb[1].abs_diff(c.rgb[2]) <= 1));
 
-    // Sort by percentage (descending)
-    colours.sort_by(|a, b| b.percentage.partial_cmp(&a.percentage).unwrap());
+        // A median is a single colour taken from the image
+        let args = Args::parse_from(["program", "--mode", "median", "test.jpg"]);
+        assert_eq!((args.analysis.mode, args.analysis.reported_colours()), (AnalysisMode::Median, 1));
+        let analysis = cluster_pixels(image_pixels(&halves([200, 40, 0], [200, 40, 0]), &args.analysis), &args.analysis)?;
+        assert_eq!(analysis.colours.iter().map(|c| c.rgb).collect::<Vec<_>>(), [[200, 40, 0]]);
+        Ok(())
+    }
 
-    Ok(colours)
-}
+    #[test]
+    fn test_preprocess_args() {
+        let args = Args::parse_from(["program", "--normalize", "--gamma", "1.8", "test.jpg"]);
+        assert_eq!((args.analysis.auto_levels, args.analysis.gamma), (true, Some(1.8)));
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    let colours = analyze_image(&args)?;
+        // A dim, flat image is stretched to the full range before clustering
+        let levels = AnalysisOptions { gamma: None, ..args.analysis.clone() };
+        let pixels = image_pixels(&halves([100, 100, 100], [150, 150, 150]), &levels);
+        let reds: Vec<f64> = pixels.values.iter().map(|v| v[0]).collect();
+        assert_eq!((reds.iter().cloned().fold(f64::MAX, f64::min), reds.iter().cloned().fold(0.0, f64::max)), (0.0, 255.0));
+        let plain = image_pixels(&halves([100, 100, 100], [150, 150, 150]), &AnalysisOptions::default());
+        assert_eq!(plain.values[0][0], 100.0);
+    }
 
-    match args.format {
-        OutputFormat::Text => {
-            println!("\nDominant colours (sorted by prevalence):");
-            for colour in &colours {
-                println!(
-                    "RGB: ({}, {}, {}) - {:.1}% of image",
-                    colour.rgb[0], colour.rgb[1], colour.rgb[2], colour.percentage
-                );
+    #[test]
+    fn test_denoise_args() {
+        let args = Args::parse_from(["program", "--denoise", "median", "test.jpg"]);
+        assert_eq!(args.analysis.denoise, Some(dominant_colours::Denoise::Median));
+        let args = Args::parse_from(["program", "--denoise", "bilateral", "test.jpg"]);
+        assert_eq!(args.analysis.denoise, Some(dominant_colours::Denoise::Bilateral));
+
+        // Salt noise on grey: the median filter takes out the stray white pixels
+        let mut noisy = RgbImage::from_pixel(20, 20, Rgb([90, 90, 90]));
+        for i in 0..5 {
+            noisy.put_pixel(3 + 3 * i, 4 + 2 * i, Rgb([255, 255, 255]));
+        }
+        let noisy = image::DynamicImage::ImageRgb8(noisy);
+        let white = |options: &AnalysisOptions| image_pixels(&noisy, options).values.iter().filter(|v| v[0] > 200.0).count();
+        assert!(white(&AnalysisOptions::default()) > 0);
+        let median = Args::parse_from(["program", "--denoise", "median", "test.jpg"]);
+        assert_eq!(white(&median.analysis), 0);
+    }
+
+    #[test]
+    fn test_exact_args() -> Result<()> {
+        assert_eq!(Args::parse_from(["program", "test.jpg"]).analysis.exact, None);
+        assert_eq!(Args::parse_from(["program", "--exact=2.5", "test.jpg"]).analysis.exact, Some(2.5));
+        assert!(Args::try_parse_from(["program", "--exact", "--mode", "luma", "test.jpg"]).is_err());
+        let args = Args::parse_from(["program", "--exact", "test.jpg"]);
+        assert_eq!(args.analysis.exact, Some(0.0));
+
+        // The colours are counted, not blended
+        let analysis = cluster_pixels(image_pixels(&halves([200, 40, 0], [0, 40, 200]), &args.analysis), &args.analysis)?;
+        let mut counted: Vec<_> = analysis.colours.iter().map(|c| (c.rgb, c.percentage)).collect();
+        counted.sort_by_key(|&(rgb, _)| rgb);
+        assert_eq!(counted, [([0, 40, 200], 50.0), ([200, 40, 0], 50.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lqip_args() -> Result<()> {
+        let dir = tempdir()?;
+        let image_path = dir.path().join("photo.png");
+        halves([200, 40, 0], [0, 40, 200]).save(&image_path)?;
+        let image = image_path.to_str().unwrap();
+
+        let args = Args::parse_from(["program", "--lqip", "test.jpg"]);
+        assert_eq!(args.extract.lqip, Some(LqipStyle::Png));
+        let ctx = OutputContext::new(&args.extract, &args.analysis, args.format)?;
+        assert!(placeholders(image, &ctx)?.0.unwrap().starts_with("data:image/png;base64,"));
+
+        let args = Args::parse_from(["program", "--lqip=css", "test.jpg"]);
+        assert_eq!(args.extract.lqip, Some(LqipStyle::Css));
+        let ctx = OutputContext::new(&args.extract, &args.analysis, args.format)?;
+        assert!(placeholders(image, &ctx)?.0.unwrap().starts_with("linear-gradient("));
+        Ok(())
+    }
+
+    #[test]
+    fn test_masks_args() -> Result<()> {
+        assert!(Args::try_parse_from(["program", "--masks", "out", "--aggregate", "a.jpg", "b.jpg"]).is_err());
+        let dir = tempdir()?;
+        let image_path = dir.path().join("photo.png");
+        halves([200, 40, 0], [0, 40, 200]).save(&image_path)?;
+        let masks = dir.path().join("masks");
+        let args = Args::parse_from(["program", "--masks", masks.to_str().unwrap(), "test.jpg"]);
+        assert_eq!(args.extract.masks.as_deref(), masks.to_str());
+
+        // One mask per colour, white where the colour is
+        let colours = [ColourInfo::new([200, 40, 0], 50.0), ColourInfo::new([0, 40, 200], 50.0)];
+        save_masks(&colours, image_path.to_str().unwrap(), args.extract.masks.as_deref().unwrap(), Metric::Cie76)?;
+        let mask = image::open(masks.join("photo-1-c82800.png"))?.to_luma8();
+        assert_eq!((mask.get_pixel(0, 0).0, mask.get_pixel(19, 0).0), ([255], [0]));
+        assert!(masks.join("photo-2-0028c8.png").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_candidates_args() -> Result<()> {
+        assert!(Args::try_parse_from(["program", "--candidates", "inks.gpl", "--exact", "test.jpg"]).is_err());
+        let dir = tempdir()?;
+        let image_path = dir.path().join("photo.png");
+        halves([200, 40, 0], [10, 40, 190]).save(&image_path)?;
+        let inks = dir.path().join("inks.gpl");
+        std::fs::write(&inks, "GIMP Palette\n200 40 0 Red\n0 40 200 Blue\n0 200 0 Green\n")?;
+        let args = Args::parse_from(["program", "--candidates", inks.to_str().unwrap(), "test.jpg"]);
+        assert_eq!(args.extract.candidates.as_deref(), inks.to_str());
+
+        // Coverage is measured for the palette's own colours
+        let ctx = OutputContext::new(&args.extract, &args.analysis, args.format)?;
+        let (analysis, _) = analyze_input(image_path.to_str().unwrap(), &ctx, false)?;
+        let coverage: Vec<_> = analysis.colours.iter().filter(|c| c.percentage > 0.0).map(|c| (c.rgb, c.percentage)).collect();
+        assert_eq!(coverage, [([200, 40, 0], 50.0), ([0, 40, 200], 50.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_logging_args() {
+        let args = Args::parse_from(["program", "test.jpg"]);
+        assert_eq!((args.verbose, args.log_format), (0, LogFormat::Text));
+        assert!(!args.quiet);
+        assert_eq!(logging::level(args.verbose), tracing::level_filters::LevelFilter::WARN);
+
+        let args = Args::parse_from(["program", "-vv", "compare", "-q", "--log-format", "json", "a.jpg", "b.jpg"]);
+        assert_eq!((args.verbose, args.log_format), (2, LogFormat::Json));
+        assert!(args.quiet);
+        assert_eq!(logging::level(args.verbose), tracing::level_filters::LevelFilter::DEBUG);
+    }
+
+    #[test]
+    fn test_threads_args() -> Result<()> {
+        let args = Args::parse_from(["program", "dedupe", "--threads", "2", "photos"]);
+        assert_eq!(args.threads, Some(2));
+        assert!(Args::try_parse_from(["program", "--threads", "0", "a.jpg"]).is_err());
+        let pool = thread_pool(args.threads.unwrap()).build()?;
+        assert_eq!(pool.install(rayon::current_num_threads), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_args() -> Result<()> {
+        assert_eq!(Args::parse_from(["program", "proof.pdf"]).analysis.page, 1);
+        assert!(Args::try_parse_from(["program", "--page", "0", "proof.pdf"]).is_err());
+        let args = Args::parse_from(["program", "--page", "3", "proof.pdf"]);
+        assert_eq!(args.analysis.page, 3);
+
+        // PDFs are rendered a page at a time by pdftoppm, whether or not it is installed
+        let dir = tempdir()?;
+        let pdf = dir.path().join("proof.pdf");
+        std::fs::write(&pdf, "%PDF-1.4\n")?;
+        let error = load_pixels(pdf.to_str().unwrap(), &args.analysis).map(|_| ()).unwrap_err();
+        let message = format!("{:#}", anyhow::Error::from(error));
+        assert!(message.contains("pdftoppm") && message.contains("page 3"), "{}", message);
+        Ok(())
+    }
+
+    #[test]
+    fn test_option_validation() {
+        assert_eq!(Args::parse_from(["program", "-c", "256", "a.jpg"]).analysis.colours, 256);
+        let invalid: [&[&str]; 13] = [
+            &["-c", "0"],
+            &["-c", "257"],
+            &["--top", "0"],
+            &["--max-pixels", "0"],
+            &["--gamma", "0"],
+            &["--gamma", "inf"],
+            &["--exact=-1"],
+            &["--exact", "--init", "random"],
+            &["--exact", "--silhouette"],
+            &["--min-coverage", "150"],
+            &["--timeline=0"],
+            &["--timeline=0.00001"],
+            &["--timeline-sheet", "sheet.png"],
+        ];
+        for argv in invalid {
+            let argv = ["program"].iter().chain(argv).chain(&["a.jpg"]).copied().collect::<Vec<_>>();
+            assert!(Args::try_parse_from(&argv).is_err(), "{:?} should be rejected", argv);
+        }
+        assert!(Args::try_parse_from(["program", "dedupe", "--threshold", "-1", "photos"]).is_err());
+        assert!(Args::try_parse_from(["program", "swatch", "--swatch-size", "0", "a.jpg"]).is_err());
+        assert!(Args::try_parse_from(["program", "swatch", "--swatch-size", "1000000", "a.jpg"]).is_err());
+        assert!(Args::try_parse_from(["program", "-s", "--swatch-size", "3000000000", "a.jpg"]).is_err());
+        assert!(Args::try_parse_from(["program", "swatch", "--swatch-size", "4096", "a.jpg"]).is_ok());
+        assert!(Args::try_parse_from(["program", "--precision", "11", "a.jpg"]).is_err());
+        let args = Args::parse_from(["program", "mix", "a.jpg", "b.jpg", "--precision", "2", "--decimal-separator", "locale"]);
+        assert_eq!((args.precision, args.decimal_separator), (Some(2), DecimalSeparator::Locale));
+    }
+
+    #[test]
+    fn test_compare_arg_parsing() {
+        let args = Args::parse_from(["program", "compare", "-c", "4", "a.jpg", "b.jpg"]);
+        assert!(args.extract.filenames.is_empty());
+        assert_eq!(args.analysis.colours, 4);
+        assert_eq!(args.format, OutputFormat::Text);
+        match args.command {
+            Some(Command::Compare { first, second, palette }) => {
+                assert_eq!((first.as_str(), second.as_deref()), ("a.jpg", Some("b.jpg")));
+                assert!(palette.is_none());
             }
+            _ => panic!("expected compare subcommand"),
         }
-        OutputFormat::Json => {
-            let json = serde_json::json!({
-                "colours": colours.iter().map(|c| {
-                    serde_json::json!({
-                        "rgb": c.rgb,
-                        "percentage": c.percentage,
-                        "hex": rgb_to_hex(c.rgb)
-                    })
-                }).collect::<Vec<_>>()
-            });
-            println!("{}", serde_json::to_string_pretty(&json)?);
+
+        let args = Args::parse_from(["program", "compare", "--palette", "brand.json", "a.jpg"]);
+        match args.command {
+            Some(Command::Compare { first, second, palette, .. }) => {
+                assert_eq!(first, "a.jpg");
+                assert!(second.is_none());
+                assert_eq!(palette.as_deref(), Some("brand.json"));
+            }
+            _ => panic!("expected compare subcommand"),
         }
+
+        assert!(Args::try_parse_from(["program"]).is_err());
+        assert!(Args::try_parse_from(["program", "compare", "a.jpg"]).is_err());
+        assert!(Args::try_parse_from(["program", "compare", "--palette", "p.json", "a.jpg", "b.jpg"]).is_err());
     }
 
-    // Save swatch if requested
-    if args.swatch {
-        println!("\nSaving colour swatch to {}...", args.output);
-        save_colour_swatch(&colours, &args.output)
-            .context("Failed to save colour swatch")?;
+    #[test]
+    fn test_subcommand_arg_parsing() {
+        // The bare form is an alias for `extract`
+        let args = Args::parse_from(["program", "extract", "--swatch", "a.jpg", "b.jpg"]);
+        match args.command {
+            Some(Command::Extract(extract)) => {
+                assert_eq!(extract.filenames, ["a.jpg", "b.jpg"]);
+                assert!(extract.swatch);
+            }
+            _ => panic!("expected extract subcommand"),
+        }
+
+        // Global options work before or after the subcommand
+        let args = Args::parse_from(["program", "-c", "3", "theme", "-f", "json", "a.jpg"]);
+        assert!(matches!(args.command, Some(Command::Theme { .. })));
+        assert_eq!(args.analysis.colours, 3);
+        assert_eq!(args.format, OutputFormat::Json);
+        let args = Args::parse_from(["program", "theme", "--themes", "light,dark", "a.jpg"]);
+        assert!(matches!(args.command, Some(Command::Theme { themes, .. }) if themes == [Scheme::Light, Scheme::Dark]));
+
+        let args = Args::parse_from(["program", "diff", "--check", "v1.png", "v2.png"]);
+        match args.command {
+            Some(Command::Diff { old, new, threshold, check }) => {
+                assert_eq!((old.as_str(), new.as_str()), ("v1.png", "v2.png"));
+                assert_eq!((threshold, check), (10.0, true));
+            }
+            _ => panic!("expected diff subcommand"),
+        }
+
+        let args = Args::parse_from(["program", "swatch", "-o", "p.png", "--simulate", "protanopia", "a.jpg"]);
+        match args.command {
+            Some(Command::Swatch { image, output, simulate, swatch_style, .. }) => {
+                assert_eq!((image.as_deref(), output.as_str()), (Some("a.jpg"), "p.png"));
+                assert_eq!(simulate, [Deficiency::Protanopia]);
+                assert_eq!(swatch_style.swatch_size, 100);
+            }
+            _ => panic!("expected swatch subcommand"),
+        }
+        let args = Args::parse_from(["program", "swatch", "--from", "results.json"]);
+        assert!(matches!(args.command, Some(Command::Swatch { from: Some(f), image: None, .. }) if f == "results.json"));
+        assert!(Args::try_parse_from(["program", "swatch"]).is_err());
+        assert!(Args::try_parse_from(["program", "swatch", "--from", "results.json", "a.jpg"]).is_err());
+
+        let args = Args::parse_from(["program", "quantize", "a.jpg"]);
+        assert!(matches!(args.command, Some(Command::Quantize { output, .. }) if output == "quantized.png"));
+        let args = Args::parse_from(["program", "quantize", "--palette", "brand.gpl", "a.jpg"]);
+        assert!(matches!(args.command, Some(Command::Quantize { palette: Some(p), .. }) if p == "brand.gpl"));
+
+        let args = Args::parse_from(["program", "nearest", "#3c79b8", "a.jpg", "--max-delta-e", "5"]);
+        match args.command {
+            Some(Command::Nearest { colour, input, max_delta_e }) => {
+                assert_eq!(colour, [0x3c, 0x79, 0xb8]);
+                assert_eq!((input.as_str(), max_delta_e), ("a.jpg", Some(5.0)));
+            }
+            _ => panic!("expected nearest subcommand"),
+        }
+        assert!(Args::try_parse_from(["program", "nearest", "blue", "a.jpg"]).is_err());
+
+        let args = Args::parse_from(["program", "mix", "a.jpg", "#ffffff", "--steps", "3"]);
+        match args.command {
+            Some(Command::Mix { from, to, ratio, steps }) => {
+                assert_eq!((from.as_str(), to.as_str()), ("a.jpg", "#ffffff"));
+                assert_eq!((ratio, steps), (0.5, Some(3)));
+            }
+            _ => panic!("expected mix subcommand"),
+        }
+        assert!(Args::try_parse_from(["program", "mix", "a.jpg", "b.jpg", "--ratio", "1.5"]).is_err());
+        assert!(Args::try_parse_from(["program", "mix", "a.jpg", "b.jpg", "--ratio", "0.2", "--steps", "2"]).is_err());
+
+        let args = Args::parse_from(["program", "transform", "brand.gpl", "--rotate", "-30", "--darken", "10"]);
+        match args.command {
+            Some(Command::Transform { input, rotate, lighten, darken, desaturate }) => {
+                assert_eq!(input, "brand.gpl");
+                assert_eq!((rotate, lighten, darken, desaturate), (-30.0, 0.0, 10.0, 0.0));
+            }
+            _ => panic!("expected transform subcommand"),
+        }
+        assert!(Args::try_parse_from(["program", "transform", "a.jpg", "--lighten", "5", "--darken", "5"]).is_err());
+        assert!(Args::try_parse_from(["program", "transform", "a.jpg", "--desaturate", "150"]).is_err());
+
+        let args = Args::parse_from(["program", "bench", "--spaces", "lab", "--runs", "3"]);
+        match args.command {
+            Some(Command::Bench { image, spaces, inits, runs }) => {
+                assert!(image.is_none());
+                assert_eq!(spaces, [ColourSpace::Lab]);
+                assert_eq!(inits, [InitMethod::KmeansPlusPlus, InitMethod::Random]);
+                assert_eq!(runs, 3);
+            }
+            _ => panic!("expected bench subcommand"),
+        }
     }
 
-    Ok(())
-}
+    #[test]
+    fn test_option_placement() {
+        let mut cmd = Args::command();
+        let matches = cmd.clone().try_get_matches_from(["program", "--stats", "theme", "a.jpg"]).unwrap();
+        let err = check_option_placement(&mut cmd, &matches).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+        assert!(err.to_string().contains("'--stats' cannot be used before the 'theme' subcommand"));
 
-#[cfg(test)]
-mod tests {
-    use crate::*;
-    use image::{RgbImage, Rgb};
-    use tempfile::tempdir;
+        for argv in [&["program", "-c", "3", "theme", "a.jpg"][..], &["program", "--stats", "a.jpg"]] {
+            let matches = cmd.clone().try_get_matches_from(argv).unwrap();
+            assert!(check_option_placement(&mut cmd, &matches).is_ok());
+        }
+    }
 
     #[test]
-    fn test_arg_parsing() {
-        let args = Args::parse_from(["program", "test.jpg"]);
-        assert_eq!(args.filename, "test.jpg");
-        assert_eq!(args.colours, 6); // default value
-        assert!(!args.swatch); // default false
+    fn test_format_support() {
+        let mut cmd = Args::command();
+        let args = Args::parse_from(["program", "-f", "meta", "mix", "a.jpg", "b.jpg"]);
+        let err = check_format_support(&mut cmd, &args).unwrap_err();
+        assert!(err.to_string().contains("only supported by the extract, theme and transform commands"));
 
-        let args = Args::parse_from(["program", "-c", "8", "test.jpg"]);
-        assert_eq!(args.colours, 8);
+        let args = Args::parse_from(["program", "-f", "gradient", "theme", "a.jpg"]);
+        let err = check_format_support(&mut cmd, &args).unwrap_err();
+        assert!(err.to_string().contains("'--format gradient' is only supported by the extract and transform commands"));
+        let args = Args::parse_from(["program", "-f", "blurhash", "transform", "a.jpg", "--rotate", "30"]);
+        assert!(check_format_support(&mut cmd, &args).is_err());
 
-        let args = Args::parse_from(["program", "--swatch", "-o", "custom.svg", "test.jpg"]);
-        assert!(args.swatch);
-        assert_eq!(args.output, "custom.svg");
+        let args = Args::parse_from(["program", "-f", "thumbhash", "--aggregate", "a.jpg", "b.jpg"]);
+        assert_eq!(check_format_support(&mut cmd, &args).unwrap_err().kind(), ErrorKind::ArgumentConflict);
+
+        for argv in [
+            &["program", "-f", "meta", "a.jpg"][..],
+            &["program", "-f", "meta", "theme", "a.jpg"],
+            &["program", "-f", "gradient", "extract", "a.jpg"],
+            &["program", "-f", "meta", "transform", "a.jpg"],
+            &["program", "-f", "gradient", "transform", "a.gpl"],
+            &["program", "-f", "blurhash", "a.jpg", "b.jpg"],
+        ] {
+            assert!(check_format_support(&mut cmd, &Args::parse_from(argv)).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_completions() {
+        let args = Args::parse_from(["program", "completions", "zsh"]);
+        assert!(matches!(args.command, Some(Command::Completions { shell: clap_complete::Shell::Zsh })));
+        assert!(Args::try_parse_from(["program", "completions", "tcsh"]).is_err());
+
+        // Value enums complete to their possible values
+        let mut script = Vec::new();
+        clap_complete::generate(clap_complete::Shell::Bash, &mut Args::command(), "dominant-colours", &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("text json"));
+        assert!(script.contains("rgb lab"));
+    }
+
+    #[test]
+    fn test_generate_man() {
+        let args = Args::parse_from(["program", "--generate-man"]);
+        assert!(args.generate_man);
+        assert!(Args::try_parse_from(["program", "--generate-man", "a.jpg"]).is_err());
+
+        let mut page = Vec::new();
+        clap_mangen::Man::new(Args::command()).render(&mut page).unwrap();
+        let page = String::from_utf8(page).unwrap();
+        assert!(page.starts_with(".ie"));
+        assert!(page.contains(".SH SUBCOMMANDS"));
+        assert!(!page.contains("generate\\-man"));
+    }
+
+    #[test]
+    fn test_strict_warnings() {
+        let analysis = Analysis {
+            colours: vec![ColourInfo::new([0, 0, 0], 100.0)],
+            inertia: 0.0,
+            silhouette: None,
+            timings: Timings::default(),
+            warnings: vec!["Only 1 of 6 colours found".to_string()],
+            greyscale: false,
+            histograms: Vec::new(),
+            vividness: None,
+            sampled_pixels: 1,
+            source_pixels: 1,
+        };
+        assert!(check_warnings(&analysis, false).is_ok());
+        let err = check_warnings(&analysis, true).unwrap_err();
+        assert_eq!(Exit::of(&err), Exit::Strict);
+        assert_eq!(err.to_string(), "Only 1 of 6 colours found");
+
+        assert!(skip("b.png", anyhow::anyhow!("unreadable"), false).is_ok());
+        assert!(skip("b.png", anyhow::anyhow!("unreadable"), true).is_err());
+        assert!(Args::parse_from(["program", "dedupe", "--strict", "photos"]).strict);
+    }
+
+    #[test]
+    fn test_aggregate_arg_parsing() {
+        let args = Args::parse_from(["program", "a.jpg", "b.jpg"]);
+        assert_eq!(args.extract.filenames, ["a.jpg", "b.jpg"]);
+        assert_eq!(args.extract.aggregate, None);
+
+        let args = Args::parse_from(["program", "--aggregate", "a.jpg", "b.jpg"]);
+        assert_eq!(args.extract.aggregate, Some(AggregateMode::Pixels));
+        assert_eq!(args.extract.filenames.len(), 2);
+
+        let args = Args::parse_from(["program", "--aggregate=palettes", "a.jpg"]);
+        assert_eq!(args.extract.aggregate, Some(AggregateMode::Palettes));
+        assert_eq!(weighted_input("hero.jpg:3"), ("hero.jpg".to_string(), 3.0));
+        assert_eq!(weighted_input("dir:name/a.jpg"), ("dir:name/a.jpg".to_string(), 1.0));
+        assert_eq!(weighted_input("b.jpg"), ("b.jpg".to_string(), 1.0));
+    }
+
+    #[test]
+    fn test_files_from_arg_parsing() {
+        let args = Args::parse_from(["program", "--files-from", "-", "-f", "json"]);
+        assert_eq!(args.extract.files_from.as_deref(), Some("-"));
+        assert!(args.extract.filenames.is_empty());
+        assert!(Args::try_parse_from(["program", "--files-from", "list.txt", "a.jpg"]).is_err());
+        assert!(Args::try_parse_from(["program", "--aggregate", "--files-from", "list.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_per_image_path() {
+        assert_eq!(per_image_path("swatch.svg", "photos/cat.jpg"), "swatch-cat.svg");
+        assert_eq!(per_image_path("out/swatch.svg", "dog.png"), "out/swatch-dog.svg");
+    }
+
+    #[test]
+    fn test_env_var_options() {
+        // Values are read from the environment in tests/env.rs, which runs the binary
+        // rather than setting variables under the other tests' threads
+        let cmd = with_env_vars(Args::command());
+        let env = |cmd: &clap::Command, id: &str| {
+            cmd.get_arguments()
+                .find(|a| a.get_id() == id)
+                .and_then(|a| a.get_env())
+                .map(|e| e.to_string_lossy().into_owned())
+        };
+        assert_eq!(env(&cmd, "cvd_threshold").as_deref(), Some("DOMINANT_COLOURS_CVD_THRESHOLD"));
+        assert_eq!(env(&cmd, "colours").as_deref(), Some("DOMINANT_COLOURS_COLOURS"));
+        assert_eq!(env(&cmd, "filenames"), None);
+        assert_eq!(env(&cmd, "generate_man"), None);
+        let dedupe = cmd.find_subcommand("dedupe").unwrap();
+        assert_eq!(env(dedupe, "threshold").as_deref(), Some("DOMINANT_COLOURS_DEDUPE_THRESHOLD"));
+        let diff = cmd.find_subcommand("diff").unwrap();
+        assert_eq!(env(diff, "threshold").as_deref(), Some("DOMINANT_COLOURS_DIFF_THRESHOLD"));
+        let extract = cmd.find_subcommand("extract").unwrap();
+        assert_eq!(env(extract, "stats").as_deref(), Some("DOMINANT_COLOURS_STATS"));
+    }
+
+    #[test]
+    fn test_require_arg_parsing() {
+        let args = Args::parse_from(["program", "--require", "#0057b8,ffd700", "--min-coverage", "30", "test.jpg"]);
+        assert_eq!(args.extract.require, vec![[0, 87, 184], [255, 215, 0]]);
+        assert_eq!(args.extract.min_coverage, 30.0);
+        assert!(Args::try_parse_from(["program", "--require", "blue", "test.jpg"]).is_err());
     }
 
 #[test]
@@ -229,26 +3104,12 @@ fn test_json_output() -> Result<(), Box<dyn std::error::Error>> {
     img.save(&image_path)?;
 
     // Run analysis with JSON output
-    let args = Args {
-        filename: image_path.to_str().unwrap().to_string(),
-        colours: 3,
-        format: OutputFormat::Json,
-        swatch: false,
-        output: "".to_string(),
-    };
+    let args = Args::parse_from(["program", "-c", "3", "-f", "json", image_path.to_str().unwrap()]);
 
-    let colours = analyze_image(&args)?;
+    let analysis = analyze_image(&args.extract.filenames[0], &args.analysis)?;
 
     // Convert to JSON and verify structure
-    let json = serde_json::json!({
-        "colours": colours.iter().map(|c| {
-            serde_json::json!({
-                "rgb": c.rgb,
-                "percentage": c.percentage,
-                "hex": rgb_to_hex(c.rgb)
-            })
-        }).collect::<Vec<_>>()
-    });
+    let json = build_json(&analysis, &Extras::default(), &OutputContext::new(&args.extract, &args.analysis, args.format)?);
 
     let json_str = serde_json::to_string_pretty(&json)?;
 
@@ -262,19 +3123,99 @@ fn test_json_output() -> Result<(), Box<dyn std::error::Error>> {
     assert!(first_colour.get("rgb").is_some());
     assert!(first_colour.get("percentage").is_some());
     assert!(first_colour.get("hex").is_some());
+    assert!(first_colour.get("text_on").is_some());
+    assert!(first_colour.get("name").is_some());
+    assert!(parsed.get("timings").is_none());
+
+    let args = Args::parse_from(["program", "--timings", image_path.to_str().unwrap()]);
+    let json = build_json(&analysis, &Extras::default(), &OutputContext::new(&args.extract, &args.analysis, args.format)?);
+    let timings = json["timings"].as_object().unwrap();
+    assert_eq!(timings.len(), 7);
+    assert!(timings["fit_ms"].as_f64().unwrap() > 0.0);
 
     Ok(())
 }
 
-#[test]
-fn test_rgb_to_hex() {
-    assert_eq!(rgb_to_hex([255, 255, 255]), "#ffffff");
-    assert_eq!(rgb_to_hex([0, 0, 0]), "#000000");
-    assert_eq!(rgb_to_hex([255, 0, 0]), "#ff0000");
-    assert_eq!(rgb_to_hex([0, 255, 0]), "#00ff00");
-    assert_eq!(rgb_to_hex([0, 0, 255]), "#0000ff");
-    assert_eq!(rgb_to_hex([85, 85, 85]), "#555555");
-}
+    #[test]
+    fn test_schema() -> Result<(), Box<dyn std::error::Error>> {
+        let schema: serde_json::Value = serde_json::from_str(SCHEMA)?;
+        assert_eq!(schema["$defs"]["schema_version"]["const"], SCHEMA_VERSION);
+        assert!(Args::parse_from(["program", "--schema"]).schema);
+
+        // Every member the output can contain is described
+        let dir = tempdir()?;
+        let regions = dir.path().join("regions.json");
+        std::fs::write(&regions, r#"[{"name": "top", "rect": [0, 0, 10, 10]}]"#)?;
+        let previous = dir.path().join("previous.json");
+        std::fs::write(&previous, r##"{"colours": [{"hex": "#ff0000", "id": 4, "name": "brand red"}]}"##)?;
+        let argv = [
+            "program", "--accent", "--stats", "--silhouette", "--timings", "--temperature", "--contrast-matrix",
+            "--wcag-pairs", "--cvd-check", "--require", "#ff0000", "--extended-values", "--cmyk", "--locate",
+            "--simulate", "protanopia", "--histogram", "hue,rgb,luma", "--regions", regions.to_str().unwrap(), "--multiscale",
+            "--counts", "--previous", previous.to_str().unwrap(), "--vividness", "--diversity", "image.png",
+        ];
+        let args = Args::parse_from(argv);
+        let mut colour = ColourInfo::new([255, 0, 0], 100.0);
+        colour.location = Some((1, 2));
+        let analysis = Analysis {
+            colours: vec![colour],
+            inertia: 0.0,
+            silhouette: None,
+            timings: Timings::default(),
+            warnings: vec!["CMYK JPEG was converted naively, having no ICC profile; colours are approximate".to_string()],
+            greyscale: true,
+            histograms: args.analysis.histogram.iter().map(|&kind| histogram::compute(kind, &[[255.0, 0.0, 0.0]])).collect(),
+            vividness: Some(vividness::compute(&[[255.0, 0.0, 0.0]])),
+            sampled_pixels: 1,
+            source_pixels: 4,
+        };
+        let stability = Stability { sides: vec![150, 75, 37], scales_found: vec![3], warnings: Vec::new() };
+        let ctx = OutputContext::new(&args.extract, &args.analysis, args.format)?;
+        let identities = identities(&analysis, &ctx);
+        let compliance = compliance_report(&analysis.colours, &ctx);
+        let extras = Extras { stability: Some(stability), identities, compliance, ..Extras::default() };
+        let json = with_schema_version(build_json(&analysis, &extras, &ctx));
+        assert_eq!((&json["colours"][0]["id"], &json["colours"][0]["name"]), (&4.into(), &"brand red".into()));
+        let defs = &schema["$defs"];
+        for key in json.as_object().unwrap().keys() {
+            assert!(defs["analysis"]["properties"].get(key).is_some(), "{} missing from schema", key);
+        }
+        for key in json["colours"][0].as_object().unwrap().keys() {
+            assert!(defs["colour"]["properties"].get(key).is_some(), "{} missing from schema", key);
+        }
+        // --timeline can't be combined with --regions
+        let args = Args::parse_from(["program", "--timeline", "image.gif"]);
+        let json = build_json(&analysis, &Extras::default(), &OutputContext::new(&args.extract, &args.analysis, args.format)?);
+        assert!(defs["analysis"]["properties"].get("timeline").is_some());
+        assert!(json.get("timeline").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sidecar() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempdir()?;
+        let image_path = dir.path().join("photo.png");
+        RgbImage::from_fn(20, 20, |x, _| if x < 10 { Rgb([200, 30, 30]) } else { Rgb([30, 30, 200]) }).save(&image_path)?;
+        let image = image_path.to_str().unwrap();
+        let args = Args::parse_from(["program", "--sidecar", "-c", "2", image]);
+        let ctx = OutputContext::new(&args.extract, &args.analysis, args.format)?;
+        let (analysis, _) = analyze_input(image, &ctx, false)?;
+
+        let sidecar: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(format!("{}.colours.json", image))?)?;
+        assert_eq!(sidecar["file"], "photo.png");
+        let hash = format!("blake3:{}", blake3::hash(&std::fs::read(&image_path)?).to_hex());
+        assert_eq!(sidecar["content_hash"], hash.as_str());
+        assert_eq!(sidecar["tool"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!((&sidecar["options"]["colours"], &sidecar["options"]["space"]), (&2.into(), &"rgb".into()));
+        assert_eq!(sidecar["analysis"]["colours"].as_array().unwrap().len(), analysis.colours.len());
+
+        let schema: serde_json::Value = serde_json::from_str(SCHEMA)?;
+        for key in sidecar.as_object().unwrap().keys() {
+            assert!(schema["$defs"]["sidecar"]["properties"].get(key).is_some(), "{} missing from schema", key);
+        }
+        assert!(Args::try_parse_from(["program", "--sidecar", "--aggregate", "a.jpg", "b.jpg"]).is_err());
+        Ok(())
+    }
 
     #[test]
     fn test_colour_swatch_generation() -> Result<(), Box<dyn std::error::Error>> {
@@ -282,21 +3223,12 @@ fn test_rgb_to_hex() {
         let output_path = temp_dir.path().join("test_swatch.svg");
 
         let colours = vec![
-            ColourInfo {
-                rgb: [255, 0, 0],
-                percentage: 50.0,
-            },
-            ColourInfo {
-                rgb: [0, 255, 0],
-                percentage: 30.0,
-            },
-            ColourInfo {
-                rgb: [0, 0, 255],
-                percentage: 20.0,
-            },
+            ColourInfo::new([255, 0, 0], 50.0),
+            ColourInfo::new([0, 255, 0], 30.0),
+            ColourInfo::new([0, 0, 255], 20.0),
         ];
 
-        save_colour_swatch(&colours, output_path.to_str().unwrap())?;
+        save_colour_swatch(&colours, &[], &SwatchStyle::default(), output_path.to_str().unwrap())?;
 
         // Verify file exists and contains expected content
         let content = std::fs::read_to_string(output_path)?;
@@ -310,6 +3242,62 @@ fn test_rgb_to_hex() {
         Ok(())
     }
 
+    #[test]
+    fn test_colour_swatch_style() {
+        let colours = vec![ColourInfo::new([255, 0, 0], 100.0)];
+        let style = SwatchStyle { swatch_size: 50, swatch_font: "Futura \"PT\"".to_string() };
+        let svg = swatch_svg(&colours, &[Deficiency::Protanopia], &style);
+        assert!(svg.contains("viewBox=\"0 0 300 160\""));
+        assert!(svg.contains("width=\"50\" height=\"50\""));
+        assert!(svg.contains("font-family=\"Futura &quot;PT&quot;\""));
+    }
+
+    #[test]
+    fn test_colour_swatch_png() -> Result<(), Box<dyn std::error::Error>> {
+        let colours = vec![ColourInfo::new([255, 0, 0], 60.0), ColourInfo::new([0, 0, 255], 40.0)];
+        let png = swatch_png(&colours, &[Deficiency::Protanopia], &SwatchStyle::default())?;
+        let img = image::load_from_memory(&png)?.to_rgb8();
+        assert_eq!(img.dimensions(), (200, 200));
+        assert_eq!(img.get_pixel(150, 50).0, [0, 0, 255]);
+        assert_ne!(img.get_pixel(50, 150).0, [255, 0, 0]);
+
+        // The output extension picks the format
+        let temp_dir = tempdir()?;
+        let output_path = temp_dir.path().join("swatch.png");
+        let style = SwatchStyle { swatch_size: 10, ..SwatchStyle::default() };
+        save_colour_swatch(&colours, &[], &style, output_path.to_str().unwrap())?;
+        assert_eq!(image::open(&output_path)?.to_rgb8().dimensions(), (20, 10));
+
+        // Sizes beyond u32 or the pixel cap are errors rather than panics or huge allocations
+        let huge = SwatchStyle { swatch_size: 3_000_000_000, ..SwatchStyle::default() };
+        assert!(swatch_png(&colours, &[], &huge).is_err());
+        let wide = SwatchStyle { swatch_size: 1_000_000, ..SwatchStyle::default() };
+        assert!(swatch_png(&colours, &[], &wide).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_colour_swatch_simulation_rows() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let output_path = temp_dir.path().join("test_swatch.svg");
+        let colours = vec![ColourInfo::new([255, 0, 0], 100.0)];
+
+        save_colour_swatch(
+            &colours,
+            &[Deficiency::Protanopia, Deficiency::Tritanopia],
+            &SwatchStyle::default(),
+            output_path.to_str().unwrap(),
+        )?;
+
+        let content = std::fs::read_to_string(output_path)?;
+        assert!(content.contains("viewBox=\"0 0 600 380\""));
+        assert!(content.contains(">protanopia<"));
+        assert!(content.contains(">tritanopia<"));
+        assert_eq!(content.matches("<rect").count(), 3);
+
+        Ok(())
+    }
+
     #[test]
     fn test_dominant_colour_extraction() -> Result<(), Box<dyn std::error::Error>> {
         // Create a test image with known colours
@@ -335,15 +3323,9 @@ fn test_rgb_to_hex() {
         img.save(&image_path)?;
 
         // Run analysis
-        let args = Args {
-            filename: image_path.to_str().unwrap().to_string(),
-            colours: 3,
-            format: OutputFormat::Text,
-            swatch: false,
-            output: "".to_string(),
-        };
+        let args = Args::parse_from(["program", "-c", "3", image_path.to_str().unwrap()]);
 
-        let colours = analyze_image(&args)?;
+        let colours = analyze_image(&args.extract.filenames[0], &args.analysis)?.colours;
 
         // Verify results (with some tolerance for k-means variation)
         assert!(colours.len() == 3);
@@ -357,6 +3339,17 @@ fn test_rgb_to_hex() {
         assert!((sorted_colours[1].percentage - 30.0).abs() < 5.0);
         assert!((sorted_colours[2].percentage - 20.0).abs() < 5.0);
 
+        // Flat colour blocks make tight clusters; only resampling at the edges adds spread
+        for colour in &sorted_colours {
+            assert!(colour.std_dev.iter().all(|&sd| sd < 40.0));
+        }
+
+        // Representative pixels land inside the matching colour band
+        let (_, red_y) = sorted_colours[0].location.unwrap();
+        let (_, blue_y) = sorted_colours[2].location.unwrap();
+        assert!(red_y < 50);
+        assert!(blue_y >= 80);
+
         Ok(())
     }
 
@@ -366,16 +3359,11 @@ fn test_rgb_to_hex() {
         let result = image::open("non_existent.jpg");
         assert!(result.is_err());
 
-        // Test invalid colour count
-        let args = Args {
-            filename: "test.jpg".to_string(),
-            colours: 0,  // Invalid number of colours
-            format: OutputFormat::Text,
-            swatch: false,
-            output: "".to_string(),
-        };
+        // Test invalid colour count, rejected by the parser and by the analysis
+        assert!(Args::try_parse_from(["program", "-c", "0", "test.jpg"]).is_err());
 
-        let result = analyze_image(&args);
+        let options = AnalysisOptions { colours: 0, ..AnalysisOptions::default() };
+        let result = analyze_image("test.jpg", &options);
         assert!(result.is_err());
     }
 }
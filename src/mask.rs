@@ -0,0 +1,129 @@
+//! Palettes of just the subject of an image, from a mask made elsewhere, e.g. by a
+//! background-removal or segmentation model.
+//!
+//! A mask is a greyscale image, white over the subject, or an image with transparency,
+//! opaque over the subject, as background removers save cut-outs. It is stretched over
+//! the image if their sizes differ. Each sampled pixel counts in proportion to the mask
+//! there, so soft edges count partly, or with `MaskMode::Gate` it is kept or dropped.
+
+use clap::ValueEnum;
+use tracing::debug;
+
+use crate::{open_image, DominantColoursError, Pixels};
+
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum MaskMode {
+    /// Count each pixel in proportion to the mask's value there
+    Weight,
+    /// Keep the pixels where the mask is at least half on and drop the rest
+    Gate,
+}
+
+impl std::fmt::Display for MaskMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaskMode::Weight => write!(f, "weight"),
+            MaskMode::Gate => write!(f, "gate"),
+        }
+    }
+}
+
+/// Fractional part of the golden ratio, which spreads `i * GOLDEN` evenly over 0 to 1.
+const GOLDEN: f64 = 0.618_033_988_749_895;
+
+/// Read a mask: the alpha channel of an image with transparency, the luma of any other.
+pub fn load(path: &str) -> Result<image::GrayImage, DominantColoursError> {
+    let img = open_image(path)?;
+    if img.color().has_alpha() {
+        let cutout = img.to_luma_alpha8();
+        Ok(image::GrayImage::from_fn(cutout.width(), cutout.height(), |x, y| image::Luma([cutout.get_pixel(x, y)[1]])))
+    } else {
+        Ok(img.to_luma8())
+    }
+}
+
+/// Leave out the pixels `mask` rules out of `pixels`, sampled from a `width` x `height`
+/// image. `source_pixels` shrinks by the same share, so areas cover the masked part only.
+pub fn apply(
+    pixels: &mut Pixels,
+    mask: &image::GrayImage,
+    width: u32,
+    height: u32,
+    mode: MaskMode,
+) -> Result<(), DominantColoursError> {
+    // Pixels without positions don't come from a single image
+    let Some(positions) = pixels.positions.take() else {
+        return Ok(());
+    };
+    let value = |(x, y): (u32, u32)| {
+        let mx = (x as u64 * mask.width() as u64 / width as u64) as u32;
+        let my = (y as u64 * mask.height() as u64 / height as u64) as u32;
+        mask.get_pixel(mx.min(mask.width() - 1), my.min(mask.height() - 1))[0] as f64 / 255.0
+    };
+    let sampled = positions.len();
+    let (values, positions): (Vec<_>, Vec<_>) = std::mem::take(&mut pixels.values)
+        .into_iter()
+        .zip(positions)
+        .enumerate()
+        .filter(|&(i, (_, position))| keep(i, value(position), mode))
+        .map(|(_, pixel)| pixel)
+        .unzip();
+    debug!(kept = values.len(), pixels = sampled, %mode, "Applied mask");
+    if values.is_empty() {
+        return Err(DominantColoursError::NoPixels("the mask leaves no pixels to cluster".to_string()));
+    }
+    pixels.source_pixels = (pixels.source_pixels as f64 * values.len() as f64 / sampled as f64).round() as u64;
+    pixels.values = values;
+    pixels.positions = Some(positions);
+    Ok(())
+}
+
+/// Whether the `i`th sampled pixel survives a mask value of `value`. Weighting keeps each
+/// pixel whose threshold, from a sequence spread evenly over 0 to 1, is below the value,
+/// so the share kept in any area follows the mask without the clumps of random dropping.
+fn keep(i: usize, value: f64, mode: MaskMode) -> bool {
+    match mode {
+        MaskMode::Weight => ((i as f64 + 0.5) * GOLDEN).fract() < value,
+        MaskMode::Gate => value >= 0.5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Timings;
+
+    fn pixels(width: u32, height: u32) -> Pixels {
+        let positions: Vec<(u32, u32)> = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).collect();
+        Pixels {
+            values: positions.iter().map(|&(x, _)| if x < width / 2 { [220.0, 20.0, 20.0] } else { [20.0, 20.0, 220.0] }).collect(),
+            positions: Some(positions),
+            source_pixels: width as u64 * height as u64,
+            timings: Timings::default(),
+            warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_apply_mask() -> Result<(), DominantColoursError> {
+        // A quarter-size mask over the left half: only red is left
+        let left = image::GrayImage::from_fn(5, 5, |x, _| image::Luma([if x < 2 { 255 } else if x == 2 { 128 } else { 0 }]));
+        let mut gated = pixels(20, 20);
+        apply(&mut gated, &left, 20, 20, MaskMode::Gate)?;
+        assert_eq!(gated.values.len(), 8 * 20 + 4 * 20);
+        assert!(gated.positions.as_ref().unwrap().iter().all(|&(x, _)| x < 12));
+        assert_eq!(gated.source_pixels, 240);
+
+        // Half-on pixels count about half
+        let grey = image::GrayImage::from_pixel(4, 4, image::Luma([128]));
+        let mut weighted = pixels(20, 20);
+        apply(&mut weighted, &grey, 20, 20, MaskMode::Weight)?;
+        assert!((190..=210).contains(&weighted.values.len()));
+        let red = weighted.values.iter().filter(|v| v[0] > 100.0).count();
+        assert!((90..=110).contains(&red));
+
+        let empty = image::GrayImage::new(4, 4);
+        assert!(matches!(apply(&mut pixels(20, 20), &empty, 20, 20, MaskMode::Weight), Err(DominantColoursError::NoPixels(_))));
+        Ok(())
+    }
+}
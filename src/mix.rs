@@ -0,0 +1,88 @@
+//! Blending palettes in CIE Lab, for themed variations between two references.
+
+use crate::colour::{self, Metric};
+use crate::ColourInfo;
+
+/// The palette `ratio` of the way from `a` to `b` (0.0 is `a`, 1.0 is `b`).
+///
+/// Colours are paired one to one, closest by `metric` first, and any left over in the
+/// larger palette pair with their nearest colour in the other. Each pair is
+/// interpolated in Lab, and so is its share of the palette; a colour in several pairs
+/// splits its share between them, so the percentages still total 100. Mixing towards a
+/// single target colour moves every colour towards it.
+pub fn mix(a: &[ColourInfo], b: &[ColourInfo], ratio: f64, metric: Metric) -> Vec<ColourInfo> {
+    let pairs = pair_colours(a, b, metric);
+    let uses = |side: fn(&(usize, usize)) -> usize, index: usize| pairs.iter().filter(|p| side(p) == index).count();
+    pairs
+        .iter()
+        .map(|&(i, j)| {
+            let (x, y) = (&a[i], &b[j]);
+            let (lab_x, lab_y) = (colour::rgb_to_lab(x.rgb), colour::rgb_to_lab(y.rgb));
+            let lab = std::array::from_fn(|c| lerp(lab_x[c], lab_y[c], ratio));
+            let share_x = x.percentage / uses(|p| p.0, i) as f64;
+            let share_y = y.percentage / uses(|p| p.1, j) as f64;
+            ColourInfo::new(colour::lab_to_rgb(lab), lerp(share_x, share_y, ratio))
+        })
+        .collect()
+}
+
+/// Index pairs `(a, b)`: a greedy nearest-first matching, then the unmatched colours of
+/// the larger palette with their nearest partner.
+fn pair_colours(a: &[ColourInfo], b: &[ColourInfo], metric: Metric) -> Vec<(usize, usize)> {
+    let mut candidates: Vec<(f64, usize, usize)> = a
+        .iter()
+        .enumerate()
+        .flat_map(|(i, x)| b.iter().enumerate().map(move |(j, y)| (metric.distance(x.rgb, y.rgb), i, j)))
+        .collect();
+    candidates.sort_by(|p, q| p.0.total_cmp(&q.0));
+
+    let (mut used_a, mut used_b) = (vec![false; a.len()], vec![false; b.len()]);
+    let mut pairs = Vec::new();
+    for &(_, i, j) in &candidates {
+        if !used_a[i] && !used_b[j] {
+            used_a[i] = true;
+            used_b[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    for &(_, i, j) in &candidates {
+        if !used_a[i] {
+            used_a[i] = true;
+            pairs.push((i, j));
+        } else if !used_b[j] {
+            used_b[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+fn lerp(from: f64, to: f64, ratio: f64) -> f64 {
+    from + (to - from) * ratio
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix() {
+        let a = [ColourInfo::new([255, 0, 0], 75.0), ColourInfo::new([0, 0, 255], 25.0)];
+        let b = [ColourInfo::new([0, 0, 200], 50.0), ColourInfo::new([200, 0, 0], 50.0)];
+        let rgb = |colours: &[ColourInfo]| colours.iter().map(|c| c.rgb).collect::<Vec<_>>();
+
+        // The ends reproduce each palette; reds pair with reds
+        assert_eq!(rgb(&mix(&a, &b, 0.0, Metric::Cie76)), [[255, 0, 0], [0, 0, 255]]);
+        assert_eq!(rgb(&mix(&a, &b, 1.0, Metric::Cie76)), [[200, 0, 0], [0, 0, 200]]);
+        let halfway = mix(&a, &b, 0.5, Metric::Cie76);
+        assert!(halfway[0].rgb[0] > 200 && halfway[0].rgb[0] < 255);
+        assert_eq!(halfway[0].percentage, 62.5);
+
+        // Towards one colour, that colour's share is split between the pairs
+        let target = [ColourInfo::new([255, 255, 255], 100.0)];
+        let mixed = mix(&a, &target, 1.0, Metric::Cie76);
+        assert_eq!(rgb(&mixed), [[255, 255, 255], [255, 255, 255]]);
+        assert!((mixed.iter().map(|c| c.percentage).sum::<f64>() - 100.0).abs() < 1e-9);
+    }
+}
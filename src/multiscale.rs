@@ -0,0 +1,100 @@
+//! How stable a palette is across downsampling levels.
+//!
+//! Resampling blends fine detail: thin red and green stripes become brown, and ringing
+//! around sharp edges adds halos. A colour that is really in the image turns up however
+//! coarsely it is sampled, while one made by resampling comes and goes between scales.
+
+use tracing::{debug, warn};
+
+use crate::{cluster_pixels, image_pixels, open_image, sample_side, Analysis, AnalysisOptions, DominantColoursError};
+
+/// Sampling boxes tried, as fractions of the side `--max-pixels` gives.
+pub const SCALES: [f64; 3] = [1.0, 0.5, 0.25];
+
+/// Smallest box side worth clustering.
+const MIN_SIDE: u32 = 8;
+
+/// Most delta-E between a colour and its nearest match for it to count as present.
+pub const STABLE_DELTA_E: f64 = 10.0;
+
+#[derive(Debug)]
+pub struct Stability {
+    /// Side of the sampling box at each scale, largest first
+    pub sides: Vec<u32>,
+    /// For each colour of the palette, the number of scales it is found at, itself included
+    pub scales_found: Vec<usize>,
+    /// Colours found at fewer than half the scales, which may be resampling artefacts
+    pub warnings: Vec<String>,
+}
+
+/// Check the colours of `analysis`, made with `options`, against palettes of the image
+/// sampled more coarsely.
+pub fn stability(
+    img: &image::DynamicImage,
+    analysis: &Analysis,
+    options: &AnalysisOptions,
+) -> Result<Stability, DominantColoursError> {
+    let side = sample_side(options.max_pixels);
+    let sides: Vec<u32> = SCALES.iter().map(|scale| ((side as f64 * scale) as u32).max(MIN_SIDE)).collect();
+    let mut scales_found = vec![1; analysis.colours.len()];
+    for &coarse in &sides[1..] {
+        debug!(side = coarse, "Clustering at a coarser scale");
+        let scaled = AnalysisOptions { max_pixels: (coarse * coarse) as usize, ..options.clone() };
+        let palette = cluster_pixels(image_pixels(img, &scaled), &scaled)?.colours;
+        for (found, colour) in scales_found.iter_mut().zip(&analysis.colours) {
+            if palette.iter().any(|other| options.metric.distance(colour.rgb, other.rgb) <= STABLE_DELTA_E) {
+                *found += 1;
+            }
+        }
+    }
+
+    let warnings = analysis
+        .colours
+        .iter()
+        .zip(&scales_found)
+        .filter(|(_, &found)| found * 2 < sides.len())
+        .map(|(colour, found)| {
+            let message = format!(
+                "{} appears at only {} of {} scales; it may be a resampling artefact",
+                crate::rgb_to_hex(colour.rgb), found, sides.len()
+            );
+            warn!("{}", message);
+            message
+        })
+        .collect();
+    Ok(Stability { sides, scales_found, warnings })
+}
+
+/// Check the palette of an image file across scales.
+pub fn stability_of_file(
+    filename: &str,
+    analysis: &Analysis,
+    options: &AnalysisOptions,
+) -> Result<Stability, DominantColoursError> {
+    let img = open_image(filename)?;
+    stability(&img, analysis, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ColourInfo;
+
+    #[test]
+    fn test_stability() -> anyhow::Result<()> {
+        // Solid halves survive any scale; a colour the image doesn't contain is flagged
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, _| {
+            if x < 16 { image::Rgb([220, 20, 20]) } else { image::Rgb([20, 20, 220]) }
+        }));
+        let options = AnalysisOptions { colours: 2, max_pixels: 32 * 32, ..AnalysisOptions::default() };
+        let mut analysis = cluster_pixels(image_pixels(&img, &options), &options)?;
+        analysis.colours.push(ColourInfo::new([20, 220, 20], 0.0));
+        let stability = stability(&img, &analysis, &options)?;
+
+        assert_eq!(stability.sides, [32, 16, 8]);
+        assert_eq!(stability.scales_found, [3, 3, 1]);
+        assert_eq!(stability.warnings.len(), 1);
+        assert!(stability.warnings[0].starts_with("#14dc14 appears at only 1 of 3 scales"));
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+//! Nearest named-colour lookup.
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::sync::OnceLock;
+
+use crate::colour::{self, Metric};
+
+/// Built-in colour-name tables.
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum NameSet {
+    /// The 148 CSS/X11 named colours
+    Css,
+    /// The 949 names from the xkcd colour survey
+    Xkcd,
+}
+
+impl std::fmt::Display for NameSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameSet::Css => write!(f, "css"),
+            NameSet::Xkcd => write!(f, "xkcd"),
+        }
+    }
+}
+
+impl NameSet {
+    pub fn table(self) -> &'static [NamedColour] {
+        match self {
+            NameSet::Css => css(),
+            NameSet::Xkcd => xkcd(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NamedColour {
+    pub name: String,
+    pub rgb: [u8; 3],
+}
+
+/// Parse a table of `name<TAB>#rrggbb` lines; blank lines and `#` comments are skipped.
+fn parse_table(data: &str) -> Vec<NamedColour> {
+    data.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (name, hex) = line.split_once('\t')?;
+            Some(NamedColour {
+                name: name.to_string(),
+                rgb: colour::parse_hex(hex)?,
+            })
+        })
+        .collect()
+}
+
+/// The CSS Color Module Level 4 named colours (which include the X11 set).
+pub fn css() -> &'static [NamedColour] {
+    static TABLE: OnceLock<Vec<NamedColour>> = OnceLock::new();
+    TABLE.get_or_init(|| parse_table(include_str!("data/css.txt")))
+}
+
+/// The xkcd colour survey names, e.g. "dusty rose".
+pub fn xkcd() -> &'static [NamedColour] {
+    static TABLE: OnceLock<Vec<NamedColour>> = OnceLock::new();
+    TABLE.get_or_init(|| parse_table(include_str!("data/xkcd.txt")))
+}
+
+/// Load a custom `name,hex` lookup table, e.g. a paint catalogue or product colour taxonomy.
+///
+/// A first line that does not parse (such as a `name,hex` header) is skipped; any other
+/// malformed line is an error.
+pub fn load_csv(path: &str) -> Result<Vec<NamedColour>> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read names file {}", path))?;
+
+    let mut table = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parsed = line.rsplit_once(',').and_then(|(name, hex)| {
+            let name = name.trim().trim_matches('"');
+            Some(NamedColour { name: name.to_string(), rgb: colour::parse_hex(hex)? })
+        });
+        match parsed {
+            Some(named) => table.push(named),
+            None if i == 0 => continue,
+            None => bail!("{}:{}: expected name,hex but found {:?}", path, i + 1, line),
+        }
+    }
+    if table.is_empty() {
+        bail!("Names file {} contains no colours", path);
+    }
+    Ok(table)
+}
+
+/// The entry in `table` closest to `rgb` by `metric`, with its distance.
+pub fn nearest(rgb: [u8; 3], table: &[NamedColour], metric: Metric) -> Option<(&NamedColour, f64)> {
+    table
+        .iter()
+        .map(|named| (named, metric.distance(rgb, named.rgb)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_css_table() {
+        assert_eq!(css().len(), 148);
+        assert!(css().iter().any(|c| c.name == "rebeccapurple" && c.rgb == [0x66, 0x33, 0x99]));
+    }
+
+    #[test]
+    fn test_xkcd_table() {
+        assert_eq!(xkcd().len(), 949);
+        let (named, _) = nearest([0xc0, 0x73, 0x7a], NameSet::Xkcd.table(), Metric::Cie76).unwrap();
+        assert_eq!(named.name, "dusty rose");
+    }
+
+    #[test]
+    fn test_nearest() {
+        let (named, distance) = nearest([0x66, 0x33, 0x99], css(), Metric::Cie76).unwrap();
+        assert_eq!(named.name, "rebeccapurple");
+        assert_eq!(distance, 0.0);
+
+        let (named, distance) = nearest([250, 5, 3], css(), Metric::Cie76).unwrap();
+        assert_eq!(named.name, "red");
+        assert!(distance > 0.0 && distance < 5.0);
+
+        assert!(nearest([0, 0, 0], &[], Metric::Cie76).is_none());
+    }
+
+    #[test]
+    fn test_load_csv() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("colours.csv");
+        std::fs::write(&path, "name,hex\nSignal Red,#c1121c\n\"Blue, Royal\",#0057b8\n")?;
+
+        let table = load_csv(path.to_str().unwrap())?;
+        assert_eq!(table.len(), 2);
+        assert_eq!(table[1].name, "Blue, Royal");
+        assert_eq!(nearest([0, 80, 190], &table, Metric::Cie76).unwrap().0.name, "Blue, Royal");
+
+        std::fs::write(&path, "Signal Red,#c1121c\nbroken line\n")?;
+        assert!(load_csv(path.to_str().unwrap()).is_err());
+        Ok(())
+    }
+}
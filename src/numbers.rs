@@ -0,0 +1,106 @@
+//! How shares of an image (percentages of its pixels) are written: to `--precision`
+//! decimal places everywhere, and with `--decimal-separator` in text and in swatch and
+//! chart labels, so reports need no reformatting downstream.
+
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum, PartialEq)]
+pub enum DecimalSeparator {
+    /// 12.5%
+    #[default]
+    Point,
+    /// 12,5%
+    Comma,
+    /// Whichever the locale in LC_ALL, LC_NUMERIC or LANG uses
+    Locale,
+}
+
+impl std::fmt::Display for DecimalSeparator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecimalSeparator::Point => write!(f, "point"),
+            DecimalSeparator::Comma => write!(f, "comma"),
+            DecimalSeparator::Locale => write!(f, "locale"),
+        }
+    }
+}
+
+/// Languages whose locales write a decimal comma.
+const COMMA_LANGUAGES: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fr", "gl", "hr", "hu", "id", "is", "it", "lt", "lv",
+    "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Format {
+    precision: Option<usize>,
+    comma: bool,
+}
+
+static FORMAT: OnceLock<Format> = OnceLock::new();
+
+/// Set how shares are written for the rest of the run.
+pub fn init(precision: Option<u8>, separator: DecimalSeparator) {
+    let comma = match separator {
+        DecimalSeparator::Point => false,
+        DecimalSeparator::Comma => true,
+        DecimalSeparator::Locale => locale_uses_comma(),
+    };
+    let _ = FORMAT.set(Format { precision: precision.map(usize::from), comma });
+}
+
+/// `value` percent for people, to `--precision` places or else `places`.
+pub fn share(value: f64, places: usize) -> String {
+    format_share(value, places, FORMAT.get().copied().unwrap_or_default())
+}
+
+/// `value` percent for JSON, rounded to `--precision` places if it was given.
+pub fn share_json(value: f64) -> f64 {
+    match FORMAT.get().and_then(|format| format.precision) {
+        Some(places) => {
+            let scale = 10f64.powi(places as i32);
+            (value * scale).round() / scale
+        }
+        None => value,
+    }
+}
+
+fn format_share(value: f64, places: usize, format: Format) -> String {
+    let text = format!("{:.*}", format.precision.unwrap_or(places), value);
+    if format.comma {
+        text.replace('.', ",")
+    } else {
+        text
+    }
+}
+
+fn locale_uses_comma() -> bool {
+    let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    comma_locale(&locale)
+}
+
+/// Whether a locale name such as `de_DE.UTF-8` writes a decimal comma.
+fn comma_locale(locale: &str) -> bool {
+    let language = locale.split(['_', '.', '@', '-']).next().unwrap_or_default().to_ascii_lowercase();
+    COMMA_LANGUAGES.contains(&language.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_format() {
+        assert_eq!(format_share(12.345, 1, Format::default()), "12.3");
+        assert_eq!(format_share(12.346, 1, Format { precision: Some(2), comma: true }), "12,35");
+        assert_eq!(format_share(12.4, 1, Format { precision: Some(0), comma: false }), "12");
+        assert!(comma_locale("de_DE.UTF-8") && comma_locale("pt-BR"));
+        assert!(!comma_locale("en_GB.UTF-8") && !comma_locale("C") && !comma_locale(""));
+    }
+}
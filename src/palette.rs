@@ -0,0 +1,283 @@
+//! Reading palettes back from files, so saved or hand-written palettes can be reused.
+
+use anyhow::{bail, Context, Result};
+
+use crate::colour;
+use crate::ColourInfo;
+
+/// Load a palette from a file: JSON (this tool's output, or a plain array of hex
+/// strings), a GIMP `.gpl` palette, an Adobe `.ase` swatch exchange file, or a text list
+/// with one hex colour per line and an optional weight after it. The format is told from
+/// the contents, not the extension.
+///
+/// Colours without a weight share the palette equally.
+pub fn load(path: &str) -> Result<Vec<ColourInfo>> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read palette file {}", path))?;
+    let colours = if bytes.starts_with(ASE_SIGNATURE) {
+        parse_ase(&bytes).with_context(|| format!("Invalid ASE palette in {}", path))?
+    } else {
+        let data = String::from_utf8(bytes).with_context(|| format!("Palette file {} is not text", path))?;
+        if data.trim_start().starts_with(['{', '[']) {
+            parse_json(&data).with_context(|| format!("Invalid JSON palette in {}", path))?
+        } else if data.trim_start().starts_with(GPL_HEADER) {
+            parse_gpl(&data).with_context(|| format!("Invalid GIMP palette in {}", path))?
+        } else {
+            parse_hex_list(&data).with_context(|| format!("Invalid hex palette in {}", path))?
+        }
+    };
+    if colours.is_empty() {
+        bail!("Palette file {} contains no colours", path);
+    }
+    Ok(with_default_weights(colours))
+}
+
+/// Parse `#rrggbb [weight]` lines; `,` may separate the two, and `//` or `;` start comments.
+fn parse_hex_list(data: &str) -> Result<Vec<([u8; 3], Option<f64>)>> {
+    let mut colours = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.split("//").next().unwrap().split(';').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split(|c: char| c == ',' || c.is_whitespace()).filter(|f| !f.is_empty());
+        let hex = fields.next().unwrap();
+        let rgb = colour::parse_hex(hex)
+            .with_context(|| format!("line {}: {:?} is not a hex colour", i + 1, hex))?;
+        let weight = fields
+            .next()
+            .map(|w| w.trim_end_matches('%').parse::<f64>())
+            .transpose()
+            .with_context(|| format!("line {}: invalid weight", i + 1))?;
+        colours.push((rgb, weight));
+    }
+    Ok(colours)
+}
+
+/// Accept `{"colours": [{"hex": .., "percentage": ..}, ..]}` or `["#rrggbb", ..]`.
+fn parse_json(data: &str) -> Result<Vec<([u8; 3], Option<f64>)>> {
+    let value: serde_json::Value = serde_json::from_str(data)?;
+    let entries = match &value {
+        serde_json::Value::Array(entries) => entries,
+        serde_json::Value::Object(map) => match map.get("colours") {
+            Some(serde_json::Value::Array(entries)) => entries,
+            _ => bail!("expected a \"colours\" array"),
+        },
+        _ => bail!("expected an object or an array"),
+    };
+
+    entries
+        .iter()
+        .map(|entry| {
+            let (hex, weight) = match entry {
+                serde_json::Value::String(hex) => (hex.as_str(), None),
+                serde_json::Value::Object(fields) => (
+                    fields.get("hex").and_then(|h| h.as_str()).context("colour entry without \"hex\"")?,
+                    fields.get("percentage").and_then(|p| p.as_f64()),
+                ),
+                _ => bail!("unexpected palette entry {}", entry),
+            };
+            let rgb = colour::parse_hex(hex).with_context(|| format!("{:?} is not a hex colour", hex))?;
+            Ok((rgb, weight))
+        })
+        .collect()
+}
+
+const GPL_HEADER: &str = "GIMP Palette";
+
+/// Parse a GIMP palette: the header line, optional `Name:` and `Columns:` lines, `#`
+/// comments, then `red green blue [name]` lines with decimal channel values.
+fn parse_gpl(data: &str) -> Result<Vec<([u8; 3], Option<f64>)>> {
+    let mut colours = Vec::new();
+    for (i, line) in data.lines().enumerate().skip_while(|(_, l)| l.trim().is_empty()).skip(1) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+            continue;
+        }
+        let mut channels = line.split_whitespace().map(|c| c.parse::<u8>());
+        let mut channel = || {
+            channels
+                .next()
+                .and_then(|c| c.ok())
+                .with_context(|| format!("line {}: expected red, green and blue values from 0 to 255", i + 1))
+        };
+        colours.push(([channel()?, channel()?, channel()?], None));
+    }
+    Ok(colours)
+}
+
+const ASE_SIGNATURE: &[u8] = b"ASEF";
+
+/// ASE block types; group markers only matter for skipping.
+const ASE_COLOUR: u16 = 0x0001;
+
+/// Parse an Adobe Swatch Exchange file: big-endian blocks of named colours in RGB, CMYK,
+/// Lab or grey, possibly inside groups. CMYK is converted without a profile.
+fn parse_ase(data: &[u8]) -> Result<Vec<([u8; 3], Option<f64>)>> {
+    let mut reader = AseReader { data, offset: ASE_SIGNATURE.len() };
+    let _version = (reader.u16()?, reader.u16()?);
+    let blocks = reader.u32()?;
+    let mut colours = Vec::new();
+    for _ in 0..blocks {
+        let kind = reader.u16()?;
+        let length = reader.u32()? as usize;
+        let end = reader.offset.checked_add(length).filter(|&end| end <= data.len()).context("truncated block")?;
+        if kind == ASE_COLOUR {
+            let name_units = reader.u16()? as usize;
+            reader.take(name_units * 2)?;
+            let model = reader.take(4)?;
+            let rgb = match model {
+                b"RGB " => [reader.f32()?, reader.f32()?, reader.f32()?].map(unit_to_u8),
+                b"CMYK" => {
+                    let [c, m, y, k] = [reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?];
+                    [c, m, y].map(|v| unit_to_u8((1.0 - v) * (1.0 - k)))
+                }
+                b"LAB " => {
+                    // Lightness is stored as a fraction rather than 0-100
+                    let [l, a, b] = [reader.f32()?, reader.f32()?, reader.f32()?];
+                    colour::lab_to_rgb([l * 100.0, a, b])
+                }
+                b"Gray" => [unit_to_u8(reader.f32()?); 3],
+                _ => bail!("unknown colour model {:?}", String::from_utf8_lossy(model)),
+            };
+            colours.push((rgb, None));
+        }
+        reader.offset = end;
+    }
+    Ok(colours)
+}
+
+fn unit_to_u8(value: f64) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+struct AseReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> AseReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset + len).context("unexpected end of file")?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f64> {
+        Ok(f32::from_be_bytes(self.take(4)?.try_into().unwrap()) as f64)
+    }
+}
+
+fn with_default_weights(colours: Vec<([u8; 3], Option<f64>)>) -> Vec<ColourInfo> {
+    let equal = 100.0 / colours.len() as f64;
+    colours
+        .into_iter()
+        .map(|(rgb, weight)| ColourInfo::new(rgb, weight.unwrap_or(equal)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_hex_list() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("brand.txt");
+        std::fs::write(&path, "// brand colours\n#0057b8 60\n#ffd700, 40%\n")?;
+
+        let palette = load(path.to_str().unwrap())?;
+        assert_eq!(palette.len(), 2);
+        assert_eq!(palette[0].rgb, [0, 87, 184]);
+        assert_eq!(palette[1].percentage, 40.0);
+
+        std::fs::write(&path, "#0057b8\n#ffd700\n")?;
+        let palette = load(path.to_str().unwrap())?;
+        assert_eq!(palette[0].percentage, 50.0);
+
+        std::fs::write(&path, "#0057b8\nnot-a-colour\n")?;
+        assert!(load(path.to_str().unwrap()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_json() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("palette.json");
+        std::fs::write(
+            &path,
+            r##"{"colours": [{"hex": "#ff0000", "percentage": 75.0, "rgb": [255, 0, 0]},
+                            {"hex": "#0000ff", "percentage": 25.0}]}"##,
+        )?;
+        let palette = load(path.to_str().unwrap())?;
+        assert_eq!(palette.len(), 2);
+        assert_eq!(palette[0].rgb, [255, 0, 0]);
+        assert_eq!(palette[1].percentage, 25.0);
+
+        std::fs::write(&path, r##"["#ff0000", "#00ff00", "#0000ff", "#ffffff"]"##)?;
+        let palette = load(path.to_str().unwrap())?;
+        assert_eq!(palette.len(), 4);
+        assert_eq!(palette[3].percentage, 25.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_gpl() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("brand.gpl");
+        std::fs::write(&path, "GIMP Palette\nName: Brand\nColumns: 2\n#\n  0  87 184\tBlue\n255 215   0 Gold\n")?;
+        let palette = load(path.to_str().unwrap())?;
+        assert_eq!(palette.iter().map(|c| c.rgb).collect::<Vec<_>>(), [[0, 87, 184], [255, 215, 0]]);
+        assert_eq!(palette[0].percentage, 50.0);
+
+        std::fs::write(&path, "GIMP Palette\n300 0 0 Too red\n")?;
+        assert!(load(path.to_str().unwrap()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_ase() -> Result<(), Box<dyn std::error::Error>> {
+        fn colour_block(model: &[u8; 4], values: &[f32]) -> Vec<u8> {
+            let name: Vec<u8> = "c\0".encode_utf16().flat_map(u16::to_be_bytes).collect();
+            let mut body = 2u16.to_be_bytes().to_vec();
+            body.extend(name);
+            body.extend(model);
+            values.iter().for_each(|v| body.extend(v.to_be_bytes()));
+            body.extend(2u16.to_be_bytes());
+            let mut block = ASE_COLOUR.to_be_bytes().to_vec();
+            block.extend((body.len() as u32).to_be_bytes());
+            block.extend(body);
+            block
+        }
+        let mut data = b"ASEF\0\x01\0\0".to_vec();
+        data.extend(6u32.to_be_bytes());
+        // A group around the first two colours
+        data.extend([0xc0, 0x01, 0, 0, 0, 4, 0, 1, 0, 0]);
+        data.extend(colour_block(b"RGB ", &[1.0, 0.5, 0.0]));
+        data.extend(colour_block(b"CMYK", &[0.0, 1.0, 1.0, 0.5]));
+        data.extend([0xc0, 0x02, 0, 0, 0, 0]);
+        data.extend(colour_block(b"Gray", &[0.2]));
+        data.extend(colour_block(b"LAB ", &[1.0, 0.0, 0.0]));
+
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("swatches.ase");
+        std::fs::write(&path, &data)?;
+        let palette = load(path.to_str().unwrap())?;
+        assert_eq!(
+            palette.iter().map(|c| c.rgb).collect::<Vec<_>>(),
+            [[255, 128, 0], [128, 0, 0], [51, 51, 51], [255, 255, 255]]
+        );
+
+        std::fs::write(&path, &data[..data.len() - 3])?;
+        assert!(load(path.to_str().unwrap()).is_err());
+        Ok(())
+    }
+}
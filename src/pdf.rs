@@ -0,0 +1,66 @@
+//! Palettes of PDF pages, e.g. for checking the colour make-up of print proofs.
+//!
+//! The chosen page is rendered by `pdftoppm`, from Poppler, which must be on the `PATH`,
+//! at a size a little larger than the clustering sample, and analysed like any image.
+
+use std::io::ErrorKind;
+use std::process::{Command, Stdio};
+
+use image::ImageError;
+use tracing::debug;
+
+use crate::DominantColoursError;
+
+/// Whether `filename` names a PDF, going by its extension.
+pub fn is_pdf(filename: &str) -> bool {
+    filename.to_ascii_lowercase().ends_with(".pdf")
+}
+
+/// Render page `page` (from 1) of a PDF file with its longer side `side` pixels long.
+pub fn render_page(filename: &str, page: u32, side: u32) -> Result<image::DynamicImage, DominantColoursError> {
+    let failed = |message: String| {
+        let source = std::io::Error::new(ErrorKind::InvalidData, message);
+        DominantColoursError::image(Some(filename), ImageError::IoError(source))
+    };
+    if crate::in_memory(filename) {
+        return Err(failed("PDF pages can only be rendered from local files".to_string()));
+    }
+    std::fs::metadata(filename).map_err(|source| DominantColoursError::Io { path: Some(filename.to_string()), source })?;
+    debug!(file = filename, page, side, "Rendering PDF page with pdftoppm");
+    let output = command(filename, page, side)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => failed(format!("pdftoppm (from Poppler) was not found to render page {} of the PDF", page)),
+            _ => DominantColoursError::Io { path: Some(filename.to_string()), source: e },
+        })?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(failed(format!("pdftoppm failed to render page {}: {}", page, message.trim())));
+    }
+    image::load_from_memory_with_format(&output.stdout, image::ImageFormat::Png)
+        .map_err(|e| DominantColoursError::image(Some(filename), e))
+}
+
+/// `pdftoppm` writing one page as a PNG to standard output.
+fn command(filename: &str, page: u32, side: u32) -> Command {
+    let mut command = Command::new("pdftoppm");
+    let page = page.to_string();
+    command
+        .args(["-f", &page, "-l", &page, "-singlefile", "-png"])
+        .args(["-scale-to", &side.to_string(), filename]);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdftoppm_command() {
+        let command = command("proof.pdf", 3, 300);
+        let args: Vec<_> = command.get_args().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(args, ["-f", "3", "-l", "3", "-singlefile", "-png", "-scale-to", "300", "proof.pdf"]);
+        assert!(is_pdf("Proof.PDF") && !is_pdf("proof.png"));
+    }
+}
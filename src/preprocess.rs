@@ -0,0 +1,186 @@
+//! Adjustments made to the pixels before clustering.
+//!
+//! An underexposed photo crowds most of its pixels into the bottom of the range, where
+//! k-means spends several clusters on near-blacks that barely differ. Stretching the
+//! levels or lifting the gamma spreads them out so the palette shows the colours the
+//! scene actually has. These tonal adjustments are made to the sampled pixels.
+//!
+//! Sensor noise and JPEG artefacts scatter pixels around the colour they should be, and
+//! centroids of small areas get pulled towards muddy mixtures of them. Denoising filters
+//! the image at the resolution it is decoded at, before it is downsampled, since a
+//! filter on the small sampling grid would wipe out small areas with the noise. So
+//! `--denoise` turns off sampling while decoding (see `stream`) and decodes the whole
+//! image, which for a huge scan means holding all of its pixels in memory at once.
+
+use image::RgbImage;
+use tracing::debug;
+
+use crate::{AnalysisOptions, Denoise};
+
+/// Pixels on each side of the centre of the bilateral filter window
+const BILATERAL_RADIUS: i64 = 2;
+/// Spread of the bilateral filter's spatial weights, in pixels
+const BILATERAL_SPATIAL_SIGMA: f64 = 1.5;
+/// Spread of the bilateral filter's weights by colour difference, in RGB units: pixels
+/// much further than this from the centre, across an edge, barely count
+const BILATERAL_RANGE_SIGMA: f64 = 20.0;
+
+/// Share of pixels at each end of the luma range left out of `--auto-levels`, so a few
+/// specular highlights or dead pixels don't stop the stretch.
+pub const CLIP_FRACTION: f64 = 0.005;
+
+/// Apply the adjustments `options` ask for: levels first, then gamma.
+pub fn apply(image: &mut RgbImage, options: &AnalysisOptions) {
+    if options.auto_levels {
+        auto_levels(image);
+    }
+    if let Some(gamma) = options.gamma.filter(|&gamma| gamma != 1.0) {
+        apply_gamma(image, gamma);
+    }
+}
+
+/// Stretch luma so the clipped darkest and lightest pixels become black and white.
+/// Every channel gets the same mapping, which keeps hues.
+pub fn auto_levels(image: &mut RgbImage) {
+    let mut histogram = [0usize; 256];
+    for p in image.pixels() {
+        histogram[luma(p.0)] += 1;
+    }
+    let clip = (image.pixels().len() as f64 * CLIP_FRACTION) as usize;
+    let low = past_clip(&histogram, clip, 0..256);
+    let high = past_clip(&histogram, clip, (0..256).rev());
+    let (Some(low), Some(high)) = (low, high) else {
+        return;
+    };
+    if high <= low {
+        return;
+    }
+    debug!(low, high, "Stretching levels");
+    let scale = 255.0 / (high - low) as f64;
+    let table: Vec<u8> =
+        (0..256).map(|c| ((c as f64 - low as f64) * scale).round().clamp(0.0, 255.0) as u8).collect();
+    for p in image.pixels_mut() {
+        p.0 = p.0.map(|c| table[c as usize]);
+    }
+}
+
+/// The first of `levels` with more than `clip` pixels at or before it.
+fn past_clip(histogram: &[usize; 256], clip: usize, mut levels: impl Iterator<Item = usize>) -> Option<usize> {
+    let mut seen = 0;
+    levels.find(|&level| {
+        seen += histogram[level];
+        seen > clip
+    })
+}
+
+/// Raise each channel to `1 / gamma`: values above 1 lift the shadows, below 1 deepen them.
+pub fn apply_gamma(image: &mut RgbImage, gamma: f64) {
+    debug!(gamma, "Adjusting gamma");
+    let table: Vec<u8> = (0..256).map(|c| (255.0 * (c as f64 / 255.0).powf(1.0 / gamma)).round() as u8).collect();
+    for p in image.pixels_mut() {
+        p.0 = p.0.map(|c| table[c as usize]);
+    }
+}
+
+/// Filter out noise with `method`.
+pub fn denoise(image: &RgbImage, method: Denoise) -> RgbImage {
+    debug!(%method, width = image.width(), height = image.height(), "Denoising");
+    match method {
+        Denoise::Median => median(image),
+        Denoise::Bilateral => bilateral(image),
+    }
+}
+
+/// Pixel at `(x, y)`, with coordinates outside the image clamped to its edge.
+fn clamped(image: &RgbImage, x: i64, y: i64) -> [u8; 3] {
+    let x = x.clamp(0, image.width() as i64 - 1) as u32;
+    let y = y.clamp(0, image.height() as i64 - 1) as u32;
+    image.get_pixel(x, y).0
+}
+
+/// 3x3 median per channel: removes isolated specks and keeps edges.
+fn median(image: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let mut window = [[0u8; 9]; 3];
+        for (i, (dx, dy)) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))).enumerate() {
+            let rgb = clamped(image, x as i64 + dx, y as i64 + dy);
+            for c in 0..3 {
+                window[c][i] = rgb[c];
+            }
+        }
+        image::Rgb(window.map(|mut channel| *channel.select_nth_unstable(4).1))
+    })
+}
+
+/// Average of the nearby pixels, each weighted by its distance and by how close its
+/// colour is to the centre's: smooths noise within areas without blurring across edges.
+fn bilateral(image: &RgbImage) -> RgbImage {
+    let offsets: Vec<(i64, i64, f64)> = (-BILATERAL_RADIUS..=BILATERAL_RADIUS)
+        .flat_map(|dy| (-BILATERAL_RADIUS..=BILATERAL_RADIUS).map(move |dx| (dx, dy)))
+        .map(|(dx, dy)| (dx, dy, (-((dx * dx + dy * dy) as f64) / (2.0 * BILATERAL_SPATIAL_SIGMA.powi(2))).exp()))
+        .collect();
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let centre = image.get_pixel(x, y).0;
+        let mut sum = [0.0; 3];
+        let mut total = 0.0;
+        for &(dx, dy, spatial) in &offsets {
+            let rgb = clamped(image, x as i64 + dx, y as i64 + dy);
+            let distance: f64 = (0..3).map(|c| (rgb[c] as f64 - centre[c] as f64).powi(2)).sum();
+            let weight = spatial * (-distance / (2.0 * BILATERAL_RANGE_SIGMA.powi(2))).exp();
+            for c in 0..3 {
+                sum[c] += rgb[c] as f64 * weight;
+            }
+            total += weight;
+        }
+        image::Rgb(sum.map(|s| (s / total).round() as u8))
+    })
+}
+
+/// Rec. 709 luma of gamma-encoded values, as in the luma histogram.
+fn luma(rgb: [u8; 3]) -> usize {
+    (0.2126 * rgb[0] as f64 + 0.7152 * rgb[1] as f64 + 0.0722 * rgb[2] as f64).round() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_levels() {
+        // A dim image from grey 10 to 59 is stretched to the full range
+        let mut image = RgbImage::from_fn(50, 2, |x, _| image::Rgb([10 + x as u8; 3]));
+        image.put_pixel(0, 1, image::Rgb([20, 10, 10]));
+        auto_levels(&mut image);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(image.get_pixel(49, 0).0, [255, 255, 255]);
+        assert_eq!(image.get_pixel(0, 1).0, [52, 0, 0]);
+
+        // A flat image has no range to stretch
+        let mut flat = RgbImage::from_pixel(4, 4, image::Rgb([30, 40, 50]));
+        auto_levels(&mut flat);
+        assert_eq!(flat.get_pixel(0, 0).0, [30, 40, 50]);
+    }
+
+    #[test]
+    fn test_denoise() {
+        // A speck of noise in a flat area, next to a hard edge
+        let mut image = RgbImage::from_fn(8, 8, |x, _| {
+            if x < 4 { image::Rgb([200, 30, 30]) } else { image::Rgb([30, 30, 200]) }
+        });
+        image.put_pixel(1, 3, image::Rgb([215, 40, 25]));
+        for method in [Denoise::Median, Denoise::Bilateral] {
+            let filtered = denoise(&image, method);
+            let speck = filtered.get_pixel(1, 3).0;
+            assert!(speck.iter().zip([200, 30, 30]).all(|(&a, b)| a.abs_diff(b) <= 2), "{} left {:?}", method, speck);
+            assert_eq!(filtered.get_pixel(3, 0).0, [200, 30, 30], "{} blurred the edge", method);
+            assert_eq!(filtered.get_pixel(4, 7).0, [30, 30, 200], "{} blurred the edge", method);
+        }
+    }
+
+    #[test]
+    fn test_gamma() {
+        let mut image = RgbImage::from_fn(3, 1, |x, _| image::Rgb([[0, 64, 255][x as usize]; 3]));
+        apply_gamma(&mut image, 2.0);
+        assert_eq!(image.pixels().map(|p| p.0[0]).collect::<Vec<_>>(), [0, 128, 255]);
+    }
+}
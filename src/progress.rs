@@ -0,0 +1,112 @@
+//! Progress bars on stderr for long runs.
+//!
+//! Bars are only drawn when stderr is a terminal and `--quiet` is not given. Log lines
+//! and report output are printed with the bars suspended, so the two never interleave.
+//! linfa does not report individual k-means iterations, so the per-image spinner shows
+//! the analysis stage in progress, taken from the library's log events.
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+/// Spinner of the image being analysed, if any.
+static STAGE: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+pub fn init(quiet: bool) {
+    let target = if !quiet && io::stderr().is_terminal() {
+        ProgressDrawTarget::stderr()
+    } else {
+        ProgressDrawTarget::hidden()
+    };
+    let _ = BARS.set(MultiProgress::with_draw_target(target));
+}
+
+fn bars() -> &'static MultiProgress {
+    BARS.get_or_init(|| MultiProgress::with_draw_target(ProgressDrawTarget::hidden()))
+}
+
+/// Run `f` with the bars cleared from the screen, e.g. to print to stdout.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    bars().suspend(f)
+}
+
+/// Bar counting files through a batch; the message names the current file.
+pub fn files(len: usize) -> ProgressBar {
+    let bar = bars().add(ProgressBar::new(len as u64));
+    bar.set_style(
+        ProgressStyle::with_template("{bar:30} {pos}/{len} images, {eta} left  {wide_msg}")
+            .expect("valid template"),
+    );
+    bar
+}
+
+/// Spinner shown while one image is analysed, until the guard is dropped.
+pub struct Stage(ProgressBar);
+
+pub fn stage(image: &str) -> Stage {
+    let bar = bars().add(ProgressBar::new_spinner());
+    bar.set_style(
+        ProgressStyle::with_template("{spinner} {prefix}: {msg} ({elapsed})").expect("valid template"),
+    );
+    bar.set_prefix(image.to_string());
+    bar.set_message("Starting");
+    bar.enable_steady_tick(Duration::from_millis(100));
+    *STAGE.lock().unwrap() = Some(bar.clone());
+    Stage(bar)
+}
+
+impl Drop for Stage {
+    fn drop(&mut self) {
+        self.0.finish_and_clear();
+        *STAGE.lock().unwrap() = None;
+    }
+}
+
+/// Tracing layer that puts progress and stage messages on the current spinner.
+pub struct StageLayer;
+
+impl<S: Subscriber> Layer<S> for StageLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        if level != Level::INFO && level != Level::DEBUG {
+            return;
+        }
+        let Some(bar) = STAGE.lock().unwrap().clone() else {
+            return;
+        };
+        let mut message = MessageVisitor(None);
+        event.record(&mut message);
+        if let Some(message) = message.0 {
+            bar.set_message(message);
+        }
+    }
+}
+
+struct MessageVisitor(Option<String>);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Log output that clears the bars while a line is written.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
@@ -0,0 +1,58 @@
+//! Python bindings (`python` feature), built with maturin:
+//!
+//! ```python
+//! import dominant_colours
+//! for colour in dominant_colours.extract("image.jpg", colours=6, space="lab"):
+//!     print(colour["hex"], colour["percentage"])
+//! ```
+
+use clap::ValueEnum;
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::{analyze_image, rgb_to_hex, AnalysisOptions, ColourInfo, ColourSpace, DominantColoursError};
+
+/// Extract the dominant colours of the image at `path`.
+///
+/// Returns a list of dicts with `rgb`, `hex`, `percentage`, `std_dev` and `location`,
+/// most prevalent first. `space` is "lab" or "rgb".
+#[pyfunction]
+#[pyo3(signature = (path, colours=6, space="lab", silhouette=false, linear=false))]
+fn extract<'py>(
+    py: Python<'py>,
+    path: &str,
+    colours: usize,
+    space: &str,
+    silhouette: bool,
+    linear: bool,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    let space = ColourSpace::from_str(space, true).map_err(PyValueError::new_err)?;
+    let options = AnalysisOptions { colours, silhouette, space, linear, ..AnalysisOptions::default() };
+
+    // Clustering holds no Python objects, so other Python threads can run meanwhile
+    let analysis = py
+        .allow_threads(|| analyze_image(path, &options))
+        .map_err(|e| {
+            let unreadable = matches!(e, DominantColoursError::Io { .. });
+            let message = format!("{:#}", anyhow::Error::new(e));
+            if unreadable { PyOSError::new_err(message) } else { PyValueError::new_err(message) }
+        })?;
+    analysis.colours.iter().map(|c| colour_dict(py, c)).collect()
+}
+
+fn colour_dict<'py>(py: Python<'py>, colour: &ColourInfo) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("rgb", (colour.rgb[0], colour.rgb[1], colour.rgb[2]))?;
+    dict.set_item("hex", rgb_to_hex(colour.rgb))?;
+    dict.set_item("percentage", colour.percentage)?;
+    dict.set_item("std_dev", (colour.std_dev[0], colour.std_dev[1], colour.std_dev[2]))?;
+    dict.set_item("location", colour.location)?;
+    Ok(dict)
+}
+
+#[pymodule]
+fn dominant_colours(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(extract, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,83 @@
+//! Recolouring an image with its extracted palette, and masks of where each colour is.
+
+use image::{GrayImage, Luma, RgbImage};
+use std::collections::HashMap;
+
+use crate::colour::Metric;
+
+/// The nearest palette colour to each RGB value asked about, by a metric. Photos repeat
+/// colours heavily, so each lookup is remembered.
+pub struct NearestCache<'a> {
+    palette: &'a [[u8; 3]],
+    metric: Metric,
+    seen: HashMap<[u8; 3], usize>,
+}
+
+impl<'a> NearestCache<'a> {
+    /// A cache for lookups in `palette`, which must not be empty.
+    pub fn new(palette: &'a [[u8; 3]], metric: Metric) -> Self {
+        assert!(!palette.is_empty(), "no palette colours to look up");
+        NearestCache { palette, metric, seen: HashMap::new() }
+    }
+
+    /// The index in the palette of the colour nearest `rgb`.
+    pub fn index(&mut self, rgb: [u8; 3]) -> usize {
+        let NearestCache { palette, metric, seen } = self;
+        *seen.entry(rgb).or_insert_with(|| {
+            (0..palette.len())
+                .min_by(|&a, &b| metric.distance(rgb, palette[a]).total_cmp(&metric.distance(rgb, palette[b])))
+                .unwrap()
+        })
+    }
+}
+
+/// Replace every pixel with the nearest palette colour by `metric`. An empty palette
+/// leaves the image unchanged.
+pub fn quantize(image: &RgbImage, palette: &[[u8; 3]], metric: Metric) -> RgbImage {
+    let mut output = image.clone();
+    if palette.is_empty() {
+        return output;
+    }
+    let mut nearest = NearestCache::new(palette, metric);
+    for pixel in output.pixels_mut() {
+        pixel.0 = palette[nearest.index(pixel.0)];
+    }
+    output
+}
+
+/// One mask per palette colour, white where the nearest palette colour of a pixel (by
+/// `metric`) is that one and black elsewhere. Together they cover every pixel once.
+pub fn masks(image: &RgbImage, palette: &[[u8; 3]], metric: Metric) -> Vec<GrayImage> {
+    let mut masks = vec![GrayImage::new(image.width(), image.height()); palette.len()];
+    if palette.is_empty() {
+        return masks;
+    }
+    let mut nearest = NearestCache::new(palette, metric);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        masks[nearest.index(pixel.0)].put_pixel(x, y, Luma([255]));
+    }
+    masks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantize() {
+        let img = RgbImage::from_fn(4, 1, |x, _| image::Rgb([x as u8 * 60, 0, 10]));
+        let out = quantize(&img, &[[0, 0, 0], [200, 0, 0]], Metric::Cie76);
+        let pixels: Vec<[u8; 3]> = out.pixels().map(|p| p.0).collect();
+        assert_eq!(pixels, [[0, 0, 0], [0, 0, 0], [200, 0, 0], [200, 0, 0]]);
+        assert_eq!(quantize(&img, &[], Metric::Cie76), img);
+    }
+
+    #[test]
+    fn test_masks() {
+        let img = RgbImage::from_fn(4, 1, |x, _| image::Rgb([x as u8 * 60, 0, 10]));
+        let masks = masks(&img, &[[0, 0, 0], [200, 0, 0]], Metric::Cie76);
+        let values = |mask: &GrayImage| mask.pixels().map(|p| p.0[0]).collect::<Vec<_>>();
+        assert_eq!(values(&masks[0]), [255, 255, 0, 0]);
+        assert_eq!(values(&masks[1]), [0, 0, 255, 255]);
+    }
+}
@@ -0,0 +1,223 @@
+//! Palettes of named parts of an image, e.g. "sky", "product" and "logo".
+//!
+//! Regions are read from a JSON file listing rectangles and polygons in original-image
+//! pixel coordinates:
+//!
+//! ```json
+//! [
+//!     { "name": "sky", "rect": [0, 0, 1920, 400] },
+//!     { "name": "logo", "polygon": [[1700, 40], [1880, 40], [1790, 200]] }
+//! ]
+//! ```
+//!
+//! `rect` is `[x, y, width, height]`. Each region is downsampled and clustered on its own
+//! with the usual analysis options.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::{apply_mask, cluster_pixels, image_pixels, open_image, Analysis, AnalysisOptions, DominantColoursError};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub name: String,
+    pub shape: Shape,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Rect { x: u32, y: u32, width: u32, height: u32 },
+    /// Vertices in order; the last joins back to the first
+    Polygon(Vec<[f64; 2]>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegionEntry {
+    name: String,
+    rect: Option<[u32; 4]>,
+    polygon: Option<Vec<[f64; 2]>>,
+}
+
+/// The analysis of one region.
+#[derive(Debug)]
+pub struct RegionPalette {
+    pub name: String,
+    pub analysis: Analysis,
+}
+
+/// Read region definitions from a JSON file.
+pub fn load(path: &str) -> Result<Vec<Region>> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read regions file {}", path))?;
+    parse(&data).with_context(|| format!("Invalid regions file {}", path))
+}
+
+fn parse(data: &str) -> Result<Vec<Region>> {
+    let entries: Vec<RegionEntry> = serde_json::from_str(data)?;
+    if entries.is_empty() {
+        bail!("no regions defined");
+    }
+    entries
+        .into_iter()
+        .map(|entry| {
+            let shape = match (entry.rect, entry.polygon) {
+                (Some([x, y, width, height]), None) if width > 0 && height > 0 => {
+                    Shape::Rect { x, y, width, height }
+                }
+                (Some(_), None) => bail!("region {:?} has an empty rect", entry.name),
+                (None, Some(points)) if points.len() >= 3 => Shape::Polygon(points),
+                (None, Some(_)) => bail!("region {:?} needs at least three polygon points", entry.name),
+                _ => bail!("region {:?} needs exactly one of \"rect\" or \"polygon\"", entry.name),
+            };
+            Ok(Region { name: entry.name, shape })
+        })
+        .collect()
+}
+
+impl Shape {
+    /// Bounding box as `(x, y, width, height)`, clipped to a `width` x `height` image.
+    fn bounds(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let (x0, y0, x1, y1) = match self {
+            Shape::Rect { x, y, width, height } => {
+                (*x as f64, *y as f64, *x as f64 + *width as f64, *y as f64 + *height as f64)
+            }
+            Shape::Polygon(points) => points.iter().fold(
+                (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+                |(x0, y0, x1, y1), &[x, y]| (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+            ),
+        };
+        let clip = |v: f64, max: u32| v.clamp(0.0, max as f64) as u32;
+        let (x0, y0) = (clip(x0.floor(), width), clip(y0.floor(), height));
+        let (x1, y1) = (clip(x1.ceil(), width), clip(y1.ceil(), height));
+        (x0, y0, x1 - x0, y1 - y0)
+    }
+
+    /// Whether the centre of pixel `(x, y)` lies inside the shape.
+    fn contains(&self, x: u32, y: u32) -> bool {
+        let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+        match self {
+            Shape::Rect { x, y, width, height } => {
+                px >= *x as f64 && px < *x as f64 + *width as f64 && py >= *y as f64 && py < *y as f64 + *height as f64
+            }
+            Shape::Polygon(points) => {
+                // Even-odd rule: count the edges a ray to the right of the point crosses
+                let mut inside = false;
+                for (i, &[xi, yi]) in points.iter().enumerate() {
+                    let [xj, yj] = points[(i + points.len() - 1) % points.len()];
+                    if (yi > py) != (yj > py) && px < (xj - xi) * (py - yi) / (yj - yi) + xi {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+        }
+    }
+}
+
+/// Analyse each region of an image file.
+pub fn analyze_file(
+    filename: &str,
+    regions: &[Region],
+    options: &AnalysisOptions,
+) -> Result<Vec<RegionPalette>, DominantColoursError> {
+    let img = open_image(filename)?;
+    analyze(&img, regions, options)
+}
+
+/// Analyse each region of a decoded image.
+pub fn analyze(
+    img: &image::DynamicImage,
+    regions: &[Region],
+    options: &AnalysisOptions,
+) -> Result<Vec<RegionPalette>, DominantColoursError> {
+    regions
+        .iter()
+        .map(|region| {
+            let (x, y, width, height) = region.shape.bounds(img.width(), img.height());
+            if width == 0 || height == 0 {
+                return Err(DominantColoursError::InvalidOptions(format!(
+                    "region {:?} lies outside the {}x{} image",
+                    region.name, img.width(), img.height()
+                )));
+            }
+            let mut pixels = image_pixels(&img.crop_imm(x, y, width, height), options);
+            // Keep the sampled pixels inside the shape, in original-image coordinates
+            if let Some(positions) = pixels.positions.take() {
+                let (values, positions): (Vec<_>, Vec<_>) = pixels
+                    .values
+                    .into_iter()
+                    .zip(positions)
+                    .map(|(value, (px, py))| (value, (px + x, py + y)))
+                    .filter(|&(_, (px, py))| region.shape.contains(px, py))
+                    .unzip();
+                pixels.values = values;
+                pixels.positions = Some(positions);
+            }
+            if !pixels.values.is_empty() {
+                apply_mask(&mut pixels, img.width(), img.height(), options)?;
+            }
+            if pixels.values.is_empty() {
+                return Err(DominantColoursError::NoPixels(format!(
+                    "region {:?} is too small to sample",
+                    region.name
+                )));
+            }
+            let analysis = cluster_pixels(pixels, options)?;
+            Ok(RegionPalette { name: region.name.clone(), analysis })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_regions() -> Result<()> {
+        let regions = parse(r#"[{"name": "sky", "rect": [0, 0, 10, 4]}, {"name": "logo", "polygon": [[0, 0], [4, 0], [0, 4]]}]"#)?;
+        assert_eq!(regions[0].shape, Shape::Rect { x: 0, y: 0, width: 10, height: 4 });
+        assert_eq!(regions[1].name, "logo");
+        assert!(matches!(&regions[1].shape, Shape::Polygon(points) if points.len() == 3));
+
+        assert!(parse("[]").is_err());
+        assert!(parse(r#"[{"name": "both", "rect": [0, 0, 1, 1], "polygon": [[0, 0], [1, 0], [0, 1]]}]"#).is_err());
+        assert!(parse(r#"[{"name": "line", "polygon": [[0, 0], [1, 1]]}]"#).is_err());
+        assert!(parse(r#"[{"name": "flat", "rect": [0, 0, 0, 5]}]"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shape_geometry() {
+        let triangle = Shape::Polygon(vec![[0.0, 0.0], [10.0, 0.0], [0.0, 10.0]]);
+        assert!(triangle.contains(1, 1));
+        assert!(!triangle.contains(8, 8));
+        assert_eq!(triangle.bounds(100, 100), (0, 0, 10, 10));
+        assert_eq!(triangle.bounds(6, 4), (0, 0, 6, 4));
+
+        let rect = Shape::Rect { x: 5, y: 5, width: 10, height: 10 };
+        assert!(rect.contains(5, 14) && !rect.contains(15, 5));
+        assert_eq!(rect.bounds(8, 100), (5, 5, 3, 10));
+        assert_eq!(rect.bounds(4, 4).2, 0);
+    }
+
+    #[test]
+    fn test_region_palettes() -> anyhow::Result<()> {
+        // Red top half, blue bottom half
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(20, 20, |_, y| {
+            if y < 10 { image::Rgb([220, 20, 20]) } else { image::Rgb([20, 20, 220]) }
+        }));
+        let regions = parse(r#"[{"name": "top", "rect": [0, 0, 20, 10]}, {"name": "corner", "polygon": [[0, 20], [6, 20], [0, 14]]}]"#)?;
+        let options = AnalysisOptions { colours: 1, ..AnalysisOptions::default() };
+        let palettes = analyze(&img, &regions, &options)?;
+        assert_eq!(palettes[0].name, "top");
+        assert_eq!(palettes[0].analysis.colours[0].rgb, [220, 20, 20]);
+        assert_eq!(palettes[1].analysis.colours[0].rgb, [20, 20, 220]);
+        let (x, y) = palettes[1].analysis.colours[0].location.unwrap();
+        assert!(x < 6 && y >= 14);
+
+        let outside = parse(r#"[{"name": "off", "rect": [50, 50, 5, 5]}]"#)?;
+        assert!(matches!(analyze(&img, &outside, &options), Err(DominantColoursError::InvalidOptions(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+//! HTTP API for palette extraction (`serve` subcommand).
+//!
+//! * `POST /analyze` with an image body, or `?url=` pointing at one, returns the JSON
+//!   palette produced by `-f json`.
+//! * `GET /swatch?url=...` (or `POST /swatch` with an image body) returns the swatch as
+//!   SVG, or as PNG with `format=png`.
+//!
+//! Other query parameters map onto the extraction flags of the same name, e.g.
+//! `/analyze?colours=8&extended-values&names=xkcd`. Only flags that affect the output
+//! are accepted; anything touching the server's filesystem is rejected.
+//!
+//! `?url=` only works with `serve --allow-fetch`, and even then only reaches public
+//! http(s) addresses, so the server can't be used to probe its own network.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use std::io::{Cursor, Read};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info, warn};
+
+use crate::{build_json, compliance_report, swatch_png, swatch_svg, with_schema_version, Args, Extras, OutputContext, OutputFormat};
+use dominant_colours::{cluster_pixels, image_pixels, MAX_IMAGE_BYTES};
+
+/// How long a fetch may take to connect, and to wait for each read.
+const FETCH_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const FETCH_READ_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Query parameters passed through to the extraction flags that take a value.
+const VALUE_FLAGS: &[&str] =
+    &["colours", "simulate", "cvd-threshold", "names", "require", "max-delta-e", "min-coverage", "metric"];
+
+/// Query parameters passed through to the extraction flags that are on or off.
+const SWITCHES: &[&str] = &[
+    "silhouette", "accent", "contrast-matrix", "wcag-pairs", "cvd-check", "temperature", "extended-values", "cmyk",
+    "linear", "stats", "locate", "timings", "vividness", "diversity",
+];
+
+/// Serve on `addr`, handling requests on one thread per worker (see `--threads`), and
+/// fetching `?url=` images only if `allow_fetch` is set.
+pub fn run(addr: &str, allow_fetch: bool) -> Result<()> {
+    let server = Server::http(addr).map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?;
+    let fetcher = allow_fetch.then(fetcher);
+    println!("Listening on http://{}", addr);
+
+    std::thread::scope(|scope| {
+        for _ in 0..rayon::current_num_threads() {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    respond(request, fetcher.as_ref());
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut request: Request, fetcher: Option<&ureq::Agent>) {
+    let response = match handle(&mut request, fetcher) {
+        Ok(response) => response,
+        Err(e) => {
+            warn!(method = %request.method(), url = request.url(), "Bad request: {:#}", e);
+            error_response(400, &format!("{:#}", e))
+        }
+    };
+    info!(method = %request.method(), url = request.url(), status = response.status_code().0, "Request");
+    if let Err(e) = request.respond(response) {
+        error!("Failed to send response: {}", e);
+    }
+}
+
+/// An HTTP client for `?url=` that times out, and only connects to public addresses.
+/// The check is made as each connection is resolved, so it covers redirects and a
+/// hostname that resolves differently the second time.
+fn fetcher() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(FETCH_CONNECT_TIMEOUT)
+        .timeout_read(FETCH_READ_TIMEOUT)
+        .resolver(|netloc: &str| -> std::io::Result<Vec<SocketAddr>> {
+            let addrs: Vec<SocketAddr> = netloc.to_socket_addrs()?.filter(|addr| is_public(addr.ip())).collect();
+            if addrs.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!("{} is not a public address", netloc),
+                ));
+            }
+            Ok(addrs)
+        })
+        .build()
+}
+
+/// Whether `ip` is on the public internet, rather than loopback, private, link-local,
+/// shared (carrier-grade NAT), multicast or otherwise reserved.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80
+                    || first == 0x2001 && ip.segments()[1] == 0x0db8)
+            }
+        },
+    }
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+fn handle(request: &mut Request, fetcher: Option<&ureq::Agent>) -> Result<HttpResponse> {
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
+    let params: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+
+    match (request.method(), path.as_str()) {
+        (Method::Post, "/analyze") => {
+            let args = request_args(&params, &["url"])?;
+            let ctx = OutputContext::new(&args.extract, &args.analysis, OutputFormat::Json)?;
+            let pixels = image_pixels(&read_image(request, &params, fetcher)?, &args.analysis);
+            let analysis = cluster_pixels(pixels, &args.analysis)?;
+            let extras = Extras { compliance: compliance_report(&analysis.colours, &ctx), ..Extras::default() };
+            let body = serde_json::to_vec_pretty(&with_schema_version(build_json(&analysis, &extras, &ctx)))?;
+            Ok(with_content_type(Response::from_data(body), "application/json"))
+        }
+        (Method::Get | Method::Post, "/swatch") => {
+            let args = request_args(&params, &["url", "format"])?;
+            let pixels = image_pixels(&read_image(request, &params, fetcher)?, &args.analysis);
+            let colours = cluster_pixels(pixels, &args.analysis)?.colours;
+            match param(&params, "format").unwrap_or("svg") {
+                "svg" => Ok(with_content_type(
+                    Response::from_data(swatch_svg(&colours, &args.extract.simulate, &args.extract.swatch_style).into_bytes()),
+                    "image/svg+xml",
+                )),
+                "png" => Ok(with_content_type(
+                    Response::from_data(swatch_png(&colours, &args.extract.simulate, &args.extract.swatch_style)?),
+                    "image/png",
+                )),
+                other => anyhow::bail!("Unknown swatch format `{}` (expected svg or png)", other),
+            }
+        }
+        (_, "/analyze" | "/swatch") => Ok(error_response(405, "Method not allowed")),
+        _ => Ok(error_response(404, "Not found")),
+    }
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// Parse the query parameters as extraction flags, so requests get the same defaults and
+/// validation as the command line. `reserved` names are handled by the endpoint itself.
+///
+/// Each parameter becomes a single `--key=value` token, and no value may start with `-`,
+/// so a value can never be read as a flag of its own (e.g. `?stats=--names-file=...`).
+fn request_args(params: &[(String, String)], reserved: &[&str]) -> Result<Args> {
+    let mut argv = vec!["dominant-colours".to_string()];
+    for (key, value) in params {
+        if reserved.contains(&key.as_str()) {
+            continue;
+        }
+        if SWITCHES.contains(&key.as_str()) {
+            if !value.is_empty() {
+                anyhow::bail!("Parameter `{}` takes no value", key);
+            }
+            argv.push(format!("--{}", key));
+        } else if VALUE_FLAGS.contains(&key.as_str()) {
+            if value.starts_with('-') {
+                anyhow::bail!("Invalid value `{}` for parameter `{}`", value, key);
+            }
+            argv.push(format!("--{}={}", key, value));
+        } else {
+            anyhow::bail!("Unsupported parameter `{}`", key);
+        }
+    }
+    // Placeholder for the required image argument; the image comes from the request
+    argv.push("request".to_string());
+    Args::try_parse_from(argv).map_err(|e| anyhow::anyhow!("{}", e.render().to_string().trim()))
+}
+
+/// Decode the image given by `?url=`, or else the request body.
+fn read_image(
+    request: &mut Request,
+    params: &[(String, String)],
+    fetcher: Option<&ureq::Agent>,
+) -> Result<image::DynamicImage> {
+    let mut bytes = Vec::new();
+    match param(params, "url") {
+        Some(url) => {
+            let fetcher = fetcher.context("Fetching images is disabled; start the server with --allow-fetch")?;
+            let parsed = url::Url::parse(url).with_context(|| format!("Invalid URL {}", url))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                anyhow::bail!("Only http and https URLs can be fetched, not {}", url);
+            }
+            let response = fetcher.get(url).call().with_context(|| format!("Failed to fetch {}", url))?;
+            response.into_reader().take(MAX_IMAGE_BYTES + 1).read_to_end(&mut bytes)?;
+        }
+        None => {
+            request.as_reader().take(MAX_IMAGE_BYTES + 1).read_to_end(&mut bytes)?;
+        }
+    }
+    if bytes.is_empty() {
+        anyhow::bail!("Send an image as the request body or pass ?url=");
+    }
+    if bytes.len() as u64 > MAX_IMAGE_BYTES {
+        anyhow::bail!("Image larger than {} bytes", MAX_IMAGE_BYTES);
+    }
+    image::load_from_memory(&bytes).context("Failed to decode image")
+}
+
+fn with_content_type(response: HttpResponse, content_type: &str) -> HttpResponse {
+    let header = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes())
+        .expect("content type is a valid header value");
+    response.with_header(header)
+}
+
+fn error_response(status: u16, message: &str) -> HttpResponse {
+    let body = serde_json::to_vec(&serde_json::json!({ "error": message })).unwrap_or_default();
+    with_content_type(Response::from_data(body).with_status_code(status), "application/json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dominant_colours::names::NameSet;
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_request_args() {
+        let args = request_args(&params(&[("colours", "8"), ("stats", ""), ("url", "http://x")]), &["url"]).unwrap();
+        assert_eq!(args.analysis.colours, 8);
+        assert!(args.extract.stats);
+
+        assert!(request_args(&params(&[("output", "/etc/passwd")]), &[]).is_err());
+        assert!(request_args(&params(&[("colours", "many")]), &[]).is_err());
+
+        // Values can't smuggle in flags of their own
+        assert!(request_args(&params(&[("stats", "--names-file=/etc/passwd")]), &[]).is_err());
+        assert!(request_args(&params(&[("names", "--names-file=/etc/passwd")]), &[]).is_err());
+        assert!(request_args(&params(&[("stats", "yes")]), &[]).is_err());
+        assert_eq!(request_args(&params(&[("names", "xkcd")]), &[]).unwrap().extract.names, NameSet::Xkcd);
+    }
+
+    #[test]
+    fn test_fetch_restrictions() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0"] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["::1", "fe80::1", "fd00::1", "::ffff:127.0.0.1", "::ffff:169.254.169.254"] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        assert!(is_public("93.184.216.34".parse().unwrap()));
+        assert!(is_public("2606:2800:220:1:248:1893:25c8:1946".parse().unwrap()));
+
+        // Refused at connection time however the address is written
+        let error = fetcher().get("http://127.0.0.1:1/image.png").call().unwrap_err();
+        assert!(format!("{:#}", anyhow::Error::from(error)).contains("not a public address"));
+        let error = fetcher().get("http://localhost:1/image.png").call().unwrap_err();
+        assert!(format!("{:#}", anyhow::Error::from(error)).contains("not a public address"));
+    }
+}
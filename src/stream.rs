@@ -0,0 +1,295 @@
+//! Downsampling images while they decode.
+//!
+//! A full decode of a 2 GB scan needs all of it in memory at once. PNG rows and TIFF
+//! strips or tiles can instead be read one at a time and averaged straight into the
+//! small grid that gets clustered, so memory stays at one chunk plus the grid. JPEGs
+//! are decoded at 1/2, 1/4 or 1/8 scale by the decoder's scaled IDCT, which is several
+//! times faster than a full decode followed by a resize. Other formats, and layouts
+//! this module does not handle (interlaced PNG, planar or exotic TIFF, CMYK JPEG),
+//! fall back to the normal decode.
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat, RgbImage};
+use std::fs::File;
+use std::io::BufReader;
+use tracing::debug;
+
+use crate::{colour, DominantColoursError};
+
+/// Decoded size above which images are sampled while decoding.
+pub const STREAM_ABOVE_BYTES: u64 = 256 << 20;
+
+/// A downsampled image and the size of the original.
+pub struct Sampled {
+    pub image: RgbImage,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Sample `path` into a grid fitting a `side` square while decoding, if it is a JPEG
+/// larger than needed or a PNG or TIFF big enough to be worth streaming.
+pub fn sample_file(path: &str, side: u32, linear: bool) -> Result<Option<Sampled>, DominantColoursError> {
+    let reader = image::io::Reader::open(path)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| DominantColoursError::image(Some(path), e.into()))?;
+    let format = reader.format();
+    if format == Some(ImageFormat::Jpeg) {
+        return sample_jpeg(path, side, linear);
+    }
+    if !matches!(format, Some(ImageFormat::Png | ImageFormat::Tiff)) {
+        return Ok(None);
+    }
+    let (width, height) = reader.into_dimensions().map_err(|e| DominantColoursError::image(Some(path), e))?;
+    if width as u64 * height as u64 * 4 <= STREAM_ABOVE_BYTES {
+        return Ok(None);
+    }
+
+    let file = File::open(path).map_err(|e| DominantColoursError::image(Some(path), e.into()))?;
+    let mut sampler = BoxSampler::new(width, height, side, linear);
+    let streamed = match format {
+        Some(ImageFormat::Png) => sample_png(BufReader::new(file), &mut sampler),
+        _ => sample_tiff(BufReader::new(file), &mut sampler),
+    };
+    if !streamed.map_err(|e| e.into_error(path))? {
+        debug!(file = path, "Image layout cannot be streamed; decoding it whole");
+        return Ok(None);
+    }
+    debug!(file = path, width, height, "Sampled image while decoding");
+    Ok(Some(Sampled { image: sampler.finish(), width, height }))
+}
+
+/// Decode the JPEG at the smallest IDCT scale still covering the `side` square.
+fn sample_jpeg(path: &str, side: u32, linear: bool) -> Result<Option<Sampled>, DominantColoursError> {
+    let file = File::open(path).map_err(|e| DominantColoursError::image(Some(path), e.into()))?;
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(file));
+    decoder.read_info().map_err(|e| Failure::Jpeg(e).into_error(path))?;
+    // Taken before scaling, which changes the reported size to the scaled one
+    let info = decoder.info().expect("read_info succeeded");
+    let request = side.min(u16::MAX as u32) as u16;
+    let scaled = decoder.scale(request, request).map_err(|e| Failure::Jpeg(e).into_error(path))?;
+    if scaled == (info.width, info.height) {
+        return Ok(None);
+    }
+    let channels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => 1,
+        jpeg_decoder::PixelFormat::RGB24 => 3,
+        _ => return Ok(None),
+    };
+    let data = decoder.decode().map_err(|e| Failure::Jpeg(e).into_error(path))?;
+    let (width, height) = (scaled.0 as u32, scaled.1 as u32);
+    let pixels: Vec<u8> = data.chunks_exact(channels).flat_map(rgb).collect();
+    let decoded = RgbImage::from_raw(width, height, pixels).expect("decoder output matches its size");
+    debug!(file = path, width, height, "Decoded JPEG at reduced scale");
+    Ok(Some(Sampled {
+        image: crate::downsample(&image::DynamicImage::ImageRgb8(decoded), side, linear),
+        width: info.width as u32,
+        height: info.height as u32,
+    }))
+}
+
+enum Failure {
+    Png(png::DecodingError),
+    Tiff(tiff::TiffError),
+    Jpeg(jpeg_decoder::Error),
+}
+
+impl Failure {
+    fn into_error(self, path: &str) -> DominantColoursError {
+        let err = match self {
+            Failure::Png(e) => DecodingError::new(ImageFormatHint::Exact(ImageFormat::Png), e),
+            Failure::Tiff(e) => DecodingError::new(ImageFormatHint::Exact(ImageFormat::Tiff), e),
+            Failure::Jpeg(e) => DecodingError::new(ImageFormatHint::Exact(ImageFormat::Jpeg), e),
+        };
+        DominantColoursError::image(Some(path), ImageError::Decoding(err))
+    }
+}
+
+/// Feed every row to the sampler; false if the image is interlaced.
+fn sample_png(file: BufReader<File>, sampler: &mut BoxSampler) -> Result<bool, Failure> {
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(Failure::Png)?;
+    if reader.info().interlaced {
+        return Ok(false);
+    }
+    let channels = match reader.output_color_type().0 {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => return Ok(false),
+    };
+    let mut y = 0;
+    while let Some(row) = reader.next_row().map_err(Failure::Png)? {
+        for (x, pixel) in row.data().chunks_exact(channels).enumerate() {
+            sampler.add(x as u32, y, rgb(pixel));
+        }
+        y += 1;
+    }
+    Ok(true)
+}
+
+/// Feed every strip or tile to the sampler; false for layouts this does not handle.
+fn sample_tiff(file: BufReader<File>, sampler: &mut BoxSampler) -> Result<bool, Failure> {
+    use tiff::decoder::{ChunkType, Decoder, DecodingResult};
+    use tiff::tags::Tag;
+    use tiff::ColorType;
+
+    let mut decoder = Decoder::new(file).map_err(Failure::Tiff)?;
+    let planar = decoder.find_tag_unsigned::<u16>(Tag::PlanarConfiguration).map_err(Failure::Tiff)?;
+    let channels = match decoder.colortype().map_err(Failure::Tiff)? {
+        ColorType::Gray(8 | 16) => 1,
+        ColorType::GrayA(8 | 16) => 2,
+        ColorType::RGB(8 | 16) => 3,
+        ColorType::RGBA(8 | 16) => 4,
+        _ => return Ok(false),
+    };
+    if planar.is_some_and(|p| p != 1) {
+        return Ok(false);
+    }
+
+    let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+    let (count, across) = match decoder.get_chunk_type() {
+        ChunkType::Strip => (decoder.strip_count().map_err(Failure::Tiff)?, 1),
+        ChunkType::Tile => (decoder.tile_count().map_err(Failure::Tiff)?, sampler.width.div_ceil(chunk_width)),
+    };
+    for index in 0..count {
+        let (data_width, _) = decoder.chunk_data_dimensions(index);
+        let (x0, y0) = ((index % across) * chunk_width, (index / across) * chunk_height);
+        let samples: Vec<u8> = match decoder.read_chunk(index).map_err(Failure::Tiff)? {
+            DecodingResult::U8(data) => data,
+            DecodingResult::U16(data) => data.into_iter().map(|v| (v >> 8) as u8).collect(),
+            _ => return Ok(false),
+        };
+        for (i, pixel) in samples.chunks_exact(channels).enumerate() {
+            let i = i as u32;
+            sampler.add(x0 + i % data_width, y0 + i / data_width, rgb(pixel));
+        }
+    }
+    Ok(true)
+}
+
+/// RGB of a grey, grey and alpha, RGB or RGBA pixel; alpha is ignored as in a full decode.
+fn rgb(pixel: &[u8]) -> [u8; 3] {
+    match pixel.len() {
+        1 | 2 => [pixel[0]; 3],
+        _ => [pixel[0], pixel[1], pixel[2]],
+    }
+}
+
+/// Averages pixels into the cells of a grid the shape of the image, fitting a square.
+struct BoxSampler {
+    width: u32,
+    height: u32,
+    grid_width: u32,
+    grid_height: u32,
+    linear: bool,
+    sums: Vec<[f64; 3]>,
+    counts: Vec<u32>,
+}
+
+impl BoxSampler {
+    fn new(width: u32, height: u32, side: u32, linear: bool) -> BoxSampler {
+        let ratio = (side as f64 / width as f64).min(side as f64 / height as f64).min(1.0);
+        let grid_width = ((width as f64 * ratio).round() as u32).max(1);
+        let grid_height = ((height as f64 * ratio).round() as u32).max(1);
+        let cells = grid_width as usize * grid_height as usize;
+        BoxSampler { width, height, grid_width, grid_height, linear, sums: vec![[0.0; 3]; cells], counts: vec![0; cells] }
+    }
+
+    fn add(&mut self, x: u32, y: u32, rgb: [u8; 3]) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let cx = (x as u64 * self.grid_width as u64 / self.width as u64) as usize;
+        let cy = (y as u64 * self.grid_height as u64 / self.height as u64) as usize;
+        let cell = cy * self.grid_width as usize + cx;
+        for (sum, c) in self.sums[cell].iter_mut().zip(rgb) {
+            *sum += if self.linear { colour::srgb_to_linear(c) } else { c as f64 };
+        }
+        self.counts[cell] += 1;
+    }
+
+    fn finish(self) -> RgbImage {
+        RgbImage::from_fn(self.grid_width, self.grid_height, |x, y| {
+            let cell = (y * self.grid_width + x) as usize;
+            let count = self.counts[cell].max(1) as f64;
+            image::Rgb(self.sums[cell].map(|sum| {
+                let mean = sum / count;
+                if self.linear { colour::linear_to_srgb(mean) } else { mean.round() as u8 }
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_box_sampler() {
+        let mut sampler = BoxSampler::new(400, 200, 100, false);
+        for y in 0..200 {
+            for x in 0..400 {
+                sampler.add(x, y, if x % 2 == 0 { [0, 0, 0] } else { [255, 255, 255] });
+            }
+        }
+        let image = sampler.finish();
+        assert_eq!(image.dimensions(), (100, 50));
+        assert!(image.pixels().all(|p| p.0 == [128, 128, 128]));
+    }
+
+    #[test]
+    fn test_sample_png() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("wide.png");
+        image::RgbImage::from_fn(600, 300, |x, _| if x < 300 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) })
+            .save(&path)?;
+        let file = File::open(&path)?;
+        let mut sampler = BoxSampler::new(600, 300, 150, false);
+        assert!(sample_png(BufReader::new(file), &mut sampler).unwrap_or(false));
+        let image = sampler.finish();
+        assert_eq!(image.dimensions(), (150, 75));
+        assert_eq!(image.get_pixel(10, 10).0, [255, 0, 0]);
+        assert_eq!(image.get_pixel(140, 70).0, [0, 0, 255]);
+
+        // Small images are left to the normal decode
+        assert!(sample_file(path.to_str().unwrap(), 150, false)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_jpeg() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("photo.jpg");
+        image::RgbImage::from_fn(1600, 800, |x, _| if x < 400 { image::Rgb([250, 250, 250]) } else { image::Rgb([200, 40, 40]) })
+            .save(&path)?;
+        let sampled = sample_file(path.to_str().unwrap(), 150, false)?.expect("a large JPEG is scaled");
+        assert_eq!((sampled.width, sampled.height), (1600, 800));
+        assert_eq!(sampled.image.dimensions(), (150, 75));
+        let [r, g, b] = sampled.image.get_pixel(120, 40).0;
+        assert!(r > 180 && g < 70 && b < 70);
+
+        // Already small enough: decode normally
+        let small = dir.path().join("small.jpg");
+        image::RgbImage::new(100, 100).save(&small)?;
+        assert!(sample_file(small.to_str().unwrap(), 150, false)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_tiff() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("tall.tiff");
+        image::RgbImage::from_fn(100, 400, |_, y| if y < 100 { image::Rgb([0, 255, 0]) } else { image::Rgb([20, 20, 20]) })
+            .save(&path)?;
+        let file = File::open(&path)?;
+        let mut sampler = BoxSampler::new(100, 400, 40, true);
+        assert!(sample_tiff(BufReader::new(file), &mut sampler).unwrap_or(false));
+        let image = sampler.finish();
+        assert_eq!(image.dimensions(), (10, 40));
+        assert_eq!(image.get_pixel(5, 5).0, [0, 255, 0]);
+        assert_eq!(image.get_pixel(5, 35).0, [20, 20, 20]);
+        Ok(())
+    }
+}
@@ -0,0 +1,689 @@
+//! Exact palettes of SVG vector art, read from its shapes instead of a rendering.
+//!
+//! Each visible shape counts its fill colour by its area and its stroke colour by the
+//! stroke's length times its width, scaled by any transforms and weighted by opacity.
+//! Rasterising and clustering would blend colours along every antialiased edge; this
+//! gives the colours the artist picked, with their shares of the drawing. Overlapping
+//! shapes count in full, as if nothing were hidden, and gradients count as their stop
+//! colours in equal shares. Text, embedded images, `<use>` references and `<style>`
+//! sheets are not read.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::time::Instant;
+
+use image::error::{DecodingError, ImageError, ImageFormatHint};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::{colour, names, DominantColoursError, Pixels, Timings, AGGREGATE_SAMPLES};
+
+/// Elements whose contents are only drawn when referenced, or never.
+const NOT_RENDERED: [&str; 12] = [
+    "defs", "clipPath", "mask", "symbol", "pattern", "marker", "filter",
+    "linearGradient", "radialGradient", "title", "desc", "metadata",
+];
+
+/// Line segments each curve is flattened into.
+const CURVE_SEGMENTS: usize = 16;
+
+/// Whether `filename` names an SVG document, going by its extension.
+pub fn is_svg(filename: &str) -> bool {
+    filename.to_ascii_lowercase().ends_with(".svg")
+}
+
+/// Clustering input for an SVG document: its colours as pseudo-pixels in proportion to
+/// the area they cover, without positions. `source_pixels` is the document's size.
+pub fn pixels(filename: &str, data: &[u8]) -> Result<Pixels, DominantColoursError> {
+    let start = Instant::now();
+    let document = parse(data).map_err(|e| decode_error(filename, e))?;
+    let total: f64 = document.colours.iter().map(|(_, weight)| weight).sum();
+    if total <= 0.0 {
+        return Err(decode_error(filename, "no filled or stroked shapes to take colours from".into()));
+    }
+    let mut values = Vec::new();
+    for &(rgb, weight) in &document.colours {
+        let count = ((AGGREGATE_SAMPLES as f64 * weight / total).round() as usize).max(1);
+        values.extend(std::iter::repeat_n(rgb.map(|c| c as f64), count));
+    }
+    Ok(Pixels {
+        values,
+        positions: None,
+        source_pixels: document.size.unwrap_or(total).round() as u64,
+        timings: Timings { decode: start.elapsed(), ..Timings::default() },
+        warnings: Vec::new(),
+    })
+}
+
+fn decode_error(filename: &str, err: Box<dyn std::error::Error + Send + Sync>) -> DominantColoursError {
+    let err = ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("SVG".to_string()), err));
+    DominantColoursError::image(Some(filename), err)
+}
+
+/// What a document draws.
+#[derive(Debug)]
+pub struct Document {
+    /// Each colour with the area it covers, in user units, most first
+    pub colours: Vec<([u8; 3], f64)>,
+    /// Width times height of the root `<svg>`, from its attributes or `viewBox`
+    pub size: Option<f64>,
+}
+
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+}
+
+enum Node {
+    Start(Element),
+    End,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Paint {
+    None,
+    Colour([u8; 3]),
+    CurrentColour,
+    /// A gradient (or pattern) by ID
+    Url(String),
+}
+
+/// Properties inherited down the document, with those of the ancestors applied.
+#[derive(Clone)]
+struct Style {
+    fill: Paint,
+    stroke: Paint,
+    stroke_width: f64,
+    fill_opacity: f64,
+    stroke_opacity: f64,
+    /// Product of the element's and its ancestors' opacity
+    opacity: f64,
+    colour: [u8; 3],
+    hidden: bool,
+    /// Inside an element that isn't rendered in place
+    unrendered: bool,
+    /// Determinant of the combined transform, which scales areas
+    scale: f64,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            fill: Paint::Colour([0, 0, 0]),
+            stroke: Paint::None,
+            stroke_width: 1.0,
+            fill_opacity: 1.0,
+            stroke_opacity: 1.0,
+            opacity: 1.0,
+            colour: [0, 0, 0],
+            hidden: false,
+            unrendered: false,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Style {
+    /// Apply a presentation attribute or `style` declaration, ignoring invalid values.
+    fn set(&mut self, property: &str, value: &str) {
+        let value = value.trim();
+        match property {
+            "fill" => self.fill = paint(value).unwrap_or(self.fill.clone()),
+            "stroke" => self.stroke = paint(value).unwrap_or(self.stroke.clone()),
+            "stroke-width" => self.stroke_width = length(value).unwrap_or(self.stroke_width),
+            "fill-opacity" => self.fill_opacity = opacity(value).unwrap_or(self.fill_opacity),
+            "stroke-opacity" => self.stroke_opacity = opacity(value).unwrap_or(self.stroke_opacity),
+            "opacity" => self.opacity *= opacity(value).unwrap_or(1.0),
+            "color" => self.colour = parse_colour(value).unwrap_or(self.colour),
+            "display" if value == "none" => self.hidden = true,
+            "visibility" if value == "hidden" || value == "collapse" => self.hidden = true,
+            _ => {}
+        }
+    }
+}
+
+/// Read the colours an SVG document draws.
+pub fn parse(data: &[u8]) -> Result<Document, Box<dyn std::error::Error + Send + Sync>> {
+    let mut nodes = Vec::new();
+    for event in EventReader::new(data) {
+        match event? {
+            XmlEvent::StartElement { name, attributes, .. } => nodes.push(Node::Start(Element {
+                name: name.local_name,
+                // Local names, so `xlink:href` is `href` either way
+                attributes: attributes.into_iter().map(|a| (a.name.local_name, a.value)).collect(),
+            })),
+            XmlEvent::EndElement { .. } => nodes.push(Node::End),
+            _ => {}
+        }
+    }
+    let gradients = gradients(&nodes);
+
+    let mut size = None;
+    let mut colours: HashMap<[u8; 3], f64> = HashMap::new();
+    let mut styles = vec![Style::default()];
+    for node in &nodes {
+        let element = match node {
+            Node::Start(element) => element,
+            Node::End => {
+                styles.pop();
+                continue;
+            }
+        };
+        let mut style = styles.last().unwrap().clone();
+        for (property, value) in &element.attributes {
+            if property == "transform" {
+                style.scale *= transform_scale(value);
+            } else {
+                style.set(property, value);
+            }
+        }
+        if let Some(declarations) = element.attributes.get("style") {
+            for (property, value) in declarations.split(';').filter_map(|d| d.split_once(':')) {
+                style.set(property.trim(), value);
+            }
+        }
+        if element.name == "svg" && styles.len() == 1 {
+            size = document_size(&element.attributes);
+        }
+        if NOT_RENDERED.contains(&element.name.as_str()) {
+            style.unrendered = true;
+        }
+        if !style.hidden && !style.unrendered {
+            if let Some((area, length)) = geometry(element) {
+                let mut add = |paint: &Paint, weight: f64| {
+                    let rgb = match paint {
+                        Paint::None => return,
+                        Paint::Colour(rgb) => *rgb,
+                        Paint::CurrentColour => style.colour,
+                        Paint::Url(id) => {
+                            let stops = gradients.get(id.as_str()).map_or(&[][..], |stops| stops.as_slice());
+                            for &(rgb, stop_opacity) in stops {
+                                *colours.entry(rgb).or_default() += weight * stop_opacity / stops.len() as f64;
+                            }
+                            return;
+                        }
+                    };
+                    *colours.entry(rgb).or_default() += weight;
+                };
+                let scale = style.scale.abs();
+                add(&style.fill, area * scale * style.fill_opacity * style.opacity);
+                add(&style.stroke, length * style.stroke_width * scale * style.stroke_opacity * style.opacity);
+            }
+        }
+        styles.push(style);
+    }
+
+    let mut colours: Vec<([u8; 3], f64)> = colours.into_iter().filter(|&(_, weight)| weight > 0.0).collect();
+    colours.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    Ok(Document { colours, size })
+}
+
+/// Stop colours and opacities of each gradient by ID, following `href` to a gradient
+/// whose stops it reuses.
+fn gradients(nodes: &[Node]) -> HashMap<&str, Vec<([u8; 3], f64)>> {
+    let mut stops: HashMap<&str, Vec<([u8; 3], f64)>> = HashMap::new();
+    let mut links = HashMap::new();
+    let mut stack: Vec<Option<&str>> = Vec::new();
+    for node in nodes {
+        match node {
+            Node::Start(element) => {
+                let gradient = element.name.ends_with("Gradient").then(|| element.attributes.get("id")).flatten();
+                if let Some(id) = gradient {
+                    stops.entry(id).or_default();
+                    if let Some(href) = element.attributes.get("href").and_then(|h| h.strip_prefix('#')) {
+                        links.insert(id.as_str(), href);
+                    }
+                }
+                if let (Some(Some(id)), "stop") = (stack.last(), element.name.as_str()) {
+                    let declarations = element.attributes.get("style").map_or("", String::as_str);
+                    let mut stop = (element.attributes.get("stop-color").and_then(|c| parse_colour(c)), 1.0);
+                    for (property, value) in declarations.split(';').filter_map(|d| d.split_once(':')) {
+                        match property.trim() {
+                            "stop-color" => stop.0 = parse_colour(value.trim()).or(stop.0),
+                            "stop-opacity" => stop.1 = opacity(value.trim()).unwrap_or(stop.1),
+                            _ => {}
+                        }
+                    }
+                    if let Some(value) = element.attributes.get("stop-opacity") {
+                        stop.1 = opacity(value).unwrap_or(stop.1);
+                    }
+                    stops.get_mut(id).unwrap().push((stop.0.unwrap_or([0, 0, 0]), stop.1));
+                }
+                stack.push(gradient.map(String::as_str));
+            }
+            Node::End => {
+                stack.pop();
+            }
+        }
+    }
+    // A gradient without stops of its own takes those of the one it links to
+    for (id, mut target) in links.clone() {
+        for _ in 0..links.len() {
+            if !stops[id].is_empty() {
+                break;
+            }
+            if let Some(linked) = stops.get(target).filter(|s| !s.is_empty()).cloned() {
+                stops.insert(id, linked);
+                break;
+            }
+            match links.get(target) {
+                Some(next) => target = next,
+                None => break,
+            }
+        }
+    }
+    stops
+}
+
+/// Area and outline length of a shape element, in its own user units.
+fn geometry(element: &Element) -> Option<(f64, f64)> {
+    let number = |name: &str| element.attributes.get(name).and_then(|v| length(v)).unwrap_or(0.0);
+    match element.name.as_str() {
+        "rect" => {
+            let (width, height) = (number("width"), number("height"));
+            Some((width * height, 2.0 * (width + height)))
+        }
+        "circle" => {
+            let r = number("r");
+            Some((PI * r * r, 2.0 * PI * r))
+        }
+        "ellipse" => {
+            let (rx, ry) = (number("rx"), number("ry"));
+            // Ramanujan's approximation of the perimeter
+            Some((PI * rx * ry, PI * (3.0 * (rx + ry) - ((3.0 * rx + ry) * (rx + 3.0 * ry)).sqrt())))
+        }
+        "line" => Some((0.0, (number("x2") - number("x1")).hypot(number("y2") - number("y1")))),
+        "polygon" | "polyline" => {
+            let mut numbers = Numbers::new(element.attributes.get("points")?);
+            let mut points = Vec::new();
+            while let (Some(x), Some(y)) = (numbers.number(), numbers.number()) {
+                points.push([x, y]);
+            }
+            let closed = element.name == "polygon";
+            Some((shoelace(&points).abs(), outline(&points, closed)))
+        }
+        "path" => {
+            let subpaths = path(element.attributes.get("d")?);
+            let area: f64 = subpaths.iter().map(|(points, _)| shoelace(points)).sum();
+            let length = subpaths.iter().map(|(points, closed)| outline(points, *closed)).sum();
+            // Subpaths wound the other way, as holes usually are, cut out of the area
+            Some((area.abs(), length))
+        }
+        _ => None,
+    }
+}
+
+/// Signed area of the polygon through `points`.
+fn shoelace(points: &[[f64; 2]]) -> f64 {
+    let mut area = 0.0;
+    for (i, &[x0, y0]) in points.iter().enumerate() {
+        let [x1, y1] = points[(i + 1) % points.len()];
+        area += x0 * y1 - x1 * y0;
+    }
+    area / 2.0
+}
+
+fn outline(points: &[[f64; 2]], closed: bool) -> f64 {
+    let mut length: f64 = points.windows(2).map(|w| (w[1][0] - w[0][0]).hypot(w[1][1] - w[0][1])).sum();
+    if let (true, Some(first), Some(last)) = (closed, points.first(), points.last()) {
+        length += (first[0] - last[0]).hypot(first[1] - last[1]);
+    }
+    length
+}
+
+/// Numbers of an attribute such as `points` or `d`, however they are separated.
+struct Numbers<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Numbers<'a> {
+    fn new(text: &'a str) -> Self {
+        Numbers { text: text.as_bytes(), position: 0 }
+    }
+
+    fn skip_separators(&mut self) {
+        while self.position < self.text.len() && (self.text[self.position].is_ascii_whitespace() || self.text[self.position] == b',') {
+            self.position += 1;
+        }
+    }
+
+    /// The next command letter of path data, if that comes next.
+    fn command(&mut self) -> Option<u8> {
+        self.skip_separators();
+        let &c = self.text.get(self.position)?;
+        (c.is_ascii_alphabetic() && c != b'e' && c != b'E').then(|| {
+            self.position += 1;
+            c
+        })
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        self.skip_separators();
+        let start = self.position;
+        let mut end = start;
+        let (mut dot, mut exponent) = (false, false);
+        while let Some(&c) = self.text.get(end) {
+            let sign_allowed = end == start || matches!(self.text[end - 1], b'e' | b'E');
+            match c {
+                b'0'..=b'9' => {}
+                b'+' | b'-' if sign_allowed => {}
+                b'.' if !dot && !exponent => dot = true,
+                b'e' | b'E' if !exponent && end > start => exponent = true,
+                _ => break,
+            }
+            end += 1;
+        }
+        let value = std::str::from_utf8(&self.text[start..end]).ok()?.parse().ok()?;
+        self.position = end;
+        Some(value)
+    }
+
+    fn point(&mut self) -> Option<[f64; 2]> {
+        Some([self.number()?, self.number()?])
+    }
+
+    /// An arc flag, which may be written without a separator before the next number.
+    fn flag(&mut self) -> Option<bool> {
+        self.skip_separators();
+        let flag = match self.text.get(self.position)? {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        self.position += 1;
+        Some(flag)
+    }
+}
+
+/// Subpaths of path data, as points along them with curves flattened, and whether each
+/// is closed. Malformed data ends the path where it goes wrong, as renderers do.
+fn path(d: &str) -> Vec<(Vec<[f64; 2]>, bool)> {
+    let mut numbers = Numbers::new(d);
+    let mut subpaths: Vec<(Vec<[f64; 2]>, bool)> = Vec::new();
+    let mut current = [0.0, 0.0];
+    let mut start = current;
+    // Second control point of the last curve, reflected by S and T
+    let mut control: Option<(u8, [f64; 2])> = None;
+    let mut command = None;
+    loop {
+        command = numbers.command().or(match command {
+            // Coordinates after a move are lines
+            Some(b'M') => Some(b'L'),
+            Some(b'm') => Some(b'l'),
+            other => other,
+        });
+        let Some(c) = command else { break };
+        let relative = c.is_ascii_lowercase();
+        let offset = |p: [f64; 2], current: [f64; 2]| if relative { [p[0] + current[0], p[1] + current[1]] } else { p };
+        let previous = control.take();
+        let reflected = |kind: u8| match previous {
+            Some((k, [x, y])) if k == kind => [2.0 * current[0] - x, 2.0 * current[1] - y],
+            _ => current,
+        };
+        let mut end = match c.to_ascii_uppercase() {
+            b'M' => {
+                let Some(p) = numbers.point() else { break };
+                let p = offset(p, current);
+                subpaths.push((vec![p], false));
+                start = p;
+                current = p;
+                continue;
+            }
+            b'Z' => {
+                if let Some(subpath) = subpaths.last_mut() {
+                    subpath.1 = true;
+                }
+                current = start;
+                command = None;
+                // A drawing command straight after Z starts a new subpath there
+                subpaths.push((vec![current], false));
+                continue;
+            }
+            b'L' => {
+                let Some(p) = numbers.point() else { break };
+                vec![offset(p, current)]
+            }
+            b'H' => {
+                let Some(x) = numbers.number() else { break };
+                vec![[if relative { current[0] + x } else { x }, current[1]]]
+            }
+            b'V' => {
+                let Some(y) = numbers.number() else { break };
+                vec![[current[0], if relative { current[1] + y } else { y }]]
+            }
+            b'C' | b'S' => {
+                let first = if c.eq_ignore_ascii_case(&b'C') {
+                    let Some(p) = numbers.point() else { break };
+                    offset(p, current)
+                } else {
+                    reflected(b'C')
+                };
+                let (Some(second), Some(p)) = (numbers.point(), numbers.point()) else { break };
+                let (second, p) = (offset(second, current), offset(p, current));
+                control = Some((b'C', second));
+                flatten(|t| cubic(current, first, second, p, t))
+            }
+            b'Q' | b'T' => {
+                let middle = if c.eq_ignore_ascii_case(&b'Q') {
+                    let Some(p) = numbers.point() else { break };
+                    offset(p, current)
+                } else {
+                    reflected(b'Q')
+                };
+                let Some(p) = numbers.point() else { break };
+                let p = offset(p, current);
+                control = Some((b'Q', middle));
+                flatten(|t| quadratic(current, middle, p, t))
+            }
+            b'A' => {
+                let (Some(rx), Some(ry), Some(angle)) = (numbers.number(), numbers.number(), numbers.number()) else { break };
+                let (Some(large), Some(sweep), Some(p)) = (numbers.flag(), numbers.flag(), numbers.point()) else { break };
+                arc(current, [rx, ry], angle, large, sweep, offset(p, current))
+            }
+            _ => break,
+        };
+        if subpaths.is_empty() {
+            subpaths.push((vec![current], false));
+        }
+        current = *end.last().unwrap();
+        subpaths.last_mut().unwrap().0.append(&mut end);
+    }
+    subpaths.retain(|(points, _)| points.len() > 1);
+    subpaths
+}
+
+fn flatten(curve: impl Fn(f64) -> [f64; 2]) -> Vec<[f64; 2]> {
+    (1..=CURVE_SEGMENTS).map(|i| curve(i as f64 / CURVE_SEGMENTS as f64)).collect()
+}
+
+fn cubic(p0: [f64; 2], p1: [f64; 2], p2: [f64; 2], p3: [f64; 2], t: f64) -> [f64; 2] {
+    let u = 1.0 - t;
+    [0, 1].map(|i| u * u * u * p0[i] + 3.0 * u * u * t * p1[i] + 3.0 * u * t * t * p2[i] + t * t * t * p3[i])
+}
+
+fn quadratic(p0: [f64; 2], p1: [f64; 2], p2: [f64; 2], t: f64) -> [f64; 2] {
+    let u = 1.0 - t;
+    [0, 1].map(|i| u * u * p0[i] + 2.0 * u * t * p1[i] + t * t * p2[i])
+}
+
+/// Points along an elliptical arc, from its endpoint form (SVG 1.1 appendix F.6).
+fn arc(from: [f64; 2], radii: [f64; 2], angle: f64, large: bool, sweep: bool, to: [f64; 2]) -> Vec<[f64; 2]> {
+    let [mut rx, mut ry] = radii.map(f64::abs);
+    if rx == 0.0 || ry == 0.0 || from == to {
+        return vec![to];
+    }
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (dx, dy) = ((from[0] - to[0]) / 2.0, (from[1] - to[1]) / 2.0);
+    let (x1, y1) = (cos * dx + sin * dy, -sin * dx + cos * dy);
+    // Radii too small to reach are scaled up until they just do
+    let lambda = x1 * x1 / (rx * rx) + y1 * y1 / (ry * ry);
+    if lambda > 1.0 {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let coefficient = (numerator / denominator).max(0.0).sqrt() * if large == sweep { -1.0 } else { 1.0 };
+    let (cx1, cy1) = (coefficient * rx * y1 / ry, -coefficient * ry * x1 / rx);
+    let (cx, cy) = (cos * cx1 - sin * cy1 + (from[0] + to[0]) / 2.0, sin * cx1 + cos * cy1 + (from[1] + to[1]) / 2.0);
+    let angle_between = |u: [f64; 2], v: [f64; 2]| (u[0] * v[1] - u[1] * v[0]).atan2(u[0] * v[0] + u[1] * v[1]);
+    let u = [(x1 - cx1) / rx, (y1 - cy1) / ry];
+    let v = [(-x1 - cx1) / rx, (-y1 - cy1) / ry];
+    let start = angle_between([1.0, 0.0], u);
+    let mut extent = angle_between(u, v);
+    if !sweep && extent > 0.0 {
+        extent -= 2.0 * PI;
+    } else if sweep && extent < 0.0 {
+        extent += 2.0 * PI;
+    }
+    let mut points = flatten(|t| {
+        let (s, c) = (start + extent * t).sin_cos();
+        [cos * rx * c - sin * ry * s + cx, sin * rx * c + cos * ry * s + cy]
+    });
+    *points.last_mut().unwrap() = to;
+    points
+}
+
+/// A paint value; `None` if it isn't one, so the inherited paint stays.
+fn paint(value: &str) -> Option<Paint> {
+    match value {
+        "none" | "transparent" => Some(Paint::None),
+        "currentColor" => Some(Paint::CurrentColour),
+        _ => match value.strip_prefix("url(") {
+            // Any fallback colour after the reference is ignored
+            Some(reference) => {
+                let id = reference.split(')').next()?.trim().trim_matches(|c| c == '\'' || c == '"');
+                Some(Paint::Url(id.strip_prefix('#')?.to_string()))
+            }
+            None => parse_colour(value).map(Paint::Colour),
+        },
+    }
+}
+
+/// A hex, `rgb()`/`rgba()` or CSS named colour.
+pub fn parse_colour(value: &str) -> Option<[u8; 3]> {
+    let value = value.trim();
+    if value.starts_with('#') {
+        return colour::parse_hex(value);
+    }
+    let lower = value.to_ascii_lowercase();
+    if let Some(arguments) = lower.strip_prefix("rgba(").or(lower.strip_prefix("rgb(")) {
+        let channels: Vec<u8> = arguments
+            .trim_end_matches(')')
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .take(3)
+            .map(|s| match s.strip_suffix('%') {
+                Some(percent) => percent.parse::<f64>().map(|p| p / 100.0 * 255.0),
+                None => s.parse::<f64>(),
+            })
+            .map(|c| c.ok().map(|c| c.round().clamp(0.0, 255.0) as u8))
+            .collect::<Option<_>>()?;
+        return channels.try_into().ok();
+    }
+    names::css().iter().find(|named| named.name == lower).map(|named| named.rgb)
+}
+
+/// The number at the start of a length, ignoring its unit; percentages aren't resolved.
+fn length(value: &str) -> Option<f64> {
+    let value = value.trim();
+    if value.ends_with('%') {
+        return None;
+    }
+    Numbers::new(value).number().filter(|v| v.is_finite() && *v >= 0.0)
+}
+
+fn opacity(value: &str) -> Option<f64> {
+    let value = value.trim();
+    match value.strip_suffix('%') {
+        Some(percent) => percent.parse::<f64>().ok().map(|p| p / 100.0),
+        None => value.parse().ok(),
+    }
+    .map(|o: f64| o.clamp(0.0, 1.0))
+}
+
+/// Determinant of a `transform` list: how much it scales areas.
+fn transform_scale(value: &str) -> f64 {
+    let mut scale = 1.0;
+    for function in value.split(')') {
+        let Some((name, arguments)) = function.split_once('(') else { continue };
+        let mut numbers = Numbers::new(arguments);
+        let arguments: Vec<f64> = std::iter::from_fn(|| numbers.number()).collect();
+        scale *= match (name.trim().trim_start_matches(',').trim(), arguments.as_slice()) {
+            ("matrix", [a, b, c, d, ..]) => a * d - b * c,
+            ("scale", [x]) => x * x,
+            ("scale", [x, y, ..]) => x * y,
+            // Translations, rotations and skews keep areas
+            _ => 1.0,
+        };
+    }
+    scale
+}
+
+/// Width times height of the root element, from `width` and `height` or the `viewBox`.
+fn document_size(attributes: &HashMap<String, String>) -> Option<f64> {
+    let dimension = |name: &str| attributes.get(name).and_then(|v| length(v));
+    if let (Some(width), Some(height)) = (dimension("width"), dimension("height")) {
+        return Some(width * height);
+    }
+    let mut numbers = Numbers::new(attributes.get("viewBox")?);
+    let (_, _, width, height) = (numbers.number()?, numbers.number()?, numbers.number()?, numbers.number()?);
+    Some(width * height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svg_palette() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let document = parse(
+            br##"<?xml version="1.0"?>
+            <svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" viewBox="0 0 100 50">
+              <defs>
+                <linearGradient id="base"><stop offset="0" stop-color="#00f"/><stop offset="1" style="stop-color: rgb(0, 255, 0)"/></linearGradient>
+                <linearGradient id="sky" xlink:href="#base"/>
+                <rect width="1000" height="1000" fill="yellow"/>
+              </defs>
+              <rect width="100" height="50" fill="#fff"/>
+              <g transform="scale(2)" style="fill: red">
+                <path d="M0 0h10v10z"/>
+                <circle r="5" fill="orange" stroke="#000" stroke-width="0.5"/>
+              </g>
+              <g display="none"><rect width="100" height="100" fill="lime"/></g>
+              <rect width="10" height="10" fill="url(#sky)" opacity="0.5"/>
+              <path d="M 0 10 A 10 10 0 0 1 20 10 A 10 10 0 0 1 0 10 Z" fill="rgb(128,0,128)"/>
+            </svg>"##,
+        )?;
+        assert_eq!(document.size, Some(5000.0));
+        let weight = |rgb: [u8; 3]| document.colours.iter().find(|c| c.0 == rgb).map_or(0.0, |c| c.1);
+        assert_eq!(weight([255, 255, 255]), 5000.0);
+        // The 10 by 10 triangle, with its area scaled four times
+        assert_eq!(weight([255, 0, 0]), 200.0);
+        assert!((weight([255, 165, 0]) - 100.0 * PI).abs() < 1e-9);
+        assert!((weight([0, 0, 0]) - 2.0 * PI * 5.0 * 0.5 * 4.0).abs() < 1e-9);
+        // Gradient stops share the half-opaque area
+        assert_eq!((weight([0, 0, 255]), weight([0, 255, 0])), (25.0, 25.0));
+        // Two flattened semicircles
+        assert!((weight([128, 0, 128]) / (100.0 * PI) - 1.0).abs() < 0.01);
+        assert_eq!((weight([255, 255, 0]), weight([0, 255, 0]) - 25.0), (0.0, 0.0));
+        assert_eq!(document.colours[0].0, [255, 255, 255]);
+
+        assert!(parse(b"<svg><rect></svg>").is_err());
+        assert!(is_svg("logo.SVG") && is_svg("icons.zip!a/b.svg") && !is_svg("photo.jpg"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_data() {
+        // Packed numbers and flags, implicit lines after a move, and a relative curve
+        let subpaths = path("M0,0 10-0 10,10Zm20 20l5.5.5L30 30a5 5 0 1120 0c0 0 0 0 0 0");
+        assert_eq!(subpaths[0], (vec![[0.0, 0.0], [10.0, 0.0], [10.0, 10.0]], true));
+        assert_eq!(subpaths[1].0[..3], [[20.0, 20.0], [25.5, 20.5], [30.0, 30.0]]);
+        assert_eq!(subpaths[1].0.last(), Some(&[50.0, 30.0]));
+        assert_eq!(shoelace(&subpaths[0].0), 50.0);
+        assert_eq!(transform_scale("translate(5 5) scale(2, 3) rotate(45)"), 6.0);
+        assert_eq!(parse_colour("RGB(100%, 0%, 50%)"), Some([255, 0, 128]));
+        assert_eq!(parse_colour("SteelBlue"), Some([70, 130, 180]));
+    }
+}
@@ -0,0 +1,336 @@
+//! CSS colour themes and gradients derived from an extracted palette.
+
+use clap::ValueEnum;
+
+use crate::colour;
+use crate::{most_prevalent, rgb_to_hex, select_accent, ColourInfo};
+
+/// Contrast a palette colour needs against the background to be used as the text colour
+/// (WCAG AA for normal text); below it the foreground falls back to black or white.
+pub const MIN_TEXT_CONTRAST: f64 = 4.5;
+
+/// Lab lightness a web page background needs at most to count as dark...
+const DARK_BACKGROUND: f64 = 25.0;
+/// ...or at least to count as light.
+const LIGHT_BACKGROUND: f64 = 85.0;
+
+/// Contrast accent and palette colours of a light or dark theme get against its
+/// background, the WCAG minimum for UI components and large text.
+pub const MIN_UI_CONTRAST: f64 = 3.0;
+
+/// Most Lab chroma of the tinted background and foreground of a light or dark theme.
+const MAX_TINT: f64 = 6.0;
+
+/// Light or dark UI, as in the CSS `prefers-color-scheme` media feature.
+#[derive(Clone, Copy, Debug, ValueEnum, PartialEq)]
+pub enum Scheme {
+    Light,
+    Dark,
+}
+
+impl std::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scheme::Light => write!(f, "light"),
+            Scheme::Dark => write!(f, "dark"),
+        }
+    }
+}
+
+impl Scheme {
+    /// Lab lightness of the background and the foreground.
+    fn lightness(self) -> (f64, f64) {
+        match self {
+            Scheme::Light => (97.0, 15.0),
+            Scheme::Dark => (12.0, 92.0),
+        }
+    }
+}
+
+/// Roles for a UI colour scheme, picked from a palette sorted by prevalence.
+#[derive(Debug)]
+pub struct Theme {
+    pub background: [u8; 3],
+    pub foreground: [u8; 3],
+    pub accent: [u8; 3],
+    /// Text colour for use on top of the accent
+    pub on_accent: [u8; 3],
+    pub palette: Vec<[u8; 3]>,
+}
+
+impl Theme {
+    /// The most prevalent colour becomes the background, the palette colour with the most
+    /// contrast against it the foreground, and [`select_accent`] picks the accent.
+    pub fn from_palette(colours: &[ColourInfo]) -> Option<Theme> {
+        let background = most_prevalent(colours)?.rgb;
+        let foreground = colours
+            .iter()
+            .map(|c| c.rgb)
+            .max_by(|&a, &b| {
+                colour::contrast_ratio(background, a).total_cmp(&colour::contrast_ratio(background, b))
+            })
+            .filter(|&rgb| colour::contrast_ratio(background, rgb) >= MIN_TEXT_CONTRAST)
+            .unwrap_or_else(|| colour::text_colour_on(background));
+        let accent = select_accent(colours).map_or(background, |c| c.rgb);
+        Some(Theme {
+            background,
+            foreground,
+            accent,
+            on_accent: colour::text_colour_on(accent),
+            palette: colours.iter().map(|c| c.rgb).collect(),
+        })
+    }
+
+    /// A light or dark theme in the image's colours, so the two variants of one image
+    /// match. The background and foreground are near-white and near-black (or the other
+    /// way round) tinted with the most prevalent colour's hue; the accent and palette
+    /// keep their hues, darkened or lightened as far as they need to stand out against
+    /// the background by [`MIN_UI_CONTRAST`].
+    pub fn for_scheme(colours: &[ColourInfo], scheme: Scheme) -> Option<Theme> {
+        let [_, a, b] = colour::rgb_to_lab(most_prevalent(colours)?.rgb);
+        let tint = (MAX_TINT / a.hypot(b)).min(1.0);
+        let (background, foreground) = scheme.lightness();
+        let background = colour::lab_to_rgb([background, a * tint, b * tint]);
+        let foreground = colour::lab_to_rgb([foreground, a * tint, b * tint]);
+        let accent = fit_contrast(select_accent(colours).map_or(foreground, |c| c.rgb), background, scheme);
+        Some(Theme {
+            background,
+            foreground,
+            accent,
+            on_accent: colour::text_colour_on(accent),
+            palette: colours.iter().map(|c| fit_contrast(c.rgb, background, scheme)).collect(),
+        })
+    }
+
+    /// CSS custom properties on `:root`, with the palette as `--palette-1` onwards.
+    pub fn to_css(&self) -> String {
+        format!(":root {{\n{}}}", self.declarations("  "))
+    }
+
+    fn declarations(&self, indent: &str) -> String {
+        let mut css = String::new();
+        let roles = [
+            ("background", self.background),
+            ("foreground", self.foreground),
+            ("accent", self.accent),
+            ("on-accent", self.on_accent),
+        ];
+        for (name, rgb) in roles {
+            css.push_str(&format!("{}--{}: {};\n", indent, name, rgb_to_hex(rgb)));
+        }
+        for (i, &rgb) in self.palette.iter().enumerate() {
+            css.push_str(&format!("{}--palette-{}: {};\n", indent, i + 1, rgb_to_hex(rgb)));
+        }
+        css
+    }
+}
+
+/// Light and dark themes as one stylesheet: the first on `:root`, and the others on
+/// `:root` inside a `prefers-color-scheme` query, so pages follow the system setting.
+pub fn schemes_css(themes: &[(Scheme, Theme)]) -> String {
+    let mut blocks = Vec::new();
+    for (i, (scheme, theme)) in themes.iter().enumerate() {
+        if i == 0 {
+            blocks.push(theme.to_css());
+        } else {
+            blocks.push(format!(
+                "@media (prefers-color-scheme: {}) {{\n  :root {{\n{}  }}\n}}",
+                scheme,
+                theme.declarations("    ")
+            ));
+        }
+    }
+    blocks.join("\n\n")
+}
+
+/// A `theme-color` meta tag per theme, each applying through a `prefers-color-scheme`
+/// media query. Manifests have one set of colours, so take those from the first theme.
+pub fn schemes_html(themes: &[(Scheme, Theme)]) -> String {
+    themes
+        .iter()
+        .map(|(scheme, theme)| {
+            format!(
+                "<meta name=\"theme-color\" media=\"(prefers-color-scheme: {})\" content=\"{}\">",
+                scheme,
+                rgb_to_hex(WebColours::from_theme(theme).theme)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `rgb` with its Lab lightness moved away from `background`'s, a step at a time, until
+/// the two have `MIN_UI_CONTRAST` or it can go no further.
+fn fit_contrast(rgb: [u8; 3], background: [u8; 3], scheme: Scheme) -> [u8; 3] {
+    let [mut lightness, a, b] = colour::rgb_to_lab(rgb);
+    let mut fitted = rgb;
+    while colour::contrast_ratio(fitted, background) < MIN_UI_CONTRAST {
+        lightness += match scheme {
+            Scheme::Light => -1.0,
+            Scheme::Dark => 1.0,
+        };
+        if !(0.0..=100.0).contains(&lightness) {
+            break;
+        }
+        fitted = colour::lab_to_rgb([lightness, a, b]);
+    }
+    fitted
+}
+
+/// Colours for a web page's `theme-color` meta tag and its app manifest.
+#[derive(Debug)]
+pub struct WebColours {
+    /// Tints the browser UI around the page
+    pub theme: [u8; 3],
+    /// Fills the splash screen while an installed app loads
+    pub background: [u8; 3],
+}
+
+impl WebColours {
+    /// The accent is the theme colour. The background is the most prevalent colour that is
+    /// light or dark, as a mid-tone rarely suits a page, falling back to the most prevalent
+    /// colour of all.
+    pub fn from_palette(colours: &[ColourInfo]) -> Option<WebColours> {
+        let dominant = most_prevalent(colours)?;
+        let background = colours
+            .iter()
+            .filter(|c| {
+                let lightness = colour::rgb_to_lab(c.rgb)[0];
+                lightness <= DARK_BACKGROUND || lightness >= LIGHT_BACKGROUND
+            })
+            .min_by(|a, b| b.percentage.total_cmp(&a.percentage))
+            .unwrap_or(dominant);
+        let theme = select_accent(colours).unwrap_or(dominant);
+        Some(WebColours { theme: theme.rgb, background: background.rgb })
+    }
+
+    /// The web colours of a derived theme: its accent and its background.
+    pub fn from_theme(theme: &Theme) -> WebColours {
+        WebColours { theme: theme.accent, background: theme.background }
+    }
+
+    /// The `<meta name="theme-color">` tag for the page head.
+    pub fn to_html(&self) -> String {
+        format!("<meta name=\"theme-color\" content=\"{}\">", rgb_to_hex(self.theme))
+    }
+
+    /// `theme_color` and `background_color` members for a web app manifest.
+    pub fn to_manifest(&self) -> String {
+        format!(
+            "\"theme_color\": \"{}\",\n\"background_color\": \"{}\"",
+            rgb_to_hex(self.theme),
+            rgb_to_hex(self.background)
+        )
+    }
+}
+
+/// A CSS `linear-gradient` with a band per colour, as wide as its share of the palette,
+/// in palette order; `--sort` picks the order, e.g. `path` for smooth neighbours.
+pub fn css_gradient(colours: &[ColourInfo]) -> String {
+    let total: f64 = colours.iter().map(|c| c.percentage).sum();
+    let mut start = 0.0;
+    let stops: Vec<String> = colours
+        .iter()
+        .map(|c| {
+            let end = if total > 0.0 { start + c.percentage / total * 100.0 } else { 100.0 };
+            let stop = format!("{} {:.1}% {:.1}%", rgb_to_hex(c.rgb), start, end);
+            start = end;
+            stop
+        })
+        .collect();
+    format!("linear-gradient(90deg, {})", stops.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_roles() {
+        let colours = vec![
+            ColourInfo::new([250, 250, 245], 60.0),
+            ColourInfo::new([40, 40, 50], 25.0),
+            ColourInfo::new([230, 60, 20], 15.0),
+        ];
+        let theme = Theme::from_palette(&colours).unwrap();
+        assert_eq!(theme.background, [250, 250, 245]);
+        assert_eq!(theme.foreground, [40, 40, 50]);
+        assert_eq!(theme.accent, [230, 60, 20]);
+        assert_eq!(theme.on_accent, [0, 0, 0]);
+
+        let css = theme.to_css();
+        assert!(css.starts_with(":root {\n  --background: #fafaf5;"));
+        assert!(css.contains("  --palette-3: #e63c14;\n}"));
+    }
+
+    #[test]
+    fn test_theme_foreground_fallback() {
+        // No palette colour is readable on the background, so fall back to black/white
+        let colours = vec![ColourInfo::new([30, 30, 120], 70.0), ColourInfo::new([60, 30, 110], 30.0)];
+        let theme = Theme::from_palette(&colours).unwrap();
+        assert_eq!(theme.foreground, [255, 255, 255]);
+        assert!(Theme::from_palette(&[]).is_none());
+    }
+
+    #[test]
+    fn test_scheme_themes() {
+        let colours = vec![
+            ColourInfo::new([250, 230, 120], 60.0),
+            ColourInfo::new([40, 40, 50], 25.0),
+            ColourInfo::new([230, 60, 20], 15.0),
+        ];
+        let light = Theme::for_scheme(&colours, Scheme::Light).unwrap();
+        let dark = Theme::for_scheme(&colours, Scheme::Dark).unwrap();
+        assert!(colour::rgb_to_lab(light.background)[0] > 95.0 && colour::rgb_to_lab(dark.background)[0] < 15.0);
+        for theme in [&light, &dark] {
+            assert!(colour::contrast_ratio(theme.foreground, theme.background) >= 12.0);
+            assert!(colour::contrast_ratio(theme.accent, theme.background) >= MIN_UI_CONTRAST);
+            assert!(theme.palette.iter().all(|&rgb| colour::contrast_ratio(rgb, theme.background) >= MIN_UI_CONTRAST));
+        }
+        // The pale yellow is darkened for the light theme and kept for the dark one
+        assert!(colour::rgb_to_lab(light.palette[0])[0] < colour::rgb_to_lab(colours[0].rgb)[0]);
+        assert_eq!(dark.palette[0], colours[0].rgb);
+
+        let themes = [(Scheme::Light, light), (Scheme::Dark, dark)];
+        let css = schemes_css(&themes);
+        assert!(css.starts_with(":root {\n  --background: #"));
+        assert!(css.contains("}\n\n@media (prefers-color-scheme: dark) {\n  :root {\n    --background: #"));
+        assert!(css.ends_with("  }\n}"));
+
+        let html = schemes_html(&themes);
+        let tags: Vec<_> = html.lines().collect();
+        assert_eq!(tags.len(), 2);
+        assert_eq!(
+            tags[1],
+            format!(r#"<meta name="theme-color" media="(prefers-color-scheme: dark)" content="{}">"#, rgb_to_hex(themes[1].1.accent))
+        );
+    }
+
+    #[test]
+    fn test_css_gradient() {
+        // Shares are of the colours reported, which need not total 100
+        let colours = vec![ColourInfo::new([255, 0, 0], 45.0), ColourInfo::new([0, 0, 255], 30.0)];
+        assert_eq!(css_gradient(&colours), "linear-gradient(90deg, #ff0000 0.0% 60.0%, #0000ff 60.0% 100.0%)");
+        assert_eq!(css_gradient(&colours[..1]), "linear-gradient(90deg, #ff0000 0.0% 100.0%)");
+    }
+
+    #[test]
+    fn test_web_colours() {
+        // A mid-tone dominates, so the background is the lighter colour behind it
+        let colours = vec![
+            ColourInfo::new([120, 130, 120], 55.0),
+            ColourInfo::new([245, 245, 240], 30.0),
+            ColourInfo::new([20, 90, 200], 15.0),
+        ];
+        let web = WebColours::from_palette(&colours).unwrap();
+        assert_eq!(web.theme, [20, 90, 200]);
+        assert_eq!(web.background, [245, 245, 240]);
+        assert_eq!(web.to_html(), r##"<meta name="theme-color" content="#145ac8">"##);
+        assert_eq!(web.to_manifest(), "\"theme_color\": \"#145ac8\",\n\"background_color\": \"#f5f5f0\"");
+
+        // With nothing light or dark, a lone colour is both
+        let web = WebColours::from_palette(&colours[..1]).unwrap();
+        assert_eq!((web.theme, web.background), ([120, 130, 120], [120, 130, 120]));
+        assert!(WebColours::from_palette(&[]).is_none());
+    }
+}
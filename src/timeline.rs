@@ -0,0 +1,450 @@
+//! Dominant colours over time, for animations and video.
+//!
+//! Frames are grouped into fixed-length time buckets and the pooled pixels of each bucket
+//! are clustered, so colour grading drift across a cut shows up bucket by bucket. Each
+//! frame can also be clustered on its own, for a sprite sheet with a row per frame.
+//! Animated GIF, PNG and WebP files are decoded directly, and still images count as a
+//! single frame. Anything else is treated as video and decoded by `ffmpeg` at a few frames
+//! per bucket; it and `ffprobe` must be on the `PATH`.
+
+use anyhow::Result;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, Frames, ImageError, ImageFormat, RgbImage};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::process::{Command, Stdio};
+use tracing::{debug, info};
+
+use crate::{
+    cluster_pixels, numbers, preprocess, sample_image, sample_side, Analysis, AnalysisOptions, ColourInfo, DominantColoursError, Pixels,
+    Timings, AGGREGATE_SAMPLES,
+};
+
+/// Frames pulled from a video per bucket.
+const VIDEO_FRAMES_PER_BUCKET: f64 = 4.0;
+
+/// Several frames are pooled per bucket, so each is downsampled to this fraction of the
+/// per-image pixel budget.
+const FRAME_SHARE: usize = 4;
+
+/// Shortest bucket, in seconds; shorter buckets hold the same frames many times over.
+pub const MIN_BUCKET_SECONDS: f64 = 0.01;
+
+/// Most buckets one input is split into, and most frames clustered on their own, each
+/// clustered separately.
+pub const MAX_BUCKETS: usize = 10_000;
+
+/// Largest PNG sprite sheet rendered, in pixels: 384 MiB of RGB.
+const MAX_SHEET_PIXELS: u64 = 1 << 27;
+
+/// Delay browsers show GIF frames for when the file asks for none, in seconds.
+const DEFAULT_FRAME_DELAY: f64 = 0.1;
+
+/// The palette of one stretch of time.
+#[derive(Debug)]
+pub struct Bucket {
+    /// Seconds from the start
+    pub start: f64,
+    pub end: f64,
+    pub analysis: Analysis,
+}
+
+#[derive(Debug)]
+pub struct Timeline {
+    /// Palette of the whole input, from the bucket palettes weighted by duration
+    pub palette: Analysis,
+    /// Buckets in time order; stretches with no frames are left out
+    pub buckets: Vec<Bucket>,
+    /// With `per_frame`, the palette of each frame, spanning the time it is shown. Videos
+    /// are decoded at a few frames per bucket, so these are the frames decoded.
+    pub frames: Vec<Bucket>,
+}
+
+/// A downsampled frame, shown from `time` for `duration` seconds.
+struct Frame {
+    time: f64,
+    duration: f64,
+    image: RgbImage,
+    source_pixels: u64,
+}
+
+/// Analyse an animation or video in buckets of `seconds`, and also frame by frame if
+/// `per_frame` is set.
+pub fn analyze(filename: &str, seconds: f64, per_frame: bool, options: &AnalysisOptions) -> Result<Timeline, DominantColoursError> {
+    if !(seconds >= MIN_BUCKET_SECONDS && seconds.is_finite()) {
+        return Err(DominantColoursError::InvalidOptions(format!(
+            "timeline buckets must be at least {} s long",
+            MIN_BUCKET_SECONDS
+        )));
+    }
+    info!(file = filename, seconds, "Analysing colours over time");
+    let side = (sample_side(options.max_pixels / FRAME_SHARE)).max(1);
+    let mut buckets = Vec::new();
+    let mut frames = Vec::new();
+    let mut open: Vec<(usize, Vec<[f64; 3]>, u64)> = Vec::new();
+    let mut end = 0.0f64;
+    let mut flush = |open: &mut Vec<(usize, Vec<[f64; 3]>, u64)>, before: usize| -> Result<(), DominantColoursError> {
+        while open.first().is_some_and(|(index, _, _)| *index < before) {
+            let (index, values, source_pixels) = open.remove(0);
+            buckets.push((index, cluster_bucket(values, source_pixels, options)?));
+        }
+        Ok(())
+    };
+
+    for_each_frame(filename, side, seconds, options, |mut frame| {
+        preprocess::apply(&mut frame.image, options);
+        let first = (frame.time / seconds) as usize;
+        // A frame belongs to every bucket it is on screen during
+        let last = (((frame.time + frame.duration) / seconds).ceil() as usize).saturating_sub(1).max(first);
+        if last >= MAX_BUCKETS {
+            return Err(DominantColoursError::InvalidOptions(format!(
+                "a timeline of {}-second buckets would need more than {} of them; use longer buckets",
+                seconds, MAX_BUCKETS
+            )));
+        }
+        end = end.max(frame.time + frame.duration);
+        flush(&mut open, first)?;
+        let values: Vec<[f64; 3]> = frame.image.pixels().map(|p| p.0.map(|c| c as f64)).collect();
+        if per_frame {
+            if frames.len() == MAX_BUCKETS {
+                return Err(DominantColoursError::InvalidOptions(format!(
+                    "more than {} frames to analyse one by one",
+                    MAX_BUCKETS
+                )));
+            }
+            let analysis = cluster_bucket(values.clone(), frame.source_pixels, options)?;
+            frames.push(Bucket { start: frame.time, end: frame.time + frame.duration, analysis });
+        }
+        for index in first..=last {
+            match open.iter_mut().find(|(i, _, _)| *i == index) {
+                Some((_, pooled, source)) => {
+                    pooled.extend_from_slice(&values);
+                    *source += frame.source_pixels;
+                }
+                None => open.push((index, values.clone(), frame.source_pixels)),
+            }
+        }
+        Ok(())
+    })?;
+    flush(&mut open, usize::MAX)?;
+
+    let buckets: Vec<Bucket> = buckets
+        .into_iter()
+        .map(|(index, analysis)| Bucket {
+            start: index as f64 * seconds,
+            end: ((index + 1) as f64 * seconds).min(end.max(index as f64 * seconds)),
+            analysis,
+        })
+        .collect();
+    if buckets.is_empty() {
+        let source = std::io::Error::new(ErrorKind::InvalidData, "no frames decoded");
+        return Err(DominantColoursError::image(Some(filename), ImageError::IoError(source)));
+    }
+    debug!(buckets = buckets.len(), "Combining bucket palettes");
+    let palette = overall_palette(&buckets, options)?;
+    Ok(Timeline { palette, buckets, frames })
+}
+
+fn cluster_bucket(values: Vec<[f64; 3]>, source_pixels: u64, options: &AnalysisOptions) -> Result<Analysis, DominantColoursError> {
+    // Busy animations can pool far more frames than the pixel budget allows
+    let step = values.len().div_ceil(options.max_pixels.max(1)).max(1);
+    let values = values.into_iter().step_by(step).collect();
+    cluster_pixels(Pixels { values, positions: None, source_pixels, timings: Timings::default(), warnings: Vec::new() }, options)
+}
+
+/// Stand each bucket colour in for pixels in proportion to its coverage and to how long
+/// the bucket lasts, as when combining the palettes of several images.
+fn overall_palette(buckets: &[Bucket], options: &AnalysisOptions) -> Result<Analysis, DominantColoursError> {
+    let total: f64 = buckets.iter().map(|b| (b.end - b.start).max(f64::EPSILON)).sum();
+    let mut values = Vec::new();
+    let mut timings = Timings::default();
+    for bucket in buckets {
+        let share = (bucket.end - bucket.start).max(f64::EPSILON) / total;
+        timings += bucket.analysis.timings;
+        for colour in &bucket.analysis.colours {
+            let count = (AGGREGATE_SAMPLES as f64 * share * colour.percentage / 100.0).round() as usize;
+            values.extend(std::iter::repeat_n(colour.rgb.map(|c| c as f64), count));
+        }
+    }
+    let mut palette = cluster_pixels(Pixels { values, positions: None, source_pixels: 0, timings, warnings: Vec::new() }, options)?;
+    palette.timings = timings;
+    Ok(palette)
+}
+
+/// Decode `filename` frame by frame, sampled to fit a `side` square as `options` ask.
+fn for_each_frame(
+    filename: &str,
+    side: u32,
+    seconds: f64,
+    options: &AnalysisOptions,
+    mut f: impl FnMut(Frame) -> Result<(), DominantColoursError>,
+) -> Result<(), DominantColoursError> {
+    let image_error = |e| DominantColoursError::image(Some(filename), e);
+    let io_error = |source| DominantColoursError::Io { path: Some(filename.to_string()), source };
+    let format = image::io::Reader::open(filename)
+        .map_err(io_error)?
+        .with_guessed_format()
+        .map_err(io_error)?
+        .format();
+    let open = || File::open(filename).map(BufReader::new).map_err(io_error);
+    let frames: Frames = match format {
+        Some(ImageFormat::Gif) => GifDecoder::new(open()?).map_err(image_error)?.into_frames(),
+        Some(ImageFormat::WebP) => WebPDecoder::new(open()?).map_err(image_error)?.into_frames(),
+        Some(ImageFormat::Png) if PngDecoder::new(open()?).map_err(image_error)?.is_apng() => {
+            PngDecoder::new(open()?).map_err(image_error)?.apng().into_frames()
+        }
+        Some(_) => {
+            let img = image::open(filename).map_err(image_error)?;
+            let source_pixels = img.width() as u64 * img.height() as u64;
+            let image = sample_image(&img, side, options);
+            return f(Frame { time: 0.0, duration: 0.0, image, source_pixels });
+        }
+        None => return video_frames(filename, side, VIDEO_FRAMES_PER_BUCKET / seconds, f),
+    };
+
+    let mut time = 0.0;
+    for frame in frames {
+        let frame = frame.map_err(image_error)?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay = match numer as f64 / denom.max(1) as f64 / 1000.0 {
+            d if d > 0.0 => d,
+            _ => DEFAULT_FRAME_DELAY,
+        };
+        let img = DynamicImage::ImageRgba8(frame.into_buffer());
+        let source_pixels = img.width() as u64 * img.height() as u64;
+        let image = sample_image(&img, side, options);
+        f(Frame { time, duration: delay, image, source_pixels })?;
+        time += delay;
+    }
+    Ok(())
+}
+
+/// Decode a video with `ffmpeg` at `fps` frames per second, scaled to `side` x `side`.
+/// Squashing the frames doesn't change the share of the picture each colour covers, but
+/// pixel counts are of the full frame, whose size `ffprobe` reads first.
+fn video_frames(
+    filename: &str,
+    side: u32,
+    fps: f64,
+    mut f: impl FnMut(Frame) -> Result<(), DominantColoursError>,
+) -> Result<(), DominantColoursError> {
+    let failed = |message: String| {
+        let source = std::io::Error::new(ErrorKind::InvalidData, message);
+        DominantColoursError::image(Some(filename), ImageError::IoError(source))
+    };
+    let probe = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0", "-show_entries", "stream=width,height", "-of", "csv=p=0:s=x"])
+        .arg(filename)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => failed("not an image, and ffprobe was not found to read it as video".to_string()),
+            _ => DominantColoursError::Io { path: Some(filename.to_string()), source: e },
+        })?;
+    if !probe.status.success() {
+        return Err(failed(format!("ffprobe failed: {}", String::from_utf8_lossy(&probe.stderr).trim())));
+    }
+    let source_pixels = frame_pixels(&String::from_utf8_lossy(&probe.stdout))
+        .ok_or_else(|| failed("ffprobe found no video stream".to_string()))?;
+
+    debug!(file = filename, fps, source_pixels, "Decoding video with ffmpeg");
+    let mut child = Command::new("ffmpeg")
+        .args(["-v", "error", "-nostdin", "-i", filename])
+        .args(["-vf", &format!("fps={},scale={}:{}", fps, side, side)])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "pipe:1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => failed("not an image, and ffmpeg was not found to read it as video".to_string()),
+            _ => DominantColoursError::Io { path: Some(filename.to_string()), source: e },
+        })?;
+
+    // Drained alongside the frames, so ffmpeg never blocks on a full stderr pipe
+    let mut stderr = child.stderr.take().expect("piped stderr");
+    let errors = std::thread::spawn(move || {
+        let mut errors = String::new();
+        let _ = stderr.read_to_string(&mut errors);
+        errors
+    });
+    let mut stdout = child.stdout.take().expect("piped stdout");
+    let mut buffer = vec![0; side as usize * side as usize * 3];
+    let mut index = 0;
+    loop {
+        match stdout.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(DominantColoursError::Io { path: Some(filename.to_string()), source: e }),
+        }
+        let image = RgbImage::from_raw(side, side, buffer.clone()).expect("buffer holds one frame");
+        let frame = Frame { time: index as f64 / fps, duration: 1.0 / fps, image, source_pixels };
+        if let Err(e) = f(frame) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        index += 1;
+    }
+    let status = child.wait().map_err(|e| DominantColoursError::Io { path: Some(filename.to_string()), source: e })?;
+    let errors = errors.join().unwrap_or_default();
+    if !status.success() {
+        return Err(failed(format!("ffmpeg failed: {}", errors.trim())));
+    }
+    Ok(())
+}
+
+/// Pixels in a frame from ffprobe's `WIDTHxHEIGHT` output.
+fn frame_pixels(probe: &str) -> Option<u64> {
+    let mut size = probe.lines().next()?.trim().split('x').map(|n| n.parse::<u64>().ok());
+    Some(size.next()?? * size.next()??)
+}
+
+/// Save the timeline as an SVG strip: one column per bucket, its colours stacked by
+/// coverage with the most prevalent at the top.
+pub fn save_svg(buckets: &[Bucket], output_file: &str) -> Result<()> {
+    const COLUMN: f64 = 20.0;
+    const HEIGHT: f64 = 100.0;
+    let end = buckets.last().map_or(0.0, |b| b.end);
+    let mut svg = format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}">"#,
+        buckets.len() as f64 * COLUMN, HEIGHT
+    );
+    for (i, bucket) in buckets.iter().enumerate() {
+        let colours = by_coverage(&bucket.analysis.colours);
+        let total: f64 = colours.iter().map(|c| c.percentage).sum();
+        let mut y = 0.0;
+        for colour in colours {
+            let height = HEIGHT * colour.percentage / total;
+            svg.push_str(&format!(
+                r#"
+    <rect x="{:.1}" y="{:.2}" width="{}" height="{:.2}" fill="rgb({}, {}, {})"><title>{:.1}-{:.1} s: {}%</title></rect>"#,
+                i as f64 * COLUMN, y, COLUMN, height,
+                colour.rgb[0], colour.rgb[1], colour.rgb[2],
+                bucket.start, bucket.end.max(bucket.start).min(end), numbers::share(colour.percentage, 1)
+            ));
+            y += height;
+        }
+    }
+    svg.push_str("\n</svg>");
+    let mut file = File::create(output_file)?;
+    file.write_all(svg.as_bytes())?;
+    Ok(())
+}
+
+/// Save per-frame palettes (`Timeline::frames`) as a sprite sheet of swatches: one row
+/// per frame, in time order, of `cell`-pixel squares from the most prevalent colour, as
+/// SVG, or as PNG for a `.png` output file.
+pub fn save_sheet(frames: &[Bucket], output_file: &str, cell: u32) -> Result<()> {
+    let rows: Vec<Vec<&ColourInfo>> = frames.iter().map(|b| by_coverage(&b.analysis.colours)).collect();
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    let too_large = || anyhow::anyhow!("A sheet of {} rows of {} {}-pixel swatches is too large", rows.len(), columns, cell);
+    let side = |count: usize| u32::try_from(count).ok().and_then(|n| n.checked_mul(cell)).ok_or_else(too_large);
+    let (width, height) = (side(columns)?, side(rows.len().max(1))?);
+    if u64::from(width) * u64::from(height) > MAX_SHEET_PIXELS {
+        return Err(too_large());
+    }
+    if std::path::Path::new(output_file).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+        let img = RgbImage::from_fn(width, height, |x, y| {
+            let colour = rows.get((y / cell) as usize).and_then(|row| row.get((x / cell) as usize));
+            image::Rgb(colour.map_or([255, 255, 255], |c| c.rgb))
+        });
+        img.save_with_format(output_file, ImageFormat::Png)?;
+        return Ok(());
+    }
+    let mut svg = format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {} {}">"#,
+        width, height
+    );
+    for (i, (frame, row)) in frames.iter().zip(&rows).enumerate() {
+        for (j, colour) in row.iter().enumerate() {
+            svg.push_str(&format!(
+                r#"
+    <rect x="{}" y="{}" width="{}" height="{}" fill="rgb({}, {}, {})"><title>{:.2}-{:.2} s: {}%</title></rect>"#,
+                j as u32 * cell, i as u32 * cell, cell, cell,
+                colour.rgb[0], colour.rgb[1], colour.rgb[2],
+                frame.start, frame.end, numbers::share(colour.percentage, 1)
+            ));
+        }
+    }
+    svg.push_str("\n</svg>");
+    let mut file = File::create(output_file)?;
+    file.write_all(svg.as_bytes())?;
+    Ok(())
+}
+
+/// `colours` from the most prevalent.
+fn by_coverage(colours: &[ColourInfo]) -> Vec<&ColourInfo> {
+    let mut colours: Vec<_> = colours.iter().collect();
+    colours.sort_by(|a, b| b.percentage.total_cmp(&a.percentage));
+    colours
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Rgba, RgbaImage};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_gif_timeline() -> anyhow::Result<()> {
+        // Half a second of red, then a full second of blue
+        let dir = tempdir()?;
+        let path = dir.path().join("cut.gif");
+        let frame = |rgba, ms| {
+            image::Frame::from_parts(RgbaImage::from_pixel(8, 8, Rgba(rgba)), 0, 0, Delay::from_numer_denom_ms(ms, 1))
+        };
+        {
+            let mut encoder = GifEncoder::new(File::create(&path)?);
+            encoder.encode_frames([frame([200, 0, 0, 255], 500), frame([0, 0, 200, 255], 1000)])?;
+        }
+        let filename = path.to_str().unwrap();
+        let options = AnalysisOptions { colours: 1, ..AnalysisOptions::default() };
+        let timeline = analyze(filename, 0.5, true, &options)?;
+
+        assert_eq!(timeline.buckets.len(), 3);
+        let first = &timeline.buckets[0];
+        assert_eq!((first.start, first.end), (0.0, 0.5));
+        assert_eq!(first.analysis.colours[0].rgb, [200, 0, 0]);
+        assert_eq!(timeline.buckets[2].analysis.colours[0].rgb, [0, 0, 200]);
+        // Blue is on screen twice as long, so weighs twice as much in the whole
+        assert_eq!(timeline.palette.colours[0].rgb, [66, 0, 133]);
+
+        let svg_path = dir.path().join("timeline.svg");
+        save_svg(&timeline.buckets, svg_path.to_str().unwrap())?;
+        let svg = std::fs::read_to_string(svg_path)?;
+        assert_eq!(svg.matches("<rect").count(), 3);
+
+        // The blue frame spans two buckets but is one row of the sheet
+        let spans: Vec<_> = timeline.frames.iter().map(|f| (f.start, f.end)).collect();
+        assert_eq!(spans, [(0.0, 0.5), (0.5, 1.5)]);
+        let sheet_path = dir.path().join("sheet.png");
+        save_sheet(&timeline.frames, sheet_path.to_str().unwrap(), 10)?;
+        let sheet = image::open(&sheet_path)?.to_rgb8();
+        assert_eq!(sheet.dimensions(), (10, 20));
+        assert_eq!((sheet.get_pixel(5, 5).0, sheet.get_pixel(5, 15).0), ([200, 0, 0], [0, 0, 200]));
+        assert!(save_sheet(&timeline.frames, sheet_path.to_str().unwrap(), u32::MAX).is_err());
+
+        assert!(matches!(analyze(filename, 0.0, false, &options), Err(DominantColoursError::InvalidOptions(_))));
+        assert!(matches!(analyze(filename, 0.00001, false, &options), Err(DominantColoursError::InvalidOptions(_))));
+
+        // Two and a half minutes of one frame would be 15,000 of the shortest buckets
+        let still = dir.path().join("still.gif");
+        GifEncoder::new(File::create(&still)?).encode_frames([frame([0, 200, 0, 255], 150_000)])?;
+        let still = still.to_str().unwrap();
+        assert!(matches!(analyze(still, MIN_BUCKET_SECONDS, false, &options), Err(DominantColoursError::InvalidOptions(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_pixels() {
+        assert_eq!(frame_pixels("1920x1080\n"), Some(1920 * 1080));
+        assert_eq!(frame_pixels("640x480x\n"), Some(640 * 480));
+        assert_eq!(frame_pixels(""), None);
+        assert_eq!(frame_pixels("N/AxN/A\n"), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! Stable identities for the colours of an asset that is analysed again and again.
+//!
+//! Cluster order follows prevalence, so a small change to an image can swap two colours
+//! and make a time series of its palette look as if both changed. Given the previous
+//! result, each new colour takes over the ID and name of the previous colour it is
+//! paired with, the previous colours choosing their nearest counterpart largest first,
+//! and only colours without a counterpart get new IDs.
+
+use anyhow::{bail, Context, Result};
+
+use crate::colour::{self, Metric};
+use crate::compare;
+use crate::ColourInfo;
+
+/// Most delta-E between a previous colour and a new one for the new colour to inherit
+/// its identity, as for a shifted colour in `compare::diff`.
+pub const MAX_DELTA_E: f64 = 10.0;
+
+/// What a colour is known as across runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    pub id: u64,
+    /// A name given in the previous result, kept in place of the nearest named colour
+    pub name: Option<String>,
+}
+
+/// Colours of a previous result with their identities, in the same order.
+#[derive(Debug)]
+pub struct Previous {
+    pub colours: Vec<ColourInfo>,
+    pub identities: Vec<Identity>,
+}
+
+/// Read a previous JSON result: this tool's output, or a `colours` array of objects
+/// with `hex` and optionally `percentage`, `id` and `name`. Colours without an `id` are
+/// numbered by position from 1, so output saved before IDs were asked for still works.
+pub fn load_previous(path: &str) -> Result<Previous> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read previous result {}", path))?;
+    parse_previous(&text).with_context(|| format!("Invalid previous result in {}", path))
+}
+
+fn parse_previous(text: &str) -> Result<Previous> {
+    let value: serde_json::Value = serde_json::from_str(text)?;
+    let Some(entries) = value.get("colours").and_then(|c| c.as_array()) else {
+        bail!("expected a \"colours\" array");
+    };
+    let mut previous = Previous { colours: Vec::new(), identities: Vec::new() };
+    for (i, entry) in entries.iter().enumerate() {
+        let hex = entry.get("hex").and_then(|h| h.as_str()).context("colour entry without \"hex\"")?;
+        let rgb = colour::parse_hex(hex).with_context(|| format!("{:?} is not a hex colour", hex))?;
+        let percentage = entry.get("percentage").and_then(|p| p.as_f64()).unwrap_or(0.0);
+        let id = match entry.get("id") {
+            Some(id) => id.as_u64().with_context(|| format!("colour {}: \"id\" is not a whole number", hex))?,
+            None => i as u64 + 1,
+        };
+        let name = entry.get("name").and_then(|n| n.as_str()).map(str::to_string);
+        previous.colours.push(ColourInfo::new(rgb, percentage));
+        previous.identities.push(Identity { id, name });
+    }
+    Ok(previous)
+}
+
+/// Identities for `colours`: each colour paired with a previous one (see
+/// `compare::pair`) inherits its ID and name; the rest get IDs above any used before.
+pub fn assign(previous: &Previous, colours: &[ColourInfo], metric: Metric) -> Vec<Identity> {
+    let mut identities: Vec<Option<Identity>> = vec![None; colours.len()];
+    for (identity, partner) in previous.identities.iter().zip(compare::pair(&previous.colours, colours, MAX_DELTA_E, metric)) {
+        if let Some((j, _)) = partner {
+            identities[j] = Some(identity.clone());
+        }
+    }
+    let mut next = previous.identities.iter().map(|identity| identity.id).max().unwrap_or(0) + 1;
+    identities
+        .into_iter()
+        .map(|identity| {
+            identity.unwrap_or_else(|| {
+                let id = next;
+                next += 1;
+                Identity { id, name: None }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign() -> Result<()> {
+        let previous = parse_previous(
+            r##"{"colours": [
+                {"hex": "#ff0000", "percentage": 60, "id": 7, "name": "brand red"},
+                {"hex": "#0000ff", "percentage": 40, "id": 3}
+            ]}"##,
+        )?;
+        // Blue grew past red, and a green appeared
+        let colours = [
+            ColourInfo::new([5, 0, 250], 50.0),
+            ColourInfo::new([250, 5, 0], 30.0),
+            ColourInfo::new([0, 200, 0], 20.0),
+        ];
+        let identities = assign(&previous, &colours, Metric::Cie76);
+        assert_eq!(
+            identities,
+            [
+                Identity { id: 3, name: None },
+                Identity { id: 7, name: Some("brand red".to_string()) },
+                Identity { id: 8, name: None },
+            ]
+        );
+
+        // Without IDs, colours are numbered by position
+        let previous = parse_previous(r##"{"colours": [{"hex": "#ff0000"}, {"hex": "#0000ff"}]}"##)?;
+        assert_eq!(previous.identities[1].id, 2);
+        assert!(parse_previous(r##"{"colours": [{"hex": "#ff0000", "id": "a"}]}"##).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_assign_keeps_main_colour_identity() -> Result<()> {
+        // A sliver of a shade right next to the new red mustn't take over the brand red
+        let previous = parse_previous(
+            r##"{"colours": [
+                {"hex": "#fe0000", "percentage": 70, "id": 1, "name": "brand red"},
+                {"hex": "#f70008", "percentage": 1, "id": 2},
+                {"hex": "#0000fe", "percentage": 29, "id": 3}
+            ]}"##,
+        )?;
+        let colours = [ColourInfo::new([250, 9, 10], 71.0), ColourInfo::new([0, 0, 250], 29.0)];
+        let identities = assign(&previous, &colours, Metric::Cie76);
+        assert_eq!(identities, [Identity { id: 1, name: Some("brand red".to_string()) }, Identity { id: 3, name: None }]);
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+//! Adjusting a palette's colours, e.g. for seasonal variants of a brand palette.
+//!
+//! Adjustments are made in CIE LCh, Lab in polar form, so turning the hue keeps each
+//! colour's lightness and chroma as they are seen, and lightening keeps its hue.
+
+use crate::colour;
+use crate::ColourInfo;
+
+/// Adjustments to make to every colour, applied in the order of the fields.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Transform {
+    /// Degrees to turn the hue by, the way red turns to yellow and then green
+    pub rotate: f64,
+    /// Points of L* lightness (0 to 100) to add, or take away if negative
+    pub lighten: f64,
+    /// Percentage of chroma to take away; 100 leaves greys of the same lightness
+    pub desaturate: f64,
+}
+
+impl Transform {
+    /// `rgb` adjusted, clipped into the sRGB gamut. Lightening or darkening as far as the
+    /// ends of the lightness scale leaves white or black.
+    pub fn apply(&self, rgb: [u8; 3]) -> [u8; 3] {
+        let [lightness, a, b] = colour::rgb_to_lab(rgb);
+        let lightness = lightness + self.lighten;
+        if !(0.0..100.0).contains(&lightness) {
+            return if lightness < 0.0 { [0, 0, 0] } else { [255, 255, 255] };
+        }
+        let chroma = a.hypot(b) * (1.0 - self.desaturate / 100.0);
+        let hue = b.atan2(a) + self.rotate.to_radians();
+        colour::lab_to_rgb([lightness, chroma * hue.cos(), chroma * hue.sin()])
+    }
+}
+
+/// `colours` adjusted by `transform`, each keeping its share of the palette.
+pub fn transform(colours: &[ColourInfo], transform: &Transform) -> Vec<ColourInfo> {
+    colours.iter().map(|c| ColourInfo::new(transform.apply(c.rgb), c.percentage)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform() {
+        let red = [170, 90, 90];
+        let [lightness, a, b] = colour::rgb_to_lab(red);
+        let hue = |lab: [f64; 3]| lab[2].atan2(lab[1]).to_degrees();
+
+        // A half turn keeps lightness and lands opposite in hue
+        let turned = colour::rgb_to_lab(Transform { rotate: 180.0, ..Transform::default() }.apply(red));
+        assert!((turned[0] - lightness).abs() < 3.0);
+        assert!((hue(turned) - hue([lightness, a, b])).rem_euclid(360.0) > 160.0);
+
+        let lighter = colour::rgb_to_lab(Transform { lighten: 20.0, ..Transform::default() }.apply(red));
+        assert!((lighter[0] - lightness - 20.0).abs() < 1.0);
+        assert!((hue(lighter) - hue([lightness, a, b])).abs() < 3.0);
+        let darker = Transform { lighten: -200.0, ..Transform::default() }.apply(red);
+        assert_eq!(darker, [0, 0, 0]);
+
+        let grey = Transform { desaturate: 100.0, ..Transform::default() }.apply(red);
+        assert!(grey[0] == grey[1] && grey[1] == grey[2]);
+
+        let palette = [ColourInfo::new(red, 60.0), ColourInfo::new([20, 60, 200], 40.0)];
+        let variant = transform(&palette, &Transform { desaturate: 50.0, ..Transform::default() });
+        assert_eq!(variant.iter().map(|c| c.percentage).collect::<Vec<_>>(), [60.0, 40.0]);
+        assert!(colour::saturation(variant[1].rgb) < colour::saturation(palette[1].rgb));
+    }
+}
@@ -0,0 +1,71 @@
+//! Whole-image measures of how vivid an image is, so a collection can be sorted or
+//! filtered by colourfulness alongside its palettes.
+
+use serde::{Deserialize, Serialize};
+
+use crate::colour;
+
+/// How colourful, saturated and contrasty an image's pixels are.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Vividness {
+    /// Colourfulness M of Hasler and Süsstrunk (2003): 0 for a grey image; they rated
+    /// about 15 slightly, 33 moderately, 59 quite and 109 extremely colourful
+    pub colourfulness: f64,
+    /// Mean HSV saturation of the pixels, in percent
+    pub mean_saturation: f64,
+    /// RMS contrast: standard deviation of relative luminance, from 0 for a flat image
+    /// to 0.5 for an even split of black and white
+    pub contrast: f64,
+}
+
+/// Measure the vividness of sRGB pixels. An empty slice measures as a grey image.
+pub fn compute(values: &[[f64; 3]]) -> Vividness {
+    if values.is_empty() {
+        return Vividness { colourfulness: 0.0, mean_saturation: 0.0, contrast: 0.0 };
+    }
+    let n = values.len() as f64;
+    // Sums and sums of squares of the opponent channels and luminance, and summed saturation
+    let (mut rg, mut yb, mut luminance) = ([0.0; 2], [0.0; 2], [0.0; 2]);
+    let mut saturation = 0.0;
+    for v in values {
+        let rgb = v.map(|c| c.round() as u8);
+        let opponent = [v[0] - v[1], 0.5 * (v[0] + v[1]) - v[2], colour::relative_luminance(rgb)];
+        for (sums, value) in [&mut rg, &mut yb, &mut luminance].into_iter().zip(opponent) {
+            sums[0] += value;
+            sums[1] += value * value;
+        }
+        saturation += colour::saturation(rgb);
+    }
+    let mean = |sums: [f64; 2]| sums[0] / n;
+    let variance = |sums: [f64; 2]| (sums[1] / n - mean(sums).powi(2)).max(0.0);
+    let spread = (variance(rg) + variance(yb)).sqrt();
+    let offset = mean(rg).hypot(mean(yb));
+    Vividness {
+        colourfulness: spread + 0.3 * offset,
+        mean_saturation: saturation / n * 100.0,
+        contrast: variance(luminance).sqrt(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vividness() {
+        let grey = compute(&[[128.0, 128.0, 128.0]; 10]);
+        assert_eq!(grey, Vividness { colourfulness: 0.0, mean_saturation: 0.0, contrast: 0.0 });
+
+        // Half black, half white: no colour, the most contrast
+        let checks = compute(&[[0.0; 3], [255.0; 3]]);
+        assert_eq!(checks.colourfulness, 0.0);
+        assert!((checks.contrast - 0.5).abs() < 1e-9);
+
+        // Pure red and green: rg is ±255 around 0 and yb a steady 127.5
+        let vivid = compute(&[[255.0, 0.0, 0.0], [0.0, 255.0, 0.0]]);
+        assert!((vivid.colourfulness - (255.0 + 0.3 * 127.5)).abs() < 1e-9);
+        assert_eq!(vivid.mean_saturation, 100.0);
+        let muted = compute(&[[150.0, 110.0, 100.0], [110.0, 150.0, 100.0]]);
+        assert!(muted.colourfulness < vivid.colourfulness / 4.0);
+    }
+}
@@ -0,0 +1,79 @@
+//! JavaScript bindings (`wasm` feature), for extracting palettes in the browser without
+//! uploading images:
+//!
+//! ```js
+//! import init, { extract_from_bytes } from "dominant-colours";
+//! await init();
+//! const bytes = new Uint8Array(await file.arrayBuffer());
+//! const { colours } = extract_from_bytes(bytes, { colours: 8 });
+//! ```
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::{analyze_bytes, rgb_to_hex, AnalysisOptions, ColourSpace};
+
+/// Options accepted from JavaScript; missing fields take the command line defaults.
+#[derive(Deserialize)]
+#[serde(default)]
+struct ExtractOptions {
+    colours: usize,
+    silhouette: bool,
+    /// "rgb" or "lab"
+    space: String,
+    linear: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions { colours: 6, silhouette: false, space: "rgb".to_string(), linear: false }
+    }
+}
+
+#[derive(Serialize)]
+struct JsColour {
+    rgb: [u8; 3],
+    hex: String,
+    percentage: f64,
+}
+
+#[derive(Serialize)]
+struct JsAnalysis {
+    colours: Vec<JsColour>,
+    inertia: f64,
+    silhouette: Option<f64>,
+}
+
+/// Extract the dominant colours of an encoded image (PNG, JPEG, ...).
+///
+/// Returns `{ colours: [{ rgb, hex, percentage }], inertia, silhouette }`, and throws
+/// the error message if the image cannot be decoded or clustered.
+#[wasm_bindgen]
+pub fn extract_from_bytes(bytes: &[u8], options: JsValue) -> Result<JsValue, JsValue> {
+    let options: ExtractOptions = if options.is_undefined() || options.is_null() {
+        ExtractOptions::default()
+    } else {
+        serde_wasm_bindgen::from_value(options)?
+    };
+    let space = ColourSpace::from_str(&options.space, true).map_err(|e| JsValue::from_str(&e))?;
+    let options = AnalysisOptions {
+        colours: options.colours,
+        silhouette: options.silhouette,
+        space,
+        linear: options.linear,
+        ..AnalysisOptions::default()
+    };
+
+    let analysis = analyze_bytes(bytes, &options).map_err(|e| JsValue::from_str(&format!("{:#}", anyhow::Error::new(e))))?;
+    let result = JsAnalysis {
+        colours: analysis
+            .colours
+            .iter()
+            .map(|c| JsColour { rgb: c.rgb, hex: rgb_to_hex(c.rgb), percentage: c.percentage })
+            .collect(),
+        inertia: analysis.inertia,
+        silhouette: analysis.silhouette,
+    };
+    Ok(serde_wasm_bindgen::to_value(&result)?)
+}
@@ -0,0 +1,60 @@
+//! Options read from `DOMINANT_COLOURS_*` environment variables. The variables are set on
+//! the child process only, as setting them in the test process would race with other
+//! tests reading the environment.
+
+use std::path::Path;
+use std::process::Command;
+
+/// Run the binary in `dir` with `vars` set, and parse its JSON output.
+fn run(dir: &Path, vars: &[(&str, &str)], args: &[&str]) -> serde_json::Value {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_dominant-colours"));
+    // Nothing from the caller's environment or config files should leak in
+    for (name, _) in std::env::vars().filter(|(name, _)| name.starts_with("DOMINANT_COLOURS_")) {
+        cmd.env_remove(name);
+    }
+    let output = cmd
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .envs(vars.iter().copied())
+        .args(["--format", "json"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn test_extract_options_from_env() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = dir.path().join("image.png");
+    image::RgbImage::from_fn(40, 20, |x, _| image::Rgb(if x < 10 { [230, 60, 20] } else { [250, 250, 245] }))
+        .save(&image)
+        .unwrap();
+    let vars = [
+        ("DOMINANT_COLOURS_SIMULATE", "protanopia,tritanopia"),
+        ("DOMINANT_COLOURS_STATS", "1"),
+        ("DOMINANT_COLOURS_LOCATE", "no"),
+    ];
+    let json = run(dir.path(), &vars, &["-c", "2", image.to_str().unwrap()]);
+    for colour in json["colours"].as_array().unwrap() {
+        let simulated = colour["simulated"].as_object().unwrap();
+        assert!(simulated.contains_key("protanopia") && simulated.contains_key("tritanopia"));
+        assert!(colour.get("std_dev").is_some());
+        assert!(colour.get("location").is_none());
+    }
+}
+
+#[test]
+fn test_subcommand_options_from_env() {
+    let dir = tempfile::tempdir().unwrap();
+    // About 6 delta-E apart: shifted at the default threshold of 10, not at 1
+    let change = |vars: &[(&str, &str)]| {
+        let json = run(dir.path(), vars, &["diff", "#ff0000", "#f00a00"]);
+        json["changes"][0]["change"].as_str().unwrap().to_string()
+    };
+    assert_eq!(change(&[]), "shifted");
+    assert_eq!(change(&[("DOMINANT_COLOURS_DIFF_THRESHOLD", "1")]), "removed");
+    // Another subcommand's threshold is named after it, so leaves diff alone
+    assert_eq!(change(&[("DOMINANT_COLOURS_DEDUPE_THRESHOLD", "1")]), "shifted");
+}